@@ -0,0 +1,28 @@
+# bash completion for porward, load it with: source <(porward completions bash)
+
+_porward() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local commands=" $(porward __complete commands 2>/dev/null | tr '\n' ' ') "
+    local command="" word
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        if [[ "$commands" == *" $word "* ]]; then
+            command="$word"
+            break
+        fi
+    done
+
+    local candidates
+    if [[ "$prev" == --session-profile ]]; then
+        candidates="$(porward __complete profiles 2>/dev/null)"
+    elif [[ "$cur" == -* ]]; then
+        candidates="$(porward __complete flags $command 2>/dev/null)"
+    elif [[ -z "$command" ]]; then
+        candidates="$commands"
+    elif [[ "$command" == up || "$command" == export ]]; then
+        candidates="$(porward __complete tunnels 2>/dev/null)"
+    fi
+    COMPREPLY=($(compgen -W "$candidates" -- "$cur"))
+}
+
+complete -F _porward porward
@@ -0,0 +1,321 @@
+use crate::{
+    config::Config,
+    error::Result,
+    porwarder::Service,
+    providers::{
+        self, AccountSummary, AutoScalingGroupSummary, ClusterSummary, DestinationProvider,
+        DestinationSummary, InstanceProvider, InstanceSummary, TargetGroupSummary, TargetSummary,
+    },
+};
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+type Fetch<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/// Keeps the listings of another provider in the cache directory, per profile, region and
+/// listing. Listings younger than `ttl` are returned right away and refreshed in the background
+/// for the next run, older ones are fetched again. Only listings are stored, never credentials.
+pub struct CachedProvider<P> {
+    inner: Arc<P>,
+    ttl: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    stored: u64,
+    data: T,
+}
+
+impl<P: Send + Sync + 'static> CachedProvider<P> {
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        CachedProvider {
+            inner: Arc::new(inner),
+            ttl,
+        }
+    }
+
+    async fn cached<T>(
+        &self,
+        path: Option<PathBuf>,
+        fetch: impl FnOnce(Arc<P>) -> Fetch<T>,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Send + 'static,
+    {
+        let Some(path) = path else {
+            return fetch(self.inner.clone()).await;
+        };
+        if let Some(entry) = read::<T>(&path) {
+            if now().saturating_sub(entry.stored) < self.ttl.as_secs() {
+                let refresh = fetch(self.inner.clone());
+                tokio::spawn(async move {
+                    if let Ok(data) = refresh.await {
+                        write(&path, &data);
+                    }
+                });
+                return Ok(entry.data);
+            }
+        }
+        let data = fetch(self.inner.clone()).await?;
+        write(&path, &data);
+        Ok(data)
+    }
+}
+
+/// The `cache_ttl` from config.toml, five minutes when it is not set.
+pub fn ttl() -> color_eyre::Result<Duration> {
+    match Config::load()?.cache_ttl {
+        Some(ttl) => {
+            humantime::parse_duration(&ttl).map_err(|e| eyre!("invalid cache_ttl '{}': {}", ttl, e))
+        }
+        None => Ok(DEFAULT_TTL),
+    }
+}
+
+/// Removes every cached listing.
+pub fn clear() -> color_eyre::Result<()> {
+    let dir = dir()?;
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| eyre!("could not remove '{}': {}", dir.display(), e))?;
+    }
+    Ok(())
+}
+
+fn dir() -> color_eyre::Result<PathBuf> {
+    dirs::cache_dir()
+        .map(|dir| dir.join("porward").join("listings"))
+        .ok_or(eyre!("could not determine the cache directory"))
+}
+
+// None when there is nowhere to cache, the listing is fetched every time then
+async fn path(profile_name: &str, region: Option<&str>, listing: &[&str]) -> Option<PathBuf> {
+    let config = providers::load_region_config(profile_name, region, None).await;
+    let region = config
+        .region()
+        .map(|region| region.to_string())
+        .unwrap_or("default".to_string());
+    let name = [profile_name, region.as_str()]
+        .iter()
+        .chain(listing)
+        .map(|part| {
+            // profile names and cluster ids may contain characters a file name can not
+            part.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    Some(dir().ok()?.join(format!("{}.json", name)))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Option<Entry<T>> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+// best effort, a listing that could not be stored is fetched again next time
+fn write<T: Serialize>(path: &Path, data: &T) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(content) = serde_json::to_string(&Entry {
+        stored: now(),
+        data,
+    }) {
+        let _ = std::fs::write(path, content);
+    }
+}
+
+// lookups of single instances and accounts are not worth caching
+#[async_trait]
+impl<P: InstanceProvider + 'static> InstanceProvider for CachedProvider<P> {
+    async fn running_instances(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<InstanceSummary>> {
+        let path = path(profile_name, region, &["instances"]).await;
+        let profile_name = profile_name.to_string();
+        let region = region.map(str::to_string);
+        self.cached(path, |inner| {
+            Box::pin(async move {
+                inner
+                    .running_instances(&profile_name, region.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    async fn find_instance(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        id_or_ip: &str,
+    ) -> Result<Option<InstanceSummary>> {
+        self.inner
+            .find_instance(profile_name, region, id_or_ip)
+            .await
+    }
+
+    async fn auto_scaling_groups(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<AutoScalingGroupSummary>> {
+        self.inner.auto_scaling_groups(profile_name, region).await
+    }
+
+    async fn image_names(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        image_ids: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        self.inner
+            .image_names(profile_name, region, image_ids)
+            .await
+    }
+
+    async fn account(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Option<AccountSummary>> {
+        self.inner.account(profile_name, region).await
+    }
+}
+
+#[async_trait]
+impl<P: DestinationProvider + 'static> DestinationProvider for CachedProvider<P> {
+    async fn destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+    ) -> Result<Vec<DestinationSummary>> {
+        let path = path(
+            profile_name,
+            region,
+            &["destinations", &service.to_string()],
+        )
+        .await;
+        let profile_name = profile_name.to_string();
+        let region = region.map(str::to_string);
+        let service = service.clone();
+        self.cached(path, |inner| {
+            Box::pin(async move {
+                inner
+                    .destinations(&profile_name, region.as_deref(), &service)
+                    .await
+            })
+        })
+        .await
+    }
+
+    async fn clusters(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+    ) -> Result<Vec<ClusterSummary>> {
+        let path = path(profile_name, region, &["clusters", &service.to_string()]).await;
+        let profile_name = profile_name.to_string();
+        let region = region.map(str::to_string);
+        let service = service.clone();
+        self.cached(path, |inner| {
+            Box::pin(async move {
+                inner
+                    .clusters(&profile_name, region.as_deref(), &service)
+                    .await
+            })
+        })
+        .await
+    }
+
+    async fn cluster_destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+        cluster_id: &str,
+    ) -> Result<Vec<DestinationSummary>> {
+        let path = path(
+            profile_name,
+            region,
+            &["cluster", &service.to_string(), cluster_id],
+        )
+        .await;
+        let profile_name = profile_name.to_string();
+        let region = region.map(str::to_string);
+        let service = service.clone();
+        let cluster_id = cluster_id.to_string();
+        self.cached(path, |inner| {
+            Box::pin(async move {
+                inner
+                    .cluster_destinations(&profile_name, region.as_deref(), &service, &cluster_id)
+                    .await
+            })
+        })
+        .await
+    }
+
+    async fn start_cluster(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+        cluster_id: &str,
+    ) -> Result<()> {
+        self.inner
+            .start_cluster(profile_name, region, service, cluster_id)
+            .await
+    }
+
+    // the health of the targets is what they are looked at for, it is never cached
+    async fn target_groups(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        load_balancer: &str,
+    ) -> Result<Vec<TargetGroupSummary>> {
+        self.inner
+            .target_groups(profile_name, region, load_balancer)
+            .await
+    }
+
+    async fn targets(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        target_group_arn: &str,
+    ) -> Result<Vec<TargetSummary>> {
+        self.inner
+            .targets(profile_name, region, target_group_arn)
+            .await
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{
+    porwarder::{validate_host_port, validate_local_port, Method, Service},
+    providers::Tags,
+    selector::Ui,
+};
+use color_eyre::{eyre::eyre, Result};
+use rand::Rng;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    net::{Ipv4Addr, TcpListener},
+    path::PathBuf,
+};
+
+// far enough from the defaults that a locally running postgres or redis is left alone
+const DEFAULT_LOCAL_PORT_OFFSET: u16 = 10000;
+const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+const MAX_BIND_ATTEMPTS: usize = 100;
+
+#[derive(Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    #[serde(default)]
+    pub local_ports: LocalPorts,
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    // profile names or account ids, a trailing * matches a prefix
+    #[serde(default)]
+    pub production: Vec<String>,
+    // destinations with one of these tags, or whose name or host matches one of the names
+    #[serde(default)]
+    pub production_tags: Tags,
+    #[serde(default)]
+    pub production_names: Vec<String>,
+    // only destinations with all of these tags are offered
+    #[serde(default)]
+    pub destination_tags: Tags,
+    // searched by porward find
+    #[serde(default)]
+    pub regions: Vec<String>,
+    // how long listings are reused, e.g. "5m"
+    pub cache_ttl: Option<String>,
+    #[serde(default)]
+    pub endpoints: Endpoints,
+    #[serde(default)]
+    pub proxy: Proxy,
+    // sessions one profile may have open at the same time, across every running porward
+    pub max_sessions: Option<usize>,
+    // tui, plain or fzf, overridden by --ui
+    pub ui: Option<Ui>,
+    // the fzf binary for --ui fzf, e.g. sk
+    pub fzf: Option<String>,
+}
+
+/// `[proxy]`, which wins over `HTTPS_PROXY` and `NO_PROXY` for the AWS calls and the aws cli and
+/// session-manager-plugin porward starts.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Proxy {
+    pub https: Option<String>,
+    pub no_proxy: Option<String>,
+}
+
+/// Endpoint urls per AWS service, e.g. VPC interface endpoints or LocalStack. Services without one
+/// use the regular endpoint, or `AWS_ENDPOINT_URL`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoints {
+    pub ec2: Option<String>,
+    pub ssm: Option<String>,
+    pub sts: Option<String>,
+    pub iam: Option<String>,
+    pub autoscaling: Option<String>,
+    // accepted without the service's feature too, so a config works with every build
+    #[cfg_attr(not(feature = "alb"), allow(dead_code))]
+    pub elbv2: Option<String>,
+    #[cfg_attr(not(feature = "rds"), allow(dead_code))]
+    pub rds: Option<String>,
+    #[cfg_attr(not(feature = "elasticache"), allow(dead_code))]
+    pub elasticache: Option<String>,
+}
+
+impl Endpoints {
+    /// The same endpoint for every service, as with `--endpoint-url`.
+    pub fn all(endpoint_url: &str) -> Self {
+        let endpoint_url = Some(endpoint_url.to_string());
+        Endpoints {
+            ec2: endpoint_url.clone(),
+            ssm: endpoint_url.clone(),
+            sts: endpoint_url.clone(),
+            iam: endpoint_url.clone(),
+            autoscaling: endpoint_url.clone(),
+            elbv2: endpoint_url.clone(),
+            rds: endpoint_url.clone(),
+            elasticache: endpoint_url,
+        }
+    }
+}
+
+// styles are words like "bold yellow on #303030", checked when the selector is created
+#[derive(Default, Deserialize)]
+pub struct ThemeConfig {
+    pub preset: Option<String>,
+    pub highlight: Option<String>,
+    pub border: Option<String>,
+    pub title: Option<String>,
+    pub detail: Option<String>,
+    pub error: Option<String>,
+    pub warning: Option<String>,
+    pub symbol: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default)]
+    pub require_reason: bool,
+    // the profile StartSession runs with, when it differs from the one for the describe calls
+    pub session_profile: Option<String>,
+    // overrides max_sessions for this profile
+    pub max_sessions: Option<usize>,
+    // how sessions of this profile connect, --method wins over it
+    pub method: Option<Method>,
+    // the ssm document of the tunnels, --document-name wins over it
+    pub document: Option<String>,
+    // the instance list starts filtered by these tags, Esc removes them
+    #[serde(default)]
+    pub instance_tags: BTreeMap<String, String>,
+    // the tunnel usually opened with this profile, offered right after it is selected
+    #[serde(flatten)]
+    pub destination: Option<DestinationDefinition>,
+}
+
+/// Service, host and ports of a tunnel, the same keys in tunnels.toml and in a profile's config.
+#[derive(Clone, Deserialize)]
+pub struct DestinationDefinition {
+    pub service: Service,
+    pub host: String,
+    pub host_port: Option<u16>,
+    pub local_port: Option<u16>,
+}
+
+impl DestinationDefinition {
+    pub fn host_port(&self) -> u16 {
+        self.host_port.unwrap_or(self.service.default_port())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(port) = self.host_port {
+            validate_host_port(port)?;
+        }
+        if let Some(port) = self.local_port {
+            validate_local_port(port)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LocalPorts {
+    #[serde(default = "default_local_port_offset")]
+    pub offset: u16,
+    #[serde(default)]
+    pub reserved: Vec<u16>,
+    // first and last port porward picks by itself, 1024..=65535 when not set
+    pub range: Option<[u16; 2]>,
+    // lets the range start below 1024
+    #[serde(default)]
+    pub allow_privileged: bool,
+}
+
+impl Default for LocalPorts {
+    fn default() -> Self {
+        LocalPorts {
+            offset: DEFAULT_LOCAL_PORT_OFFSET,
+            reserved: vec![],
+            range: None,
+            allow_privileged: false,
+        }
+    }
+}
+
+fn default_local_port_offset() -> u16 {
+    DEFAULT_LOCAL_PORT_OFFSET
+}
+
+impl LocalPorts {
+    fn validate(&self) -> Result<()> {
+        let Some([first, last]) = self.range else {
+            return Ok(());
+        };
+        if first > last {
+            return Err(eyre!(
+                "local_ports.range starts at {} after it ends at {}",
+                first,
+                last
+            ));
+        }
+        if first == 0 || (first < FIRST_UNPRIVILEGED_PORT && !self.allow_privileged) {
+            return Err(eyre!(
+                "local_ports.range starts at {}, below {} (set allow_privileged = true to allow it)",
+                first,
+                FIRST_UNPRIVILEGED_PORT
+            ));
+        }
+        Ok(())
+    }
+
+    fn bounds(&self) -> (u32, u32) {
+        let [first, last] = self.range.unwrap_or([FIRST_UNPRIVILEGED_PORT, u16::MAX]);
+        (first as u32, last as u32)
+    }
+
+    fn wrap(&self, port: u32) -> u16 {
+        let (first, last) = self.bounds();
+        let size = last - first + 1;
+        (first + (port % size + size - first % size) % size) as u16
+    }
+
+    // a warning for ports given explicitly that the range would not have picked
+    pub fn outside_range(&self, port: u16) -> Option<String> {
+        let [first, last] = self.range?;
+        (port < first || port > last).then(|| {
+            format!(
+                "local port {} is outside the configured range {}-{}",
+                port, first, last
+            )
+        })
+    }
+
+    // host port + offset and the ports after it, wrapped into the range, without reserved ones
+    fn candidates(&self, host_port: u16) -> impl Iterator<Item = u16> + '_ {
+        let base = host_port as u32 + self.offset as u32;
+        let (low, high) = self.bounds();
+        (0..=high - low)
+            .map(move |step| self.wrap(base + step))
+            .filter(|port| !self.reserved.contains(port))
+    }
+
+    /// Host port + offset, wrapped into the range and moved up past reserved ports. Only when
+    /// every port of the range is reserved is the wrapped port itself returned.
+    pub fn wrap_port(&self, host_port: u16) -> u16 {
+        self.candidates(host_port)
+            .next()
+            .unwrap_or_else(|| self.wrap(host_port as u32 + self.offset as u32))
+    }
+
+    // the mapped port, moved up past the ones something already listens on
+    pub fn local_port(&self, host_port: u16) -> u16 {
+        self.candidates(host_port)
+            .take(MAX_BIND_ATTEMPTS)
+            .find(|port| is_free(*port))
+            .unwrap_or_else(|| self.wrap_port(host_port))
+    }
+
+    // any free port, or a free one in the range when it is configured
+    pub fn free_port(&self) -> Result<u16> {
+        if self.range.is_none() {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+            return Ok(listener.local_addr()?.port());
+        }
+        let (first, last) = self.bounds();
+        let start = rand::thread_rng().gen_range(first..=last);
+        (0..=last - first)
+            .map(|step| self.wrap(start + step))
+            .filter(|port| !self.reserved.contains(port))
+            .take(MAX_BIND_ATTEMPTS)
+            .find(|port| is_free(*port))
+            .ok_or(eyre!("no free local port in {}-{}", first, last))
+    }
+}
+
+fn is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+// a trailing * matches a prefix
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => value == pattern,
+    }
+}
+
+impl Config {
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("porward").join("config.toml"))
+            .ok_or(eyre!("could not determine the config directory"))
+    }
+
+    // a missing config file is the same as an empty one
+    pub fn load() -> Result<Self> {
+        let path = Self::default_path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| eyre!("could not read config file '{}': {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| eyre!("invalid config file '{}': {}", path.display(), e))?;
+        config
+            .local_ports
+            .validate()
+            .map_err(|e| eyre!("invalid config file '{}': {}", path.display(), e))?;
+        for (name, profile) in config.profiles.iter() {
+            if let Some(destination) = profile.destination.as_ref() {
+                destination.validate().map_err(|e| {
+                    eyre!(
+                        "invalid config file '{}': profile '{}': {}",
+                        path.display(),
+                        name,
+                        e
+                    )
+                })?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn profile(&self, name: &str) -> ProfileConfig {
+        self.profiles.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn max_sessions(&self, profile_name: &str) -> Option<usize> {
+        self.profile(profile_name)
+            .max_sessions
+            .or(self.max_sessions)
+    }
+
+    pub fn is_production(&self, profile_name: &str, account_id: Option<&str>) -> bool {
+        self.production.iter().any(|pattern| {
+            matches_pattern(pattern, profile_name)
+                || account_id.is_some_and(|account_id| matches_pattern(pattern, account_id))
+        })
+    }
+
+    pub fn is_production_destination(&self, names: &[&str], tags: &Tags) -> bool {
+        self.production_names
+            .iter()
+            .any(|pattern| names.iter().any(|name| matches_pattern(pattern, name)))
+            || self.production_tags.iter().any(|(key, pattern)| {
+                tags.get(key)
+                    .is_some_and(|value| matches_pattern(pattern, value))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn local_ports(offset: u16, range: Option<[u16; 2]>, reserved: Vec<u16>) -> LocalPorts {
+        LocalPorts {
+            offset,
+            reserved,
+            range,
+            allow_privileged: false,
+        }
+    }
+
+    #[test]
+    fn destinations_with_port_zero_are_rejected() {
+        let service = Service::all().remove(0).config_name();
+        let destination = |ports: &str| -> DestinationDefinition {
+            toml::from_str(&format!("service = '{}'\nhost = 'db'\n{}", service, ports)).unwrap()
+        };
+        assert!(destination("").validate().is_ok());
+        assert!(destination("host_port = 5432\nlocal_port = 15432")
+            .validate()
+            .is_ok());
+        assert_eq!(
+            destination("local_port = 0")
+                .validate()
+                .unwrap_err()
+                .to_string(),
+            "invalid local port '0'"
+        );
+        assert_eq!(
+            destination("host_port = 0")
+                .validate()
+                .unwrap_err()
+                .to_string(),
+            "invalid host port '0'"
+        );
+    }
+
+    #[test]
+    fn unknown_methods_are_rejected() {
+        let config = |method: &str| {
+            toml::from_str::<Config>(&format!("[profiles.dev]\nmethod = '{}'", method))
+        };
+        assert_eq!(
+            config("eice").unwrap().profile("dev").method,
+            Some(Method::Eice)
+        );
+        assert_eq!(
+            config("ssm").unwrap().profile("dev").method,
+            Some(Method::Ssm)
+        );
+        let error = config("bogus")
+            .err()
+            .expect("expected an error")
+            .to_string();
+        assert!(error.contains("unknown variant `bogus`"), "{}", error);
+        assert!("bogus".parse::<Method>().is_err());
+    }
+
+    #[test]
+    fn default_offset_maps_common_ports_past_local_services() {
+        let local_ports = LocalPorts::default();
+        assert_eq!(local_ports.wrap_port(5432), 15432);
+        assert_eq!(local_ports.wrap_port(443), 10443);
+        assert_eq!(local_ports.wrap_port(6379), 16379);
+    }
+
+    #[test]
+    fn reserved_ports_are_skipped() {
+        let local_ports = local_ports(10000, None, vec![15432, 15433]);
+        assert_eq!(local_ports.wrap_port(5432), 15434);
+    }
+
+    #[test]
+    fn every_host_port_maps_into_the_range() {
+        let reserved = vec![1024, 1025, 10000, 15432, 65535];
+        for local_ports in [
+            LocalPorts::default(),
+            local_ports(10000, None, reserved.clone()),
+            local_ports(u16::MAX, Some([20000, 20009]), vec![20000, 20005]),
+        ] {
+            let (first, last) = local_ports.bounds();
+            for host_port in 0..=u16::MAX {
+                let port = local_ports.wrap_port(host_port);
+                assert_ne!(port, 0, "host port {}", host_port);
+                assert!(
+                    (first..=last).contains(&(port as u32)),
+                    "host port {}",
+                    host_port
+                );
+                assert!(
+                    !local_ports.reserved.contains(&port),
+                    "host port {}",
+                    host_port
+                );
+            }
+        }
+    }
+
+    // a range with at least one port that is not reserved
+    fn configured() -> impl Strategy<Value = LocalPorts> {
+        (
+            any::<u16>(),
+            FIRST_UNPRIVILEGED_PORT..=u16::MAX,
+            any::<u16>(),
+            prop::collection::vec(any::<u16>(), 0..64),
+        )
+            .prop_map(|(offset, first, len, reserved)| {
+                let last = first.saturating_add(len % 256);
+                local_ports(offset, Some([first, last]), reserved)
+            })
+            .prop_filter("every port of the range is reserved", |local_ports| {
+                let (first, last) = local_ports.bounds();
+                (first..=last).any(|port| !local_ports.reserved.contains(&(port as u16)))
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn mapped_ports_are_valid_and_never_reserved(
+            local_ports in configured(),
+            host_port in any::<u16>(),
+        ) {
+            let port = local_ports.wrap_port(host_port);
+            let (first, last) = local_ports.bounds();
+            prop_assert_ne!(port, 0);
+            prop_assert!((first..=last).contains(&(port as u32)));
+            prop_assert!(!local_ports.reserved.contains(&port));
+            prop_assert!(local_ports.validate().is_ok());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::providers::load_region_config;
+use aws_credential_types::{provider::ProvideCredentials, Credentials};
+use aws_runtime::env_config;
+use aws_sdk_ssm::error::DisplayErrorContext;
+use color_eyre::{eyre::eyre, Result};
+use std::{collections::BTreeMap, sync::RwLock, time::SystemTime};
+use tokio::process::Command;
+
+// per session profile, only kept in memory
+static PASSED: RwLock<BTreeMap<String, Credentials>> = RwLock::new(BTreeMap::new());
+
+// the profiles whose credentials come from a program, e.g. aws-vault, which the aws cli would
+// run again
+pub(crate) async fn uses_credential_process(profile_name: &str) -> bool {
+    let fs = aws_types::os_shim_internal::Fs::real();
+    let env = aws_types::os_shim_internal::Env::real();
+    let profile_files = env_config::file::EnvConfigFiles::default();
+    aws_config::profile::load(&fs, &env, &profile_files, None)
+        .await
+        .ok()
+        .and_then(|profiles| {
+            profiles
+                .get_profile(profile_name)
+                .and_then(|profile| profile.get("credential_process"))
+                .map(|_| ())
+        })
+        .is_some()
+}
+
+/// Resolves the credentials of `profile_name` once, to hand them to the aws cli and the plugin
+/// instead of the profile.
+pub(crate) async fn pass(profile_name: &str, region: Option<&str>) -> Result<()> {
+    let config = load_region_config(profile_name, region, None).await;
+    let provider = config
+        .credentials_provider()
+        .ok_or(eyre!("profile '{}' has no credentials", profile_name))?;
+    let credentials = provider.provide_credentials().await.map_err(|e| {
+        eyre!(
+            "could not resolve the credentials of profile '{}': {}",
+            profile_name,
+            DisplayErrorContext(e)
+        )
+    })?;
+    PASSED
+        .write()
+        .unwrap()
+        .insert(profile_name.to_string(), credentials);
+    Ok(())
+}
+
+// only the rds command snapshots pass credentials
+#[cfg(all(test, unix, feature = "rds"))]
+pub(crate) fn set_passed(profile_name: &str, credentials: Credentials) {
+    PASSED
+        .write()
+        .unwrap()
+        .insert(profile_name.to_string(), credentials);
+}
+
+pub(crate) fn is_passed(profile_name: &str) -> bool {
+    PASSED.read().unwrap().contains_key(profile_name)
+}
+
+// the environment only, they never end up in a printed command
+pub(crate) fn apply(profile_name: &str, command: &mut Command) {
+    let passed = PASSED.read().unwrap();
+    let Some(credentials) = passed.get(profile_name) else {
+        return;
+    };
+    command
+        .env_remove("AWS_PROFILE")
+        .env("AWS_ACCESS_KEY_ID", credentials.access_key_id())
+        .env("AWS_SECRET_ACCESS_KEY", credentials.secret_access_key());
+    match credentials.session_token() {
+        Some(token) => command.env("AWS_SESSION_TOKEN", token),
+        None => command.env_remove("AWS_SESSION_TOKEN"),
+    };
+}
+
+fn expiry(profile_name: &str) -> Option<SystemTime> {
+    PASSED.read().unwrap().get(profile_name)?.expiry()
+}
+
+pub(crate) fn expiry_warning(profile_name: &str) -> Option<String> {
+    let expiry = expiry(profile_name)?;
+    Some(format!(
+        "the credentials of profile '{}' are passed to the session and expire at {}, reconnects after that fail",
+        profile_name,
+        humantime::format_rfc3339_seconds(expiry)
+    ))
+}
+
+// checked before every reconnect, the session would otherwise fail with an unclear error
+pub(crate) fn expired_warning(profile_name: &str) -> Option<String> {
+    let expiry = expiry(profile_name).filter(|expiry| *expiry <= SystemTime::now())?;
+    Some(format!(
+        "the passed credentials of profile '{}' expired at {}, restart porward to resolve them again",
+        profile_name,
+        humantime::format_rfc3339_seconds(expiry)
+    ))
+}
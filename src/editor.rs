@@ -0,0 +1,62 @@
+use color_eyre::{eyre::eyre, Result};
+use std::{fs::OpenOptions, io::Write, path::Path};
+use tokio::process::Command;
+
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Opens `content` in `$VISUAL` or `$EDITOR` and returns the saved file. The file lives in the
+/// temporary directory only while the editor runs.
+pub(crate) async fn edit(file_name: &str, content: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or(std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or(DEFAULT_EDITOR.to_string());
+    let path = std::env::temp_dir().join(format!("porward-{}-{}", std::process::id(), file_name));
+    write_new(&path, content).map_err(|e| eyre!("could not write '{}': {}", path.display(), e))?;
+    // editors are often given with arguments, e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = Command::new(program).args(words).arg(&path).status().await;
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    let status = status.map_err(|e| eyre!("could not start editor '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(eyre!("editor '{}' exited with {}", editor, status));
+    }
+    edited.map_err(|e| eyre!("could not read '{}': {}", path.display(), e))
+}
+
+// the temporary directory may be shared, so the file must not exist yet, e.g. as a symlink someone
+// else placed there, and only the user can read it
+fn write_new(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn the_file_is_new_and_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("porward-editor-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        write_new(&path, "{}").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // an existing file, or a symlink in its place, is not written through
+        assert!(write_new(&path, "[]").is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
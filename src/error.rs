@@ -0,0 +1,68 @@
+use color_eyre::eyre::Report;
+use std::{error::Error, fmt::Debug};
+
+pub type Result<T, E = PorwardError> = std::result::Result<T, E>;
+
+/// Failures callers may want to tell apart, e.g. a cancelled selection from an AWS error.
+#[derive(Debug, thiserror::Error)]
+pub enum PorwardError {
+    #[error("{tool} is not installed. {hint}")]
+    PrerequisiteMissing { tool: String, hint: String },
+    #[error("User canceled")]
+    Cancelled,
+    #[error("No options to select from for '{step}'")]
+    NoOptions { step: String },
+    #[error("{}", error_chain(.0.as_ref()))]
+    Aws(Box<dyn Error + Send + Sync>),
+    // the user chose the running session of another porward over starting one
+    #[error("already forwarded: {mapping}")]
+    AlreadyForwarded { mapping: String },
+    #[error("session failed ({status}): {message}")]
+    SessionFailed { status: String, message: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("{0:#}")]
+    Other(Report),
+}
+
+impl<E, R> From<aws_sdk_ssm::error::SdkError<E, R>> for PorwardError
+where
+    E: Error + Send + Sync + 'static,
+    R: Debug + Send + Sync + 'static,
+{
+    fn from(error: aws_sdk_ssm::error::SdkError<E, R>) -> Self {
+        PorwardError::Aws(Box::new(error))
+    }
+}
+
+impl PorwardError {
+    /// True for an expired SSO session or temporary credentials, which a new login fixes.
+    pub fn is_expired_credentials(&self) -> bool {
+        let message = self.to_string().to_lowercase();
+        message.contains("expiredtoken")
+            || (message.contains("expired")
+                && (message.contains("token") || message.contains("sso")))
+    }
+}
+
+// errors that went through eyre keep their variant
+impl From<Report> for PorwardError {
+    fn from(report: Report) -> Self {
+        report
+            .downcast::<PorwardError>()
+            .unwrap_or_else(PorwardError::Other)
+    }
+}
+
+// sdk errors only say "service error" at the top, the useful part is further down the chain
+fn error_chain(error: &(dyn Error + Send + Sync)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {}", error));
+        source = error.source();
+    }
+    message
+}
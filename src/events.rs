@@ -0,0 +1,129 @@
+use crate::{porwarder::TunnelSummary, timing};
+use serde::Serialize;
+use std::{
+    io::Write,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Machine readable progress, one JSON object per line on stdout with `--output json`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    Step { name: &'a str, value: &'a str },
+    Start(TunnelSummary),
+    Ready { local_port: u16, host: &'a str },
+    Reconnect { attempt: u32 },
+    Exit(ExitEvent),
+}
+
+/// How a session ended, also part of every history entry.
+#[derive(Clone, Copy, Serialize)]
+pub struct ExitEvent {
+    pub code: i32,
+    pub duration_s: u64,
+}
+
+/// Progress of the builder and the session for applications that render it themselves, set with
+/// [`PortForwarderBuilder::with_event_sink`](crate::porwarder::PortForwarderBuilder::with_event_sink).
+/// Every method does nothing by default.
+///
+/// The calls come in order from the task that drives the builder or the session. A step that asks
+/// reports `on_step_started`, then `on_options_fetched` once its options are listed (not for steps
+/// without a list, e.g. ports), then `on_selection`. Steps that are decided without asking, e.g. a
+/// single cluster or a configured destination, only report `on_selection`. The `cluster` step of
+/// clustered services happens while the `host` step lists its options. Going back repeats a step.
+/// Session events follow the last selection: `Start` first, then any `Ready` and
+/// `Reconnect`, and `Exit` last.
+pub trait EventSink: Send + Sync {
+    fn on_step_started(&self, _step: &str) {}
+    fn on_options_fetched(&self, _step: &str, _count: usize) {}
+    fn on_selection(&self, _step: &str, _value: &str) {}
+    fn on_session_event(&self, _event: &Event) {}
+}
+
+/// The sink used unless another one is set, which prints the events with `--output json`.
+pub struct JsonEvents;
+
+impl EventSink for JsonEvents {
+    fn on_selection(&self, step: &str, value: &str) {
+        emit(&Event::Step { name: step, value });
+    }
+
+    fn on_session_event(&self, event: &Event) {
+        emit(event);
+    }
+}
+
+// shared by the builder, the forwarder and its session
+#[derive(Clone)]
+pub(crate) struct Sink(Arc<dyn EventSink>);
+
+impl Sink {
+    pub fn new(sink: Arc<dyn EventSink>) -> Self {
+        Sink(sink)
+    }
+
+    // the time until the options are listed is what --timing reports for a step
+    pub fn on_step_started(&self, step: &str) {
+        timing::step_started(step);
+        self.0.on_step_started(step);
+    }
+
+    pub fn on_options_fetched(&self, step: &str, count: usize) {
+        timing::step_finished(step);
+        self.0.on_options_fetched(step, count);
+    }
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink(Arc::new(JsonEvents))
+    }
+}
+
+impl Deref for Sink {
+    type Target = dyn EventSink;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+/// Switches stdout to JSON events, everything meant for people goes to stderr from then on.
+pub fn json_output() {
+    JSON.store(true, Ordering::SeqCst);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::SeqCst)
+}
+
+fn emit(event: &Event) {
+    if !is_json() {
+        return;
+    }
+    if let Ok(line) = serde_json::to_string(event) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+// stdout, or stderr when stdout carries the events
+pub(crate) fn human() -> Box<dyn Write> {
+    if is_json() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+pub(crate) fn say(message: &str) {
+    let _ = writeln!(human(), "{}", message);
+}
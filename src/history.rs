@@ -0,0 +1,39 @@
+use crate::{events::ExitEvent, porwarder::TunnelSummary, session::ExitReason};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub ended: String,
+    pub mapping: String,
+    pub tunnel: TunnelSummary,
+    // the same fields as the exit event of --output json
+    #[serde(flatten)]
+    pub exit: ExitEvent,
+    pub reconnects: u32,
+    pub reason: Option<String>,
+    pub exit_reason: ExitReason,
+    pub bytes: Option<u64>,
+    pub session_ids: Vec<String>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("porward").join("history.jsonl"))
+}
+
+// best effort, a missing history entry is not worth failing the session over
+pub fn append(path: &Path, entry: &HistoryEntry) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let Ok(line) = serde_json::to_string(entry) else {
+        return;
+    };
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{events, porwarder::validate_host_name};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    io::{ErrorKind, Write},
+    process::{Command, Stdio},
+};
+
+const HOSTS_FILE: &str = "/etc/hosts";
+const MARKER: &str = "# added by porward, pid ";
+
+/// A `127.0.0.1 <host>` line in /etc/hosts that is removed again when dropped.
+pub(crate) struct HostsEntry {
+    host: String,
+}
+
+impl HostsEntry {
+    // entries of sessions that are no longer running, e.g. after a crash, are removed first
+    pub fn add(host: &str) -> Result<Self> {
+        if cfg!(windows) {
+            return Err(eyre!("--etc-hosts is not supported on windows"));
+        }
+        // the line is written to a file owned by root, it may hold nothing but the host
+        let host = validate_host_name(host)?;
+        let mut lines: Vec<String> = read()?
+            .lines()
+            .filter(|line| !is_stale(line))
+            .map(|line| line.to_string())
+            .collect();
+        lines.push(format!(
+            "127.0.0.1 {} {}{}",
+            host,
+            MARKER,
+            std::process::id()
+        ));
+        write(&lines)?;
+        Ok(HostsEntry {
+            host: host.to_string(),
+        })
+    }
+}
+
+impl Drop for HostsEntry {
+    fn drop(&mut self) {
+        let own = format!("{}{}", MARKER, std::process::id());
+        let result = read().and_then(|content| {
+            write(
+                &content
+                    .lines()
+                    .filter(|line| !line.ends_with(&own))
+                    .map(|line| line.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        });
+        if let Err(e) = result {
+            events::say(&format!(
+                "warning: could not remove '{}' from {}: {}",
+                self.host, HOSTS_FILE, e
+            ));
+        }
+    }
+}
+
+fn is_stale(line: &str) -> bool {
+    line.split_once(MARKER)
+        .and_then(|(_, pid)| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| !is_running(pid))
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // signal 0 only checks that the process exists, EPERM means it belongs to someone else
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+fn read() -> Result<String> {
+    std::fs::read_to_string(HOSTS_FILE).map_err(|e| eyre!("could not read {}: {}", HOSTS_FILE, e))
+}
+
+// through sudo when porward does not run as root, which asks for the password on the terminal
+fn write(lines: &[String]) -> Result<()> {
+    let content = format!("{}\n", lines.join("\n"));
+    match std::fs::write(HOSTS_FILE, &content) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            let mut child = Command::new("sudo")
+                .arg("tee")
+                .arg(HOSTS_FILE)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .map_err(|e| eyre!("could not run sudo: {}", e))?;
+            child
+                .stdin
+                .take()
+                .ok_or(eyre!("could not write to sudo tee"))?
+                .write_all(content.as_bytes())?;
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(eyre!("sudo tee {} failed with {}", HOSTS_FILE, status));
+            }
+            Ok(())
+        }
+        Err(e) => Err(eyre!("could not write {}: {}", HOSTS_FILE, e)),
+    }
+}
@@ -0,0 +1,577 @@
+use crate::{config::Config, porwarder::Service, tunnels::TunnelsFile};
+use color_eyre::{eyre::eyre, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
+    path::Path,
+};
+
+// a published range like 9000-9999 is expanded up to this many ports
+const MAX_RANGE_PORTS: u16 = 1000;
+
+/// A `LocalForward` of a `Host` block in an ssh config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SshForward {
+    pub host: String,
+    pub hostname: Option<String>,
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+/// A port a docker-compose service publishes on the host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComposePort {
+    pub service: String,
+    pub port: u16,
+}
+
+/// A tunnel of tunnels.toml whose local port a compose service also publishes.
+pub struct PortChange {
+    pub tunnel: String,
+    pub service: String,
+    pub from: u16,
+    pub to: u16,
+}
+
+/// A new tunnel for tunnels.toml, with the same keys a hand written one has.
+#[derive(Serialize)]
+pub struct ImportedTunnel {
+    #[serde(skip)]
+    pub name: String,
+    pub profile: String,
+    pub instance: String,
+    pub service: Service,
+    pub host: String,
+    pub host_port: u16,
+    pub local_port: u16,
+}
+
+/// The `LocalForward` lines of every `Host` block. Lines that do not parse, `Match` blocks,
+/// wildcard hosts and unix socket forwards are skipped, and `Include` is not followed.
+pub fn parse_ssh_config(content: &str) -> Vec<SshForward> {
+    let mut forwards = vec![];
+    let mut host: Option<String> = None;
+    let mut hostname: Option<String> = None;
+    let mut pending: Vec<(u16, String, u16)> = vec![];
+    let mut flush = |host: &Option<String>,
+                     hostname: &Option<String>,
+                     pending: &mut Vec<(u16, String, u16)>| {
+        if let Some(host) = host.as_ref() {
+            forwards.extend(
+                pending
+                    .iter()
+                    .map(|(local_port, remote_host, remote_port)| SshForward {
+                        host: host.clone(),
+                        hostname: hostname.clone(),
+                        local_port: *local_port,
+                        remote_host: remote_host.clone(),
+                        remote_port: *remote_port,
+                    }),
+            );
+        }
+        pending.clear();
+    };
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // "Keyword value", "Keyword=value" and "Keyword = value" are all valid
+        let split = line
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(line.len());
+        let keyword = line[..split].to_lowercase();
+        let value = line[split..]
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+            .trim();
+        match keyword.as_str() {
+            "host" => {
+                flush(&host, &hostname, &mut pending);
+                // the first alias that is not a pattern names the tunnels
+                host = value
+                    .split_whitespace()
+                    .map(unquote)
+                    .find(|alias| !alias.contains(['*', '?', '!']))
+                    .map(str::to_string);
+                hostname = None;
+            }
+            "match" => {
+                flush(&host, &hostname, &mut pending);
+                host = None;
+                hostname = None;
+            }
+            "hostname" => hostname = Some(unquote(value).to_string()),
+            "localforward" => pending.extend(parse_local_forward(value)),
+            _ => {}
+        }
+    }
+    flush(&host, &hostname, &mut pending);
+    forwards
+}
+
+// [bind_address:]port host:hostport, also with [ipv6] addresses or host/hostport
+fn parse_local_forward(value: &str) -> Option<(u16, String, u16)> {
+    let mut parts = value.split_whitespace().map(unquote);
+    let (listen, target) = (parts.next()?, parts.next()?);
+    if listen.contains('/') {
+        return None;
+    }
+    let local_port = listen.rsplit(':').next()?.parse::<u16>().ok()?;
+    let (remote_host, remote_port) = match target.rsplit_once(':') {
+        Some(split) => split,
+        None => target.split_once('/')?,
+    };
+    let remote_host = remote_host.trim_start_matches('[').trim_end_matches(']');
+    let remote_port = remote_port.parse::<u16>().ok()?;
+    (!remote_host.is_empty() && local_port != 0 && remote_port != 0)
+        .then(|| (local_port, remote_host.to_string(), remote_port))
+}
+
+/// The host ports published under `services.<name>.ports`, in the short ("8080:80") as well as the
+/// long syntax (`published: 8080`). Not a YAML parser: anchors, merges and multi-line strings are
+/// not understood, and ports only exposed to other containers are not published.
+pub fn parse_compose_ports(content: &str) -> Vec<ComposePort> {
+    let mut ports = vec![];
+    let mut in_services = false;
+    let mut service_indent: Option<usize> = None;
+    let mut service: Option<String> = None;
+    let mut ports_indent: Option<usize> = None;
+    for line in content.lines() {
+        let line = strip_yaml_comment(line);
+        let text = line.trim();
+        if text.is_empty() || text == "---" {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        if indent == 0 {
+            in_services = text.starts_with("services:");
+            service_indent = None;
+            service = None;
+            ports_indent = None;
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+        if let Some(ports_indent_value) = ports_indent {
+            // list items may start at the indentation of the key itself
+            if indent > ports_indent_value
+                || (indent == ports_indent_value && text.starts_with('-'))
+            {
+                if let Some(service) = service.as_ref() {
+                    ports.extend(parse_port_item(text).into_iter().map(|port| ComposePort {
+                        service: service.clone(),
+                        port,
+                    }));
+                }
+                continue;
+            }
+            ports_indent = None;
+        }
+        let service_indent = *service_indent.get_or_insert(indent);
+        if indent == service_indent {
+            service = text
+                .strip_suffix(':')
+                .map(|name| unquote(name.trim()).to_string());
+            continue;
+        }
+        if let Some(rest) = text.strip_prefix("ports:") {
+            ports_indent = Some(indent);
+            // the flow style, e.g. ports: ["5432:5432", "6379:6379"]
+            let rest = rest.trim();
+            if let (Some(items), Some(service)) = (
+                rest.strip_prefix('[')
+                    .and_then(|rest| rest.strip_suffix(']')),
+                service.as_ref(),
+            ) {
+                ports.extend(
+                    items
+                        .split(',')
+                        .flat_map(parse_short_port)
+                        .map(|port| ComposePort {
+                            service: service.clone(),
+                            port,
+                        }),
+                );
+            }
+        }
+    }
+    ports
+}
+
+// "- 8080:80", "- published: 8080" or a "published: 8080" line of a long syntax item
+fn parse_port_item(text: &str) -> Vec<u16> {
+    let item = text.strip_prefix('-').unwrap_or(text).trim();
+    if let Some(published) = item.strip_prefix("published:") {
+        return parse_range(unquote(published.trim()));
+    }
+    // another key of a long syntax item, e.g. target: 80
+    if !text.starts_with('-') || item.contains(": ") || item.ends_with(':') {
+        return vec![];
+    }
+    parse_short_port(item)
+}
+
+// [ip:][host port[-end]:]container port[/protocol], only ports with a host part are published
+fn parse_short_port(item: &str) -> Vec<u16> {
+    let item = unquote(item.trim());
+    let item = item.split('/').next().unwrap_or_default();
+    // an ipv6 host address in brackets
+    let item = match item.strip_prefix('[') {
+        Some(rest) => rest
+            .split_once("]:")
+            .map(|(_, rest)| rest)
+            .unwrap_or_default(),
+        None => item,
+    };
+    let parts: Vec<&str> = item.split(':').collect();
+    if parts.len() < 2 {
+        return vec![];
+    }
+    parse_range(parts[parts.len() - 2])
+}
+
+fn parse_range(value: &str) -> Vec<u16> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    match (start.trim().parse::<u16>(), end.trim().parse::<u16>()) {
+        (Ok(start), Ok(end)) if start > 0 && start <= end => {
+            (start..=end.min(start.saturating_add(MAX_RANGE_PORTS))).collect()
+        }
+        _ => vec![],
+    }
+}
+
+fn strip_yaml_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+    line.split(" #").next().unwrap_or(line)
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|c| c == '"' || c == '\'')
+}
+
+impl ImportedTunnel {
+    /// The `[tunnels.<name>]` table, as appended to tunnels.toml.
+    pub fn to_toml(&self) -> Result<String> {
+        let table = BTreeMap::from([("tunnels", BTreeMap::from([(&self.name, self)]))]);
+        toml::to_string(&table).map_err(|e| eyre!("could not write tunnel '{}': {}", self.name, e))
+    }
+}
+
+/// The tunnels whose local port one of the compose services publishes, each moved to the next
+/// port nothing else uses.
+pub fn compose_collisions(
+    tunnels: &TunnelsFile,
+    compose: &[ComposePort],
+) -> Result<Vec<PortChange>> {
+    let local_ports = Config::load()?.local_ports;
+    let current: BTreeMap<&String, u16> = tunnels
+        .tunnels
+        .iter()
+        .map(|(name, tunnel)| {
+            let local_port = tunnel
+                .destination
+                .local_port
+                .unwrap_or_else(|| local_ports.local_port(tunnel.destination.host_port()));
+            (name, local_port)
+        })
+        .collect();
+    let mut taken: BTreeSet<u16> = compose.iter().map(|port| port.port).collect();
+    taken.extend(current.values());
+    taken.extend(local_ports.reserved.iter());
+    let mut changes = vec![];
+    for (name, local_port) in current.iter() {
+        let Some(published) = compose.iter().find(|port| port.port == *local_port) else {
+            continue;
+        };
+        let Some(free) = (*local_port..=u16::MAX).find(|port| !taken.contains(port)) else {
+            continue;
+        };
+        taken.insert(free);
+        changes.push(PortChange {
+            tunnel: name.to_string(),
+            service: published.service.clone(),
+            from: *local_port,
+            to: free,
+        });
+    }
+    Ok(changes)
+}
+
+/// Sets `local_port` in the table of tunnel `name`, keeping the rest of the file as it is.
+pub fn set_local_port(content: &str, name: &str, port: u16) -> Result<String> {
+    let headers = [
+        format!("[tunnels.{}]", name),
+        format!("[tunnels.\"{}\"]", name),
+    ];
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| headers.iter().any(|header| line.trim() == header))
+        .ok_or(eyre!("tunnel '{}' is not a table of its own", name))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map(|offset| start + 1 + offset)
+        .unwrap_or(lines.len());
+    let mut updated: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    let setting = format!("local_port = {}", port);
+    match (start + 1..end).find(|idx| {
+        lines[*idx]
+            .split('=')
+            .next()
+            .is_some_and(|key| key.trim() == "local_port")
+    }) {
+        Some(idx) => updated[idx] = setting,
+        None => updated.insert(start + 1, setting),
+    }
+    let mut content = updated.join("\n");
+    content.push('\n');
+    Ok(content)
+}
+
+/// Appends `tables` to the tunnels file at `path`, which is created when it does not exist yet.
+pub fn append_tunnels(path: &Path, tables: &str) -> Result<()> {
+    // a file that can not be read is not overwritten with only the new tunnels
+    let mut content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(eyre!(
+                "could not read tunnels file '{}': {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    if !content.is_empty() && !content.ends_with("\n\n") {
+        content.push_str(if content.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        });
+    }
+    content.push_str(tables);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, content)
+        .map_err(|e| eyre!("could not write tunnels file '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn forward(
+        host: &str,
+        hostname: Option<&str>,
+        local: u16,
+        remote: &str,
+        port: u16,
+    ) -> SshForward {
+        SshForward {
+            host: host.to_string(),
+            hostname: hostname.map(str::to_string),
+            local_port: local,
+            remote_host: remote.to_string(),
+            remote_port: port,
+        }
+    }
+
+    // service:port per published port
+    fn ports(content: &str) -> Vec<String> {
+        parse_compose_ports(content)
+            .into_iter()
+            .map(|port| format!("{}:{}", port.service, port.port))
+            .collect()
+    }
+
+    #[test]
+    fn ssh_config_forwards_in_every_keyword_style() {
+        let content = "
+# the bastion of staging
+Host staging-bastion bastion-*
+    HostName 10.0.0.5
+    LocalForward 15432 db.internal:5432
+    LocalForward=16379 cache.internal:6379
+    localforward = 127.0.0.1:10443 \"alb.internal:443\"
+
+Host prod
+  HostName=bastion.example.com
+  LocalForward [::1]:15433 [fd00::10]:5432
+  LocalForward 18080 app.internal/8080
+";
+        assert_eq!(
+            parse_ssh_config(content),
+            vec![
+                forward(
+                    "staging-bastion",
+                    Some("10.0.0.5"),
+                    15432,
+                    "db.internal",
+                    5432
+                ),
+                forward(
+                    "staging-bastion",
+                    Some("10.0.0.5"),
+                    16379,
+                    "cache.internal",
+                    6379
+                ),
+                forward(
+                    "staging-bastion",
+                    Some("10.0.0.5"),
+                    10443,
+                    "alb.internal",
+                    443
+                ),
+                forward("prod", Some("bastion.example.com"), 15433, "fd00::10", 5432),
+                forward(
+                    "prod",
+                    Some("bastion.example.com"),
+                    18080,
+                    "app.internal",
+                    8080
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ssh_config_skips_sockets_match_blocks_and_patterns() {
+        let content = "
+Host dev
+    LocalForward /tmp/db.sock db.internal:5432
+    LocalForward 15432 /var/run/postgresql/.s.PGSQL.5432
+    LocalForward 0 db.internal:5432
+    LocalForward 15432
+    LocalForward 16379 cache.internal:6379
+
+Match host dev exec \"true\"
+    LocalForward 17000 matched.internal:7000
+
+Host *.internal !secret
+    LocalForward 18000 pattern.internal:8000
+
+Host *
+    LocalForward 19000 all.internal:9000
+";
+        assert_eq!(
+            parse_ssh_config(content),
+            vec![forward("dev", None, 16379, "cache.internal", 6379)]
+        );
+    }
+
+    #[test]
+    fn compose_short_syntax_ports() {
+        let content = r#"
+version: "3.8"
+services:
+  db:
+    image: postgres:16
+    ports:
+      - "5432:5432"
+      - 127.0.0.1:15432:5432/tcp
+      - "[::1]:25432:5432"
+      - "5433"
+    expose:
+      - "9999"
+  cache:
+    image: redis
+    ports: ["6379:6379", '127.0.0.1:16379:6379']
+  workers:
+    ports:
+    - 9000-9002:9000-9002
+    - "8080:80" # the admin ui
+volumes:
+  data:
+    ports:
+      - "1111:1111"
+"#;
+        assert_eq!(
+            ports(content),
+            vec![
+                "db:5432",
+                "db:15432",
+                "db:25432",
+                "cache:6379",
+                "cache:16379",
+                "workers:9000",
+                "workers:9001",
+                "workers:9002",
+                "workers:8080",
+            ]
+        );
+    }
+
+    #[test]
+    fn compose_long_syntax_ports() {
+        let content = "
+services:
+  web:
+    ports:
+      - target: 80
+        published: \"8080\"
+        protocol: tcp
+      - published: 8443
+        target: 443
+      - target: 9090
+        host_ip: 127.0.0.1
+";
+        assert_eq!(ports(content), vec!["web:8080", "web:8443"]);
+    }
+
+    #[test]
+    fn compose_ranges_are_bounded() {
+        assert_eq!(parse_range("10-12"), vec![10, 11, 12]);
+        assert_eq!(parse_range("12-10"), Vec::<u16>::new());
+        assert_eq!(parse_range("0"), Vec::<u16>::new());
+        assert_eq!(parse_range("x"), Vec::<u16>::new());
+        assert_eq!(
+            parse_range("1024-65535").len(),
+            MAX_RANGE_PORTS as usize + 1
+        );
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("porward-import-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn tunnels_are_appended_to_a_new_or_existing_file() {
+        let dir = scratch("append");
+        let path = dir.join("porward").join("tunnels.toml");
+        append_tunnels(&path, "[tunnels.db]\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[tunnels.db]\n");
+        append_tunnels(&path, "[tunnels.cache]\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[tunnels.db]\n\n[tunnels.cache]\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_tunnels_files_are_left_alone() {
+        let dir = scratch("unreadable");
+        // a directory where the file should be
+        assert!(append_tunnels(&dir, "[tunnels.db]\n").is_err());
+        // not utf-8
+        let path = dir.join("tunnels.toml");
+        std::fs::write(&path, [0xff, 0xfe, b'\n']).unwrap();
+        let error = append_tunnels(&path, "[tunnels.db]\n").unwrap_err();
+        assert!(error.to_string().contains("could not read tunnels file"));
+        assert_eq!(std::fs::read(&path).unwrap(), [0xff, 0xfe, b'\n']);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! Port forwarding to AWS services through SSM sessions on an EC2 instance.
+//!
+//! [`PortForwarder::builder`] walks through profile, instance, destination type and destination
+//! selection with a [`StringListSelector`], one type-state at a time. The terminal is only touched
+//! by the selector that is passed in, so an embedding application can bring its own.
+//!
+//! ```no_run
+//! use porward::{session::SessionOptions, PortForwarder, TUIStringListSelector};
+//!
+//! # async fn wizard() -> porward::error::Result<i32> {
+//! let options = SessionOptions::default();
+//! let port_forwarder = PortForwarder::builder(Box::new(TUIStringListSelector::inline_view(10)))
+//!     .setup(&options)?
+//!     .profile()
+//!     .await?
+//!     .instance()
+//!     .await?
+//!     .destination_type()?
+//!     .destination()
+//!     .await?
+//!     .build()
+//!     .await?;
+//! port_forwarder.run(options).await
+//! # }
+//! ```
+
+#[cfg(not(any(feature = "alb", feature = "elasticache", feature = "rds")))]
+compile_error!("at least one of the alb, elasticache and rds features has to be enabled");
+
+pub mod cache;
+pub(crate) mod config;
+pub(crate) mod credentials;
+pub(crate) mod editor;
+pub mod error;
+pub mod events;
+pub(crate) mod history;
+pub(crate) mod hosts;
+pub mod import;
+pub(crate) mod metrics;
+#[cfg(any(test, feature = "mock-selector"))]
+pub mod mock;
+#[cfg(feature = "native-tunnel")]
+pub(crate) mod native;
+pub(crate) mod notification;
+pub(crate) mod permissions;
+pub mod porwarder;
+pub(crate) mod prerequisites;
+pub mod probe;
+pub mod providers;
+pub mod proxy;
+pub(crate) mod reachability;
+pub(crate) mod relay;
+pub mod selector;
+pub mod session;
+pub(crate) mod session_log;
+pub(crate) mod sessions;
+pub mod shutdown;
+pub mod socks;
+pub(crate) mod state;
+pub(crate) mod status;
+pub mod supervisor;
+pub mod terminal;
+pub mod theme;
+pub mod timing;
+pub mod tunnels;
+pub mod uri;
+
+pub use error::PorwardError;
+pub use porwarder::{
+    Destination, ErrorAction, InstanceTarget, PortForwarder, PortForwarderBuilder, SelectItem,
+    Service, StringListSelector, TunnelAdder, TunnelSummary,
+};
+pub use selector::{FzfStringListSelector, PlainStringListSelector, TUIStringListSelector, Ui};
@@ -1,33 +1,1048 @@
-use porwarder::PortForwarder;
-use selector::TUIStringListSelector;
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum,
+};
+use color_eyre::eyre::eyre;
+use crossterm::{
+    cursor::MoveToColumn,
+    terminal::{Clear, ClearType},
+};
+use porward::{
+    cache::{self, CachedProvider},
+    events, import,
+    porwarder::{
+        available_profiles, parse_hosts, DestinationType, InstanceTarget, Method, Parameters,
+        Ready, SshOptions,
+    },
+    probe::{self, Expect},
+    providers::{
+        self, AwsDestinationProvider, AwsInstanceProvider, DestinationProvider, InstanceProvider,
+    },
+    proxy,
+    session::SessionOptions,
+    shutdown::Shutdown,
+    socks::SocksProxy,
+    supervisor::Supervisor,
+    terminal::{self, TerminalSupport},
+    theme::Theme,
+    timing,
+    tunnels::TunnelsFile,
+    uri::TunnelUri,
+    Destination, FzfStringListSelector, PlainStringListSelector, PortForwarder,
+    PortForwarderBuilder, PorwardError, Service, StringListSelector, TUIStringListSelector, Ui,
+};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-pub mod porwarder;
-pub mod selector;
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// Do not restart the session when it drops
+    #[arg(long)]
+    no_reconnect: bool,
+    /// Stream the session output instead of showing the status panel
+    #[arg(long)]
+    no_status: bool,
+    /// Continue even if session-manager-plugin is older than the supported minimum
+    #[arg(long)]
+    force: bool,
+    /// Start sessions through the aws cli instead of calling StartSession directly
+    #[arg(long, global = true)]
+    aws_cli: bool,
+    /// Always list instances and destinations from AWS instead of reusing recent listings
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Send every AWS call to this endpoint, e.g. LocalStack, instead of [endpoints] in config.toml
+    #[arg(long, global = true)]
+    endpoint_url: Option<String>,
+    /// Format of stdout; json prints one event per line and everything else on stderr
+    #[arg(long, value_enum, default_value_t = Output::Text, global = true)]
+    output: Output,
+    /// Print how long each step and the AWS calls of each service took when porward exits
+    #[arg(long, global = true)]
+    timing: bool,
+    /// How options are selected: tui, plain or fzf (default from config.toml, then the terminal)
+    #[arg(long, global = true)]
+    ui: Option<Ui>,
+    /// Print the effective settings, e.g. the proxy, before starting
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Periodically open a connection to the local port to avoid the SSM idle timeout (e.g. 5m)
+    #[arg(long, value_parser = humantime::parse_duration)]
+    keep_alive: Option<Duration>,
+    /// Probe the tunnel at this interval like porward check and mark it degraded when it fails
+    #[arg(long, value_parser = humantime::parse_duration)]
+    health_interval: Option<Duration>,
+    /// Reason for the session, e.g. a ticket number, recorded by SSM and in the history
+    #[arg(long)]
+    reason: Option<String>,
+    /// Profile to start the SSM session with, when it differs from the one used to list resources
+    #[arg(long)]
+    session_profile: Option<String>,
+    /// Close the tunnel after this long without traffic (e.g. 15m), exiting with code 3
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "keep_alive")]
+    idle_timeout: Option<Duration>,
+    /// How long to wait for the session to start listening on the local port
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    ready_timeout: Duration,
+    /// Write a timestamped log of each session (output, reconnects, exit status) to this directory
+    #[arg(long, value_name = "DIR")]
+    session_log: Option<PathBuf>,
+    /// SSM document to start the session with instead of AWS-StartPortForwardingSessionToRemoteHost
+    #[arg(long)]
+    document_name: Option<String>,
+    /// How the session connects: ssm or eice (defaults to the profile's method in config.toml)
+    #[arg(long)]
+    method: Option<Method>,
+    /// Extra document parameter as key=value, repeat a key to pass a list (overrides computed values)
+    #[arg(long = "parameter", value_name = "KEY=VALUE", value_parser = parse_parameter)]
+    parameters: Vec<(String, String)>,
+    /// Review and edit the document parameters as JSON in $EDITOR before the session starts
+    #[arg(long)]
+    edit_parameters: bool,
+    /// Show a desktop notification when the tunnel becomes ready or drops
+    #[arg(long)]
+    notify: bool,
+    /// Address to listen on; anything other than localhost goes through a local relay
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: IpAddr,
+    /// Allow --bind to expose the tunnel to the network
+    #[arg(long)]
+    i_know_this_is_exposed: bool,
+    /// Relay connections through porward to count traffic and connections
+    #[arg(long)]
+    stats: bool,
+    /// Only offer destinations with this tag as key=value, repeat for several tags
+    #[arg(long = "destination-tag", value_name = "KEY=VALUE", value_parser = parse_parameter)]
+    destination_tags: Vec<(String, String)>,
+    /// Leave out destinations in a different VPC than the selected instance
+    #[arg(long)]
+    same_vpc: bool,
+    /// Select an Auto Scaling Group and use one of its healthy instances
+    #[arg(long = "asg")]
+    auto_scaling_group: bool,
+    /// Point the destination's host name at localhost in /etc/hosts while the session runs
+    #[arg(long)]
+    etc_hosts: bool,
+    /// Open the destination in the browser once the tunnel is ready (load balancers only)
+    #[arg(long)]
+    open: bool,
+    /// Take the configured destination without asking
+    #[arg(long)]
+    yes: bool,
+    /// Let --yes also skip the confirmation for production accounts and destinations
+    #[arg(long, requires = "yes")]
+    allow_production: bool,
+    /// Serve Prometheus metrics of the session on this localhost port
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
+    /// Select the destination before the instance and list the instances in its VPC first
+    #[arg(long)]
+    destination_first: bool,
+    /// Destination type to use instead of asking, e.g. postgresql or redis
+    #[arg(long)]
+    service: Option<Service>,
+    /// Offer the host[:port] lines of this file (- for stdin) instead of listing destinations
+    #[arg(long, value_name = "FILE", requires = "service")]
+    hosts_from: Option<PathBuf>,
+    /// Check that the instance can reach the destination before starting (needs ssm:SendCommand)
+    #[arg(long)]
+    check: bool,
+    /// Check the describe and ssm:StartSession permissions of the profile right after selecting it
+    #[arg(long)]
+    check_permissions: bool,
+    /// Resolve the credentials once and pass them to the aws cli and plugin instead of the profile
+    #[arg(long)]
+    pass_credentials_env: bool,
+    /// Forward connections over the SSM data channel without session-manager-plugin
+    #[cfg(feature = "native-tunnel")]
+    #[arg(long, conflicts_with = "aws_cli")]
+    native_tunnel: bool,
+}
 
-async fn run() -> color_eyre::Result<()> {
-    color_eyre::install()?;
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ExportFormat {
+    /// A bash script running aws ssm start-session
+    Sh,
+    /// A ~/.ssh/config stanza with the ProxyCommand for the tunnel's instance
+    SshConfig,
+}
 
-    let selector = Box::new(TUIStringListSelector::inline_view(6));
-    let result = PortForwarder::builder(selector)
-        .setup()?
-        .profile()
-        .await?
-        .instance()
-        .await?
-        .destination_type()?
-        .destination()
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Start tunnels defined in the tunnels file
+    Up {
+        /// Names of the tunnels to start
+        #[arg(required_unless_present = "all")]
+        names: Vec<String>,
+        /// Start every tunnel defined in the tunnels file
+        #[arg(long, conflicts_with = "names")]
+        all: bool,
+        /// Path to the tunnels file (defaults to <config dir>/porward/tunnels.toml)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Print a tunnel from the tunnels file as a script or ssh config that only needs the aws cli
+    Export {
+        /// Name of the tunnel to export
+        name: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Sh)]
+        format: ExportFormat,
+        /// User in the ssh-config stanza
+        #[arg(long)]
+        login_user: Option<String>,
+        /// Path to the tunnels file (defaults to <config dir>/porward/tunnels.toml)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Save tunnels from an ssh config or move them off the ports docker compose publishes
+    Import {
+        #[command(subcommand)]
+        command: ImportCommands,
+    },
+    /// Connect to an instance with ssh through an AWS-StartSSHSession session
+    Ssh {
+        /// User to log in as; without it the ~/.ssh/config stanza is printed instead
+        #[arg(long)]
+        login_user: Option<String>,
+        /// Port sshd listens on inside the instance
+        #[arg(long, default_value_t = 22)]
+        port: u16,
+        /// Print the ~/.ssh/config stanza and the ssh command without running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Open an interactive shell on an instance
+    Shell,
+    /// End an SSM session, e.g. one left behind by a client that crashed
+    TerminateSession {
+        /// Id of the session, as shown in the status panel, the summary and the history
+        session_id: String,
+        /// Profile the session was started with (defaults to AWS_PROFILE or default)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Probe a running tunnel through its local port, exiting with 1 when the probe fails
+    Check {
+        /// Local port of the tunnel
+        #[arg(long)]
+        local_port: u16,
+        /// What to send: tcp, http, redis or postgres
+        #[arg(long, default_value = "tcp")]
+        expect: Expect,
+    },
+    /// Start the tunnel a porward:// uri describes, as printed when a tunnel starts
+    Open {
+        /// porward://profile/<profile>?instance=<id or tag:key=value>&service=..&host=..&port=..
+        uri: TunnelUri,
+    },
+    /// Search regions for an instance, database cluster or load balancer by name and tunnel there
+    Find {
+        /// Part of the name or id to look for
+        name: String,
+        /// Regions to search, comma separated (defaults to regions in config.toml)
+        #[arg(long, value_delimiter = ',')]
+        regions: Vec<String>,
+    },
+    /// Manage the listings porward keeps for a few minutes
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Start a local SOCKS5 proxy that reaches any host the instance can reach
+    Socks {
+        /// Port to listen on, always on localhost
+        #[arg(long, default_value_t = 1080)]
+        port: u16,
+    },
+    /// Print a completion script, e.g. source <(porward completions bash)
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Candidates for the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(value_enum)]
+        kind: CompletionKind,
+        /// Subcommand whose flags are completed
+        command: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CompletionKind {
+    Commands,
+    Flags,
+    Profiles,
+    Tunnels,
+}
+
+#[derive(Subcommand)]
+enum ImportCommands {
+    /// Turn the LocalForward lines of the Host blocks into tunnels
+    SshConfig {
+        /// Path to the ssh config (defaults to ~/.ssh/config)
+        path: Option<PathBuf>,
+        /// Path to the tunnels file (defaults to <config dir>/porward/tunnels.toml)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Give tunnels new local ports where a compose service publishes theirs
+    Compose {
+        /// Compose files (defaults to the compose and override files in the current directory)
+        paths: Vec<PathBuf>,
+        /// Path to the tunnels file (defaults to <config dir>/porward/tunnels.toml)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Remove all cached listings
+    Clear,
+}
+
+// only local files are read, completion has to be quick
+async fn complete(kind: CompletionKind, command: Option<String>) -> color_eyre::Result<()> {
+    let mut cli = Cli::command();
+    // propagates the global flags to the subcommands
+    cli.build();
+    let candidates: Vec<String> = match kind {
+        CompletionKind::Commands => cli
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect(),
+        CompletionKind::Flags => {
+            let command = match command {
+                Some(name) => cli.find_subcommand(&name).unwrap_or(&cli),
+                None => &cli,
+            };
+            command
+                .get_arguments()
+                .filter(|arg| !arg.is_hide_set())
+                .filter_map(|arg| arg.get_long())
+                .map(|long| format!("--{}", long))
+                .collect()
+        }
+        CompletionKind::Profiles => available_profiles().await?,
+        CompletionKind::Tunnels => {
+            let path = TunnelsFile::default_path()?;
+            if path.exists() {
+                TunnelsFile::load(&path)?.tunnels.into_keys().collect()
+            } else {
+                vec![]
+            }
+        }
+    };
+    for candidate in candidates {
+        println!("{}", candidate);
+    }
+    Ok(())
+}
+
+fn parse_parameter(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got '{}'", value)),
+    }
+}
+
+// loads the theme up front, so a bad color is reported before anything is drawn
+// --ui wins over ui in config.toml, without either the terminal decides
+fn selector(ui: Option<Ui>) -> color_eyre::Result<Box<dyn StringListSelector>> {
+    let ui = match ui {
+        Some(ui) => Some(ui),
+        None => Ui::configured()?,
+    };
+    Ok(match (ui, TerminalSupport::detect()) {
+        (Some(Ui::Fzf), _) => Box::new(FzfStringListSelector::from_config()?),
+        (Some(Ui::Plain), _) | (None, TerminalSupport::Plain) => Box::new(PlainStringListSelector),
+        (_, TerminalSupport::NoColor) => Box::new(
+            TUIStringListSelector::inline_view(6)
+                .with_theme(Theme::load()?)
+                .monochrome(),
+        ),
+        (_, TerminalSupport::Full | TerminalSupport::Plain) => {
+            Box::new(TUIStringListSelector::inline_view(6).with_theme(Theme::load()?))
+        }
+    })
+}
+
+// listings are reused across runs unless --no-cache is given
+fn providers(
+    no_cache: bool,
+) -> color_eyre::Result<(Box<dyn InstanceProvider>, Box<dyn DestinationProvider>)> {
+    if no_cache {
+        return Ok((
+            Box::new(AwsInstanceProvider::default()),
+            Box::new(AwsDestinationProvider::default()),
+        ));
+    }
+    let ttl = cache::ttl()?;
+    Ok((
+        Box::new(CachedProvider::new(AwsInstanceProvider::default(), ttl)),
+        Box::new(CachedProvider::new(AwsDestinationProvider::default(), ttl)),
+    ))
+}
+
+// what the wizard starts from besides the selections
+enum Start {
+    Select,
+    Find(String, Vec<String>),
+    Open(TunnelUri),
+}
+
+async fn wizard(
+    builder: PortForwarderBuilder,
+    options: SessionOptions,
+    destination_first: bool,
+    start: Start,
+    // --service, with the hosts of --hosts-from
+    preset: Option<(Service, Option<Vec<Destination>>)>,
+    document_name: Option<String>,
+    parameters: Parameters,
+) -> color_eyre::Result<i32> {
+    let builder = builder.setup(&options)?;
+    let builder = match start {
+        Start::Find(name, regions) => {
+            builder
+                .profile()
+                .await?
+                .find(&name, &regions)
+                .await?
+                .destination_type()?
+                .destination()
+                .await?
+        }
+        // only what no longer resolves is asked for
+        Start::Open(uri) => {
+            let builder = if available_profiles().await?.contains(&uri.profile) {
+                builder.with_profile(&uri.profile).await?
+            } else {
+                builder.profile().await?
+            };
+            let builder = match uri.instance.as_ref() {
+                Some(instance) => builder.with_matching_instance(instance).await?,
+                None => builder.instance().await?,
+            };
+            builder
+                .with_service(uri.service)
+                .with_candidate_hosts(vec![Destination {
+                    host: uri.host,
+                    port: uri.port,
+                }])?
+        }
+        Start::Select if destination_first => {
+            let builder = builder.profile().await?;
+            // a destination given on the command line wins over the configured one
+            let builder = match preset {
+                Some(_) => builder,
+                None => builder.configured_destination()?,
+            };
+            destination(builder.defer_instance(), preset)
+                .await?
+                .ranked_instance()
+                .await?
+        }
+        Start::Select => {
+            let builder = builder.profile().await?;
+            let builder = match preset {
+                Some(_) => builder,
+                None => builder.configured_destination()?,
+            };
+            destination(builder.instance().await?, preset).await?
+        }
+    };
+    // the status view adds tunnels through the same profile and instance
+    let (port_forwarder, adder) = match builder.build_with_adder().await {
+        // the other porward keeps forwarding, which is all this run was for
+        Err(PorwardError::AlreadyForwarded { mapping }) => {
+            terminal::restore();
+            println!("{}", mapping);
+            return Ok(0);
+        }
+        result => result?,
+    };
+    let adder = options.status_view.then_some(adder);
+    let result = port_forwarder
+        .with_document(document_name, parameters)
+        .run_with_adder(options, adder)
+        .await;
+    terminal::restore();
+    Ok(result?)
+}
+
+async fn destination(
+    builder: PortForwarderBuilder<DestinationType>,
+    preset: Option<(Service, Option<Vec<Destination>>)>,
+) -> color_eyre::Result<PortForwarderBuilder<Ready>> {
+    Ok(match preset {
+        Some((service, Some(hosts))) => {
+            builder.with_service(service).with_candidate_hosts(hosts)?
+        }
+        Some((service, None)) => builder.with_service(service).destination().await?,
+        None => builder.destination_type()?.destination().await?,
+    })
+}
+
+// host[:port] lines from a file or stdin; without a terminal to ask, only one may be given
+fn read_hosts(path: &Path, service: &Service) -> color_eyre::Result<Vec<Destination>> {
+    let content = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read hosts from '{}': {}", path.display(), e))?
+    };
+    let hosts = parse_hosts(&content, service.default_port())?;
+    if hosts.len() > 1 && TerminalSupport::detect() == TerminalSupport::Plain {
+        return Err(eyre!(
+            "{} hosts were given and there is no terminal to select one:\n{}",
+            hosts.len(),
+            hosts
+                .iter()
+                .map(|host| format!("{}:{}", host.host, host.port))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+    }
+    Ok(hosts)
+}
+
+// profile and instance selection for the modes without a fixed destination
+async fn instance_wizard(
+    selector: Box<dyn StringListSelector>,
+    force: bool,
+    aws_cli: bool,
+    no_cache: bool,
+) -> color_eyre::Result<Box<InstanceTarget>> {
+    let (instances, destinations) = providers(no_cache)?;
+    let options = SessionOptions {
+        force,
+        aws_cli,
+        ..Default::default()
+    };
+    let result = async {
+        PortForwarder::builder_with_providers(selector, instances, destinations)
+            .setup(&options)?
+            .profile()
+            .await?
+            .instance()
+            .await?
+            .build_for_instance()
+    }
+    .await;
+    terminal::restore();
+    Ok(result?)
+}
+
+async fn export(
+    name: &str,
+    format: ExportFormat,
+    login_user: Option<String>,
+    file: Option<PathBuf>,
+) -> color_eyre::Result<String> {
+    let path = match file {
+        Some(path) => path,
+        None => TunnelsFile::default_path()?,
+    };
+    let (name, definition) = TunnelsFile::load(&path)?
+        .select(&[name.to_string()])?
+        .remove(0);
+    match format {
+        ExportFormat::Sh => definition.export_script(&name).await,
+        ExportFormat::SshConfig => {
+            definition
+                .export_ssh_config(&name, login_user.as_deref())
+                .await
+        }
+    }
+}
+
+// the files docker compose reads by itself
+const COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+    "compose.override.yaml",
+    "compose.override.yml",
+    "docker-compose.override.yaml",
+    "docker-compose.override.yml",
+];
+
+async fn import_ssh_config(
+    path: Option<PathBuf>,
+    file: Option<PathBuf>,
+    cli: &Cli,
+) -> color_eyre::Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => dirs::home_dir()
+            .map(|dir| dir.join(".ssh").join("config"))
+            .ok_or(eyre!("could not determine the home directory"))?,
+    };
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| eyre!("could not read ssh config '{}': {}", path.display(), e))?;
+    let forwards = import::parse_ssh_config(&content);
+    if forwards.is_empty() {
+        return Err(eyre!("no LocalForward found in '{}'", path.display()));
+    }
+    let file = match file {
+        Some(file) => file,
+        None => TunnelsFile::default_path()?,
+    };
+    let existing = if file.exists() {
+        TunnelsFile::load(&file)?
+    } else {
+        TunnelsFile::default()
+    };
+    let mut hosts: Vec<&str> = vec![];
+    for forward in forwards.iter() {
+        if !hosts.contains(&forward.host.as_str()) {
+            hosts.push(&forward.host);
+        }
+    }
+    let mut tunnels = vec![];
+    for host in hosts {
+        let host_forwards: Vec<_> = forwards
+            .iter()
+            .filter(|forward| forward.host == host)
+            .collect();
+        let hostname = host_forwards[0]
+            .hostname
+            .as_ref()
+            .map(|hostname| format!(" ({})", hostname))
+            .unwrap_or_default();
+        println!(
+            "Host {}{}: select the profile and instance it reaches",
+            host, hostname
+        );
+        let target =
+            instance_wizard(selector(cli.ui)?, cli.force, cli.aws_cli, cli.no_cache).await?;
+        for forward in host_forwards {
+            let name = format!("{}-{}", forward.host, forward.local_port);
+            if existing.tunnels.contains_key(&name) {
+                println!("Skipping {}, the tunnels file already has it", name);
+                continue;
+            }
+            let service = match Service::for_port(forward.remote_port) {
+                Some(service) => service,
+                None => {
+                    let selected = selector(cli.ui)?.select(
+                        format!(
+                            "Destination type of {}:{}",
+                            forward.remote_host, forward.remote_port
+                        ),
+                        Service::all()
+                            .iter()
+                            .map(|service| service.to_string())
+                            .collect(),
+                    )?;
+                    terminal::restore();
+                    Service::all()
+                        .into_iter()
+                        .find(|service| service.to_string() == selected)
+                        .ok_or(eyre!("destination type is empty"))?
+                }
+            };
+            tunnels.push(import::ImportedTunnel {
+                name,
+                profile: target.profile_name().to_string(),
+                instance: target.instance_id().to_string(),
+                service,
+                host: forward.remote_host.clone(),
+                host_port: forward.remote_port,
+                local_port: forward.local_port,
+            });
+        }
+    }
+    if tunnels.is_empty() {
+        println!("Nothing to import");
+        return Ok(());
+    }
+    let tables = tunnels
+        .iter()
+        .map(|tunnel| tunnel.to_toml())
+        .collect::<color_eyre::Result<Vec<_>>>()?
+        .join("\n");
+    println!("To be appended to {}:\n\n{}", file.display(), tables);
+    if !confirm(cli, format!("Write {} tunnels?", tunnels.len()))? {
+        return Ok(());
+    }
+    import::append_tunnels(&file, &tables)?;
+    println!("Saved {} tunnels to {}", tunnels.len(), file.display());
+    Ok(())
+}
+
+fn import_compose(paths: Vec<PathBuf>, file: Option<PathBuf>, cli: &Cli) -> color_eyre::Result<()> {
+    let paths = if paths.is_empty() {
+        COMPOSE_FILES
+            .iter()
+            .map(PathBuf::from)
+            .filter(|path| path.exists())
+            .collect()
+    } else {
+        paths
+    };
+    if paths.is_empty() {
+        return Err(eyre!("no compose file found in the current directory"));
+    }
+    let mut published = vec![];
+    for path in paths.iter() {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read compose file '{}': {}", path.display(), e))?;
+        published.extend(import::parse_compose_ports(&content));
+    }
+    let file = match file {
+        Some(file) => file,
+        None => TunnelsFile::default_path()?,
+    };
+    let changes = import::compose_collisions(&TunnelsFile::load(&file)?, &published)?;
+    if changes.is_empty() {
+        println!(
+            "No tunnel uses a port the compose services publish ({} ports)",
+            published.len()
+        );
+        return Ok(());
+    }
+    let mut content = std::fs::read_to_string(&file)?;
+    println!("To be changed in {}:\n", file.display());
+    for change in changes.iter() {
+        println!(
+            "[tunnels.{}]\nlocal_port = {}  # was {}, published by {}\n",
+            change.tunnel, change.to, change.from, change.service
+        );
+        content = import::set_local_port(&content, &change.tunnel, change.to)?;
+    }
+    if !confirm(cli, format!("Change {} tunnels?", changes.len()))? {
+        return Ok(());
+    }
+    std::fs::write(&file, content)
+        .map_err(|e| eyre!("could not write tunnels file '{}': {}", file.display(), e))?;
+    println!("Updated {} tunnels in {}", changes.len(), file.display());
+    Ok(())
+}
+
+// --yes writes without asking
+fn confirm(cli: &Cli, title: String) -> color_eyre::Result<bool> {
+    if cli.yes {
+        return Ok(true);
+    }
+    let answer = selector(cli.ui)?.select(title, vec!["Yes".into(), "No".into()]);
+    terminal::restore();
+    Ok(answer? == "Yes")
+}
+
+async fn up(names: Vec<String>, file: Option<PathBuf>, aws_cli: bool) -> color_eyre::Result<i32> {
+    let path = match file {
+        Some(path) => path,
+        None => TunnelsFile::default_path()?,
+    };
+    let definitions = TunnelsFile::load(&path)?.select(&names)?;
+    let shutdown = Shutdown::install();
+    Supervisor::start(definitions, aws_cli)
         .await?
-        .build()?
-        .run();
-    ratatui::restore();
-    result
+        .wait(&shutdown)
+        .await?;
+    Ok(shutdown.exit_code().unwrap_or(0))
+}
+
+// the tunnels of porward up are started as defined, the flags of a single tunnel would be ignored
+fn single_tunnel_flags(matches: &ArgMatches) -> Vec<String> {
+    Cli::command()
+        .get_arguments()
+        .filter(|arg| !arg.is_global_set())
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{}", long))
+        .collect()
+}
+
+fn reject_single_tunnel_flags(matches: &ArgMatches) {
+    let flags = single_tunnel_flags(matches);
+    if !flags.is_empty() {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "{} only apply to a single tunnel and cannot be used with 'up'",
+                    flags.join(", ")
+                ),
+            )
+            .exit();
+    }
+}
+
+async fn run() -> color_eyre::Result<i32> {
+    color_eyre::install()?;
+    terminal::install_panic_hook();
+
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(("up", _)) = matches.subcommand() {
+        reject_single_tunnel_flags(&matches);
+    }
+    if cli.output == Output::Json {
+        events::json_output();
+    }
+    if cli.timing {
+        timing::enable();
+    }
+    providers::use_endpoints(cli.endpoint_url.as_deref())?;
+    let proxy = proxy::use_proxy()?;
+    if cli.verbose {
+        let region = std::env::var("AWS_REGION").ok();
+        for line in proxy.describe(region.as_deref()) {
+            eprintln!("{}", line);
+        }
+    }
+    match cli.command {
+        command @ (None | Some(Commands::Find { .. }) | Some(Commands::Open { .. })) => {
+            let start = match command {
+                Some(Commands::Find { name, regions }) => Start::Find(name, regions),
+                Some(Commands::Open { uri }) => Start::Open(uri),
+                _ => Start::Select,
+            };
+            let shutdown = Shutdown::install();
+            let mut parameters = Parameters::new();
+            for (key, value) in cli.parameters {
+                parameters.entry(key).or_default().push(value);
+            }
+            let options = SessionOptions {
+                force: cli.force,
+                aws_cli: cli.aws_cli,
+                reconnect: !cli.no_reconnect,
+                // the status panel draws to stdout
+                status_view: !cli.no_status
+                    && cli.output == Output::Text
+                    && TerminalSupport::detect() != TerminalSupport::Plain,
+                keep_alive: cli.keep_alive,
+                health_interval: cli.health_interval,
+                reason: cli.reason,
+                session_profile: cli.session_profile,
+                idle_timeout: cli.idle_timeout,
+                ready_timeout: cli.ready_timeout,
+                session_log: cli.session_log,
+                notify: cli.notify,
+                bind: cli.bind,
+                allow_exposed: cli.i_know_this_is_exposed,
+                stats: cli.stats,
+                destination_tags: cli.destination_tags.into_iter().collect(),
+                same_vpc: cli.same_vpc,
+                auto_scaling_group: cli.auto_scaling_group,
+                check: cli.check,
+                check_permissions: cli.check_permissions,
+                pass_credentials: cli.pass_credentials_env,
+                method: cli.method,
+                etc_hosts: cli.etc_hosts,
+                open: cli.open,
+                metrics_port: cli.metrics_port,
+                yes: cli.yes,
+                allow_production: cli.allow_production,
+                edit_parameters: cli.edit_parameters,
+                #[cfg(feature = "native-tunnel")]
+                native_tunnel: cli.native_tunnel,
+                shutdown: shutdown.clone(),
+            };
+            let preset = match cli.service {
+                Some(service) => {
+                    let hosts = cli
+                        .hosts_from
+                        .map(|path| read_hosts(&path, &service))
+                        .transpose()?;
+                    Some((service, hosts))
+                }
+                None => None,
+            };
+            let (instances, destinations) = providers(cli.no_cache)?;
+            let builder =
+                PortForwarder::builder_with_providers(selector(cli.ui)?, instances, destinations);
+            let result = wizard(
+                builder,
+                options,
+                cli.destination_first,
+                start,
+                preset,
+                cli.document_name,
+                parameters,
+            )
+            .await;
+            if let Some(exit_code) = shutdown.exit_code() {
+                terminal::restore();
+                print_timing();
+                std::process::exit(exit_code);
+            }
+            result
+        }
+        Some(Commands::Cache {
+            command: CacheCommands::Clear,
+        }) => {
+            cache::clear()?;
+            println!("Removed the cached listings");
+            Ok(0)
+        }
+        Some(Commands::Up { names, file, .. }) => up(names, file, cli.aws_cli).await,
+        Some(Commands::Export {
+            name,
+            format,
+            login_user,
+            file,
+        }) => {
+            print!("{}", export(&name, format, login_user, file).await?);
+            Ok(0)
+        }
+        Some(Commands::Import {
+            command: ImportCommands::SshConfig { ref path, ref file },
+        }) => import_ssh_config(path.clone(), file.clone(), &cli)
+            .await
+            .map(|_| 0),
+        Some(Commands::Import {
+            command:
+                ImportCommands::Compose {
+                    ref paths,
+                    ref file,
+                },
+        }) => import_compose(paths.clone(), file.clone(), &cli).map(|_| 0),
+        Some(Commands::Ssh {
+            login_user,
+            port,
+            dry_run,
+        }) => {
+            instance_wizard(selector(cli.ui)?, cli.force, true, cli.no_cache)
+                .await?
+                .ssh(&SshOptions {
+                    login_user,
+                    port,
+                    dry_run,
+                })
+                .await
+        }
+        Some(Commands::Shell) => {
+            instance_wizard(selector(cli.ui)?, cli.force, true, cli.no_cache)
+                .await?
+                .shell()
+                .await
+        }
+        Some(Commands::Check { local_port, expect }) => {
+            let probe = probe::probe(local_port, expect).await;
+            println!("localhost:{}: {}", local_port, probe);
+            Ok(if probe.is_ok() { 0 } else { 1 })
+        }
+        Some(Commands::TerminateSession {
+            session_id,
+            profile,
+        }) => {
+            let profile = profile
+                .or(std::env::var("AWS_PROFILE").ok())
+                .unwrap_or("default".to_string());
+            providers::terminate_session(&profile, None, &session_id).await?;
+            println!("Terminated session {}", session_id);
+            Ok(0)
+        }
+        Some(Commands::Completions { shell }) => {
+            print!(
+                "{}",
+                match shell {
+                    Shell::Bash => include_str!("../completions/porward.bash"),
+                    Shell::Zsh => include_str!("../completions/porward.zsh"),
+                }
+            );
+            Ok(0)
+        }
+        Some(Commands::Complete { kind, command }) => complete(kind, command).await.map(|_| 0),
+        Some(Commands::Socks { port }) => {
+            let target =
+                instance_wizard(selector(cli.ui)?, cli.force, cli.aws_cli, cli.no_cache).await?;
+            let shutdown = Shutdown::install();
+            SocksProxy::new(*target, cli.aws_cli)
+                .serve(port, &shutdown)
+                .await?;
+            Ok(shutdown.exit_code().unwrap_or(0))
+        }
+    }
+}
+
+fn print_timing() {
+    if let Some(report) = timing::report() {
+        eprint!("{}", report);
+    }
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
-    if let Err(e) = run().await {
-        ratatui::restore();
-        println!("{}{}", e, " ".repeat(80));
+    // dropped before anything is printed, on a panic as well
+    let result = {
+        let _terminal = terminal::guard();
+        run().await
+    };
+    match result {
+        Ok(exit_code) => {
+            print_timing();
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            // the inline viewport leaves the cursor on a line that may still hold a selector row
+            let _ = crossterm::execute!(
+                std::io::stderr(),
+                MoveToColumn(0),
+                Clear(ClearType::FromCursorDown)
+            );
+            eprintln!("{}", e);
+            // also after a failure, which is when a slow step is most interesting
+            print_timing();
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(args: &[&str]) -> Vec<String> {
+        single_tunnel_flags(&Cli::command().get_matches_from(args))
+    }
+
+    #[test]
+    fn up_takes_the_shared_flags() {
+        assert!(flags(&["porward", "--aws-cli", "--no-cache", "up", "--all"]).is_empty());
+        assert!(flags(&["porward", "up", "db", "--verbose", "--ui", "plain"]).is_empty());
+    }
+
+    #[test]
+    fn up_finds_the_flags_of_a_single_tunnel() {
+        assert_eq!(
+            flags(&["porward", "--keep-alive", "5m", "--force", "up", "db"]),
+            vec!["--force", "--keep-alive"]
+        );
+        assert_eq!(
+            flags(&["porward", "--parameter", "a=b", "up", "--all"]),
+            vec!["--parameter"]
+        );
     }
-    Ok(())
 }
@@ -0,0 +1,236 @@
+use crate::{
+    relay::TrafficSnapshot,
+    session::{SessionState, SessionStatus},
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    fmt::Write,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+// Serves the counters of the running session in the Prometheus text format on localhost, for
+// tunnels that stay up for hours. Updated by the session on every tick.
+pub(crate) struct MetricsServer {
+    task: JoinHandle<()>,
+    sample: Arc<Mutex<Sample>>,
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    up: bool,
+    reconnects: u32,
+    started: Instant,
+    traffic: Option<TrafficSnapshot>,
+}
+
+impl MetricsServer {
+    pub async fn start(port: u16, started: Instant) -> Result<Self> {
+        let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| eyre!("could not serve metrics on {}: {}", address, e))?;
+        let sample = Arc::new(Mutex::new(Sample {
+            up: false,
+            reconnects: 0,
+            started,
+            traffic: None,
+        }));
+        let task = tokio::spawn(accept(listener, sample.clone()));
+        Ok(MetricsServer { task, sample })
+    }
+
+    pub fn update(&self, status: &SessionStatus) {
+        let mut sample = self.sample.lock().unwrap();
+        sample.up = status.state == SessionState::Ready;
+        sample.reconnects = status.reconnects;
+        sample.traffic = status.traffic;
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// a failed accept, e.g. out of file descriptors, does not end the metrics of the session
+async fn accept(listener: TcpListener, sample: Arc<Mutex<Sample>>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let sample = *sample.lock().unwrap();
+        tokio::spawn(respond(stream, sample));
+    }
+}
+
+// one request per connection, anything but GET /metrics is a 404
+async fn respond(mut stream: TcpStream, sample: Sample) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return;
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", exposition(&sample)),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn exposition(sample: &Sample) -> String {
+    let mut body = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = writeln!(body, "# HELP porward_{} {}", name, help);
+        let _ = writeln!(body, "# TYPE porward_{} {}", name, kind);
+        let _ = writeln!(body, "porward_{} {}", name, value);
+    };
+    metric(
+        "tunnel_up",
+        "gauge",
+        "1 while the tunnel accepts connections.",
+        sample.up as u64,
+    );
+    metric(
+        "reconnects_total",
+        "counter",
+        "Sessions restarted after a drop or on request.",
+        sample.reconnects as u64,
+    );
+    metric(
+        "session_age_seconds",
+        "gauge",
+        "Seconds since porward started the session.",
+        sample.started.elapsed().as_secs(),
+    );
+    // only connections through the relay are counted
+    if let Some(traffic) = sample.traffic {
+        metric(
+            "bytes_in_total",
+            "counter",
+            "Bytes received from the destination.",
+            traffic.received,
+        );
+        metric(
+            "bytes_out_total",
+            "counter",
+            "Bytes sent to the destination.",
+            traffic.sent,
+        );
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn serve(sample: Sample) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(accept(listener, Arc::new(Mutex::new(sample))));
+        port
+    }
+
+    async fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_in_the_text_format() {
+        let port = serve(Sample {
+            up: true,
+            reconnects: 2,
+            started: Instant::now() - Duration::from_secs(90),
+            traffic: Some(TrafficSnapshot {
+                sent: 1200,
+                received: 34000,
+                active: 1,
+                connections: 3,
+            }),
+        })
+        .await;
+        let response = get(port, "/metrics").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        let samples: Vec<_> = body.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            vec![
+                "porward_tunnel_up 1",
+                "porward_reconnects_total 2",
+                "porward_session_age_seconds 90",
+                "porward_bytes_in_total 34000",
+                "porward_bytes_out_total 1200",
+            ]
+        );
+        assert!(body.contains("# TYPE porward_reconnects_total counter\n"));
+        assert!(body.contains("# TYPE porward_tunnel_up gauge\n"));
+    }
+
+    #[tokio::test]
+    async fn traffic_is_left_out_without_the_relay() {
+        let port = serve(Sample {
+            up: false,
+            reconnects: 0,
+            started: Instant::now(),
+            traffic: None,
+        })
+        .await;
+        let response = get(port, "/metrics").await;
+        assert!(response.contains("porward_tunnel_up 0\n"));
+        assert!(!response.contains("bytes_"));
+    }
+
+    #[tokio::test]
+    async fn other_paths_are_not_found() {
+        let port = serve(Sample {
+            up: true,
+            reconnects: 0,
+            started: Instant::now(),
+            traffic: None,
+        })
+        .await;
+        for path in ["/", "/metrics/extra", "/favicon.ico"] {
+            let response = get(port, path).await;
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{}",
+                path
+            );
+            assert!(response.ends_with("\r\n\r\nnot found\n"));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    error::{PorwardError, Result},
+    porwarder::{ErrorAction, SelectItem, Service, StringListSelector},
+    providers::{
+        all_pages, AutoScalingGroupSummary, DestinationProvider, DestinationSummary,
+        InstanceProvider, InstanceSummary,
+    },
+};
+use async_trait::async_trait;
+use aws_smithy_async::future::pagination_stream::{fn_stream::FnStream, PaginationStream};
+use color_eyre::eyre::eyre;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// A scripted answer for [`MockStringListSelector`].
+pub enum Answer {
+    Index(usize),
+    Label(String),
+    // the options of a multi-select, by index
+    Indices(Vec<usize>),
+    Text(String),
+    // what to do after a step failed
+    Action(ErrorAction),
+}
+
+/// A prompt the selector was shown, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prompt {
+    pub title: String,
+    pub options: Vec<String>,
+    // the option the selector started on, e.g. the last used destination
+    pub initial: usize,
+}
+
+/// Selector that answers from a script instead of the terminal and records every prompt.
+#[derive(Default)]
+pub struct MockStringListSelector {
+    answers: VecDeque<Answer>,
+    prompts: Arc<Mutex<Vec<Prompt>>>,
+}
+
+impl MockStringListSelector {
+    pub fn new(answers: Vec<Answer>) -> Self {
+        Self {
+            answers: answers.into(),
+            prompts: Arc::default(),
+        }
+    }
+
+    // the builder takes ownership of the selector, so prompts are read through a shared handle
+    pub fn prompts(&self) -> Arc<Mutex<Vec<Prompt>>> {
+        self.prompts.clone()
+    }
+
+    fn next_answer(&mut self, title: &str, options: Vec<String>, initial: usize) -> Result<Answer> {
+        self.prompts.lock().unwrap().push(Prompt {
+            title: title.to_string(),
+            options,
+            initial,
+        });
+        self.answers
+            .pop_front()
+            .ok_or_else(|| eyre!("no scripted answer for '{}'", title).into())
+    }
+}
+
+impl StringListSelector for MockStringListSelector {
+    fn select_items(&mut self, title: String, items: Vec<SelectItem>) -> Result<SelectItem> {
+        self.select_items_at(title, items, 0)
+    }
+
+    fn select_items_at(
+        &mut self,
+        title: String,
+        items: Vec<SelectItem>,
+        initial: usize,
+    ) -> Result<SelectItem> {
+        if items.is_empty() {
+            return Err(PorwardError::NoOptions { step: title });
+        }
+        let labels = items.iter().map(|item| item.label.clone()).collect();
+        let index = match self.next_answer(&title, labels, initial)? {
+            Answer::Index(index) => index,
+            Answer::Label(label) => position(&title, &items, &label)?,
+            _ => return Err(eyre!("expected a selection for '{}'", title).into()),
+        };
+        option(&title, &items, index)
+    }
+
+    fn select_many(&mut self, title: String, items: Vec<SelectItem>) -> Result<Vec<SelectItem>> {
+        self.select_many_at(title, items, 0)
+    }
+
+    fn select_many_at(
+        &mut self,
+        title: String,
+        items: Vec<SelectItem>,
+        initial: usize,
+    ) -> Result<Vec<SelectItem>> {
+        if items.is_empty() {
+            return Err(PorwardError::NoOptions { step: title });
+        }
+        let labels = items.iter().map(|item| item.label.clone()).collect();
+        let indices = match self.next_answer(&title, labels, initial)? {
+            Answer::Index(index) => vec![index],
+            Answer::Label(label) => vec![position(&title, &items, &label)?],
+            Answer::Indices(indices) => indices,
+            _ => return Err(eyre!("expected a selection for '{}'", title).into()),
+        };
+        indices
+            .into_iter()
+            .map(|index| option(&title, &items, index))
+            .collect()
+    }
+
+    fn input(&mut self, title: String) -> Result<String> {
+        match self.next_answer(&title, vec![], 0)? {
+            Answer::Text(text) => Ok(text),
+            _ => Err(eyre!("expected text input for '{}'", title).into()),
+        }
+    }
+
+    fn error(&mut self, title: String, error: &PorwardError) -> Result<ErrorAction> {
+        match self.next_answer(&title, vec![error.to_string()], 0)? {
+            Answer::Action(action) => Ok(action),
+            _ => Err(eyre!("expected an action for '{}'", title).into()),
+        }
+    }
+}
+
+fn position(title: &str, items: &[SelectItem], label: &str) -> Result<usize> {
+    items
+        .iter()
+        .position(|item| item.label == label)
+        .ok_or_else(|| eyre!("'{}' is not an option for '{}'", label, title).into())
+}
+
+fn option(title: &str, items: &[SelectItem], index: usize) -> Result<SelectItem> {
+    items
+        .get(index)
+        .cloned()
+        .ok_or_else(|| eyre!("option {} is out of range for '{}'", index, title).into())
+}
+
+/// Instance provider that returns the same instances for every profile.
+pub struct StaticInstanceProvider(pub Vec<InstanceSummary>);
+
+#[async_trait]
+impl InstanceProvider for StaticInstanceProvider {
+    async fn running_instances(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+    ) -> Result<Vec<InstanceSummary>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Instance provider with Auto Scaling Groups, the same for every profile.
+pub struct StaticGroupProvider {
+    pub groups: Vec<AutoScalingGroupSummary>,
+    pub instances: Vec<InstanceSummary>,
+}
+
+#[async_trait]
+impl InstanceProvider for StaticGroupProvider {
+    async fn running_instances(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+    ) -> Result<Vec<InstanceSummary>> {
+        Ok(self.instances.clone())
+    }
+
+    async fn auto_scaling_groups(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+    ) -> Result<Vec<AutoScalingGroupSummary>> {
+        Ok(self.groups.clone())
+    }
+}
+
+/// Destination provider that returns the same destinations for every profile and service.
+pub struct StaticDestinationProvider(pub Vec<DestinationSummary>);
+
+#[async_trait]
+impl DestinationProvider for StaticDestinationProvider {
+    async fn destinations(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _service: &Service,
+    ) -> Result<Vec<DestinationSummary>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Destination provider that hands out its destinations in pages, the way the SDK paginators do.
+pub struct PagedDestinationProvider(pub Vec<Vec<DestinationSummary>>);
+
+#[async_trait]
+impl DestinationProvider for PagedDestinationProvider {
+    async fn destinations(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _service: &Service,
+    ) -> Result<Vec<DestinationSummary>> {
+        let pages = self.0.clone();
+        let stream = FnStream::new(|tx| {
+            Box::pin(async move {
+                for page in pages {
+                    if tx.send(Ok::<_, PorwardError>(page)).await.is_err() {
+                        return;
+                    }
+                }
+            })
+        });
+        Ok(all_pages(PaginationStream::new(stream))
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
@@ -0,0 +1,494 @@
+// Minimal implementation of the Session Manager data channel, enough to forward a single TCP
+// connection at a time without session-manager-plugin. Message framing follows the plugin's
+// `ClientMessage` layout; multiplexed port sessions are avoided by announcing a client version
+// that predates them.
+use crate::{
+    events,
+    porwarder::{PortForwarder, StartedSession},
+    providers,
+    shutdown::Shutdown,
+};
+use color_eyre::{eyre::eyre, Result};
+use futures_util::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+const CLIENT_VERSION: &str = "1.0.0.0";
+const HEADER_LENGTH: u32 = 116;
+const MESSAGE_TYPE_LENGTH: usize = 32;
+const PAYLOAD_OFFSET: usize = 120;
+const PING_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const INPUT_STREAM_DATA: &str = "input_stream_data";
+const OUTPUT_STREAM_DATA: &str = "output_stream_data";
+const ACKNOWLEDGE: &str = "acknowledge";
+const CHANNEL_CLOSED: &str = "channel_closed";
+
+const PAYLOAD_OUTPUT: u32 = 1;
+const PAYLOAD_HANDSHAKE_REQUEST: u32 = 5;
+const PAYLOAD_HANDSHAKE_RESPONSE: u32 = 6;
+const PAYLOAD_HANDSHAKE_COMPLETE: u32 = 7;
+const PAYLOAD_FLAG: u32 = 10;
+
+const FLAG_DISCONNECT_TO_PORT: u32 = 1;
+const FLAG_CONNECT_TO_PORT_ERROR: u32 = 3;
+
+const ACTION_STATUS_SUCCESS: u32 = 1;
+const ACTION_STATUS_FAILED: u32 = 2;
+
+struct ClientMessage {
+    message_type: String,
+    sequence_number: i64,
+    flags: u64,
+    message_id: Uuid,
+    payload_type: u32,
+    payload: Vec<u8>,
+}
+
+impl ClientMessage {
+    fn new(message_type: &str, sequence_number: i64, payload_type: u32, payload: Vec<u8>) -> Self {
+        ClientMessage {
+            message_type: message_type.to_string(),
+            sequence_number,
+            flags: 0,
+            message_id: Uuid::new_v4(),
+            payload_type,
+            payload,
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let created_date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        let mut message_type = self.message_type.as_bytes().to_vec();
+        message_type.resize(MESSAGE_TYPE_LENGTH, b' ');
+        // the agent expects the two halves of the uuid swapped
+        let id = self.message_id.as_bytes();
+
+        let mut bytes = Vec::with_capacity(PAYLOAD_OFFSET + self.payload.len());
+        bytes.extend_from_slice(&HEADER_LENGTH.to_be_bytes());
+        bytes.extend_from_slice(&message_type);
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.extend_from_slice(&created_date.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence_number.to_be_bytes());
+        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        bytes.extend_from_slice(&id[8..]);
+        bytes.extend_from_slice(&id[..8]);
+        bytes.extend_from_slice(&Sha256::digest(&self.payload));
+        bytes.extend_from_slice(&self.payload_type.to_be_bytes());
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    // every field is read with bounds checks, a malformed frame from the service is an error
+    fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < PAYLOAD_OFFSET {
+            return Err(eyre!(
+                "data channel message is too short ({} bytes)",
+                bytes.len()
+            ));
+        }
+        let u32_at = |offset: usize| field::<4>(bytes, offset).map(u32::from_be_bytes);
+        let u64_at = |offset: usize| field::<8>(bytes, offset).map(u64::from_be_bytes);
+
+        let header_length = u32_at(0)? as usize;
+        let payload_length = u32_at(header_length)? as usize;
+        let payload = header_length
+            .checked_add(4)
+            .and_then(|start| Some((start, start.checked_add(payload_length)?)))
+            .and_then(|(start, end)| bytes.get(start..end))
+            .ok_or(eyre!("data channel message payload is truncated"))?
+            .to_vec();
+        let mut id = [0u8; 16];
+        id[..8].copy_from_slice(&field::<8>(bytes, 72)?);
+        id[8..].copy_from_slice(&field::<8>(bytes, 64)?);
+
+        Ok(ClientMessage {
+            message_type: String::from_utf8_lossy(&field::<MESSAGE_TYPE_LENGTH>(bytes, 4)?)
+                .trim_end_matches([' ', '\0'])
+                .to_string(),
+            sequence_number: u64_at(48)? as i64,
+            flags: u64_at(56)?,
+            message_id: Uuid::from_bytes(id),
+            payload_type: u32_at(112)?,
+            payload,
+        })
+    }
+
+    fn acknowledge(&self) -> Self {
+        let content = serde_json::json!({
+            "AcknowledgedMessageType": self.message_type,
+            "AcknowledgedMessageId": self.message_id.to_string(),
+            "AcknowledgedMessageSequenceNumber": self.sequence_number,
+            "IsSequentialMessage": true,
+        });
+        ClientMessage {
+            flags: 3,
+            ..ClientMessage::new(ACKNOWLEDGE, 0, 0, content.to_string().into_bytes())
+        }
+    }
+}
+
+fn field<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+    offset
+        .checked_add(N)
+        .and_then(|end| bytes.get(offset..end))
+        .and_then(|field| field.try_into().ok())
+        .ok_or(eyre!(
+            "data channel message is truncated at byte {}",
+            offset
+        ))
+}
+
+pub async fn serve(
+    port_forwarder: &PortForwarder,
+    address: SocketAddr,
+    mapping: &str,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    events::say(&format!("tunnel ready: {}", mapping));
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.wait() => return Ok(()),
+        };
+        events::say(&format!("connection from {}", peer));
+        // only this connection fails, the next one starts a session of its own
+        let session = match port_forwarder.start_session().await {
+            Ok(session) => session,
+            Err(e) => {
+                events::say(&format!("connection from {} failed: {}", peer, e));
+                continue;
+            }
+        };
+        let stopped = match session
+            .output
+            .stream_url()
+            .zip(session.output.token_value())
+        {
+            Some((stream_url, token)) => tokio::select! {
+                result = forward(stream, stream_url, token) => {
+                    if let Err(e) = result {
+                        events::say(&format!("connection from {} failed: {}", peer, e));
+                    }
+                    false
+                }
+                _ = shutdown.wait() => true,
+            },
+            None => {
+                events::say(&format!(
+                    "connection from {} failed: StartSession did not return a stream url and token",
+                    peer
+                ));
+                false
+            }
+        };
+        terminate(port_forwarder, &session).await;
+        if stopped {
+            return Ok(());
+        }
+        events::say(&format!("connection from {} closed", peer));
+    }
+}
+
+// a session is otherwise left open until the service's idle timeout, one per connection
+async fn terminate(port_forwarder: &PortForwarder, session: &StartedSession) {
+    let Some(session_id) = session.output.session_id() else {
+        return;
+    };
+    if let Err(e) = providers::terminate_session(
+        port_forwarder.target().session_profile(),
+        Some(&session.region),
+        session_id,
+    )
+    .await
+    {
+        events::say(&format!(
+            "warning: could not terminate session {}: {}",
+            session_id, e
+        ));
+    }
+}
+
+async fn forward(stream: TcpStream, stream_url: &str, token: &str) -> Result<()> {
+    let (websocket, _) = tokio_tungstenite::connect_async(stream_url).await?;
+    let (mut sink, mut source) = websocket.split();
+    let open_data_channel = serde_json::json!({
+        "MessageSchemaVersion": "1.0",
+        "RequestId": Uuid::new_v4().to_string(),
+        "TokenValue": token,
+        "ClientId": Uuid::new_v4().to_string(),
+        "ClientVersion": CLIENT_VERSION,
+    });
+    sink.send(Message::Text(open_data_channel.to_string().into()))
+        .await?;
+
+    let (mut tcp_read, mut tcp_write) = stream.into_split();
+    let mut buffer = vec![0u8; 16 * 1024];
+    let mut sequence_number = 0i64;
+    let mut expected_sequence_number = 0i64;
+    let mut pending = BTreeMap::new();
+    let mut handshake_complete = false;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+
+    loop {
+        tokio::select! {
+            read = tcp_read.read(&mut buffer), if handshake_complete => {
+                let read = read?;
+                let message = if read == 0 {
+                    ClientMessage::new(
+                        INPUT_STREAM_DATA,
+                        sequence_number,
+                        PAYLOAD_FLAG,
+                        FLAG_DISCONNECT_TO_PORT.to_be_bytes().to_vec(),
+                    )
+                } else {
+                    ClientMessage::new(
+                        INPUT_STREAM_DATA,
+                        sequence_number,
+                        PAYLOAD_OUTPUT,
+                        buffer[..read].to_vec(),
+                    )
+                };
+                sequence_number += 1;
+                sink.send(Message::Binary(message.serialize().into())).await?;
+                if read == 0 {
+                    break;
+                }
+            }
+            frame = source.next() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                let bytes = match frame? {
+                    Message::Binary(bytes) => bytes,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                let message = ClientMessage::deserialize(&bytes)?;
+                match message.message_type.as_str() {
+                    OUTPUT_STREAM_DATA => {
+                        sink.send(Message::Binary(message.acknowledge().serialize().into()))
+                            .await?;
+                        if message.sequence_number >= expected_sequence_number {
+                            pending.insert(message.sequence_number, message);
+                        }
+                    }
+                    CHANNEL_CLOSED => break,
+                    // acknowledgements and publication flow control need no handling
+                    // without retransmission
+                    _ => {}
+                }
+                while let Some(message) = pending.remove(&expected_sequence_number) {
+                    expected_sequence_number += 1;
+                    match message.payload_type {
+                        PAYLOAD_HANDSHAKE_REQUEST => {
+                            let response = ClientMessage::new(
+                                INPUT_STREAM_DATA,
+                                sequence_number,
+                                PAYLOAD_HANDSHAKE_RESPONSE,
+                                handshake_response(&message.payload)?,
+                            );
+                            sequence_number += 1;
+                            sink.send(Message::Binary(response.serialize().into())).await?;
+                        }
+                        PAYLOAD_HANDSHAKE_COMPLETE => handshake_complete = true,
+                        PAYLOAD_OUTPUT => tcp_write.write_all(&message.payload).await?,
+                        PAYLOAD_FLAG if message.payload[..] == FLAG_CONNECT_TO_PORT_ERROR.to_be_bytes() => {
+                            return Err(eyre!("the instance could not connect to the destination"));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ = ping.tick() => {
+                sink.send(Message::Ping(Vec::new().into())).await?;
+            }
+        }
+    }
+    let _ = sink.close().await;
+    Ok(())
+}
+
+fn handshake_response(request: &[u8]) -> Result<Vec<u8>> {
+    let request: serde_json::Value = serde_json::from_slice(request)?;
+    let actions = request["RequestedClientActions"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .map(|action| {
+            let action_type = action["ActionType"].as_str().unwrap_or_default();
+            if action_type == "SessionType" {
+                serde_json::json!({
+                    "ActionType": action_type,
+                    "ActionStatus": ACTION_STATUS_SUCCESS,
+                })
+            } else {
+                serde_json::json!({
+                    "ActionType": action_type,
+                    "ActionStatus": ACTION_STATUS_FAILED,
+                    "Error": format!("{} is not supported by the native tunnel", action_type),
+                })
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_vec(&serde_json::json!({
+        "ClientVersion": CLIENT_VERSION,
+        "ProcessedClientActions": actions,
+        "Errors": [],
+    }))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::{accept_async, WebSocketStream};
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let message = ClientMessage {
+            flags: 3,
+            ..ClientMessage::new(OUTPUT_STREAM_DATA, 42, PAYLOAD_OUTPUT, b"hello".to_vec())
+        };
+        let parsed = ClientMessage::deserialize(&message.serialize()).unwrap();
+        assert_eq!(parsed.message_type, OUTPUT_STREAM_DATA);
+        assert_eq!(parsed.sequence_number, 42);
+        assert_eq!(parsed.flags, 3);
+        assert_eq!(parsed.message_id, message.message_id);
+        assert_eq!(parsed.payload_type, PAYLOAD_OUTPUT);
+        assert_eq!(parsed.payload, b"hello");
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let mut bytes =
+            ClientMessage::new(OUTPUT_STREAM_DATA, 0, PAYLOAD_OUTPUT, vec![1, 2, 3]).serialize();
+        assert!(ClientMessage::deserialize(&bytes[..PAYLOAD_OFFSET - 1]).is_err());
+        // a payload longer than the frame
+        assert!(ClientMessage::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        // a header length pointing past the end of the frame
+        bytes[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(ClientMessage::deserialize(&bytes).is_err());
+        bytes[..4].copy_from_slice(&(bytes.len() as u32 - 2).to_be_bytes());
+        assert!(ClientMessage::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn handshake_accepts_only_the_session_type() {
+        let request = serde_json::json!({
+            "AgentVersion": "3.3.0.0",
+            "RequestedClientActions": [
+                {"ActionType": "SessionType", "ActionParameters": {"SessionType": "Port"}},
+                {"ActionType": "KMSEncryption", "ActionParameters": {"KMSKeyId": "key"}},
+            ],
+        });
+        let response: serde_json::Value =
+            serde_json::from_slice(&handshake_response(request.to_string().as_bytes()).unwrap())
+                .unwrap();
+        assert_eq!(response["ClientVersion"], CLIENT_VERSION);
+        let actions = response["ProcessedClientActions"].as_array().unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0]["ActionType"], "SessionType");
+        assert_eq!(actions[0]["ActionStatus"], ACTION_STATUS_SUCCESS);
+        assert_eq!(actions[1]["ActionType"], "KMSEncryption");
+        assert_eq!(actions[1]["ActionStatus"], ACTION_STATUS_FAILED);
+    }
+
+    async fn send(websocket: &mut WebSocketStream<TcpStream>, message: ClientMessage) {
+        websocket
+            .send(Message::Binary(message.serialize().into()))
+            .await
+            .unwrap();
+    }
+
+    // the next message of the client that is not an acknowledgement or a ping
+    async fn receive(websocket: &mut WebSocketStream<TcpStream>) -> ClientMessage {
+        loop {
+            let Message::Binary(bytes) = websocket.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            let message = ClientMessage::deserialize(&bytes).unwrap();
+            if message.message_type != ACKNOWLEDGE {
+                return message;
+            }
+        }
+    }
+
+    // a data channel endpoint that does the handshake, answers "ping" with "pong" and closes
+    async fn mock_endpoint(listener: TcpListener) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut websocket = accept_async(stream).await.unwrap();
+        let open = websocket.next().await.unwrap().unwrap();
+        let open: serde_json::Value = serde_json::from_str(open.to_text().unwrap()).unwrap();
+        assert_eq!(open["TokenValue"], "token");
+
+        let request = serde_json::json!({
+            "RequestedClientActions": [{"ActionType": "SessionType"}],
+        });
+        let request = request.to_string().into_bytes();
+        send(
+            &mut websocket,
+            ClientMessage::new(OUTPUT_STREAM_DATA, 0, PAYLOAD_HANDSHAKE_REQUEST, request),
+        )
+        .await;
+        let response = receive(&mut websocket).await;
+        assert_eq!(response.payload_type, PAYLOAD_HANDSHAKE_RESPONSE);
+        send(
+            &mut websocket,
+            ClientMessage::new(OUTPUT_STREAM_DATA, 1, PAYLOAD_HANDSHAKE_COMPLETE, vec![]),
+        )
+        .await;
+
+        let data = receive(&mut websocket).await;
+        assert_eq!(data.message_type, INPUT_STREAM_DATA);
+        assert_eq!(data.payload_type, PAYLOAD_OUTPUT);
+        assert_eq!(data.payload, b"ping");
+        send(
+            &mut websocket,
+            ClientMessage::new(OUTPUT_STREAM_DATA, 2, PAYLOAD_OUTPUT, b"pong".to_vec()),
+        )
+        .await;
+        send(
+            &mut websocket,
+            ClientMessage::new(CHANNEL_CLOSED, 3, PAYLOAD_OUTPUT, vec![]),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn forwards_a_connection_through_a_mocked_endpoint() {
+        let endpoint = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream_url = format!("ws://{}", endpoint.local_addr().unwrap());
+        let endpoint = tokio::spawn(mock_endpoint(endpoint));
+
+        let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(local.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = local.accept().await.unwrap();
+        let forwarded = tokio::spawn(async move { forward(accepted, &stream_url, "token").await });
+
+        client.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"pong");
+
+        endpoint.await.unwrap();
+        forwarded.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+use std::io::Write;
+
+pub fn notify(message: &str) {
+    // the terminal bell still gets attention when there is no notification service
+    print!("\x07");
+    let _ = std::io::stdout().flush();
+    let _ = notify_rust::Notification::new()
+        .appname("porward")
+        .summary("porward")
+        .body(message)
+        .show();
+}
@@ -0,0 +1,297 @@
+use crate::{
+    error::Result,
+    porwarder::PORT_FORWARDING_DOCUMENT,
+    providers::{client, load_region_config},
+};
+use aws_config::SdkConfig;
+use aws_sdk_iam::{
+    error::{ProvideErrorMetadata, SdkError},
+    types::PolicyEvaluationDecisionType,
+};
+use std::fmt::{Display, Formatter};
+
+const START_SESSION: &str = "ssm:StartSession";
+// what listing the instances and destinations needs
+const DESCRIBE_ACTIONS: &[&str] = &[
+    "ec2:DescribeInstances",
+    #[cfg(feature = "alb")]
+    "elasticloadbalancing:DescribeLoadBalancers",
+    #[cfg(feature = "rds")]
+    "rds:DescribeDBClusterEndpoints",
+];
+// error codes of a call the caller is not allowed to make
+const DENIED_CODES: &[&str] = &[
+    "AccessDenied",
+    "AccessDeniedException",
+    "UnauthorizedOperation",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Decision {
+    Allowed,
+    Denied,
+    Unknown,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Allowed => write!(f, "✓ allowed"),
+            Decision::Denied => write!(f, "✗ denied"),
+            Decision::Unknown => write!(f, "? unknown"),
+        }
+    }
+}
+
+pub(crate) struct PermissionCheck {
+    pub action: &'static str,
+    pub decision: Decision,
+    pub detail: Option<String>,
+}
+
+/// The permissions of the principal behind a profile, simulated with
+/// iam:SimulatePrincipalPolicy or, when that is not allowed either, by trying the calls.
+pub(crate) struct PermissionReport {
+    pub profile: String,
+    pub principal: String,
+    pub simulated: bool,
+    pub checks: Vec<PermissionCheck>,
+}
+
+impl PermissionReport {
+    pub fn denied(&self) -> Vec<&'static str> {
+        self.checks
+            .iter()
+            .filter(|check| check.decision == Decision::Denied)
+            .map(|check| check.action)
+            .collect()
+    }
+}
+
+impl Display for PermissionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Permissions of {} (profile '{}', {})",
+            self.principal,
+            self.profile,
+            if self.simulated {
+                "simulated"
+            } else {
+                "tried with dry-run calls"
+            }
+        )?;
+        let width = self
+            .checks
+            .iter()
+            .map(|check| check.action.len())
+            .max()
+            .unwrap_or_default();
+        for check in self.checks.iter() {
+            write!(f, "  {:<width$}  {}", check.action, check.decision)?;
+            if let Some(detail) = check.detail.as_ref() {
+                write!(f, " ({})", detail)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the describe calls with `profile_name` and ssm:StartSession with the profile sessions
+/// are started with, which is the same one unless a session profile is configured. Both are
+/// checked in `region` when it is set.
+pub(crate) async fn check(
+    profile_name: &str,
+    session_profile: &str,
+    region: Option<&str>,
+) -> Result<Vec<PermissionReport>> {
+    let mut actions = DESCRIBE_ACTIONS.to_vec();
+    if session_profile == profile_name {
+        actions.push(START_SESSION);
+        return Ok(vec![report(profile_name, region, &actions).await?]);
+    }
+    Ok(vec![
+        report(profile_name, region, &actions).await?,
+        report(session_profile, region, &[START_SESSION]).await?,
+    ])
+}
+
+async fn report(
+    profile_name: &str,
+    region: Option<&str>,
+    actions: &[&'static str],
+) -> Result<PermissionReport> {
+    let config = load_region_config(profile_name, region, None).await;
+    let identity = client!(aws_sdk_sts, &config, sts)
+        .get_caller_identity()
+        .send()
+        .await?;
+    let principal = identity.arn().unwrap_or_default().to_string();
+    let iam = client!(aws_sdk_iam, &config, iam);
+    let source = policy_source(&iam, &principal).await;
+    let mut checks = vec![];
+    for action in actions.iter() {
+        let resources = resources(action, &principal, &config);
+        match simulate(&iam, &source, action, resources).await {
+            Ok(check) => checks.push(check),
+            // callers that may not simulate their own policies try the calls instead
+            Err(_) => {
+                return Ok(PermissionReport {
+                    profile: profile_name.to_string(),
+                    principal,
+                    simulated: false,
+                    checks: try_actions(&config, actions).await,
+                })
+            }
+        }
+    }
+    Ok(PermissionReport {
+        profile: profile_name.to_string(),
+        principal,
+        simulated: true,
+        checks,
+    })
+}
+
+// policies are attached to the role, not to the assumed-role session the caller is
+async fn policy_source(iam: &aws_sdk_iam::Client, principal: &str) -> String {
+    let Some((prefix, session)) = principal.split_once(":assumed-role/") else {
+        return principal.to_string();
+    };
+    let role_name = session.split('/').next().unwrap_or_default();
+    // the role arn has a path that only iam:GetRole knows, e.g. for sso roles
+    iam.get_role()
+        .role_name(role_name)
+        .send()
+        .await
+        .ok()
+        .and_then(|output| output.role().map(|role| role.arn().to_string()))
+        .unwrap_or_else(|| {
+            format!(
+                "{}:role/{}",
+                prefix.replacen(":sts:", ":iam:", 1),
+                role_name
+            )
+        })
+}
+
+// a session is started on an instance with the port forwarding document
+fn resources(action: &str, principal: &str, config: &SdkConfig) -> Vec<String> {
+    if action != START_SESSION {
+        return vec!["*".to_string()];
+    }
+    let mut parts = principal.split(':');
+    let partition = parts.nth(1).unwrap_or("aws");
+    let account = parts.nth(2).unwrap_or_default();
+    let region = config
+        .region()
+        .map(|region| region.to_string())
+        .unwrap_or_default();
+    vec![
+        format!("arn:{}:ec2:{}:{}:instance/*", partition, region, account),
+        format!(
+            "arn:{}:ssm:{}::document/{}",
+            partition, region, PORT_FORWARDING_DOCUMENT
+        ),
+    ]
+}
+
+async fn simulate(
+    iam: &aws_sdk_iam::Client,
+    source: &str,
+    action: &'static str,
+    resources: Vec<String>,
+) -> Result<PermissionCheck> {
+    let output = iam
+        .simulate_principal_policy()
+        .policy_source_arn(source)
+        .action_names(action)
+        .set_resource_arns(Some(resources))
+        .send()
+        .await?;
+    let (decision, detail) = match output.evaluation_results().first() {
+        Some(result) if *result.eval_decision() == PolicyEvaluationDecisionType::Allowed => {
+            (Decision::Allowed, None)
+        }
+        // e.g. tag conditions, which are only known for a specific instance
+        Some(result) if !result.missing_context_values().is_empty() => (
+            Decision::Unknown,
+            Some(format!(
+                "depends on {}",
+                result.missing_context_values().join(", ")
+            )),
+        ),
+        Some(_) => (Decision::Denied, None),
+        None => (Decision::Unknown, None),
+    };
+    Ok(PermissionCheck {
+        action,
+        decision,
+        detail,
+    })
+}
+
+async fn try_actions(config: &SdkConfig, actions: &[&'static str]) -> Vec<PermissionCheck> {
+    let mut checks = vec![];
+    for action in actions.iter().copied() {
+        let check = match action {
+            "ec2:DescribeInstances" => tried(
+                action,
+                client!(aws_sdk_ec2, config, ec2)
+                    .describe_instances()
+                    .dry_run(true)
+                    .send()
+                    .await,
+            ),
+            #[cfg(feature = "alb")]
+            "elasticloadbalancing:DescribeLoadBalancers" => tried(
+                action,
+                client!(aws_sdk_elasticloadbalancingv2, config, elbv2)
+                    .describe_load_balancers()
+                    .page_size(1)
+                    .send()
+                    .await,
+            ),
+            #[cfg(feature = "rds")]
+            "rds:DescribeDBClusterEndpoints" => tried(
+                action,
+                client!(aws_sdk_rds, config, rds)
+                    .describe_db_cluster_endpoints()
+                    .max_records(20)
+                    .send()
+                    .await,
+            ),
+            // there is no dry run for starting a session
+            _ => PermissionCheck {
+                action,
+                decision: Decision::Unknown,
+                detail: Some("can not be tried without starting a session".to_string()),
+            },
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+fn tried<T, E: ProvideErrorMetadata, R>(
+    action: &'static str,
+    result: std::result::Result<T, SdkError<E, R>>,
+) -> PermissionCheck {
+    let (decision, detail) = match result {
+        Ok(_) => (Decision::Allowed, None),
+        Err(e) => match e.code() {
+            Some("DryRunOperation") => (Decision::Allowed, None),
+            Some(code) if DENIED_CODES.contains(&code) => (Decision::Denied, None),
+            code => (
+                Decision::Unknown,
+                Some(e.message().or(code).unwrap_or("no answer").to_string()),
+            ),
+        },
+    };
+    PermissionCheck {
+        action,
+        decision,
+        detail,
+    }
+}
@@ -1,39 +1,451 @@
-use aws_config::BehaviorVersion;
+use crate::{
+    config::{Config, LocalPorts},
+    credentials, editor,
+    error::{self, PorwardError},
+    events::{self, Event, EventSink, Sink},
+    hosts::HostsEntry,
+    permissions::{self, PermissionReport},
+    prerequisites::{self, Version, AWS_CLI, SESSION_MANAGER_PLUGIN},
+    probe::Expect,
+    providers::{
+        client, endpoints, find_resources, load_region_config, AccountSummary,
+        AutoScalingGroupSummary, AwsDestinationProvider, AwsInstanceProvider, DestinationProvider,
+        DestinationSummary, InstanceProvider, InstanceSummary, ResourceKind, Tags,
+    },
+    proxy,
+    reachability::{self, reachability},
+    relay::{self, Relay},
+    session::{Session, SessionFailure, SessionOptions},
+    session_log::SessionLog,
+    sessions::{self, Registration},
+    state, terminal,
+    uri::{InstanceRef, TunnelUri},
+};
+use aws_config::profile::ProfileSet;
 use aws_runtime::env_config;
-use color_eyre::{eyre::eyre, Result};
+use aws_sdk_ssm::{
+    error::{DisplayErrorContext, ProvideErrorMetadata, SdkError},
+    operation::{
+        get_command_invocation::GetCommandInvocationError,
+        start_session::{StartSessionError, StartSessionOutput},
+    },
+    types::CommandInvocationStatus,
+};
+use aws_types::os_shim_internal::{Env, Fs};
+use color_eyre::{
+    eyre::{eyre, Report},
+    Result,
+};
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fmt::{Display, Formatter},
-    process::{Command, Stdio},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
+use tokio::process::Command;
 
-#[derive(Clone)]
+/// Kind of destination behind the instance, which decides the default ports.
+///
+/// ```
+/// use porward::Service;
+///
+/// for service in Service::all() {
+///     let parsed: Service = service.config_name().parse().unwrap();
+///     assert_eq!(parsed.default_port(), service.default_port());
+/// }
+/// assert!("mongodb".parse::<Service>().is_err());
+/// ```
+#[cfg_attr(
+    feature = "rds",
+    doc = r#"
+```
+assert_eq!(porward::Service::Postgresql.default_port(), 5432);
+```"#
+)]
+#[cfg_attr(
+    feature = "alb",
+    doc = r#"
+```
+assert_eq!(porward::Service::ApplicationLoadBalancer.default_port(), 443);
+```"#
+)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Service {
+    #[cfg(feature = "alb")]
+    #[serde(alias = "alb")]
     ApplicationLoadBalancer,
+    #[cfg(feature = "rds")]
     Postgresql,
+    #[cfg(feature = "elasticache")]
     Redis,
+    #[cfg(feature = "elasticache")]
     Valkey,
 }
 
+const SSH_DOCUMENT: &str = "AWS-StartSSHSession";
+const DEFAULT_LOGIN_USER: &str = "ec2-user";
+pub(crate) const PORT_FORWARDING_DOCUMENT: &str = "AWS-StartPortForwardingSessionToRemoteHost";
+const RUN_SHELL_DOCUMENT: &str = "AWS-RunShellScript";
+// ids of the instance list entries that ask for an id or ip, or narrow the list by a tag
+const DIRECT_INSTANCE: &str = "direct";
+const TAG_FILTER: &str = "tag-filter";
+// the first agent that supports AWS-StartPortForwardingSessionToRemoteHost
+const REMOTE_HOST_AGENT_VERSION: &str = "3.1.1374.0";
+// offered for a profile without a region when config.toml lists no regions
+const REGIONS: &[&str] = &[
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+    "ca-central-1",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "eu-central-1",
+    "eu-north-1",
+    "ap-south-1",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "ap-northeast-1",
+    "ap-northeast-2",
+    "sa-east-1",
+];
+const CHECK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+// the longest a dns name can be
+const MAX_HOST_NAME_LEN: usize = 253;
+
+pub type Parameters = BTreeMap<String, Vec<String>>;
+
+pub struct SshOptions {
+    pub login_user: Option<String>,
+    pub port: u16,
+    pub dry_run: bool,
+}
+
+pub(crate) struct StartedSession {
+    pub region: String,
+    pub endpoint: String,
+    pub output: StartSessionOutput,
+}
+
 pub trait BuilderState {}
 
+/// An option offered by a selector. `id` is what the caller gets back, `label` is what is shown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectItem {
+    pub id: String,
+    pub label: String,
+    pub detail: Option<String>,
+}
+
+impl SelectItem {
+    pub fn new(id: impl Into<String>, label: impl Into<String>) -> Self {
+        SelectItem {
+            id: id.into(),
+            label: label.into(),
+            detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// What to do after a builder step failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorAction {
+    Retry,
+    Back,
+    Quit,
+}
+
+/// Asks the user to pick from a list or type a value at each builder step.
+///
+/// ```
+/// use porward::{PorwardError, SelectItem, StringListSelector};
+///
+/// // picks the first option and gives the same answer to every question
+/// struct First(String);
+///
+/// impl StringListSelector for First {
+///     fn select_items(
+///         &mut self,
+///         title: String,
+///         items: Vec<SelectItem>,
+///     ) -> porward::error::Result<SelectItem> {
+///         items
+///             .into_iter()
+///             .next()
+///             .ok_or(PorwardError::NoOptions { step: title })
+///     }
+///
+///     fn input(&mut self, _title: String) -> porward::error::Result<String> {
+///         Ok(self.0.clone())
+///     }
+/// }
+///
+/// let mut selector = First("db.internal".to_string());
+/// let profiles = vec!["dev".to_string(), "prod".to_string()];
+/// assert_eq!(selector.select("Select Profile".into(), profiles)?, "dev");
+/// assert_eq!(selector.input("Host".into())?, "db.internal");
+/// assert!(selector.select("Select Profile".into(), vec![]).is_err());
+/// # Ok::<(), PorwardError>(())
+/// ```
 pub trait StringListSelector {
-    fn select(&mut self, title: String, options: Vec<String>) -> Result<(usize, String)>;
+    fn select_items(&mut self, title: String, items: Vec<SelectItem>) -> error::Result<SelectItem>;
+    fn input(&mut self, title: String) -> error::Result<String>;
+
+    // selectors that can not ask give up on the first failure
+    fn error(&mut self, _title: String, _error: &PorwardError) -> error::Result<ErrorAction> {
+        Ok(ErrorAction::Quit)
+    }
+
+    // shown next to every following prompt, production accounts as a warning
+    fn show_account(&mut self, _account: &str, _production: bool) {}
+
+    // the next prompt starts where the cursor is, after something else used the terminal
+    fn release(&mut self) {}
+
+    // selectors without a multi-select mode pick one
+    fn select_many(
+        &mut self,
+        title: String,
+        items: Vec<SelectItem>,
+    ) -> error::Result<Vec<SelectItem>> {
+        Ok(vec![self.select_items(title, items)?])
+    }
+
+    // starts on the item at `initial`, e.g. the last used one, selectors without a cursor ignore it
+    fn select_items_at(
+        &mut self,
+        title: String,
+        items: Vec<SelectItem>,
+        _initial: usize,
+    ) -> error::Result<SelectItem> {
+        self.select_items(title, items)
+    }
+
+    fn select_many_at(
+        &mut self,
+        title: String,
+        items: Vec<SelectItem>,
+        _initial: usize,
+    ) -> error::Result<Vec<SelectItem>> {
+        self.select_many(title, items)
+    }
+
+    // for plain string lists, where the label is also the id
+    fn select(&mut self, title: String, options: Vec<String>) -> error::Result<String> {
+        let items = options
+            .into_iter()
+            .map(|option| SelectItem::new(option.clone(), option))
+            .collect();
+        Ok(self.select_items(title, items)?.id)
+    }
+}
+
+/// The instance a session runs on, which is all the shell and ssh modes need.
+#[derive(Clone)]
+pub struct InstanceTarget {
+    profile_name: String,
+    session_profile: Option<String>,
+    instance_id: String,
+    instance_name: Option<String>,
+    reason: Option<String>,
+    // where the resources were listed, the session has to start in the same region
+    region: Option<String>,
+}
+
+/// The host and port the instance forwards to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Destination {
+    pub host: String,
+    pub port: u16,
+}
+
+/// What a tunnel connects, for confirmation prompts and logs of embedding applications. The
+/// same object is part of every history entry and the `start` event of `--output json`.
+#[derive(Clone, Debug, Serialize)]
+pub struct TunnelSummary {
+    pub profile: String,
+    pub session_profile: String,
+    pub instance_id: String,
+    pub instance_name: Option<String>,
+    pub service: Option<Service>,
+    pub host: String,
+    pub host_port: u16,
+    pub local_port: u16,
+    pub document_name: String,
+    // porward open starts the same tunnel from it, only for tunnels to a known service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
 }
 
-#[allow(unused)]
+/// A resolved tunnel: profile, instance and destination host and ports.
+/// The service is not known for tunnels to arbitrary hosts, e.g. through the SOCKS proxy.
+///
+/// ```
+/// use porward::{PortForwarder, Service};
+///
+/// let service = Service::all().remove(0);
+/// let port = service.default_port();
+/// let port_forwarder = PortForwarder::new(
+///     "staging".to_string(),
+///     "i-0123456789abcdef0".to_string(),
+///     service,
+///     "db.internal".to_string(),
+///     port,
+///     15000,
+/// )?;
+/// assert_eq!(port_forwarder.local_port(), 15000);
+/// assert_eq!(port_forwarder.destination().host, "db.internal");
+/// let args = port_forwarder.command_args()?;
+/// assert!(args.contains(&"i-0123456789abcdef0".to_string()));
+/// # Ok::<(), color_eyre::Report>(())
+/// ```
+#[cfg_attr(
+    feature = "rds",
+    doc = r#"
+```
+use porward::{PortForwarder, Service};
+
+let port_forwarder = PortForwarder::new(
+    "staging".to_string(),
+    "i-0123456789abcdef0".to_string(),
+    Service::Postgresql,
+    "db.internal".to_string(),
+    5432,
+    15432,
+)?;
+assert!(port_forwarder.reason().is_none());
+// port zero is never a valid destination
+let invalid = PortForwarder::new(
+    "staging".to_string(),
+    "i-0123456789abcdef0".to_string(),
+    Service::Postgresql,
+    "db.internal".to_string(),
+    0,
+    15432,
+);
+assert!(invalid.is_err());
+# Ok::<(), color_eyre::Report>(())
+```"#
+)]
 pub struct PortForwarder {
+    target: InstanceTarget,
+    service: Option<Service>,
+    destination: Destination,
+    local_port: u16,
+    method: Method,
+    document_name: Option<String>,
+    extra_parameters: Parameters,
+    warnings: Vec<String>,
+    // printed before the session starts with --check-permissions
+    permissions: Option<String>,
+    events: Sink,
+    // the other hosts selected together with this one, supervised by the same session
+    additional: Vec<PortForwarder>,
+    // looked up when the builder builds an eice forwarder
+    eice: Option<EiceRoute>,
+}
+
+// the endpoint in the instance's vpc, and the private ip the destination resolves to or none to
+// tunnel to the instance itself
+#[derive(Clone, Debug, PartialEq)]
+struct EiceRoute {
+    endpoint_id: String,
+    private_ip: Option<String>,
+}
+
+// what the builder has collected so far, checked once in build()
+#[derive(Default)]
+struct Draft {
     profile_name: Option<String>,
+    // from the command line, otherwise from the selected profile's config
+    session_profile: Option<String>,
     instance_id: Option<String>,
+    instance_name: Option<String>,
     service: Option<Service>,
     host_name: Option<String>,
-    host_port: Option<String>,
-    local_port: Option<String>,
-    read_only: bool,
+    host_port: Option<u16>,
+    local_port: Option<u16>,
+    reason: Option<String>,
+    local_ports: LocalPorts,
+    // looked up once per profile, going back to a profile does not ask again
+    accounts: BTreeMap<String, Option<AccountSummary>>,
+    // picked for profiles that have no region, or settled on by find, per profile
+    regions: BTreeMap<String, String>,
+    production: bool,
+    // the name of a destination that matches the production tags or names
+    production_destination: Option<String>,
+    // --yes, and --allow-production to let it skip the production confirmation too
+    assume_yes: bool,
+    allow_production: bool,
+    destination_tags: Tags,
+    same_vpc: bool,
+    auto_scaling_group: bool,
+    // the profile's configured destination was accepted, its steps are skipped
+    configured_destination: bool,
+    instance_vpc_id: Option<String>,
+    instance_zone: Option<String>,
+    instance_security_groups: Vec<String>,
+    instance_private_ip: Option<String>,
+    destination_vpc_id: Option<String>,
+    destination_zones: Vec<String>,
+    warnings: Vec<String>,
+    // the hosts selected after the first one, with their local ports
+    additional_destinations: Vec<(Destination, u16)>,
+    check_permissions: bool,
+    permissions: Option<String>,
+    // --method, which wins over the method of the selected profile's config
+    method_flag: Option<Method>,
+    method: Method,
+    // from the selected profile's config, --document-name wins over it
+    document_name: Option<String>,
+    instance_tags: Vec<(String, String)>,
+    events: Sink,
+    // loaded once in setup, the steps read it from here
+    config: Config,
+    // where the last destinations are remembered, none outside of setup, e.g. in tests
+    state: Option<PathBuf>,
 }
 
+/// Type-state builder for [`PortForwarder`], created with [`PortForwarder::builder`].
+/// Each step either asks the selector or takes a given value.
+///
+/// ```no_run
+/// use porward::{session::SessionOptions, PlainStringListSelector, PortForwarder, Service};
+///
+/// # async fn given() -> porward::error::Result<()> {
+/// let service = Service::all().remove(0);
+/// let port = service.default_port();
+/// let port_forwarder = PortForwarder::builder(Box::new(PlainStringListSelector))
+///     .setup(&SessionOptions::default())?
+///     .with_profile("staging")
+///     .await?
+///     .with_instance("i-0123456789abcdef0")
+///     .await?
+///     .with_service(service)
+///     .with_destination("db.internal", port)?
+///     .build()
+///     .await?;
+/// println!("localhost:{}", port_forwarder.local_port());
+/// # Ok(())
+/// # }
+/// ```
 pub struct PortForwarderBuilder<S: BuilderState = Start> {
-    port_forwarder: Box<PortForwarder>,
+    draft: Draft,
     selector: Box<dyn StringListSelector>,
+    instances: Box<dyn InstanceProvider>,
+    destinations: Box<dyn DestinationProvider>,
     marker: std::marker::PhantomData<S>,
 }
 
@@ -41,28 +453,32 @@ pub struct Start;
 pub struct Profile;
 pub struct Instance;
 pub struct DestinationType;
-pub struct Destination;
+pub struct Host;
 pub struct Ready;
 
 impl BuilderState for Start {}
 impl BuilderState for Profile {}
 impl BuilderState for Instance {}
 impl BuilderState for DestinationType {}
-impl BuilderState for Destination {}
+impl BuilderState for Host {}
 impl BuilderState for Ready {}
 
 impl Display for Service {
     fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
         match self {
+            #[cfg(feature = "alb")]
             Service::ApplicationLoadBalancer => {
                 write!(f, "ApplicationLoadBalancer")
             }
+            #[cfg(feature = "rds")]
             Service::Postgresql => {
                 write!(f, "Postgresql")
             }
+            #[cfg(feature = "elasticache")]
             Service::Redis => {
                 write!(f, "Redis")
             }
+            #[cfg(feature = "elasticache")]
             Service::Valkey => {
                 write!(f, "Valkey")
             }
@@ -70,368 +486,4583 @@ impl Display for Service {
     }
 }
 
+// the names used in config.toml, e.g. postgresql or alb
+impl FromStr for Service {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Service::deserialize(value.into_deserializer())
+            .map_err(|e: serde::de::value::Error| e.to_string())
+    }
+}
+
+/// How a session reaches the instance: Session Manager, or an EC2 Instance Connect Endpoint in
+/// the instance's VPC.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+    #[default]
+    Ssm,
+    Eice,
+}
+
+// the names used in config.toml, ssm or eice
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Method::deserialize(value.into_deserializer())
+            .map_err(|e: serde::de::value::Error| e.to_string())
+    }
+}
+
 impl Service {
-    fn default_port(&self) -> u16 {
+    /// The destination types this build was compiled with.
+    pub fn all() -> Vec<Service> {
+        vec![
+            #[cfg(feature = "alb")]
+            Service::ApplicationLoadBalancer,
+            #[cfg(feature = "elasticache")]
+            Service::Redis,
+            #[cfg(feature = "elasticache")]
+            Service::Valkey,
+            #[cfg(feature = "rds")]
+            Service::Postgresql,
+        ]
+    }
+
+    // the only service listening on its default port, redis and valkey share 6379
+    pub fn for_port(port: u16) -> Option<Service> {
+        let mut matching = Service::all()
+            .into_iter()
+            .filter(|service| service.default_port() == port);
+        matching.next().filter(|_| matching.next().is_none())
+    }
+
+    // the name FromStr takes, as in config.toml
+    pub fn config_name(&self) -> &'static str {
         match self {
+            #[cfg(feature = "alb")]
+            Service::ApplicationLoadBalancer => "application-load-balancer",
+            #[cfg(feature = "rds")]
+            Service::Postgresql => "postgresql",
+            #[cfg(feature = "elasticache")]
+            Service::Redis => "redis",
+            #[cfg(feature = "elasticache")]
+            Service::Valkey => "valkey",
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            #[cfg(feature = "alb")]
             Service::ApplicationLoadBalancer => 443,
+            #[cfg(feature = "rds")]
             Service::Postgresql => 5432,
+            #[cfg(feature = "elasticache")]
             Service::Redis => 6379,
+            #[cfg(feature = "elasticache")]
             Service::Valkey => 6379,
         }
     }
-}
 
-impl PortForwarderBuilder<Start> {
-    pub fn setup(self) -> Result<PortForwarderBuilder<Profile>> {
-        Command::new("aws").arg("--version").output().map_err(|_| {
-            eyre!("aws cli is not installed. Please install it before running this program.")
-        })?;
-        Command::new("session-manager-plugin")
-            .arg("--version")
-            .output()
-            .map_err(|_| {
-                eyre!("session-manager-plugin is not installed. Please install it before running this program.")
-            })?;
-        Ok(PortForwarderBuilder {
-            port_forwarder: self.port_forwarder,
-            selector: self.selector,
-            marker: std::marker::PhantomData,
-        })
+    // what the health check and porward check send through a tunnel to the service
+    pub fn expect(&self) -> Expect {
+        match self {
+            // the listener usually speaks TLS, a connection that stays open is all that is checked
+            #[cfg(feature = "alb")]
+            Service::ApplicationLoadBalancer => Expect::Tcp,
+            #[cfg(feature = "rds")]
+            Service::Postgresql => Expect::Postgres,
+            #[cfg(feature = "elasticache")]
+            Service::Redis | Service::Valkey => Expect::Redis,
+        }
+    }
+
+    // services with nodes worth a tunnel each, e.g. the shards of a cache
+    pub fn multiple_hosts(&self) -> bool {
+        match self {
+            #[cfg(feature = "alb")]
+            Service::ApplicationLoadBalancer => false,
+            #[cfg(feature = "rds")]
+            Service::Postgresql => false,
+            #[cfg(feature = "elasticache")]
+            Service::Redis | Service::Valkey => true,
+        }
     }
 }
 
-impl PortForwarderBuilder<Profile> {
-    pub async fn profile(mut self) -> Result<PortForwarderBuilder<Instance>> {
-        let fs = aws_types::os_shim_internal::Fs::real();
-        let env = aws_types::os_shim_internal::Env::real();
-        let profile_files = env_config::file::EnvConfigFiles::default();
-        let profiles_set = aws_config::profile::load(&fs, &env, &profile_files, None).await?;
-
-        let available_profiles = profiles_set
-            .profiles()
-            .map(|name| name.to_string())
-            .collect();
+pub fn validate_host_port(port: u16) -> Result<u16> {
+    if port == 0 {
+        return Err(eyre!("invalid host port '{}'", port));
+    }
+    Ok(port)
+}
 
-        let (_, profile_name) = self
-            .selector
-            .select("Select Profile".into(), available_profiles)?;
+// 0 would let the plugin pick a port nobody knows about
+pub fn validate_local_port(port: u16) -> Result<u16> {
+    if port == 0 {
+        return Err(eyre!("invalid local port '{}'", port));
+    }
+    Ok(port)
+}
 
-        self.port_forwarder.profile_name = Some(profile_name);
-        Ok(PortForwarderBuilder {
-            port_forwarder: self.port_forwarder,
-            selector: self.selector,
-            marker: std::marker::PhantomData,
-        })
+/// A dns name or an ip address, nothing a shell or /etc/hosts would read as more than a host.
+pub fn validate_host_name(host: &str) -> Result<&str> {
+    let valid = host.parse::<IpAddr>().is_ok()
+        || (!host.is_empty()
+            && host.len() <= MAX_HOST_NAME_LEN
+            && !host.starts_with(['-', '.'])
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'));
+    if !valid {
+        return Err(eyre!("invalid host name '{}'", host));
     }
+    Ok(host)
 }
 
-impl PortForwarderBuilder<Instance> {
-    pub async fn instance(mut self) -> Result<PortForwarderBuilder<DestinationType>> {
-        let profile_name = self
-            .port_forwarder
-            .profile_name
-            .as_ref()
-            .ok_or(eyre!("profile name is not set"))?;
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .profile_name(profile_name)
-            .load()
-            .await;
-        let client = aws_sdk_ec2::Client::new(&config);
-        let instances = client
-            .describe_instances()
-            .filters(
-                aws_sdk_ec2::types::Filter::builder()
-                    .name("instance-state-name")
-                    .values("running")
-                    .build(),
-            )
-            .send()
-            .await?
-            .reservations
-            .unwrap_or(vec![])
-            .iter()
-            .flat_map(|reservation| {
-                reservation.instances().iter().filter_map(|instance| {
-                    if let Some(id) = instance.instance_id() {
-                        if let Some(name) = instance
-                            .tags()
-                            .iter()
-                            .find(|tag| tag.key().unwrap_or_default() == "Name")
-                        {
-                            Some((id.to_string(), name.value().unwrap_or_default().to_string()))
-                        } else {
-                            Some((id.to_string(), "".to_string()))
-                        }
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
+impl PortForwarderBuilder<Start> {
+    /// Reports the progress of the builder and the session to `sink` instead of the JSON events.
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.draft.events = Sink::new(sink);
+        self
+    }
 
-        let (idx, _) = self.selector.select(
-            "Select EC2 Instance".into(),
-            instances
-                .iter()
-                .map(|(id, name)| format!("{} ({})", name, id))
-                .collect(),
-        )?;
-        self.port_forwarder.instance_id = instances.get(idx).map(|(id, _)| id.clone()).clone();
+    pub fn setup(
+        mut self,
+        options: &SessionOptions,
+    ) -> error::Result<PortForwarderBuilder<Profile>> {
+        #[cfg(feature = "native-tunnel")]
+        let plugin = !options.native_tunnel;
+        #[cfg(not(feature = "native-tunnel"))]
+        let plugin = true;
+        for warning in prerequisites::check(options.force, options.aws_cli, plugin)? {
+            events::say(&format!("warning: {}", warning));
+        }
+        let config = Config::load()?;
+        self.draft.reason = options.reason.clone();
+        self.draft.session_profile = options.session_profile.clone();
+        self.draft.assume_yes = options.yes;
+        self.draft.method_flag = options.method;
+        self.draft.allow_production = options.allow_production;
+        self.draft.local_ports = config.local_ports.clone();
+        // tags given on the command line replace configured ones with the same key
+        self.draft.destination_tags = config.destination_tags.clone();
+        self.draft
+            .destination_tags
+            .extend(options.destination_tags.clone());
+        self.draft.same_vpc = options.same_vpc;
+        self.draft.auto_scaling_group = options.auto_scaling_group;
+        self.draft.check_permissions = options.check_permissions;
+        self.draft.config = config;
+        self.draft.state = state::path();
         Ok(PortForwarderBuilder {
-            port_forwarder: self.port_forwarder,
+            draft: self.draft,
             selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
             marker: std::marker::PhantomData,
         })
     }
 }
 
-impl PortForwarderBuilder<DestinationType> {
-    pub fn destination_type(mut self) -> Result<PortForwarderBuilder<Destination>> {
-        let services = [
-            Service::ApplicationLoadBalancer,
-            Service::Redis,
-            Service::Valkey,
-            Service::Postgresql,
-        ];
-
-        let (idx, _) = self.selector.select(
-            "Select Destination Type".into(),
-            services.iter().map(|service| service.to_string()).collect(),
-        )?;
-
-        self.port_forwarder.service = services.get(idx).cloned();
-        let default_port = self
-            .port_forwarder
-            .service
-            .as_ref()
-            .map(|service| service.default_port());
-        self.port_forwarder.host_port = default_port.map(|port| port.to_string());
-        self.port_forwarder.local_port = default_port.map(|port| {
-            if port < 1000 {
-                (port + 1000).to_string()
-            } else {
-                port.to_string()
+// steps that are also reached by going back from a later one
+impl<S: BuilderState> PortForwarderBuilder<S> {
+    async fn select_profile(&mut self) -> error::Result<String> {
+        loop {
+            self.draft.events.on_step_started("profile");
+            let profiles = available_profiles().await?;
+            self.draft
+                .events
+                .on_options_fetched("profile", profiles.len());
+            let profile_name = self.selector.select("Select Profile".into(), profiles)?;
+            self.ensure_region(&profile_name, true).await?;
+            if !self.show_account(&profile_name).await? {
+                continue;
             }
-        });
-        Ok(PortForwarderBuilder {
-            port_forwarder: self.port_forwarder,
-            selector: self.selector,
-            marker: std::marker::PhantomData,
-        })
+            self.reason_for(&profile_name, true)?;
+            self.check_session_profile(&profile_name).await?;
+            self.apply_profile_settings(&profile_name)?;
+            if !self.check_permissions(&profile_name).await? {
+                continue;
+            }
+            self.draft.events.on_selection("profile", &profile_name);
+            self.draft.profile_name = Some(profile_name.clone());
+            self.draft.configured_destination = false;
+            return Ok(profile_name);
+        }
     }
-}
 
-impl PortForwarderBuilder<Destination> {
-    pub async fn destination(mut self) -> Result<PortForwarderBuilder<Ready>> {
-        let destinations = match self
-            .port_forwarder
-            .service
-            .clone()
-            .ok_or(eyre!("destination type is empty"))?
-        {
-            Service::ApplicationLoadBalancer => self.application_load_balancers().await?,
-            Service::Postgresql => self.postgresql_servers().await?,
-            Service::Redis => self.redis_servers().await?,
-            Service::Valkey => self.valkey_servers()?,
+    // false when the user went back to the profile list after the lookup failed
+    async fn show_account(&mut self, profile_name: &str) -> error::Result<bool> {
+        let account = match self.draft.accounts.get(profile_name) {
+            Some(account) => account.clone(),
+            None => loop {
+                match self
+                    .instances
+                    .account(profile_name, self.draft.region(profile_name))
+                    .await
+                {
+                    Ok(account) => break account,
+                    Err(e) => match self.step_error(
+                        format!("Could not validate '{}'", profile_name),
+                        profile_name,
+                        &e,
+                    )? {
+                        ErrorAction::Retry => {}
+                        ErrorAction::Back => return Ok(false),
+                        ErrorAction::Quit => return Err(e),
+                    },
+                }
+            },
         };
+        self.draft
+            .accounts
+            .insert(profile_name.to_string(), account.clone());
+        self.draft.production = self.draft.config.is_production(
+            profile_name,
+            account.as_ref().map(|account| account.id.as_str()),
+        );
+        let header = match (account, self.draft.production) {
+            (Some(account), true) => format!("PRODUCTION {}", account),
+            (Some(account), false) => account.to_string(),
+            (None, true) => format!("PRODUCTION {}", profile_name),
+            (None, false) => return Ok(true),
+        };
+        self.selector.show_account(&header, self.draft.production);
+        Ok(true)
+    }
 
-        let (idx, _) = self.selector.select(
-            "Select Host".into(),
-            destinations
-                .iter()
-                .map(|(_, title)| title.to_owned())
-                .collect(),
-        )?;
-        self.port_forwarder.host_name = destinations
-            .get(idx)
-            .map(|(host_name, _)| host_name.to_owned());
-
-        Ok(PortForwarderBuilder {
-            port_forwarder: self.port_forwarder,
-            selector: self.selector,
-            marker: std::marker::PhantomData,
-        })
+    // a last chance to back out before anything is started in production, by typing its name
+    // like destructive CLIs ask for it
+    fn confirm_production(&mut self) -> error::Result<()> {
+        let profile_name = self.draft.profile_name.clone().unwrap_or_default();
+        let (kind, name) = match self.draft.production_destination.clone() {
+            Some(destination) => ("destination", destination),
+            None if self.draft.production => ("account", profile_name.clone()),
+            None => return Ok(()),
+        };
+        if self.draft.assume_yes && self.draft.allow_production {
+            return Ok(());
+        }
+        let question = match self.draft.session_profile_for(&profile_name)? {
+            Some(session_profile) if session_profile != profile_name => format!(
+                "'{}' is a production {}, type its name to continue with session profile '{}'",
+                name, kind, session_profile
+            ),
+            _ => format!(
+                "'{}' is a production {}, type its name to continue",
+                name, kind
+            ),
+        };
+        let mut title = question.clone();
+        loop {
+            let answer = self.selector.input(title)?;
+            match answer.trim() {
+                "" => return Err(PorwardError::Cancelled),
+                answer if answer == name => return Ok(()),
+                answer => title = format!("'{}' does not match. {}", answer, question),
+            }
+        }
     }
 
-    async fn application_load_balancers(&self) -> Result<Vec<(String, String)>> {
-        let profile_name = self
-            .port_forwarder
-            .profile_name
-            .as_ref()
-            .ok_or(eyre!("profile name is not set"))?;
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .profile_name(profile_name)
-            .load()
-            .await;
-        let client = aws_sdk_elasticloadbalancingv2::Client::new(&config);
-        let response = client.describe_load_balancers().send().await?;
-        Ok(response
-            .load_balancers
-            .unwrap_or(vec![])
-            .iter()
-            .filter_map(|lb| {
-                lb.dns_name
-                    .as_ref()
-                    .map(|dns_name| {
-                        (
-                            dns_name.to_owned(),
-                            lb.load_balancer_name.to_owned().unwrap_or(dns_name.clone()),
-                        )
-                    })
-                    .clone()
-            })
-            .collect())
+    // the connection settings of the profile's config, decided without asking
+    fn apply_profile_settings(&mut self, profile_name: &str) -> error::Result<()> {
+        let profile = self.draft.config.profile(profile_name);
+        self.draft.method = self
+            .draft
+            .method_flag
+            .or(profile.method)
+            .unwrap_or_default();
+        self.draft.document_name = profile.document;
+        self.draft.instance_tags = profile.instance_tags.into_iter().collect();
+        Ok(())
     }
 
-    async fn postgresql_servers(&self) -> Result<Vec<(String, String)>> {
-        let profile_name = self
-            .port_forwarder
-            .profile_name
-            .as_ref()
-            .ok_or(eyre!("profile name is not set"))?;
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .profile_name(profile_name)
-            .load()
-            .await;
-        let client = aws_sdk_rds::Client::new(&config);
-        let response = client.describe_db_cluster_endpoints().send().await?;
-        Ok(response
-            .db_cluster_endpoints
-            .unwrap_or(vec![])
-            .iter()
-            .filter_map(|db_cluster_endpoint| {
-                db_cluster_endpoint
-                    .endpoint
-                    .as_ref()
-                    .map(|dns_name| {
-                        (
-                            dns_name.to_owned(),
-                            db_cluster_endpoint
-                                .endpoint
-                                .to_owned()
-                                .unwrap_or(dns_name.clone()),
-                        )
-                    })
-                    .clone()
-            })
-            .collect())
+    async fn check_session_profile(&self, profile_name: &str) -> error::Result<()> {
+        let Some(session_profile) = self.draft.session_profile_for(profile_name)? else {
+            return Ok(());
+        };
+        if !available_profiles().await?.contains(&session_profile) {
+            return Err(eyre!("session profile '{}' does not exist", session_profile).into());
+        }
+        Ok(())
     }
 
-    async fn redis_servers(&self) -> Result<Vec<(String, String)>> {
-        let profile_name = self
-            .port_forwarder
-            .profile_name
-            .as_ref()
-            .ok_or(eyre!("profile name is not set"))?;
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .profile_name(profile_name)
-            .load()
-            .await;
-        let client = aws_sdk_elasticache::Client::new(&config);
-        let response = client
-            .describe_cache_clusters()
-            .show_cache_node_info(true)
-            .send()
-            .await?;
-        Ok(response
-            .cache_clusters
-            .unwrap_or(vec![])
-            .iter()
-            .filter_map(|cluster| {
-                cluster.cache_nodes.as_ref().map(|cache_node| {
-                    let endpoint = cache_node.first().unwrap().endpoint.clone().unwrap();
+    // before the slow steps, so that a denied ssm:StartSession does not wait for the end of the
+    // wizard; false when the user went back to the profile list
+    async fn check_permissions(&mut self, profile_name: &str) -> error::Result<bool> {
+        if !self.draft.check_permissions {
+            return Ok(true);
+        }
+        let session_profile = self
+            .draft
+            .session_profile_for(profile_name)?
+            .unwrap_or(profile_name.to_string());
+        loop {
+            let (title, error) = match permissions::check(
+                profile_name,
+                &session_profile,
+                self.draft.region(profile_name),
+            )
+            .await
+            {
+                Ok(reports) => {
+                    let report = reports.iter().map(ToString::to_string).collect::<String>();
+                    let denied: Vec<_> =
+                        reports.iter().flat_map(PermissionReport::denied).collect();
+                    if denied.is_empty() {
+                        self.draft.permissions = Some(report);
+                        return Ok(true);
+                    }
                     (
-                        endpoint.address.clone().unwrap().to_string(),
-                        endpoint.address.unwrap().to_string(),
+                        format!("'{}' is missing {}", profile_name, denied.join(", ")),
+                        PorwardError::Other(eyre!("{}", report.trim_end())),
                     )
-                })
-            })
-            .collect())
+                }
+                Err(e) => (
+                    format!("Could not check the permissions of '{}'", profile_name),
+                    e,
+                ),
+            };
+            match self.step_error(title, profile_name, &error)? {
+                ErrorAction::Retry => {}
+                ErrorAction::Back => return Ok(false),
+                ErrorAction::Quit => return Err(error),
+            }
+        }
     }
 
-    fn valkey_servers(&self) -> Result<Vec<(String, String)>> {
-        Ok(vec![])
+    // the selected choices stay when a step is retried, an expired login only needs a new one
+    fn step_error(
+        &mut self,
+        title: String,
+        profile_name: &str,
+        error: &PorwardError,
+    ) -> error::Result<ErrorAction> {
+        let title = if error.is_expired_credentials() {
+            format!(
+                "{}, run 'aws sso login --profile {}' and retry",
+                title, profile_name
+            )
+        } else {
+            title
+        };
+        self.selector.error(title, error)
     }
-}
 
-impl PortForwarderBuilder<Ready> {
-    pub fn build(self) -> Result<Box<PortForwarder>> {
-        Ok(self.port_forwarder)
+    // without a region in the profile or the environment the first describe call fails with
+    // "a region must be set", the region picked here is used for every call of this profile and
+    // its session
+    async fn ensure_region(&mut self, profile_name: &str, prompt: bool) -> error::Result<()> {
+        if self.draft.regions.contains_key(profile_name) {
+            return Ok(());
+        }
+        let env = Env::real();
+        let profiles = load_profiles(&Fs::real(), &env).await?;
+        if let Some(region) = self.missing_region(&profiles, &env, profile_name, prompt)? {
+            self.draft.regions.insert(profile_name.to_string(), region);
+        }
+        Ok(())
     }
-}
 
-impl PortForwarder {
-    pub fn builder(selector: Box<dyn StringListSelector>) -> PortForwarderBuilder {
-        PortForwarderBuilder {
-            port_forwarder: Box::new(PortForwarder {
-                profile_name: None,
-                instance_id: None,
-                service: None,
-                host_name: None,
-                host_port: None,
-                local_port: None,
-                read_only: true,
-            }),
-            selector,
-            marker: std::marker::PhantomData,
+    // the region picked for a profile that has none
+    fn missing_region(
+        &mut self,
+        profiles: &ProfileSet,
+        env: &Env,
+        profile_name: &str,
+        prompt: bool,
+    ) -> error::Result<Option<String>> {
+        if !needs_region(profiles, env, profile_name) {
+            return Ok(None);
+        }
+        if !prompt {
+            return Err(eyre!(
+                "profile '{}' has no region, add 'region = ...' to its section in ~/.aws/config or set AWS_REGION",
+                profile_name
+            )
+            .into());
+        }
+        let mut regions = self.draft.config.regions.clone();
+        if regions.is_empty() {
+            regions = REGIONS.iter().map(|region| region.to_string()).collect();
         }
+        let region = self.selector.select(
+            format!("Select Region, '{}' has none", profile_name),
+            regions,
+        )?;
+        self.draft.events.on_selection("region", &region);
+        Ok(Some(region))
     }
 
-    pub fn run(self) -> Result<()> {
-        let profile_name = self
+    fn reason_for(&mut self, profile_name: &str, prompt: bool) -> error::Result<()> {
+        if !self.draft.config.profile(profile_name).require_reason {
+            return Ok(());
+        }
+        let reason = match self.draft.reason.take() {
+            Some(reason) => reason,
+            None if prompt => self
+                .selector
+                .input(format!("Reason for using '{}'", profile_name))?,
+            None => String::new(),
+        };
+        if reason.trim().is_empty() {
+            return Err(eyre!("profile '{}' requires a reason", profile_name).into());
+        }
+        self.draft.reason = Some(reason.trim().to_string());
+        Ok(())
+    }
+
+    // once the destination is known, instances in its vpc and zone are listed first
+    async fn select_instance(&mut self, back_to_profile: bool) -> error::Result<()> {
+        let mut profile_name = self
+            .draft
             .profile_name
-            .as_ref()
+            .clone()
             .ok_or(eyre!("profile name is not set"))?;
-        let instance_id = self
-            .instance_id
-            .as_ref()
-            .ok_or(eyre!("instance id is not set"))?;
-        let host_name = self
-            .host_name
-            .as_ref()
-            .ok_or(eyre!("host name is not set"))?;
-        let host_port = self
-            .host_port
-            .as_ref()
-            .ok_or(eyre!("host port is not set"))?;
-        let local_port = self
-            .local_port
-            .as_ref()
-            .ok_or(eyre!("local port is not set"))?;
-        let command = format!(
-            r#"aws --profile {} ssm start-session --target {} --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{{"host":["{}"],"portNumber":["{}"], "localPortNumber":["{}"]}}'"#,
-            profile_name, instance_id, host_name, host_port, local_port
-        );
-        ratatui::restore();
-        println!("Running:\r\n{}", command);
-        let mut child = Command::new("aws")
-            .arg("--profile")
-            .arg(profile_name)
-            .arg("ssm")
-            .arg("start-session")
-            .arg("--target")
-            .arg(instance_id)
-            .arg("--document-name")
-            .arg("AWS-StartPortForwardingSessionToRemoteHost")
-            .arg("--parameters")
-            .arg(format!(
-                r#"{{"host":["{}"],"portNumber":["{}"], "localPortNumber":["{}"]}}"#,
-                host_name, host_port, local_port
-            ))
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
+        self.draft.events.on_step_started("instance");
+        let mut instances = loop {
+            match self
+                .instances
+                .running_instances(&profile_name, self.draft.region(&profile_name))
+                .await
+            {
+                Ok(instances) => break instances,
+                Err(e) => match self.step_error(
+                    "Could not list EC2 instances".into(),
+                    &profile_name,
+                    &e,
+                )? {
+                    ErrorAction::Retry => {}
+                    ErrorAction::Back if back_to_profile => {
+                        profile_name = self.select_profile().await?
+                    }
+                    ErrorAction::Back | ErrorAction::Quit => return Err(e),
+                },
+            }
+        };
+        self.draft
+            .events
+            .on_options_fetched("instance", instances.len());
+        if self.draft.auto_scaling_group {
+            let instance = self.select_group_instance(&profile_name, instances).await?;
+            self.set_instance(instance);
+            return Ok(());
+        }
+        instances.sort_by_key(|instance| self.draft.instance_rank(instance));
+        group_by_name(&mut instances);
+        let duplicates = duplicate_names(&instances);
+        // only looked up when there is something to tell apart, and not worth failing over
+        let image_ids: BTreeSet<String> = instances
+            .iter()
+            .filter(|instance| duplicates.contains(&instance.name))
+            .filter_map(|instance| instance.image_id.clone())
+            .collect();
+        let image_names = if image_ids.is_empty() {
+            BTreeMap::new()
+        } else {
+            self.instances
+                .image_names(
+                    &profile_name,
+                    self.draft.region(&profile_name),
+                    &image_ids.into_iter().collect::<Vec<_>>(),
+                )
+                .await
+                .unwrap_or_default()
+        };
 
-        child.wait().map_err(|_| {
-            eyre!(
-                    r#"aws --profile {} ssm start-session --target {} --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{{"host":["{}"],"portNumber":["{}"], "localPortNumber":["{}"]}}'"#,
-                    profile_name,
-                    instance_id,
-                    host_name,
-                    host_port,
-                    local_port
+        // tag filters chosen so far, starting with the profile's, Esc removes the last one
+        let mut filters: Vec<(String, String)> = self.draft.instance_tags.clone();
+        let instance = loop {
+            let visible: Vec<&InstanceSummary> = instances
+                .iter()
+                .filter(|instance| {
+                    filters
+                        .iter()
+                        .all(|(key, value)| instance.tags.get(key) == Some(value))
+                })
+                .collect();
+            let mut items: Vec<SelectItem> = visible
+                .iter()
+                .enumerate()
+                .map(|(idx, instance)| {
+                    // the rest of a group of instances with the same name is indented under it
+                    let continued = idx > 0
+                        && !instance.name.is_empty()
+                        && visible[idx - 1].name == instance.name;
+                    let label = match (continued, instance.hybrid) {
+                        (true, _) => format!("  └ {}", instance.id),
+                        (false, true) => format!("{} ({}) (hybrid)", instance.name, instance.id),
+                        (false, false) => format!("{} ({})", instance.name, instance.id),
+                    };
+                    let mut details = vec![];
+                    match self.draft.instance_rank(instance) {
+                        (0, 0) => details.push("same vpc and zone".to_string()),
+                        (0, _) => details.push("same vpc".to_string()),
+                        (2, _) => details.push(format!(
+                            "other vpc {}",
+                            instance.vpc_id.clone().unwrap_or_default()
+                        )),
+                        _ => {}
+                    }
+                    if duplicates.contains(&instance.name) {
+                        details.extend(disambiguation(instance, &image_names));
+                    }
+                    details.extend(instance.platform.clone());
+                    if let Some(agent_version) = instance.agent_version.as_ref() {
+                        details.push(format!("agent {}", agent_version));
+                    }
+                    let item = SelectItem::new(instance.id.clone(), label);
+                    if details.is_empty() {
+                        item
+                    } else {
+                        item.with_detail(details.join("  "))
+                    }
+                })
+                .collect();
+            if !tag_keys(&visible, &filters).is_empty() {
+                items.push(SelectItem::new(TAG_FILTER, "Filter by tag"));
+            }
+            items.push(SelectItem::new(
+                DIRECT_INSTANCE,
+                "Enter an instance id or private IP",
+            ));
+            let title = if filters.is_empty() {
+                "Select SSM Target".to_string()
+            } else {
+                format!(
+                    "Select SSM Target · {}",
+                    filters
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect::<Vec<_>>()
+                        .join(" › ")
                 )
-        })?;
+            };
+            let selected = match self.selector.select_items(title, items) {
+                Err(PorwardError::Cancelled) if !filters.is_empty() => {
+                    filters.pop();
+                    continue;
+                }
+                selected => selected?,
+            };
+            match selected.id.as_str() {
+                TAG_FILTER => {
+                    if let Some(filter) = self.select_tag_filter(&visible, &filters)? {
+                        filters.push(filter);
+                    }
+                }
+                DIRECT_INSTANCE => break self.direct_instance(&profile_name, &instances).await?,
+                id => {
+                    break instances
+                        .iter()
+                        .find(|summary| summary.id == id)
+                        .cloned()
+                        .ok_or(eyre!("instance '{}' is not running", id))?
+                }
+            }
+        };
+        self.set_instance(instance);
         Ok(())
     }
+
+    // a tag key, then one of its values; None when Esc went back to the instances
+    fn select_tag_filter(
+        &mut self,
+        instances: &[&InstanceSummary],
+        filters: &[(String, String)],
+    ) -> error::Result<Option<(String, String)>> {
+        loop {
+            let keys = tag_keys(instances, filters);
+            let items = keys
+                .iter()
+                .map(|(key, count)| {
+                    SelectItem::new(key.clone(), key.clone())
+                        .with_detail(format!("{} instances", count))
+                })
+                .collect();
+            let key = match self.selector.select_items("Select Tag".into(), items) {
+                Err(PorwardError::Cancelled) => return Ok(None),
+                key => key?.id,
+            };
+            let mut values: BTreeMap<&str, usize> = BTreeMap::new();
+            for value in instances
+                .iter()
+                .filter_map(|instance| instance.tags.get(&key))
+            {
+                *values.entry(value.as_str()).or_default() += 1;
+            }
+            let items = values
+                .iter()
+                .map(|(value, count)| {
+                    SelectItem::new(*value, *value).with_detail(format!("{} instances", count))
+                })
+                .collect();
+            match self
+                .selector
+                .select_items(format!("Select Value of {}", key), items)
+            {
+                Err(PorwardError::Cancelled) => {}
+                value => return Ok(Some((key, value?.id))),
+            }
+        }
+    }
+
+    fn set_instance(&mut self, instance: InstanceSummary) {
+        self.draft.events.on_selection("instance", &instance.id);
+        self.draft.warnings.extend(agent_warning(&instance));
+        self.draft.instance_vpc_id = instance.vpc_id;
+        self.draft.instance_zone = instance.availability_zone;
+        self.draft.instance_security_groups = instance.security_groups;
+        self.draft.instance_private_ip = instance.private_ip;
+        self.draft.instance_name = Some(instance.name).filter(|name| !name.is_empty());
+        self.draft.instance_id = Some(instance.id);
+    }
+
+    // a group with one healthy instance needs no second prompt, several are listed newest first
+    async fn select_group_instance(
+        &mut self,
+        profile_name: &str,
+        running: Vec<InstanceSummary>,
+    ) -> error::Result<InstanceSummary> {
+        let groups = loop {
+            match self
+                .instances
+                .auto_scaling_groups(profile_name, self.draft.region(profile_name))
+                .await
+            {
+                Ok(groups) => break groups,
+                Err(e) => match self.step_error(
+                    "Could not list Auto Scaling Groups".into(),
+                    profile_name,
+                    &e,
+                )? {
+                    ErrorAction::Retry => {}
+                    ErrorAction::Back | ErrorAction::Quit => return Err(e),
+                },
+            }
+        };
+        loop {
+            let items = groups
+                .iter()
+                .map(|group| {
+                    SelectItem::new(group.name.clone(), group.name.clone()).with_detail(format!(
+                        "{} healthy",
+                        group.instances(running.clone()).len()
+                    ))
+                })
+                .collect();
+            let selected = self
+                .selector
+                .select_items("Select Auto Scaling Group".into(), items)?;
+            let group: &AutoScalingGroupSummary = groups
+                .iter()
+                .find(|group| group.name == selected.id)
+                .ok_or(eyre!("auto scaling group '{}' does not exist", selected.id))?;
+            let mut instances = group.instances(running.clone());
+            match instances.len() {
+                0 => {
+                    let e = PorwardError::NoOptions {
+                        step: format!("healthy instances of {}", group.name),
+                    };
+                    match self.step_error(
+                        format!("'{}' has no running instance", group.name),
+                        profile_name,
+                        &e,
+                    )? {
+                        ErrorAction::Retry | ErrorAction::Back => {}
+                        ErrorAction::Quit => return Err(e),
+                    }
+                }
+                1 => return Ok(instances.remove(0)),
+                _ => {
+                    let items = instances
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, instance)| {
+                            let item = SelectItem::new(
+                                instance.id.clone(),
+                                format!("{} ({})", instance.name, instance.id),
+                            );
+                            if idx == 0 {
+                                item.with_detail("newest")
+                            } else {
+                                item
+                            }
+                        })
+                        .collect();
+                    let selected = self
+                        .selector
+                        .select_items(format!("Select Instance of {}", group.name), items)?;
+                    return instances
+                        .into_iter()
+                        .find(|instance| instance.id == selected.id)
+                        .ok_or(eyre!("instance '{}' is not running", selected.id).into());
+                }
+            }
+        }
+    }
+
+    // exact matches only, instances that were not listed are looked up by id or ip
+    async fn direct_instance(
+        &mut self,
+        profile_name: &str,
+        instances: &[InstanceSummary],
+    ) -> error::Result<InstanceSummary> {
+        let mut title = "Instance id (i-...) or private IP".to_string();
+        loop {
+            let id_or_ip = self.selector.input(title.clone())?.trim().to_string();
+            if id_or_ip.is_empty() {
+                return Err(PorwardError::Cancelled);
+            }
+            if let Some(instance) = instances
+                .iter()
+                .find(|instance| instance.matches(&id_or_ip))
+            {
+                return Ok(instance.clone());
+            }
+            match self
+                .instances
+                .find_instance(profile_name, self.draft.region(profile_name), &id_or_ip)
+                .await
+            {
+                Ok(Some(instance)) => return Ok(instance),
+                Ok(None) => {
+                    title = format!(
+                        "No running instance '{}', enter another id or private IP",
+                        id_or_ip
+                    );
+                }
+                Err(e) => match self.step_error(
+                    format!("Could not look up '{}'", id_or_ip),
+                    profile_name,
+                    &e,
+                )? {
+                    ErrorAction::Retry | ErrorAction::Back => {}
+                    ErrorAction::Quit => return Err(e),
+                },
+            }
+        }
+    }
+
+    fn select_service(&mut self) -> error::Result<Service> {
+        let services = Service::all();
+        self.draft.events.on_step_started("destination-type");
+        self.draft
+            .events
+            .on_options_fetched("destination-type", services.len());
+
+        let selected = self.selector.select(
+            "Select Destination Type".into(),
+            services.iter().map(|service| service.to_string()).collect(),
+        )?;
+
+        let service = services
+            .into_iter()
+            .find(|service| service.to_string() == selected)
+            .ok_or(eyre!("destination type is empty"))?;
+        self.draft
+            .events
+            .on_selection("destination-type", &selected);
+        self.set_service(service.clone());
+        Ok(service)
+    }
+
+    fn set_service(&mut self, service: Service) {
+        self.draft.host_port = Some(service.default_port());
+        self.draft.local_port = Some(self.draft.local_ports.local_port(service.default_port()));
+        self.draft.service = Some(service);
+    }
+
+    fn edit_ports(&mut self) -> error::Result<()> {
+        let proposed = self.draft.host_port.ok_or(eyre!("host port is not set"))?;
+        let host_port = self.port_input("Host port", proposed)?;
+        // a local port derived from the proposed host port follows the edited one
+        let local_port = match self.draft.local_port {
+            Some(local_port) if host_port == proposed => local_port,
+            _ => self.draft.local_ports.local_port(host_port),
+        };
+        let local_port = self.port_input("Local port", local_port)?;
+        self.draft
+            .warnings
+            .extend(self.draft.local_ports.outside_range(local_port));
+        self.draft
+            .events
+            .on_selection("host-port", &host_port.to_string());
+        self.draft
+            .events
+            .on_selection("local-port", &local_port.to_string());
+        self.draft.host_port = Some(host_port);
+        self.draft.local_port = Some(local_port);
+        Ok(())
+    }
+
+    fn port_input(&mut self, name: &str, port: u16) -> error::Result<u16> {
+        let mut title = format!("{} [{}], Enter to keep", name, port);
+        loop {
+            let value = self.selector.input(title.clone())?;
+            if value.trim().is_empty() {
+                return Ok(port);
+            }
+            match value.trim().parse::<u16>() {
+                Ok(port) if port != 0 => return Ok(port),
+                _ => {
+                    title = format!(
+                        "'{}' is not a valid port. {} [{}], Enter to keep",
+                        value.trim(),
+                        name,
+                        port
+                    )
+                }
+            }
+        }
+    }
+}
+
+impl PortForwarderBuilder<Profile> {
+    pub async fn profile(mut self) -> error::Result<PortForwarderBuilder<Instance>> {
+        self.select_profile().await?;
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    pub async fn with_profile(
+        mut self,
+        profile_name: &str,
+    ) -> error::Result<PortForwarderBuilder<Instance>> {
+        if !available_profiles()
+            .await?
+            .iter()
+            .any(|name| name == profile_name)
+        {
+            return Err(eyre!("profile '{}' does not exist", profile_name).into());
+        }
+        self.ensure_region(profile_name, false).await?;
+        self.reason_for(profile_name, false)?;
+        self.check_session_profile(profile_name).await?;
+        self.apply_profile_settings(profile_name)?;
+        if !self.check_permissions(profile_name).await? {
+            return Err(PorwardError::Cancelled);
+        }
+
+        self.draft.profile_name = Some(profile_name.to_string());
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+// instances with the same name follow the best ranked one of them
+fn group_by_name(instances: &mut Vec<InstanceSummary>) {
+    let mut first: BTreeMap<String, usize> = BTreeMap::new();
+    let mut keyed: Vec<(usize, InstanceSummary)> = instances
+        .drain(..)
+        .enumerate()
+        .map(|(idx, instance)| {
+            let key = match instance.name.as_str() {
+                "" => idx,
+                name => *first.entry(name.to_string()).or_insert(idx),
+            };
+            (key, instance)
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    instances.extend(keyed.into_iter().map(|(_, instance)| instance));
+}
+
+fn duplicate_names(instances: &[InstanceSummary]) -> BTreeSet<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for instance in instances
+        .iter()
+        .filter(|instance| !instance.name.is_empty())
+    {
+        *counts.entry(instance.name.as_str()).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+// launch time, image and lifecycle, which differ between instances of the same group
+fn disambiguation(
+    instance: &InstanceSummary,
+    image_names: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut details = vec![];
+    if let Some(launch_time) = instance
+        .launch_time
+        .and_then(|secs| u64::try_from(secs).ok())
+    {
+        details.push(format!(
+            "launched {}",
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(launch_time))
+        ));
+    }
+    if let Some(image_id) = instance.image_id.as_ref() {
+        details.push(image_names.get(image_id).unwrap_or(image_id).clone());
+    }
+    details.push(if instance.spot { "spot" } else { "on-demand" }.to_string());
+    details
+}
+
+// the tag keys to narrow instances by, with the number of instances that have them
+fn tag_keys(
+    instances: &[&InstanceSummary],
+    filters: &[(String, String)],
+) -> BTreeMap<String, usize> {
+    let mut keys = BTreeMap::new();
+    for key in instances.iter().flat_map(|instance| instance.tags.keys()) {
+        if !filters.iter().any(|(filtered, _)| filtered == key) {
+            *keys.entry(key.clone()).or_default() += 1;
+        }
+    }
+    keys
+}
+
+/// Profile names from the aws config and credentials files, the same list the wizard offers.
+pub async fn available_profiles() -> error::Result<Vec<String>> {
+    let profiles = load_profiles(&Fs::real(), &Env::real()).await?;
+    Ok(profiles.profiles().map(|name| name.to_string()).collect())
+}
+
+async fn load_profiles(fs: &Fs, env: &Env) -> error::Result<ProfileSet> {
+    let profile_files = env_config::file::EnvConfigFiles::default();
+    aws_config::profile::load(fs, env, &profile_files, None)
+        .await
+        .map_err(|e| PorwardError::Aws(Box::new(e)))
+}
+
+// the sdk takes the region from the environment before the profile
+fn needs_region(profiles: &ProfileSet, env: &Env, profile_name: &str) -> bool {
+    ["AWS_REGION", "AWS_DEFAULT_REGION"]
+        .iter()
+        .all(|var| env.get(var).unwrap_or_default().is_empty())
+        && profiles
+            .get_profile(profile_name)
+            .and_then(|profile| profile.get("region"))
+            .is_none()
+}
+
+impl PortForwarderBuilder<Instance> {
+    // Enter takes the profile's destination as it is, edit asks for its host and ports
+    pub fn configured_destination(mut self) -> error::Result<Self> {
+        let profile_name = self
+            .draft
+            .profile_name
+            .clone()
+            .ok_or(eyre!("profile name is not set"))?;
+        let Some(destination) = self.draft.config.profile(&profile_name).destination else {
+            return Ok(self);
+        };
+        let host_port = destination.host_port();
+        let answer = if self.draft.assume_yes {
+            SelectItem::new("yes", "Yes")
+        } else {
+            self.selector.select_items(
+                format!(
+                    "Use configured destination {}:{}?",
+                    destination.host, host_port
+                ),
+                vec![
+                    SelectItem::new("yes", "Yes"),
+                    SelectItem::new("edit", "Edit"),
+                    SelectItem::new("no", "No, select the destination"),
+                ],
+            )?
+        };
+        if answer.id == "no" {
+            return Ok(self);
+        }
+        self.draft
+            .events
+            .on_selection("destination-type", &destination.service.to_string());
+        self.draft.service = Some(destination.service.clone());
+        self.draft.host_port = Some(host_port);
+        self.draft.local_port = Some(
+            destination
+                .local_port
+                .unwrap_or(self.draft.local_ports.local_port(host_port)),
+        );
+        let host = if answer.id == "edit" {
+            let host = self
+                .selector
+                .input(format!("Host [{}], Enter to keep", destination.host))?;
+            match host.trim() {
+                "" => destination.host,
+                host => host.to_string(),
+            }
+        } else {
+            destination.host
+        };
+        self.draft.events.on_selection("host", &host);
+        // tags of a configured destination are not looked up
+        self.draft.production_destination = self
+            .draft
+            .config
+            .is_production_destination(&[&host], &Tags::new())
+            .then(|| host.clone());
+        self.draft.host_name = Some(host);
+        if answer.id == "edit" {
+            self.edit_ports()?;
+        } else {
+            let local_port = self.draft.local_port.unwrap_or_default();
+            self.draft
+                .warnings
+                .extend(self.draft.local_ports.outside_range(local_port));
+            self.draft
+                .events
+                .on_selection("host-port", &host_port.to_string());
+            self.draft
+                .events
+                .on_selection("local-port", &local_port.to_string());
+        }
+        self.draft.configured_destination = true;
+        Ok(self)
+    }
+
+    /// Searches the profile's account in `regions` (or the configured ones) for instances, database
+    /// clusters and load balancers whose name contains `name`, and continues in the region of the
+    /// selected one. A selected instance is used as the target, otherwise one is asked for.
+    pub async fn find(
+        mut self,
+        name: &str,
+        regions: &[String],
+    ) -> error::Result<PortForwarderBuilder<DestinationType>> {
+        let profile_name = self
+            .draft
+            .profile_name
+            .clone()
+            .ok_or(eyre!("profile name is not set"))?;
+        let regions = match regions {
+            [] => self.draft.config.regions.clone(),
+            regions => regions.to_vec(),
+        };
+        if regions.is_empty() {
+            return Err(eyre!(
+                "no regions to search, pass --regions or set regions in config.toml"
+            )
+            .into());
+        }
+        let (found, warnings) = loop {
+            let (found, warnings) = find_resources(&profile_name, &regions, name).await;
+            if !found.is_empty() || warnings.len() < regions.len() {
+                break (found, warnings);
+            }
+            // every region failed, which is more likely the credentials than the regions
+            let e = PorwardError::from(eyre!("{}", warnings.join("\r\n")));
+            match self.step_error(
+                format!("Could not search for '{}'", name),
+                &profile_name,
+                &e,
+            )? {
+                ErrorAction::Retry => {}
+                ErrorAction::Back | ErrorAction::Quit => return Err(e),
+            }
+        };
+        if found.is_empty() {
+            return Err(eyre!(
+                "nothing matching '{}' in {}{}",
+                name,
+                regions.join(", "),
+                warnings
+                    .iter()
+                    .map(|warning| format!("\r\nwarning: {}", warning))
+                    .collect::<String>()
+            )
+            .into());
+        }
+        let title = match warnings.len() {
+            0 => format!("Select '{}'", name),
+            failed => format!("Select '{}' ({} regions failed)", name, failed),
+        };
+        // shown again before the session starts, the selector has no room for them
+        self.draft.warnings.extend(warnings);
+        let items = found
+            .iter()
+            .enumerate()
+            .map(|(idx, resource)| {
+                let label = if resource.id == resource.name || resource.name.is_empty() {
+                    resource.id.clone()
+                } else {
+                    format!("{} ({})", resource.name, resource.id)
+                };
+                SelectItem::new(idx.to_string(), label)
+                    .with_detail(format!("{}  {}", resource.kind, resource.region))
+            })
+            .collect();
+        let selected = self.selector.select_items(title, items)?;
+        let resource = selected
+            .id
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| found.get(idx))
+            .ok_or(eyre!("unknown selection '{}'", selected.id))?;
+        self.draft
+            .regions
+            .insert(profile_name.clone(), resource.region.clone());
+        self.draft.events.on_selection("region", &resource.region);
+        // the header shows the region the rest of the flow uses
+        self.draft.accounts.remove(&profile_name);
+        self.show_account(&profile_name).await?;
+        if resource.kind == ResourceKind::Instance {
+            let instance = self
+                .instances
+                .find_instance(
+                    &profile_name,
+                    self.draft.region(&profile_name),
+                    &resource.id,
+                )
+                .await?
+                .ok_or(eyre!("instance '{}' is not running", resource.id))?;
+            self.set_instance(instance);
+        } else {
+            self.select_instance(true).await?;
+        }
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    pub async fn instance(mut self) -> error::Result<PortForwarderBuilder<DestinationType>> {
+        self.select_instance(true).await?;
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    // the instance is selected after the destination with ranked_instance()
+    pub fn defer_instance(self) -> PortForwarderBuilder<DestinationType> {
+        PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    pub async fn with_instance(
+        mut self,
+        instance_id: &str,
+    ) -> error::Result<PortForwarderBuilder<DestinationType>> {
+        let profile_name = self
+            .draft
+            .profile_name
+            .as_ref()
+            .ok_or(eyre!("profile name is not set"))?;
+        let instance = self
+            .instances
+            .running_instances(profile_name, self.draft.region(profile_name))
+            .await?
+            .into_iter()
+            .find(|instance| instance.id == instance_id)
+            .ok_or(eyre!("instance '{}' is not running", instance_id))?;
+        self.set_instance(instance);
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl PortForwarderBuilder<Instance> {
+    /// Takes the running instance `instance` refers to. Several instances with its tag are
+    /// offered to pick from, and without any match every instance is.
+    pub async fn with_matching_instance(
+        mut self,
+        instance: &InstanceRef,
+    ) -> error::Result<PortForwarderBuilder<DestinationType>> {
+        let profile_name = self
+            .draft
+            .profile_name
+            .clone()
+            .ok_or(eyre!("profile name is not set"))?;
+        self.draft.events.on_step_started("instance");
+        let mut matching: Vec<InstanceSummary> = self
+            .instances
+            .running_instances(&profile_name, self.draft.region(&profile_name))
+            .await?
+            .into_iter()
+            .filter(|summary| instance.matches(summary))
+            .collect();
+        self.draft
+            .events
+            .on_options_fetched("instance", matching.len());
+        match matching.len() {
+            0 => return self.instance().await,
+            1 => self.set_instance(matching.remove(0)),
+            _ => {
+                let items = matching
+                    .iter()
+                    .map(|summary| {
+                        SelectItem::new(
+                            summary.id.clone(),
+                            format!("{} ({})", summary.name, summary.id),
+                        )
+                    })
+                    .collect();
+                let selected = self
+                    .selector
+                    .select_items(format!("Select SSM Target · {}", instance), items)?;
+                let position = matching
+                    .iter()
+                    .position(|summary| summary.id == selected.id)
+                    .ok_or(eyre!("unknown selection '{}'", selected.id))?;
+                self.set_instance(matching.remove(position));
+            }
+        }
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl PortForwarderBuilder<DestinationType> {
+    pub fn destination_type(mut self) -> error::Result<PortForwarderBuilder<Host>> {
+        if !self.draft.configured_destination {
+            self.select_service()?;
+        }
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    pub fn with_service(mut self, service: Service) -> PortForwarderBuilder<Host> {
+        self.set_service(service);
+        PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl PortForwarderBuilder<DestinationType> {
+    // for modes that only need a profile and an instance
+    pub fn build_for_instance(mut self) -> error::Result<Box<InstanceTarget>> {
+        self.confirm_production()?;
+        Ok(Box::new(self.draft.target()?))
+    }
+}
+
+// the host step, also asked again for tunnels added to a running session
+impl<S: BuilderState> PortForwarderBuilder<S> {
+    async fn select_destination(&mut self) -> error::Result<()> {
+        let profile_name = self
+            .draft
+            .profile_name
+            .clone()
+            .ok_or(eyre!("profile name is not set"))?;
+        let mut service = self
+            .draft
+            .service
+            .clone()
+            .ok_or(eyre!("destination type is empty"))?;
+        self.draft.events.on_step_started("host");
+        // looked up while the destinations are listed, a failure only leaves the hints unknown
+        let security_groups = self.draft.instance_vpc_id.clone().map(|vpc_id| {
+            tokio::spawn(reachability::security_groups(
+                profile_name.clone(),
+                self.draft.region(&profile_name).map(str::to_string),
+                vpc_id,
+            ))
+        });
+        let destinations = loop {
+            match self.list_destinations(&profile_name, &service).await {
+                Ok(destinations) => break destinations,
+                Err(PorwardError::Cancelled) => return Err(PorwardError::Cancelled),
+                Err(e) => match self.step_error(
+                    format!("Could not list {} destinations", service),
+                    &profile_name,
+                    &e,
+                )? {
+                    ErrorAction::Retry => {}
+                    ErrorAction::Back => service = self.select_service()?,
+                    ErrorAction::Quit => return Err(e),
+                },
+            }
+        };
+
+        let security_groups = match security_groups {
+            Some(task) => task.await.ok().and_then(|groups| groups.ok()),
+            None => None,
+        }
+        .unwrap_or_default();
+        let mut destinations = self.draft.filter_destinations(destinations);
+        self.draft
+            .events
+            .on_options_fetched("host", destinations.len());
+        let last_used = self
+            .draft
+            .state
+            .as_deref()
+            .and_then(|path| state::last_destination(path, &profile_name, &service))
+            .filter(|last| destinations.iter().any(|d| d.host == last.host));
+        // stopped or still creating destinations fail at connect time, so they come last
+        destinations.sort_by_key(|destination| !destination.usable());
+        // the list keeps its order, the selector starts on the last used destination
+        let initial = last_used
+            .as_ref()
+            .and_then(|last| destinations.iter().position(|d| d.host == last.host))
+            .unwrap_or(0);
+        let items: Vec<SelectItem> = destinations
+            .iter()
+            .map(|destination| {
+                let mut details = vec![];
+                if let Some(state) = destination.state.as_ref().filter(|_| !destination.usable()) {
+                    details.push(format!("({})", state));
+                }
+                if let Some(last) = last_used
+                    .as_ref()
+                    .filter(|last| last.host == destination.host)
+                {
+                    details.push(format!("last used {}", last.ago()));
+                }
+                // load balancers are listed by name, the dns name is still worth seeing
+                if destination.label != destination.host {
+                    details.push(destination.host.clone());
+                }
+                if destination.cluster_mode {
+                    details.push("cluster-mode".to_string());
+                }
+                if destination.tls {
+                    details.push("TLS".to_string());
+                }
+                if destination.member && self.draft.in_instance_zone(destination) == Some(true) {
+                    details.push("same zone".to_string());
+                }
+                // without an instance, e.g. with --destination-first, there is nothing to check
+                if self.draft.instance_id.is_some() && !destination.security_groups.is_empty() {
+                    details.push(
+                        reachability(
+                            &security_groups,
+                            &destination.security_groups,
+                            &self.draft.instance_security_groups,
+                            self.draft.instance_private_ip.as_deref(),
+                            destination.port.unwrap_or(service.default_port()),
+                        )
+                        .to_string(),
+                    );
+                }
+                if self.draft.in_instance_vpc(destination) == Some(false) {
+                    details.push(format!(
+                        "other vpc {}",
+                        destination.vpc_id.clone().unwrap_or_default()
+                    ));
+                }
+                let item = SelectItem::new(destination.host.clone(), destination.label.clone());
+                if details.is_empty() {
+                    item
+                } else {
+                    item.with_detail(details.join("  "))
+                }
+            })
+            .collect();
+        let mut selected = loop {
+            let selected = if service.multiple_hosts() {
+                self.selector.select_many_at(
+                    "Select Destination Hosts".into(),
+                    items.clone(),
+                    initial,
+                )?
+            } else {
+                vec![self.selector.select_items_at(
+                    "Select Destination Host".into(),
+                    items.clone(),
+                    initial,
+                )?]
+            };
+            if self
+                .confirm_unusable(&profile_name, &service, &mut destinations, &selected)
+                .await?
+            {
+                break selected.into_iter();
+            }
+        };
+        let destination = selected.next().ok_or(eyre!("no destination selected"))?;
+        // the api knows the real port for some services, e.g. a cache node on a custom port
+        if let Some(port) = destinations
+            .iter()
+            .find(|summary| summary.host == destination.id)
+            .and_then(|summary| summary.port)
+        {
+            self.draft.host_port = Some(port);
+            self.draft.local_port = Some(self.draft.local_ports.local_port(port));
+        }
+        self.draft.events.on_selection("host", &destination.id);
+        self.draft.host_name = Some(destination.id.clone());
+        #[cfg(feature = "alb")]
+        if matches!(service, Service::ApplicationLoadBalancer) {
+            if let Some(summary) = destinations
+                .iter()
+                .find(|summary| summary.host == destination.id)
+            {
+                self.select_target(&profile_name, &summary.label).await?;
+            }
+        }
+        self.edit_ports()?;
+        // the same check as for the other hosts, a reserved port is never forwarded
+        let first_local_port = self
+            .draft
+            .local_port
+            .ok_or(eyre!("local port is not set"))?;
+        let local_port = self.draft.unclaimed_local_port(first_local_port, &[])?;
+        if local_port != first_local_port {
+            self.draft.warnings.push(format!(
+                "local port {} is reserved, using {}",
+                first_local_port, local_port
+            ));
+            self.draft.local_port = Some(local_port);
+        }
+        if let Some(summary) = destinations
+            .iter()
+            .find(|summary| summary.host == destination.id)
+        {
+            self.draft.production_destination = self
+                .draft
+                .config
+                .is_production_destination(&[&summary.label, &summary.host], &summary.tags)
+                .then(|| summary.label.clone());
+            self.draft.destination_vpc_id = summary.vpc_id.clone();
+            self.draft.destination_zones = summary.zones.clone();
+            self.draft.warnings.extend(client_warnings(
+                summary,
+                self.draft.local_port.unwrap_or_default(),
+            ));
+        }
+        // the first local port + index, moved up past the ports of earlier hosts and reserved ones,
+        // whether something listens on them is checked when the forwarder is built
+        let mut taken = vec![local_port];
+        self.draft.additional_destinations.clear();
+        for (idx, item) in selected.enumerate() {
+            let summary = destinations.iter().find(|summary| summary.host == item.id);
+            let port = summary
+                .and_then(|summary| summary.port)
+                .or(self.draft.host_port)
+                .unwrap_or(service.default_port());
+            let local_port = self
+                .draft
+                .unclaimed_local_port(taken[0].saturating_add(idx as u16 + 1), &taken)?;
+            taken.push(local_port);
+            self.draft.events.on_selection("host", &item.id);
+            if let Some(summary) = summary {
+                if self.draft.production_destination.is_none() {
+                    self.draft.production_destination = self
+                        .draft
+                        .config
+                        .is_production_destination(&[&summary.label, &summary.host], &summary.tags)
+                        .then(|| summary.label.clone());
+                }
+                self.draft
+                    .warnings
+                    .extend(client_warnings(summary, local_port));
+            }
+            self.draft.additional_destinations.push((
+                Destination {
+                    host: item.id,
+                    port,
+                },
+                local_port,
+            ));
+        }
+        Ok(())
+    }
+
+    // a stopped aurora cluster can be started, anything else unusable is connected to anyway or
+    // another destination is selected, --yes connects anyway
+    async fn confirm_unusable(
+        &mut self,
+        profile_name: &str,
+        service: &Service,
+        destinations: &mut [DestinationSummary],
+        selected: &[SelectItem],
+    ) -> error::Result<bool> {
+        let Some(summary) = destinations
+            .iter()
+            .find(|summary| {
+                !summary.usable() && selected.iter().any(|item| item.id == summary.host)
+            })
+            .cloned()
+        else {
+            return Ok(true);
+        };
+        if self.draft.assume_yes {
+            return Ok(true);
+        }
+        let state = summary.state.clone().unwrap_or_default();
+        let mut options = vec![];
+        // only database endpoints know their cluster
+        if let Some(cluster_id) = summary.cluster_id.as_ref().filter(|_| state == "stopped") {
+            options.push(SelectItem::new(
+                "start",
+                format!(
+                    "Start cluster {} and wait until it is available",
+                    cluster_id
+                ),
+            ));
+        }
+        options.push(SelectItem::new("connect", "Connect anyway"));
+        options.push(SelectItem::new("other", "Select another destination"));
+        let answer = self
+            .selector
+            .select_items(format!("{} is {}", summary.label, state), options)?;
+        match answer.id.as_str() {
+            "start" => {
+                let cluster_id = summary.cluster_id.unwrap_or_default();
+                // the wait is printed below the selector, which is drawn again for the next step
+                self.selector.release();
+                events::say(&format!(
+                    "starting cluster {}, this takes a few minutes",
+                    cluster_id
+                ));
+                self.destinations
+                    .start_cluster(
+                        profile_name,
+                        self.draft.region(profile_name),
+                        service,
+                        &cluster_id,
+                    )
+                    .await?;
+                // its endpoints and members are available with it
+                for destination in destinations
+                    .iter_mut()
+                    .filter(|destination| destination.cluster_id.as_ref() == Some(&cluster_id))
+                {
+                    destination.state = Some("available".to_string());
+                }
+                Ok(true)
+            }
+            "connect" => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    // the load balancer can be skipped for one of its targets, e.g. to reach a single bad host
+    #[cfg(feature = "alb")]
+    async fn select_target(
+        &mut self,
+        profile_name: &str,
+        load_balancer: &str,
+    ) -> error::Result<()> {
+        let groups = match self
+            .destinations
+            .target_groups(profile_name, self.draft.region(profile_name), load_balancer)
+            .await
+        {
+            Ok(groups) => groups,
+            // the load balancer itself still works without elasticloadbalancing:DescribeTargetGroups
+            Err(e) => {
+                self.draft.warnings.push(format!(
+                    "could not list the target groups of {}: {}",
+                    load_balancer, e
+                ));
+                return Ok(());
+            }
+        };
+        if groups.is_empty() {
+            return Ok(());
+        }
+        self.draft.events.on_step_started("target-group");
+        self.draft
+            .events
+            .on_options_fetched("target-group", groups.len());
+        let items = std::iter::once(SelectItem::new("load-balancer", "The load balancer itself"))
+            .chain(groups.iter().map(|group| {
+                let detail = [
+                    group.protocol.clone(),
+                    group.port.map(|port| port.to_string()),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+                SelectItem::new(group.arn.clone(), group.name.clone()).with_detail(detail)
+            }))
+            .collect();
+        let selected = self.selector.select_items("Forward To".into(), items)?;
+        let Some(group) = groups.iter().find(|group| group.arn == selected.id) else {
+            return Ok(());
+        };
+        self.draft.events.on_selection("target-group", &group.name);
+
+        let title = "Select Target".to_string();
+        self.draft.events.on_step_started("target");
+        let targets = self
+            .destinations
+            .targets(profile_name, self.draft.region(profile_name), &group.arn)
+            .await?;
+        self.draft
+            .events
+            .on_options_fetched("target", targets.len());
+        if targets.is_empty() {
+            return Err(PorwardError::NoOptions { step: title });
+        }
+        let items = targets
+            .iter()
+            .enumerate()
+            .map(|(idx, target)| {
+                let health = match target.reason.as_ref() {
+                    Some(reason) => format!("{} — {}", target.state, reason),
+                    None => target.state.clone(),
+                };
+                let label = format!(
+                    "{}:{} ({})",
+                    target.host,
+                    target.port.or(group.port).unwrap_or_default(),
+                    health
+                );
+                let item = SelectItem::new(idx.to_string(), label);
+                // instance targets are shown by ip, the id is still worth seeing
+                if target.id != target.host {
+                    item.with_detail(target.id.clone())
+                } else {
+                    item
+                }
+            })
+            .collect();
+        let selected = self.selector.select_items(title, items)?;
+        let target = selected
+            .id
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| targets.get(idx))
+            .ok_or(eyre!("target is empty"))?;
+        let port = target
+            .port
+            .or(group.port)
+            .ok_or(eyre!("target {} has no port", target.id))?;
+        self.draft.events.on_selection("target", &target.host);
+        self.draft.host_name = Some(target.host.clone());
+        self.draft.host_port = Some(port);
+        self.draft.local_port = Some(self.draft.local_ports.local_port(port));
+        Ok(())
+    }
+
+    // clustered services pick the cluster first, a single cluster is picked without asking
+    async fn list_destinations(
+        &mut self,
+        profile_name: &str,
+        service: &Service,
+    ) -> error::Result<Vec<DestinationSummary>> {
+        let clusters: Vec<_> = self
+            .destinations
+            .clusters(profile_name, self.draft.region(profile_name), service)
+            .await?
+            .into_iter()
+            .filter(|cluster| self.draft.has_destination_tags(&cluster.tags))
+            .collect();
+        let cluster_id = match clusters.as_slice() {
+            [] => {
+                return self
+                    .destinations
+                    .destinations(profile_name, self.draft.region(profile_name), service)
+                    .await
+            }
+            [cluster] => cluster.id.clone(),
+            clusters => {
+                self.draft.events.on_step_started("cluster");
+                self.draft
+                    .events
+                    .on_options_fetched("cluster", clusters.len());
+                let items = clusters
+                    .iter()
+                    .map(|cluster| {
+                        // clusters that are not available are listed last with their state
+                        let state = cluster
+                            .state
+                            .as_ref()
+                            .filter(|_| !cluster.usable())
+                            .map(|state| format!("({})", state));
+                        let detail = [state, cluster.engine.clone(), cluster.version.clone()]
+                            .into_iter()
+                            .flatten()
+                            .chain(
+                                cluster
+                                    .tags
+                                    .iter()
+                                    .map(|(key, value)| format!("{}={}", key, value)),
+                            )
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        let item = SelectItem::new(cluster.id.clone(), cluster.id.clone());
+                        if detail.is_empty() {
+                            item
+                        } else {
+                            item.with_detail(detail)
+                        }
+                    })
+                    .collect();
+                self.selector
+                    .select_items("Select Cluster".into(), items)?
+                    .id
+            }
+        };
+        self.draft.events.on_selection("cluster", &cluster_id);
+        self.destinations
+            .cluster_destinations(
+                profile_name,
+                self.draft.region(profile_name),
+                service,
+                &cluster_id,
+            )
+            .await
+    }
+}
+
+impl PortForwarderBuilder<Host> {
+    pub async fn destination(mut self) -> error::Result<PortForwarderBuilder<Ready>> {
+        if !self.draft.configured_destination {
+            self.select_destination().await?;
+        }
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    pub fn with_destination(
+        mut self,
+        host_name: &str,
+        host_port: u16,
+    ) -> error::Result<PortForwarderBuilder<Ready>> {
+        if host_name.trim().is_empty() {
+            return Err(eyre!("host name is empty").into());
+        }
+        self.draft.host_name = Some(host_name.to_string());
+        self.draft.host_port = Some(validate_host_port(host_port)?);
+
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Offers `hosts` instead of the destinations listed from AWS, e.g. hosts piped in from other
+    /// tools. A single host is taken without asking.
+    pub fn with_candidate_hosts(
+        mut self,
+        hosts: Vec<Destination>,
+    ) -> error::Result<PortForwarderBuilder<Ready>> {
+        let title = "Select Destination Host".to_string();
+        self.draft.events.on_step_started("host");
+        self.draft.events.on_options_fetched("host", hosts.len());
+        let destination = match hosts.as_slice() {
+            [] => return Err(PorwardError::NoOptions { step: title }),
+            [host] => host.clone(),
+            _ => {
+                let items = hosts
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, host)| {
+                        SelectItem::new(idx.to_string(), format!("{}:{}", host.host, host.port))
+                    })
+                    .collect();
+                let selected = self.selector.select_items(title, items)?;
+                selected
+                    .id
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|idx| hosts.get(idx))
+                    .cloned()
+                    .ok_or(eyre!("unknown selection '{}'", selected.id))?
+            }
+        };
+        self.draft.events.on_selection("host", &destination.host);
+        self.draft.production_destination = self
+            .draft
+            .config
+            .is_production_destination(&[&destination.host], &Tags::new())
+            .then(|| destination.host.clone());
+        self.draft.local_port = Some(self.draft.local_ports.local_port(destination.port));
+        self.draft.host_port = Some(destination.port);
+        self.draft.host_name = Some(destination.host);
+        Ok(PortForwarderBuilder {
+            draft: self.draft,
+            selector: self.selector,
+            instances: self.instances,
+            destinations: self.destinations,
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+/// Parses `host[:port]` lines, skipping blank lines and `#` comments, with `default_port` for
+/// hosts without one. IPv6 addresses take a port as `[address]:port`, a bare one is only a host.
+pub fn parse_hosts(content: &str, default_port: u16) -> Result<Vec<Destination>> {
+    let mut hosts = vec![];
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (host, port) = match line.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']').ok_or(eyre!(
+                    "line {}: missing ']' in '{}'",
+                    idx + 1,
+                    line
+                ))?;
+                let port = match rest {
+                    "" => None,
+                    rest => Some(rest.strip_prefix(':').ok_or(eyre!(
+                        "line {}: expected ':<port>' after ']' in '{}'",
+                        idx + 1,
+                        line
+                    ))?),
+                };
+                (host, port)
+            }
+            None if line.matches(':').count() > 1 => (line, None),
+            None => match line.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (line, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| eyre!("line {}: invalid port '{}'", idx + 1, port))
+                .and_then(validate_host_port)?,
+            None => default_port,
+        };
+        if host.is_empty() {
+            return Err(eyre!("line {}: host is empty", idx + 1));
+        }
+        // the lines come from another tool's output, they end up in commands and /etc/hosts
+        let host = validate_host_name(host).map_err(|e| eyre!("line {}: {}", idx + 1, e))?;
+        hosts.push(Destination {
+            host: host.to_string(),
+            port,
+        });
+    }
+    Ok(hosts)
+}
+
+impl PortForwarderBuilder<Ready> {
+    // for builders that deferred the instance, the ones that can reach the destination come first
+    pub async fn ranked_instance(mut self) -> error::Result<PortForwarderBuilder<Ready>> {
+        self.select_instance(false).await?;
+        Ok(self)
+    }
+
+    /// Fails with [`PorwardError::AlreadyForwarded`] when the user keeps another porward
+    /// session that forwards the same local port.
+    pub async fn build(mut self) -> error::Result<Box<PortForwarder>> {
+        self.confirm_production()?;
+        self.resolve_local_port().await?;
+        let mut port_forwarder = self.draft.forwarder()?;
+        port_forwarder.resolve_eice().await?;
+        Ok(port_forwarder)
+    }
+
+    /// Like [`build`](Self::build), and keeps the selector and providers to add more tunnels
+    /// through the same profile and instance from the status view.
+    pub async fn build_with_adder(mut self) -> error::Result<(Box<PortForwarder>, TunnelAdder)> {
+        self.confirm_production()?;
+        self.resolve_local_port().await?;
+        let mut port_forwarder = self.draft.forwarder()?;
+        port_forwarder.resolve_eice().await?;
+        Ok((
+            port_forwarder,
+            TunnelAdder {
+                builder: PortForwarderBuilder {
+                    draft: self.draft,
+                    selector: self.selector,
+                    instances: self.instances,
+                    destinations: self.destinations,
+                    marker: std::marker::PhantomData,
+                },
+            },
+        ))
+    }
+}
+
+// how long a stopped porward gets to close its session and free the port
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+// free ports that another host of the same session already uses are skipped this often
+const FREE_PORT_ATTEMPTS: usize = 10;
+
+impl PortForwarderBuilder<Ready> {
+    // a local port another porward forwards is reused, taken over or replaced, --yes reuses it
+    async fn resolve_local_port(&mut self) -> error::Result<()> {
+        loop {
+            let local_port = self
+                .draft
+                .local_port
+                .ok_or(eyre!("local port is not set"))?;
+            if TcpListener::bind((Ipv4Addr::LOCALHOST, local_port)).is_ok() {
+                break;
+            }
+            let Some(session) = sessions::forwarding(local_port) else {
+                return Err(eyre!(
+                    "local port {} is in use, something else is listening on it",
+                    local_port
+                )
+                .into());
+            };
+            let title = format!(
+                "local port {} already forwarded to {} by pid {}, started {}",
+                local_port,
+                session.destination(),
+                session.pid,
+                session.ago()
+            );
+            let answer = if self.draft.assume_yes {
+                SelectItem::new("reuse", "Reuse")
+            } else {
+                self.selector.select_items(
+                    title,
+                    vec![
+                        SelectItem::new("reuse", "Reuse it and exit"),
+                        SelectItem::new(
+                            "stop",
+                            format!("Stop pid {} and start this one", session.pid),
+                        ),
+                        SelectItem::new("other", "Use another local port"),
+                    ],
+                )?
+            };
+            match answer.id.as_str() {
+                "reuse" => {
+                    return Err(PorwardError::AlreadyForwarded {
+                        mapping: session.mapping,
+                    })
+                }
+                "stop" => {
+                    if !sessions::stop(&session) {
+                        return Err(eyre!("could not stop pid {}", session.pid).into());
+                    }
+                    let started = Instant::now();
+                    while TcpListener::bind((Ipv4Addr::LOCALHOST, local_port)).is_err() {
+                        if started.elapsed() > STOP_TIMEOUT {
+                            return Err(eyre!(
+                                "pid {} still holds local port {}",
+                                session.pid,
+                                local_port
+                            )
+                            .into());
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+                _ => {
+                    // the next port that is neither reserved nor taken
+                    let host_port = self.draft.host_port.ok_or(eyre!("host port is not set"))?;
+                    let proposed = self.draft.local_ports.local_port(host_port);
+                    let local_port = self.port_input("Local port", proposed)?;
+                    self.draft
+                        .events
+                        .on_selection("local-port", &local_port.to_string());
+                    self.draft.local_port = Some(local_port);
+                }
+            }
+        }
+        // the other hosts take a free port instead of one something listens on by now, a port
+        // taken after this is reported when the session binds it
+        let mut taken: Vec<u16> = self.draft.local_port.into_iter().collect();
+        taken.extend(
+            self.draft
+                .additional_destinations
+                .iter()
+                .map(|(_, local_port)| *local_port),
+        );
+        for idx in 0..self.draft.additional_destinations.len() {
+            let local_port = self.draft.additional_destinations[idx].1;
+            if TcpListener::bind((Ipv4Addr::LOCALHOST, local_port)).is_ok() {
+                continue;
+            }
+            let free_port = self.draft.free_local_port(&taken)?;
+            self.draft.warnings.push(format!(
+                "local port {} is in use, using {}",
+                local_port, free_port
+            ));
+            self.draft.additional_destinations[idx].1 = free_port;
+            taken.push(free_port);
+        }
+        Ok(())
+    }
+}
+
+/// Asks for another destination through the profile and instance of a running session.
+pub struct TunnelAdder {
+    builder: PortForwarderBuilder<Instance>,
+}
+
+impl TunnelAdder {
+    // a local port taken by the running tunnels is replaced with a free one
+    pub(crate) async fn next(&mut self, ports_in_use: &[u16]) -> error::Result<Box<PortForwarder>> {
+        let builder = &mut self.builder;
+        builder.selector.release();
+        builder.draft.reset_destination();
+        builder.select_service()?;
+        builder.select_destination().await?;
+        builder.confirm_production()?;
+        let draft = &mut builder.draft;
+        let local_ports = draft.local_port.iter_mut().chain(
+            draft
+                .additional_destinations
+                .iter_mut()
+                .map(|(_, local_port)| local_port),
+        );
+        for local_port in local_ports.filter(|port| ports_in_use.contains(port)) {
+            let free_port = relay::free_local_port()?;
+            draft.warnings.push(format!(
+                "local port {} is already forwarded, using {}",
+                local_port, free_port
+            ));
+            *local_port = free_port;
+        }
+        let mut port_forwarder = draft.forwarder()?;
+        port_forwarder.resolve_eice().await?;
+        Ok(port_forwarder)
+    }
+}
+
+// windows instances often run agents from before port forwarding to remote hosts, which then
+// fails with a generic error
+fn agent_warning(instance: &InstanceSummary) -> Option<String> {
+    let windows = instance
+        .platform
+        .as_ref()
+        .is_some_and(|platform| platform.to_lowercase().contains("windows"));
+    let agent_version = instance.agent_version.as_ref()?;
+    // missing parts count as 0, versions that do not parse are given the benefit of the doubt
+    let outdated = match (
+        Version::parse(agent_version),
+        Version::parse(REMOTE_HOST_AGENT_VERSION),
+    ) {
+        (Some(version), Some(minimum)) => version < minimum,
+        _ => false,
+    };
+    (windows && outdated).then(|| {
+        format!(
+            "'{}' is a Windows instance with SSM agent {}, port forwarding to a remote host needs {} or later",
+            instance.id, agent_version, REMOTE_HOST_AGENT_VERSION
+        )
+    })
+}
+
+// a single forwarded port works for these, but only with a client that knows about it
+fn client_warnings(destination: &DestinationSummary, local_port: u16) -> Vec<String> {
+    let mut warnings = vec![];
+    if destination.cluster_mode {
+        warnings.push(format!(
+            "'{}' belongs to a cluster-mode replication group, redirects to other nodes will not go through the tunnel. Connect without cluster support, e.g. redis-cli without -c",
+            destination.host
+        ));
+    }
+    if destination.tls {
+        warnings.push(format!(
+            "'{}' requires TLS with the original host name, e.g. redis-cli --tls --sni {} -p {}",
+            destination.host, destination.host, local_port
+        ));
+    }
+    warnings
+}
+
+impl Draft {
+    // None leaves the region to the profile and the environment
+    fn region(&self, profile_name: &str) -> Option<&str> {
+        self.regions.get(profile_name).map(String::as_str)
+    }
+
+    fn forwarder(&self) -> error::Result<Box<PortForwarder>> {
+        let additional = self
+            .additional_destinations
+            .iter()
+            .map(|(destination, local_port)| {
+                Ok(PortForwarder {
+                    target: self.target()?,
+                    service: self.service.clone(),
+                    destination: destination.clone(),
+                    local_port: *local_port,
+                    method: self.method,
+                    document_name: self.document_name.clone(),
+                    extra_parameters: Parameters::new(),
+                    warnings: vec![],
+                    permissions: None,
+                    events: self.events.clone(),
+                    additional: vec![],
+                    eice: None,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(Box::new(PortForwarder {
+            target: self.target()?,
+            service: Some(
+                self.service
+                    .clone()
+                    .ok_or(eyre!("destination type is not set"))?,
+            ),
+            destination: Destination {
+                host: self
+                    .host_name
+                    .clone()
+                    .ok_or(eyre!("host name is not set"))?,
+                port: self.host_port.ok_or(eyre!("host port is not set"))?,
+            },
+            local_port: validate_local_port(
+                self.local_port.ok_or(eyre!("local port is not set"))?,
+            )?,
+            method: self.method,
+            document_name: self.document_name.clone(),
+            extra_parameters: Parameters::new(),
+            warnings: self.warnings.clone(),
+            permissions: self.permissions.clone(),
+            events: self.events.clone(),
+            additional,
+            eice: None,
+        }))
+    }
+
+    // everything the destination steps decide, before they are asked again
+    fn reset_destination(&mut self) {
+        self.configured_destination = false;
+        self.service = None;
+        self.host_name = None;
+        self.host_port = None;
+        self.local_port = None;
+        self.production_destination = None;
+        self.destination_vpc_id = None;
+        self.destination_zones.clear();
+        self.warnings.clear();
+        self.additional_destinations.clear();
+    }
+
+    // (vpc, zone), each 0 for a match with the destination, 1 when unknown and 2 otherwise
+    fn instance_rank(&self, instance: &InstanceSummary) -> (u8, u8) {
+        let vpc = match (self.destination_vpc_id.as_ref(), instance.vpc_id.as_ref()) {
+            (Some(destination), Some(instance)) if destination == instance => 0,
+            (Some(_), Some(_)) => 2,
+            _ => 1,
+        };
+        let zone = match instance.availability_zone.as_ref() {
+            Some(zone) if self.destination_zones.contains(zone) => 0,
+            Some(_) if !self.destination_zones.is_empty() => 2,
+            _ => 1,
+        };
+        (vpc, zone)
+    }
+
+    fn has_destination_tags(&self, tags: &Tags) -> bool {
+        self.destination_tags
+            .iter()
+            .all(|(key, value)| tags.get(key) == Some(value))
+    }
+
+    // None when either vpc is unknown, e.g. for cache clusters
+    fn in_instance_vpc(&self, destination: &DestinationSummary) -> Option<bool> {
+        Some(self.instance_vpc_id.as_ref()? == destination.vpc_id.as_ref()?)
+    }
+
+    // only destinations with every requested tag, the ones reachable from the instance first
+    fn filter_destinations(
+        &self,
+        destinations: Vec<DestinationSummary>,
+    ) -> Vec<DestinationSummary> {
+        let mut destinations: Vec<_> = destinations
+            .into_iter()
+            .filter(|destination| self.has_destination_tags(&destination.tags))
+            .filter(|destination| {
+                !self.same_vpc || self.in_instance_vpc(destination) != Some(false)
+            })
+            .collect();
+        // single cluster members in the instance's zone before those in other zones
+        destinations.sort_by_key(|destination| {
+            (
+                self.in_instance_vpc(destination) == Some(false),
+                destination.member && self.in_instance_zone(destination) == Some(false),
+            )
+        });
+        destinations
+    }
+
+    fn in_instance_zone(&self, destination: &DestinationSummary) -> Option<bool> {
+        Some(destination.zones.contains(self.instance_zone.as_ref()?))
+    }
+
+    // `port` or the next one up that is not reserved and no other host of the session uses
+    fn unclaimed_local_port(&self, port: u16, taken: &[u16]) -> error::Result<u16> {
+        (port..=u16::MAX)
+            .find(|port| !taken.contains(port) && !self.local_ports.reserved.contains(port))
+            .ok_or(eyre!("no local port left from {}", port).into())
+    }
+
+    // a free port of the configured range that no other host of the session uses
+    fn free_local_port(&self, taken: &[u16]) -> error::Result<u16> {
+        for _ in 0..FREE_PORT_ATTEMPTS {
+            let port = self.local_ports.free_port()?;
+            if !taken.contains(&port) {
+                return Ok(port);
+            }
+        }
+        Err(eyre!("no free local port left for {} hosts", taken.len() + 1).into())
+    }
+
+    fn session_profile_for(&self, profile_name: &str) -> error::Result<Option<String>> {
+        match &self.session_profile {
+            Some(session_profile) => Ok(Some(session_profile.clone())),
+            None => Ok(self.config.profile(profile_name).session_profile),
+        }
+    }
+
+    fn target(&self) -> error::Result<InstanceTarget> {
+        let profile_name = self
+            .profile_name
+            .clone()
+            .ok_or(eyre!("profile name is not set"))?;
+        Ok(InstanceTarget {
+            session_profile: self.session_profile_for(&profile_name)?,
+            region: self.regions.get(&profile_name).cloned().or(self
+                .accounts
+                .get(&profile_name)
+                .cloned()
+                .flatten()
+                .and_then(|account| account.region)),
+            profile_name,
+            instance_id: self
+                .instance_id
+                .clone()
+                .ok_or(eyre!("instance id is not set"))?,
+            instance_name: self.instance_name.clone(),
+            reason: self.reason.clone(),
+        })
+    }
+}
+
+impl PortForwarder {
+    pub fn builder(selector: Box<dyn StringListSelector>) -> PortForwarderBuilder {
+        Self::builder_with_providers(
+            selector,
+            Box::new(AwsInstanceProvider::default()),
+            Box::new(AwsDestinationProvider::default()),
+        )
+    }
+
+    pub fn builder_with_providers(
+        selector: Box<dyn StringListSelector>,
+        instances: Box<dyn InstanceProvider>,
+        destinations: Box<dyn DestinationProvider>,
+    ) -> PortForwarderBuilder {
+        PortForwarderBuilder {
+            draft: Draft::default(),
+            selector,
+            instances,
+            destinations,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn new(
+        profile_name: String,
+        instance_id: String,
+        service: Service,
+        host_name: String,
+        host_port: u16,
+        local_port: u16,
+    ) -> Result<Self> {
+        Ok(PortForwarder {
+            target: InstanceTarget {
+                profile_name,
+                session_profile: None,
+                instance_id,
+                instance_name: None,
+                reason: None,
+                region: None,
+            },
+            service: Some(service),
+            destination: Destination {
+                host: host_name,
+                port: validate_host_port(host_port)?,
+            },
+            local_port: validate_local_port(local_port)?,
+            method: Method::default(),
+            document_name: None,
+            extra_parameters: Parameters::new(),
+            warnings: vec![],
+            permissions: None,
+            events: Sink::default(),
+            additional: vec![],
+            eice: None,
+        })
+    }
+
+    /// Without `document_name`, the document of the profile's config or the default one is used.
+    pub fn with_document(mut self, document_name: Option<String>, parameters: Parameters) -> Self {
+        self.additional = self
+            .additional
+            .into_iter()
+            .map(|tunnel| tunnel.with_document(document_name.clone(), parameters.clone()))
+            .collect();
+        if document_name.is_some() {
+            self.document_name = document_name;
+        }
+        self.extra_parameters = parameters;
+        self
+    }
+
+    // max_sessions counts the live sessions of the profile across every running porward, --force
+    // starts one more with a warning
+    fn check_session_limit(&mut self, force: bool) -> error::Result<()> {
+        let profile_name = &self.target.profile_name;
+        let Some(max_sessions) = Config::load()?.max_sessions(profile_name) else {
+            return Ok(());
+        };
+        if !sessions::TRACKED {
+            self.warnings.push(format!(
+                "max_sessions = {} of profile '{}' is not enforced on this platform",
+                max_sessions, profile_name
+            ));
+            return Ok(());
+        }
+        let Some(live) = sessions::over_limit(profile_name, 1, max_sessions) else {
+            return Ok(());
+        };
+        if force {
+            self.warnings.push(format!(
+                "profile '{}' already has {} sessions, going past max_sessions = {} because of --force",
+                profile_name,
+                live.len(),
+                max_sessions
+            ));
+            return Ok(());
+        }
+        Err(eyre!(
+            "profile '{}' already has {} of at most {} sessions:\n{}\nclose one (q in its status panel or kill <pid>), or pass --force",
+            profile_name,
+            live.len(),
+            max_sessions,
+            sessions::listing(&live)
+        )
+        .into())
+    }
+
+    // counted and registered under the sessions lock, a porward started at the same time either
+    // sees this session or has taken the last one itself
+    fn register_session(
+        &mut self,
+        force: bool,
+        mapping: &str,
+        local_port: u16,
+    ) -> error::Result<Option<Registration>> {
+        let _lock = sessions::lock();
+        self.check_session_limit(force)?;
+        Ok(sessions::register(
+            &self.target.profile_name,
+            mapping,
+            local_port,
+        ))
+    }
+
+    // started next to this tunnel by the session
+    pub(crate) fn take_additional(&mut self) -> Vec<PortForwarder> {
+        std::mem::take(&mut self.additional)
+    }
+
+    // the parameters document in $EDITOR, opened again with the problem on top until it is valid
+    async fn edit_parameters(&mut self) -> error::Result<()> {
+        let mut content = serde_json::to_string_pretty(&self.parameters())?;
+        let mut problem = None;
+        loop {
+            let text = match problem {
+                Some(problem) => format!(
+                    "// {}\n// lines starting with // are ignored, save an empty file to cancel\n{}\n",
+                    problem, content
+                ),
+                None => format!("{}\n", content),
+            };
+            let edited = editor::edit("parameters.json", &text).await?;
+            content = edited
+                .lines()
+                .filter(|line| !line.trim_start().starts_with("//"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if content.trim().is_empty() {
+                return Err(PorwardError::Cancelled);
+            }
+            match self.apply_parameters(&content) {
+                Ok(()) => return Ok(()),
+                Err(e) => problem = Some(e),
+            }
+        }
+    }
+
+    // host and ports go back to the destination, so the relay and the summary use them too
+    fn apply_parameters(&mut self, content: &str) -> std::result::Result<(), String> {
+        let mut parameters: Parameters = serde_json::from_str(content).map_err(|e| {
+            format!(
+                "invalid parameters, expected an object of string arrays: {}",
+                e
+            )
+        })?;
+        let single = |parameters: &mut Parameters, key: &str| match parameters.remove(key) {
+            None => Ok(None),
+            Some(values) if values.len() == 1 => Ok(values.into_iter().next()),
+            Some(_) => Err(format!("'{}' must have exactly one value", key)),
+        };
+        let port = |value: Option<String>, key: &str| {
+            value
+                .map(|value| {
+                    value
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|port| *port > 0)
+                        .ok_or(format!("'{}' is not a port: '{}'", key, value))
+                })
+                .transpose()
+        };
+        let host = single(&mut parameters, "host")?;
+        let host_port = port(single(&mut parameters, "portNumber")?, "portNumber")?;
+        let local_port = port(
+            single(&mut parameters, "localPortNumber")?,
+            "localPortNumber",
+        )?;
+        if host.as_ref().is_some_and(|host| host.trim().is_empty()) {
+            return Err("'host' is empty".to_string());
+        }
+        if let Some(host) = host.as_deref() {
+            validate_host_name(host).map_err(|e| e.to_string())?;
+        }
+        if let Some(local_port) = local_port.filter(|port| *port != self.local_port) {
+            self.check_local_port(local_port)?;
+        }
+        if let Some(host) = host {
+            self.destination.host = host;
+        }
+        if let Some(host_port) = host_port {
+            self.destination.port = host_port;
+        }
+        if let Some(local_port) = local_port {
+            self.local_port = local_port;
+        }
+        self.extra_parameters = parameters;
+        Ok(())
+    }
+
+    // what resolve_local_port checked for the port it replaces, reported instead of asked about
+    fn check_local_port(&mut self, local_port: u16) -> std::result::Result<(), String> {
+        let local_ports = Config::load().map_err(|e| e.to_string())?.local_ports;
+        if local_ports.reserved.contains(&local_port) {
+            return Err(format!("local port {} is reserved", local_port));
+        }
+        if self
+            .additional
+            .iter()
+            .any(|tunnel| tunnel.local_port == local_port)
+        {
+            return Err(format!(
+                "local port {} is used by another host of this session",
+                local_port
+            ));
+        }
+        if TcpListener::bind((Ipv4Addr::LOCALHOST, local_port)).is_err() {
+            return Err(match sessions::forwarding(local_port) {
+                Some(session) => format!(
+                    "local port {} is already forwarded to {} by pid {}",
+                    local_port,
+                    session.destination(),
+                    session.pid
+                ),
+                None => format!(
+                    "local port {} is in use, something else is listening on it",
+                    local_port
+                ),
+            });
+        }
+        self.warnings.extend(local_ports.outside_range(local_port));
+        Ok(())
+    }
+
+    pub(crate) fn events(&self) -> &dyn EventSink {
+        &*self.events
+    }
+
+    pub fn with_session_profile(mut self, session_profile: Option<String>) -> Self {
+        self.target.session_profile = session_profile;
+        self
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.target.reason.as_deref()
+    }
+
+    pub fn service(&self) -> Option<&Service> {
+        self.service.as_ref()
+    }
+
+    pub fn destination(&self) -> &Destination {
+        &self.destination
+    }
+
+    pub fn target(&self) -> &InstanceTarget {
+        &self.target
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // how the sessions of max_sessions show a tunnel that listens on localhost
+    pub(crate) fn mapping(&self) -> String {
+        format!(
+            "localhost:{} → {}:{} via {}",
+            self.local_port,
+            self.destination.host,
+            self.destination.port,
+            self.target.label()
+        )
+    }
+
+    pub fn summary(&self) -> TunnelSummary {
+        TunnelSummary {
+            profile: self.target.profile_name.clone(),
+            session_profile: self.target.session_profile().to_string(),
+            instance_id: self.target.instance_id.clone(),
+            instance_name: self.target.instance_name.clone(),
+            service: self.service.clone(),
+            host: self.destination.host.clone(),
+            host_port: self.destination.port,
+            local_port: self.local_port,
+            document_name: self.document_name().to_string(),
+            uri: self.service.clone().map(|service| {
+                TunnelUri {
+                    profile: self.target.profile_name.clone(),
+                    instance: Some(InstanceRef::Id(self.target.instance_id.clone())),
+                    service,
+                    host: self.destination.host.clone(),
+                    port: self.destination.port,
+                }
+                .to_string()
+            }),
+        }
+    }
+
+    fn document_name(&self) -> &str {
+        self.document_name
+            .as_deref()
+            .unwrap_or(PORT_FORWARDING_DOCUMENT)
+    }
+
+    fn parameters(&self) -> Parameters {
+        let mut parameters = Parameters::from([
+            ("host".to_string(), vec![self.destination.host.clone()]),
+            (
+                "portNumber".to_string(),
+                vec![self.destination.port.to_string()],
+            ),
+            (
+                "localPortNumber".to_string(),
+                vec![self.local_port.to_string()],
+            ),
+        ]);
+        // explicitly configured parameters win over the computed ones
+        parameters.extend(self.extra_parameters.clone());
+        parameters
+    }
+
+    // the aws cli arguments without the program, which has to be looked up in PATH
+    pub fn command_args(&self) -> Result<Vec<String>> {
+        let mut args = self.target.shell_args();
+        args.extend([
+            "--document-name".to_string(),
+            self.document_name().to_string(),
+            "--parameters".to_string(),
+            serde_json::to_string(&self.parameters())?,
+        ]);
+        if let Some(reason) = self.target.reason.as_ref() {
+            args.extend(["--reason".to_string(), reason.clone()]);
+        }
+        Ok(args)
+    }
+
+    pub fn command(&self) -> Result<Command> {
+        let mut command = Command::new(prerequisites::executable(AWS_CLI)?);
+        command.args(self.command_args()?);
+        self.target.child_env(&mut command);
+        Ok(command)
+    }
+
+    /// Looks up the region of the profile for tunnels that were not selected interactively, so the
+    /// session starts where the instance was checked.
+    pub async fn with_resolved_region(mut self) -> Self {
+        self.target.resolve_region().await;
+        self
+    }
+
+    pub(crate) async fn start_session(&self) -> Result<StartedSession> {
+        let profile_name = self.target.session_profile();
+        let instance_id = &self.target.instance_id;
+        let parameters = self.parameters();
+        let config = load_region_config(profile_name, self.target.region.as_deref(), None).await;
+        let region = config
+            .region()
+            .map(|region| region.to_string())
+            .ok_or(eyre!("region is not set for profile '{}'", profile_name))?;
+        let output = client!(aws_sdk_ssm, &config, ssm)
+            .start_session()
+            .target(instance_id)
+            .document_name(self.document_name())
+            .set_parameters(Some(parameters.into_iter().collect()))
+            .set_reason(self.target.reason.clone())
+            .send()
+            .await
+            .map_err(|e| start_session_error(e, instance_id))?;
+        // the plugin talks to the same endpoint StartSession was called on
+        let endpoint = endpoints()
+            .ssm
+            .clone()
+            .or(config.endpoint_url().map(|endpoint| endpoint.to_string()))
+            .unwrap_or(format!("https://ssm.{}.amazonaws.com", region));
+        Ok(StartedSession {
+            region,
+            endpoint,
+            output,
+        })
+    }
+
+    // opens a tcp connection to the destination from the instance itself
+    pub async fn check_reachability(&self) -> Result<()> {
+        let instance_id = &self.target.instance_id;
+        let destination = format!("{}:{}", self.destination.host, self.destination.port);
+        let config = load_region_config(
+            self.target.session_profile(),
+            self.target.region.as_deref(),
+            None,
+        )
+        .await;
+        let client = client!(aws_sdk_ssm, &config, ssm);
+        // the script runs in a shell on the instance, so the host may only be a name or an ip
+        let script = format!(
+            "timeout 3 bash -c '</dev/tcp/{}/{}'",
+            validate_host_name(&self.destination.host)?,
+            validate_host_port(self.destination.port)?
+        );
+        let output = client
+            .send_command()
+            .instance_ids(instance_id)
+            .document_name(RUN_SHELL_DOCUMENT)
+            .parameters("commands", vec![script])
+            .send()
+            .await
+            .map_err(|e| {
+                eyre!(
+                    "could not run the reachability check on '{}', it needs ssm:SendCommand: {}",
+                    instance_id,
+                    DisplayErrorContext(&e)
+                )
+            })?;
+        let command_id = output
+            .command()
+            .and_then(|command| command.command_id())
+            .ok_or(eyre!("SendCommand did not return a command id"))?;
+        let started = Instant::now();
+        loop {
+            tokio::time::sleep(CHECK_POLL_INTERVAL).await;
+            if started.elapsed() > CHECK_TIMEOUT {
+                return Err(eyre!(
+                    "the reachability check of {} on '{}' did not finish within {}s",
+                    destination,
+                    instance_id,
+                    CHECK_TIMEOUT.as_secs()
+                ));
+            }
+            let invocation = match client
+                .get_command_invocation()
+                .command_id(command_id)
+                .instance_id(instance_id)
+                .send()
+                .await
+            {
+                Ok(invocation) => invocation,
+                // the invocation shows up shortly after the command
+                Err(e)
+                    if matches!(
+                        e.as_service_error(),
+                        Some(GetCommandInvocationError::InvocationDoesNotExist(_))
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(eyre!(DisplayErrorContext(&e).to_string())),
+            };
+            match invocation.status() {
+                Some(CommandInvocationStatus::Success) => return Ok(()),
+                Some(
+                    CommandInvocationStatus::Pending
+                    | CommandInvocationStatus::InProgress
+                    | CommandInvocationStatus::Delayed,
+                )
+                | None => {}
+                Some(_) => {
+                    return Err(eyre!(
+                        "{} is not reachable from instance '{}', check that the security groups of both allow the connection",
+                        destination,
+                        instance_id
+                    ))
+                }
+            }
+        }
+    }
+
+    pub async fn plugin_command(&self) -> Result<Command> {
+        Ok(self.plugin_session().await?.0)
+    }
+
+    // the command with the id StartSession returned
+    async fn plugin_session(&self) -> Result<(Command, Option<String>)> {
+        let parameters = self.parameters();
+        let session = self.start_session().await?;
+
+        // same arguments the aws cli passes to the plugin after calling StartSession itself
+        let mut command = Command::new(prerequisites::executable(SESSION_MANAGER_PLUGIN)?);
+        command
+            .arg(
+                serde_json::json!({
+                    "SessionId": session.output.session_id(),
+                    "TokenValue": session.output.token_value(),
+                    "StreamUrl": session.output.stream_url(),
+                })
+                .to_string(),
+            )
+            .arg(session.region)
+            .arg("StartSession")
+            // empty like the aws cli passes it without a profile
+            .arg(if credentials::is_passed(self.target.session_profile()) {
+                ""
+            } else {
+                self.target.session_profile()
+            })
+            .arg(
+                serde_json::json!({
+                    "Target": self.target.instance_id,
+                    "DocumentName": self.document_name(),
+                    "Parameters": parameters,
+                })
+                .to_string(),
+            )
+            .arg(session.endpoint);
+        self.target.child_env(&mut command);
+        Ok((command, session.output.session_id().map(str::to_string)))
+    }
+
+    pub async fn session_command(&self, aws_cli: bool) -> Result<Command> {
+        Ok(self.started_command(aws_cli).await?.0)
+    }
+
+    // the aws cli calls StartSession itself, its session id only shows up in the output
+    pub(crate) async fn started_command(&self, aws_cli: bool) -> Result<(Command, Option<String>)> {
+        if self.method == Method::Eice {
+            Ok((self.eice_command(&self.eice_route().await?)?, None))
+        } else if aws_cli {
+            Ok((self.command()?, None))
+        } else {
+            self.plugin_session().await
+        }
+    }
+
+    // once per built forwarder and its additional hosts, the display and every attempt reuse it
+    async fn resolve_eice(&mut self) -> Result<()> {
+        if self.method == Method::Eice && self.eice.is_none() {
+            self.eice = Some(self.lookup_eice().await?);
+        }
+        for additional in &mut self.additional {
+            if additional.method == Method::Eice && additional.eice.is_none() {
+                additional.eice = Some(additional.lookup_eice().await?);
+            }
+        }
+        Ok(())
+    }
+
+    // forwarders that were not built by the builder look the route up for every attempt
+    async fn eice_route(&self) -> Result<EiceRoute> {
+        match &self.eice {
+            Some(route) => Ok(route.clone()),
+            None => self.lookup_eice().await,
+        }
+    }
+
+    // the endpoint in the instance's vpc, to the instance itself or to the private ip the
+    // destination resolves to
+    async fn lookup_eice(&self) -> Result<EiceRoute> {
+        let session_profile = self.target.session_profile();
+        let config = load_region_config(session_profile, self.target.region.as_deref(), None).await;
+        let ec2 = client!(aws_sdk_ec2, &config, ec2);
+        let output = ec2
+            .describe_instances()
+            .instance_ids(&self.target.instance_id)
+            .send()
+            .await?;
+        let instance = output
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances())
+            .next()
+            .ok_or(eyre!(
+                "{} is not an EC2 instance, only those can be reached through an EC2 Instance Connect Endpoint",
+                self.target.instance_id
+            ))?;
+        let vpc_id = instance.vpc_id().ok_or(eyre!(
+            "instance {} is not in a vpc",
+            self.target.instance_id
+        ))?;
+        let output = ec2
+            .describe_instance_connect_endpoints()
+            .filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name("vpc-id")
+                    .values(vpc_id)
+                    .build(),
+            )
+            .send()
+            .await?;
+        let endpoint_id = output
+            .instance_connect_endpoints()
+            .iter()
+            .filter(|endpoint| {
+                endpoint.state().map(|state| state.as_str()) == Some("create-complete")
+            })
+            .find_map(|endpoint| endpoint.instance_connect_endpoint_id())
+            .ok_or(eyre!("vpc {} has no EC2 Instance Connect Endpoint", vpc_id))?;
+
+        let host = &self.destination.host;
+        let private_ip =
+            if host == "localhost" || instance.private_ip_address() == Some(host.as_str()) {
+                None
+            } else {
+                let address = tokio::net::lookup_host((host.as_str(), self.destination.port))
+                    .await?
+                    .find(|address| address.is_ipv4())
+                    .ok_or(eyre!("{} does not resolve to an ipv4 address", host))?;
+                Some(address.ip().to_string())
+            };
+        Ok(EiceRoute {
+            endpoint_id: endpoint_id.to_string(),
+            private_ip,
+        })
+    }
+
+    // aws ec2-instance-connect open-tunnel through the endpoint, with the current local port as
+    // the relay may have replaced it
+    fn eice_args(&self, route: &EiceRoute) -> Vec<String> {
+        let mut args = vec![
+            "ec2-instance-connect".to_string(),
+            "open-tunnel".to_string(),
+            "--instance-connect-endpoint-id".to_string(),
+            route.endpoint_id.clone(),
+        ];
+        match &route.private_ip {
+            Some(private_ip) => {
+                args.extend(["--private-ip-address".to_string(), private_ip.clone()])
+            }
+            None => args.extend(["--instance-id".to_string(), self.target.instance_id.clone()]),
+        }
+        args.extend([
+            "--remote-port".to_string(),
+            self.destination.port.to_string(),
+            "--local-port".to_string(),
+            self.local_port.to_string(),
+        ]);
+        // passed credentials take the place of the profile
+        let session_profile = self.target.session_profile();
+        if !credentials::is_passed(session_profile) {
+            args.extend(["--profile".to_string(), session_profile.to_string()]);
+        }
+        args
+    }
+
+    fn eice_command(&self, route: &EiceRoute) -> Result<Command> {
+        let mut command = Command::new(prerequisites::executable(AWS_CLI)?);
+        command.args(self.eice_args(route));
+        self.target.child_env(&mut command);
+        Ok(command)
+    }
+
+    // certificates and host based routing expect the original name, not localhost
+    #[cfg_attr(not(feature = "alb"), allow(unused_variables))]
+    fn https_hint(&self, etc_hosts: bool, local_port: u16) -> Option<String> {
+        #[cfg(feature = "alb")]
+        if matches!(self.service, Some(Service::ApplicationLoadBalancer)) {
+            let host = &self.destination.host;
+            return Some(if etc_hosts {
+                format!(
+                    "Connect with the original name: curl https://{}:{}/",
+                    host, local_port
+                )
+            } else {
+                format!(
+                    "Connect with the original name: curl --resolve {}:{}:127.0.0.1 https://{}:{}/",
+                    host, local_port, host, local_port
+                )
+            });
+        }
+        None
+    }
+
+    // only load balancers serve a web page, other services have no url to open
+    #[cfg_attr(not(feature = "alb"), allow(unused_variables))]
+    fn browser_url(&self, etc_hosts: bool, local_port: u16) -> Option<String> {
+        #[cfg(feature = "alb")]
+        if matches!(self.service, Some(Service::ApplicationLoadBalancer)) {
+            let scheme = if self.destination.port == 80 {
+                "http"
+            } else {
+                "https"
+            };
+            let host = if etc_hosts {
+                self.destination.host.as_str()
+            } else {
+                "localhost"
+            };
+            return Some(format!("{}://{}:{}/", scheme, host, local_port));
+        }
+        None
+    }
+
+    pub fn command_string(&self) -> Result<String> {
+        Ok(display_command(&self.command()?))
+    }
+
+    /// The command with the aws cli by name, for scripts that run where its path differs.
+    pub fn portable_command_string(&self) -> Result<String> {
+        Ok(std::iter::once(AWS_CLI.to_string())
+            .chain(self.command_args()?)
+            .map(|arg| shell_quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    pub async fn run(self, options: SessionOptions) -> error::Result<i32> {
+        self.run_with_adder(options, None).await
+    }
+
+    /// Runs the session, with `a` in the status view adding tunnels through the adder.
+    pub async fn run_with_adder(
+        mut self,
+        options: SessionOptions,
+        adder: Option<TunnelAdder>,
+    ) -> error::Result<i32> {
+        self.target.resolve_region().await;
+        if options.edit_parameters {
+            terminal::restore();
+            self.edit_parameters().await?;
+        }
+        let local_port = self.local_port;
+        if !options.bind.is_loopback() && !options.allow_exposed {
+            return Err(eyre!(
+                "binding to {} exposes the tunnel to the network, pass --i-know-this-is-exposed to continue",
+                options.bind
+            )
+            .into());
+        }
+        let session_profile = self.target.session_profile().to_string();
+        if options.pass_credentials || credentials::uses_credential_process(&session_profile).await
+        {
+            credentials::pass(&session_profile, self.target.region.as_deref()).await?;
+            self.warnings
+                .extend(credentials::expiry_warning(&session_profile));
+        }
+        #[cfg(feature = "native-tunnel")]
+        if options.native_tunnel && self.method == Method::Eice {
+            return Err(eyre!("--native-tunnel only works with the ssm method").into());
+        }
+        #[cfg(feature = "native-tunnel")]
+        if options.native_tunnel {
+            let mapping = format!(
+                "{} → {}:{} via {}",
+                SocketAddr::new(options.bind, local_port),
+                self.destination.host,
+                self.destination.port,
+                self.target.label()
+            );
+            let _registration = self.register_session(options.force, &mapping, local_port)?;
+            terminal::restore();
+            return crate::native::serve(
+                &self,
+                SocketAddr::new(options.bind, local_port),
+                &mapping,
+                &options.shutdown,
+            )
+            .await
+            .map(|_| 0)
+            .map_err(PorwardError::from);
+        }
+        // before the relay replaces the local port with the plugin's
+        let summary = self.summary();
+        let relay =
+            if options.bind.is_loopback() && !options.stats && options.idle_timeout.is_none() {
+                None
+            } else {
+                // the plugin listens on an internal port and the relay takes the requested one
+                let tunnel_port = relay::free_local_port()?;
+                self.local_port = tunnel_port;
+                Some(Relay::start(SocketAddr::new(options.bind, local_port), tunnel_port).await?)
+            };
+        let tunnel_port = self.local_port;
+
+        let instance_id = self.target.instance_id.clone();
+        let listen = match relay.as_ref() {
+            Some(relay) if !options.bind.is_loopback() => relay.address().to_string(),
+            _ => format!("localhost:{}", local_port),
+        };
+        // the instance the session runs on is a hop of its own, e.g. an app-tier host
+        let mapping = format!(
+            "{} → {}:{} via {}",
+            listen,
+            self.destination.host,
+            self.destination.port,
+            self.target.label()
+        );
+        let command = if self.method == Method::Eice {
+            display_command(&self.eice_command(&self.eice_route().await?)?)
+        } else if options.aws_cli {
+            self.command_string()?
+        } else {
+            format!(
+                "StartSession --profile {} --target {} --document-name {} --parameters '{}'",
+                self.target.session_profile(),
+                instance_id,
+                self.document_name(),
+                serde_json::to_string(&self.parameters())?
+            )
+        };
+        // counted by max_sessions until the session ends
+        let _registration = self.register_session(options.force, &mapping, local_port)?;
+        let log = options
+            .session_log
+            .as_ref()
+            .map(|dir| SessionLog::create(dir, local_port))
+            .transpose()?;
+        if let Some(log) = log.as_ref() {
+            for warning in self.warnings.iter() {
+                log.write(&format!("warning: {}", warning));
+            }
+            log.write(&format!("tunnel: {}", mapping));
+            log.write(&format!("instance: {}", instance_id));
+            log.write(&format!("command: {}", command));
+            if let Some(reason) = self.reason() {
+                log.write(&format!("reason: {}", reason));
+            }
+        }
+        terminal::restore();
+        if let Some(permissions) = self.permissions.as_ref() {
+            events::say(&permissions.replace('\n', "\r\n"));
+        }
+        for warning in self.warnings.iter() {
+            events::say(&format!("warning: {}\r", warning));
+        }
+        // removed again when the session ends
+        let _hosts_entry = options
+            .etc_hosts
+            .then(|| HostsEntry::add(&self.destination.host))
+            .transpose()?;
+        if let Some(hint) = self.https_hint(options.etc_hosts, local_port) {
+            events::say(&format!("{}\r", hint));
+        }
+        let url = options
+            .open
+            .then(|| self.browser_url(options.etc_hosts, local_port))
+            .flatten();
+        if let Some(url) = url.as_ref() {
+            if url.starts_with("https://localhost") {
+                events::say(&format!(
+                    "warning: the certificate is issued for {}, the browser will not trust it on localhost (use --etc-hosts to keep the name)\r",
+                    self.destination.host
+                ));
+            }
+            events::say(&format!("Opening {} once the tunnel is ready\r", url));
+        }
+        if options.check {
+            events::say(&format!(
+                "Checking {}:{} from {}\r",
+                self.destination.host, self.destination.port, instance_id
+            ));
+            self.check_reachability().await?;
+        }
+        if self.target.session_profile() != self.target.profile_name {
+            events::say(&format!(
+                "Resources listed with profile '{}', session started with profile '{}'\r",
+                self.target.profile_name,
+                self.target.session_profile()
+            ));
+        }
+        if let Some(uri) = summary.uri.as_ref() {
+            events::say(&format!("Share: {}\r", uri));
+        }
+        events::say(&format!("Running:\r\n{}", command));
+        self.events.on_session_event(&Event::Start(summary.clone()));
+        let additional = self.take_additional();
+        Session::new(&options, mapping, log, relay, url)
+            .with_adder(adder)
+            .with_tunnels(additional)
+            .run(&self, &summary, tunnel_port)
+            .await
+            .map_err(|e| match PorwardError::from(e) {
+                // the typed failures already explain themselves
+                PorwardError::Other(e) => PorwardError::Other(eyre!("{}\r\n{}", e, command)),
+                e => e,
+            })
+    }
+}
+
+impl InstanceTarget {
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn profile_name(&self) -> &str {
+        &self.profile_name
+    }
+
+    pub(crate) fn label(&self) -> String {
+        match self.instance_name.as_deref() {
+            Some(name) if !name.is_empty() => format!("{} ({})", name, self.instance_id),
+            _ => self.instance_id.clone(),
+        }
+    }
+
+    // the describe calls always use the selected profile
+    pub fn session_profile(&self) -> &str {
+        self.session_profile
+            .as_deref()
+            .unwrap_or(&self.profile_name)
+    }
+
+    // a tunnel to any host the instance can reach
+    pub fn forward_to(&self, destination: Destination, local_port: u16) -> PortForwarder {
+        PortForwarder {
+            target: self.clone(),
+            service: None,
+            destination,
+            local_port,
+            method: Method::default(),
+            document_name: None,
+            extra_parameters: Parameters::new(),
+            warnings: vec![],
+            permissions: None,
+            events: Sink::default(),
+            additional: vec![],
+            eice: None,
+        }
+    }
+
+    pub fn shell_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        // passed credentials take the place of the profile
+        if !credentials::is_passed(self.session_profile()) {
+            args.extend(["--profile".to_string(), self.session_profile().to_string()]);
+        }
+        args.extend([
+            "ssm".to_string(),
+            "start-session".to_string(),
+            "--target".to_string(),
+            self.instance_id.clone(),
+        ]);
+        if let Some(region) = self.region.as_ref() {
+            args.extend(["--region".to_string(), region.clone()]);
+        }
+        if let Some(endpoint_url) = endpoints().ssm.as_ref() {
+            args.extend(["--endpoint-url".to_string(), endpoint_url.clone()]);
+        }
+        args
+    }
+
+    // the profile's region, unless the listing already settled on one
+    pub(crate) async fn resolve_region(&mut self) {
+        if self.region.is_none() {
+            self.region = load_region_config(&self.profile_name, None, None)
+                .await
+                .region()
+                .map(|region| region.to_string());
+        }
+    }
+
+    // the region for the plugin, which does not take a --region argument, and the same proxy
+    // as the AWS calls
+    fn child_env(&self, command: &mut Command) {
+        if let Some(region) = self.region.as_ref() {
+            command.env("AWS_REGION", region);
+        }
+        proxy::proxy().apply(command);
+        credentials::apply(self.session_profile(), command);
+    }
+
+    pub fn shell_command(&self) -> Result<Command> {
+        let mut command = Command::new(prerequisites::executable(AWS_CLI)?);
+        command.args(self.shell_args());
+        self.child_env(&mut command);
+        Ok(command)
+    }
+
+    pub async fn shell(mut self) -> Result<i32> {
+        self.resolve_region().await;
+        let mut command = self.shell_command()?;
+        // the session needs the terminal as it is, not as the selector left it
+        terminal::restore();
+        println!("Running:\r\n{}", display_command(&command));
+        let status = command.status().await?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    pub fn ssh_proxy_command(&self) -> Result<String> {
+        // ssh substitutes %h and %p with the host and port it connects to
+        let mut proxy_command = format!(
+            "{} ssm start-session --target %h --document-name {} --parameters portNumber=%p --profile {}",
+            AWS_CLI,
+            SSH_DOCUMENT,
+            shell_quote(self.session_profile())
+        );
+        if let Some(region) = self.region.as_ref() {
+            proxy_command.push_str(&format!(" --region {}", shell_quote(region)));
+        }
+        if let Some(endpoint_url) = endpoints().ssm.as_ref() {
+            proxy_command.push_str(&format!(" --endpoint-url {}", shell_quote(endpoint_url)));
+        }
+        Ok(proxy_command)
+    }
+
+    /// The `~/.ssh/config` stanza that reaches the instance as `host`.
+    pub fn ssh_config(&self, host: &str, login_user: &str, port: u16) -> Result<String> {
+        Ok(format!(
+            "Host {}\n    HostName {}\n    User {}\n    Port {}\n    ProxyCommand {}\n",
+            host,
+            self.instance_id,
+            login_user,
+            port,
+            self.ssh_proxy_command()?
+        ))
+    }
+
+    pub async fn ssh(mut self, options: &SshOptions) -> Result<i32> {
+        self.resolve_region().await;
+        let instance_id = &self.instance_id;
+        let proxy_command = self.ssh_proxy_command()?;
+        let login_user = options
+            .login_user
+            .clone()
+            .unwrap_or(DEFAULT_LOGIN_USER.to_string());
+        let mut command = Command::new(prerequisites::executable("ssh")?);
+        command
+            .arg("-o")
+            .arg(format!("ProxyCommand={}", proxy_command))
+            .arg("-p")
+            .arg(options.port.to_string())
+            .arg(format!("{}@{}", login_user, instance_id));
+        // for the aws cli in the ProxyCommand
+        proxy::proxy().apply(&mut command);
+
+        terminal::restore();
+        if options.dry_run || options.login_user.is_none() {
+            println!("# ~/.ssh/config");
+            print!(
+                "{}",
+                self.ssh_config(instance_id, &login_user, options.port)?
+            );
+            if options.dry_run {
+                println!("\r\nRunning:\r\n{}", display_command(&command));
+            }
+            return Ok(0);
+        }
+        let status = command.status().await?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+// failures a new session can not fix are a SessionFailed, the others are retried by the session
+fn start_session_error(error: SdkError<StartSessionError>, instance_id: &str) -> Report {
+    let details = vec![DisplayErrorContext(&error).to_string()];
+    let failure = match error.as_service_error() {
+        Some(StartSessionError::TargetNotConnected(_)) => SessionFailure::TargetNotConnected,
+        Some(StartSessionError::InvalidDocument(_)) => SessionFailure::InvalidParameters,
+        Some(service_error) if service_error.code() == Some("AccessDeniedException") => {
+            SessionFailure::StartSessionDenied
+        }
+        _ => SessionFailure::classify(&details),
+    };
+    if !failure.is_permanent() {
+        return eyre!(failure.message(instance_id, &details));
+    }
+    PorwardError::SessionFailed {
+        status: "StartSession failed".to_string(),
+        message: failure.message(instance_id, &details),
+    }
+    .into()
+}
+
+fn display_command(command: &Command) -> String {
+    let command = command.as_std();
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,".contains(c))
+    {
+        arg.to_string()
+    } else if cfg!(windows) {
+        // powershell single quotes, which keep the json double quotes intact
+        format!("'{}'", arg.replace('\'', "''"))
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "rds")]
+    use crate::mock::PagedDestinationProvider;
+    use crate::{
+        mock::{
+            Answer, MockStringListSelector, Prompt, StaticDestinationProvider, StaticGroupProvider,
+            StaticInstanceProvider,
+        },
+        providers::{AutoScalingGroupSummary, InstanceSummary},
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn draft(profile_name: Option<&str>, instance_id: Option<&str>) -> Draft {
+        Draft {
+            profile_name: profile_name.map(str::to_string),
+            instance_id: instance_id.map(str::to_string),
+            ..Draft::default()
+        }
+    }
+
+    // a builder in any state, with the prompts the scripted selector is shown
+    fn scripted<S: BuilderState>(
+        draft: Draft,
+        answers: Vec<Answer>,
+    ) -> (PortForwarderBuilder<S>, Arc<Mutex<Vec<Prompt>>>) {
+        builder_with(
+            draft,
+            answers,
+            StaticInstanceProvider(vec![]),
+            StaticDestinationProvider(vec![]),
+        )
+    }
+
+    fn builder_with<S: BuilderState>(
+        draft: Draft,
+        answers: Vec<Answer>,
+        instances: impl InstanceProvider + 'static,
+        destinations: impl DestinationProvider + 'static,
+    ) -> (PortForwarderBuilder<S>, Arc<Mutex<Vec<Prompt>>>) {
+        let selector = MockStringListSelector::new(answers);
+        let prompts = selector.prompts();
+        let builder = PortForwarderBuilder {
+            draft,
+            selector: Box::new(selector),
+            instances: Box::new(instances),
+            destinations: Box::new(destinations),
+            marker: std::marker::PhantomData,
+        };
+        (builder, prompts)
+    }
+
+    #[cfg(any(feature = "elasticache", feature = "rds"))]
+    fn destination(host: &str, port: Option<u16>) -> DestinationSummary {
+        DestinationSummary {
+            host: host.to_string(),
+            label: host.to_string(),
+            port,
+            vpc_id: None,
+            zones: vec![],
+            tags: Tags::new(),
+            tls: false,
+            cluster_mode: false,
+            security_groups: vec![],
+            member: false,
+            state: None,
+            cluster_id: None,
+        }
+    }
+
+    fn error_message<T>(result: error::Result<T>) -> String {
+        result.err().expect("expected an error").to_string()
+    }
+
+    #[test]
+    fn destination_type_sets_the_default_ports_of_the_service() {
+        for service in Service::all() {
+            let (builder, prompts) = scripted::<DestinationType>(
+                draft(Some("dev"), Some("i-0123")),
+                vec![Answer::Label(service.to_string())],
+            );
+            let builder = builder.destination_type().ok().unwrap();
+            let port = service.default_port();
+            assert_eq!(
+                builder.draft.service.as_ref().map(Service::config_name),
+                Some(service.config_name())
+            );
+            assert_eq!(builder.draft.host_port, Some(port));
+            assert_eq!(
+                builder.draft.local_port,
+                Some(builder.draft.local_ports.local_port(port))
+            );
+            assert_eq!(
+                *prompts.lock().unwrap(),
+                vec![Prompt {
+                    title: "Select Destination Type".to_string(),
+                    options: Service::all().iter().map(Service::to_string).collect(),
+                    initial: 0,
+                }]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn instance_steps_fail_without_a_profile() {
+        let (builder, prompts) = scripted::<Instance>(draft(None, None), vec![]);
+        assert!(error_message(builder.with_instance("i-0123").await)
+            .contains("profile name is not set"));
+        let (builder, _) = scripted::<Instance>(draft(None, None), vec![]);
+        assert!(error_message(builder.instance().await).contains("profile name is not set"));
+        let (builder, _) = scripted::<Instance>(draft(None, None), vec![]);
+        let instance = InstanceRef::Id("i-0123".to_string());
+        assert!(
+            error_message(builder.with_matching_instance(&instance).await)
+                .contains("profile name is not set")
+        );
+        assert!(prompts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn building_fails_without_a_profile_or_an_instance() {
+        let (builder, _) = scripted::<DestinationType>(draft(None, Some("i-0123")), vec![]);
+        assert!(error_message(builder.build_for_instance()).contains("profile name is not set"));
+        let (builder, _) = scripted::<DestinationType>(draft(Some("dev"), None), vec![]);
+        assert!(error_message(builder.build_for_instance()).contains("instance id is not set"));
+    }
+
+    #[tokio::test]
+    async fn an_unknown_profile_is_an_error_without_a_prompt() {
+        let (builder, prompts) = scripted::<Profile>(Draft::default(), vec![]);
+        assert_eq!(
+            error_message(builder.with_profile("porward-test-missing").await),
+            "profile 'porward-test-missing' does not exist"
+        );
+        assert!(prompts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_instance_that_is_not_running_is_an_error() {
+        let (mut builder, prompts) = scripted::<Instance>(draft(Some("dev"), None), vec![]);
+        builder.instances = Box::new(StaticInstanceProvider(vec![InstanceSummary {
+            id: "i-0123".to_string(),
+            name: "web".to_string(),
+            vpc_id: None,
+            availability_zone: None,
+            private_ip: None,
+            launch_time: None,
+            tags: Tags::new(),
+            hybrid: false,
+            security_groups: vec![],
+            image_id: None,
+            spot: false,
+            platform: None,
+            agent_version: None,
+        }]));
+        assert_eq!(
+            error_message(builder.with_instance("i-4567").await),
+            "instance 'i-4567' is not running"
+        );
+        assert!(prompts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_single_matching_instance_is_taken_without_a_prompt() {
+        let instances = || {
+            let mut bastion = instance("i-0123", "web", 0);
+            bastion.tags.insert("Name".to_string(), "web".to_string());
+            bastion
+                .tags
+                .insert("role".to_string(), "bastion".to_string());
+            let mut other = instance("i-4567", "web", 0);
+            other.tags.insert("Name".to_string(), "web".to_string());
+            StaticInstanceProvider(vec![bastion, other])
+        };
+        let tag = |key: &str, value: &str| InstanceRef::Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let (mut builder, prompts) = scripted::<Instance>(draft(Some("dev"), None), vec![]);
+        builder.instances = Box::new(instances());
+        let builder = builder
+            .with_matching_instance(&tag("role", "bastion"))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(builder.draft.instance_id.as_deref(), Some("i-0123"));
+        assert!(prompts.lock().unwrap().is_empty());
+
+        // several matches are offered to pick from
+        let (mut builder, prompts) =
+            scripted::<Instance>(draft(Some("dev"), None), vec![Answer::Index(1)]);
+        builder.instances = Box::new(instances());
+        let builder = builder
+            .with_matching_instance(&tag("Name", "web"))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(builder.draft.instance_id.as_deref(), Some("i-4567"));
+        assert_eq!(
+            prompts.lock().unwrap()[0].options,
+            ["web (i-0123)", "web (i-4567)"]
+        );
+    }
+
+    fn instance(id: &str, name: &str, launch_time: i64) -> InstanceSummary {
+        InstanceSummary {
+            id: id.to_string(),
+            name: name.to_string(),
+            vpc_id: None,
+            availability_zone: None,
+            private_ip: None,
+            launch_time: Some(launch_time),
+            tags: Tags::new(),
+            hybrid: false,
+            security_groups: vec![],
+            image_id: None,
+            spot: false,
+            platform: None,
+            agent_version: None,
+        }
+    }
+
+    // the selections the builder reports, as "step=value"
+    #[derive(Default)]
+    struct Selections(Mutex<Vec<String>>);
+
+    impl EventSink for Selections {
+        fn on_selection(&self, step: &str, value: &str) {
+            self.0.lock().unwrap().push(format!("{}={}", step, value));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_given_instance_is_taken_like_a_selected_one() {
+        let mut given = instance("i-0123", "bastion", 100);
+        given.vpc_id = Some("vpc-1".to_string());
+        given.private_ip = Some("10.0.0.5".to_string());
+        given.security_groups = vec!["sg-1".to_string()];
+        given.platform = Some("Windows".to_string());
+        given.agent_version = Some("3.0.0.0".to_string());
+        let selections = Arc::new(Selections::default());
+        let mut draft = draft(Some("dev"), None);
+        draft.events = Sink::new(selections.clone());
+        let (mut builder, _) = scripted::<Instance>(draft, vec![]);
+        builder.instances = Box::new(StaticInstanceProvider(vec![given]));
+        let builder = builder.with_instance("i-0123").await.ok().unwrap();
+        assert_eq!(builder.draft.instance_id.as_deref(), Some("i-0123"));
+        assert_eq!(builder.draft.instance_vpc_id.as_deref(), Some("vpc-1"));
+        assert_eq!(builder.draft.instance_name.as_deref(), Some("bastion"));
+        assert_eq!(
+            builder.draft.instance_private_ip.as_deref(),
+            Some("10.0.0.5")
+        );
+        assert_eq!(builder.draft.instance_security_groups, vec!["sg-1"]);
+        assert_eq!(builder.draft.warnings.len(), 1);
+        assert_eq!(*selections.0.lock().unwrap(), vec!["instance=i-0123"]);
+    }
+
+    // web has two healthy instances and a newer one that is not in service, drained has none
+    fn groups() -> StaticGroupProvider {
+        StaticGroupProvider {
+            groups: vec![
+                AutoScalingGroupSummary {
+                    name: "web".to_string(),
+                    instance_ids: vec!["i-old".to_string(), "i-new".to_string()],
+                },
+                AutoScalingGroupSummary {
+                    name: "drained".to_string(),
+                    instance_ids: vec!["i-gone".to_string()],
+                },
+            ],
+            instances: vec![
+                instance("i-old", "web", 100),
+                instance("i-new", "web", 200),
+                instance("i-launching", "web", 300),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn group_instances_are_offered_newest_first() {
+        let mut draft = draft(Some("dev"), None);
+        draft.auto_scaling_group = true;
+        let (mut builder, prompts) = scripted::<Instance>(
+            draft,
+            vec![Answer::Label("web".to_string()), Answer::Index(0)],
+        );
+        builder.instances = Box::new(groups());
+        builder.select_instance(false).await.ok().unwrap();
+        assert_eq!(builder.draft.instance_id.as_deref(), Some("i-new"));
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts[0].options, ["web", "drained"]);
+        assert_eq!(prompts[1].title, "Select Instance of web");
+        assert_eq!(prompts[1].options, ["web (i-new)", "web (i-old)"]);
+    }
+
+    #[tokio::test]
+    async fn a_group_without_healthy_instances_is_an_error() {
+        let mut draft = draft(Some("dev"), None);
+        draft.auto_scaling_group = true;
+        let (mut builder, prompts) = scripted::<Instance>(
+            draft,
+            vec![
+                Answer::Label("drained".to_string()),
+                Answer::Action(ErrorAction::Quit),
+            ],
+        );
+        builder.instances = Box::new(groups());
+        assert!(matches!(
+            builder.select_instance(false).await,
+            Err(PorwardError::NoOptions { .. })
+        ));
+        assert_eq!(
+            prompts.lock().unwrap()[1].title,
+            "'drained' has no running instance"
+        );
+    }
+
+    #[test]
+    fn a_given_service_and_destination_skip_their_prompts() {
+        let service = Service::all().remove(0);
+        let (builder, prompts) =
+            scripted::<DestinationType>(draft(Some("dev"), Some("i-0123")), vec![]);
+        let builder = builder
+            .with_service(service.clone())
+            .with_destination("db.internal", 6543)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            builder.draft.service.as_ref().map(Service::config_name),
+            Some(service.config_name())
+        );
+        assert_eq!(builder.draft.host_name.as_deref(), Some("db.internal"));
+        assert_eq!(builder.draft.host_port, Some(6543));
+        assert!(builder.draft.local_port.is_some());
+        assert!(prompts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_empty_host_or_host_port_zero_is_an_error() {
+        let destination = |host: &str, port: u16| {
+            let (builder, _) = scripted::<Host>(draft(Some("dev"), Some("i-0123")), vec![]);
+            error_message(builder.with_destination(host, port))
+        };
+        assert_eq!(destination(" ", 5432), "host name is empty");
+        assert_eq!(destination("db.internal", 0), "invalid host port '0'");
+    }
+
+    #[test]
+    fn a_single_candidate_host_is_taken_without_a_prompt() {
+        let host = |host: &str, port: u16| Destination {
+            host: host.to_string(),
+            port,
+        };
+        let (builder, prompts) = scripted::<Host>(draft(Some("dev"), Some("i-0123")), vec![]);
+        let builder = builder
+            .with_candidate_hosts(vec![host("10.0.0.5", 8080)])
+            .ok()
+            .unwrap();
+        assert_eq!(builder.draft.host_name.as_deref(), Some("10.0.0.5"));
+        assert_eq!(builder.draft.host_port, Some(8080));
+        assert!(prompts.lock().unwrap().is_empty());
+
+        let (builder, prompts) =
+            scripted::<Host>(draft(Some("dev"), Some("i-0123")), vec![Answer::Index(1)]);
+        let builder = builder
+            .with_candidate_hosts(vec![host("10.0.0.5", 8080), host("10.0.0.6", 9090)])
+            .ok()
+            .unwrap();
+        assert_eq!(builder.draft.host_name.as_deref(), Some("10.0.0.6"));
+        assert_eq!(builder.draft.host_port, Some(9090));
+        assert_eq!(
+            prompts.lock().unwrap()[0].options,
+            ["10.0.0.5:8080", "10.0.0.6:9090"]
+        );
+
+        let (builder, _) = scripted::<Host>(draft(Some("dev"), Some("i-0123")), vec![]);
+        assert!(matches!(
+            builder.with_candidate_hosts(vec![]),
+            Err(PorwardError::NoOptions { .. })
+        ));
+    }
+
+    // every builder event, in order
+    #[derive(Default)]
+    struct Events(Mutex<Vec<String>>);
+
+    impl EventSink for Events {
+        fn on_step_started(&self, step: &str) {
+            self.0.lock().unwrap().push(format!("{} started", step));
+        }
+
+        fn on_options_fetched(&self, step: &str, count: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} fetched {}", step, count));
+        }
+
+        fn on_selection(&self, step: &str, value: &str) {
+            self.0.lock().unwrap().push(format!("{}={}", step, value));
+        }
+    }
+
+    // profiles as the sdk reads them from a fabricated aws config file
+    async fn profiles(config: &str, vars: &[(&str, &str)]) -> (ProfileSet, Env) {
+        let env = Env::from_slice(&[&[("AWS_CONFIG_FILE", "config")][..], vars].concat());
+        let fs = Fs::from_slice(&[("config", config)]);
+        (load_profiles(&fs, &env).await.ok().unwrap(), env)
+    }
+
+    const CONFIG: &str = "[profile dev]\nregion = eu-west-1\n\n[profile ops]\noutput = json\n";
+
+    #[tokio::test]
+    async fn a_profile_without_a_region_prompts_for_one() {
+        let (profiles, env) = profiles(CONFIG, &[]).await;
+        assert_eq!(
+            profiles.profiles().collect::<BTreeSet<_>>(),
+            BTreeSet::from(["dev", "ops"])
+        );
+        let events = Arc::new(Events::default());
+        let mut draft = draft(None, None);
+        draft.events = Sink::new(events.clone());
+        let (mut builder, prompts) =
+            scripted::<Profile>(draft, vec![Answer::Label("us-west-2".to_string())]);
+        assert_eq!(
+            builder
+                .missing_region(&profiles, &env, "dev", true)
+                .ok()
+                .unwrap(),
+            None
+        );
+        assert!(prompts.lock().unwrap().is_empty());
+        assert_eq!(
+            builder
+                .missing_region(&profiles, &env, "ops", true)
+                .ok()
+                .unwrap(),
+            Some("us-west-2".to_string())
+        );
+        assert_eq!(
+            *prompts.lock().unwrap(),
+            vec![Prompt {
+                title: "Select Region, 'ops' has none".to_string(),
+                options: REGIONS.iter().map(|region| region.to_string()).collect(),
+                initial: 0,
+            }]
+        );
+        assert_eq!(*events.0.lock().unwrap(), ["region=us-west-2".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn a_region_from_the_environment_skips_the_prompt() {
+        for var in ["AWS_REGION", "AWS_DEFAULT_REGION"] {
+            let (profiles, env) = profiles(CONFIG, &[(var, "eu-central-1")]).await;
+            let (mut builder, prompts) = scripted::<Profile>(draft(None, None), vec![]);
+            assert_eq!(
+                builder
+                    .missing_region(&profiles, &env, "ops", true)
+                    .ok()
+                    .unwrap(),
+                None
+            );
+            assert!(prompts.lock().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_missing_region_is_an_error_without_prompts() {
+        let (profiles, env) = profiles(CONFIG, &[]).await;
+        let (mut builder, _) = scripted::<Profile>(draft(None, None), vec![]);
+        let message = error_message(builder.missing_region(&profiles, &env, "ops", false));
+        assert!(message.contains("profile 'ops' has no region"));
+        assert!(message.contains("AWS_REGION"));
+    }
+
+    #[test]
+    fn a_picked_region_only_applies_to_its_profile() {
+        let mut draft = draft(Some("dev"), Some("i-0123"));
+        draft
+            .regions
+            .insert("ops".to_string(), "eu-west-1".to_string());
+        assert_eq!(draft.region("dev"), None);
+        assert_eq!(draft.target().ok().unwrap().region, None);
+        draft.profile_name = Some("ops".to_string());
+        assert_eq!(draft.region("ops"), Some("eu-west-1"));
+        assert_eq!(
+            draft.target().ok().unwrap().region,
+            Some("eu-west-1".to_string())
+        );
+    }
+
+    #[cfg(feature = "rds")]
+    #[tokio::test]
+    async fn a_wizard_run_reports_each_step_in_order() {
+        let events = Arc::new(Events::default());
+        let mut draft = draft(Some("dev"), None);
+        draft.events = Sink::new(events.clone());
+        let (mut builder, prompts) = scripted::<Instance>(
+            draft,
+            vec![
+                Answer::Index(0),
+                Answer::Label(Service::Postgresql.to_string()),
+                Answer::Index(0),
+                Answer::Text(String::new()),
+                Answer::Text("20000".to_string()),
+            ],
+        );
+        builder.instances = Box::new(StaticInstanceProvider(vec![instance(
+            "i-0123", "bastion", 0,
+        )]));
+        builder.destinations = Box::new(StaticDestinationProvider(vec![destination(
+            "db.internal",
+            None,
+        )]));
+        builder
+            .instance()
+            .await
+            .ok()
+            .unwrap()
+            .destination_type()
+            .ok()
+            .unwrap()
+            .destination()
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                "instance started".to_string(),
+                "instance fetched 1".to_string(),
+                "instance=i-0123".to_string(),
+                "destination-type started".to_string(),
+                format!(
+                    "destination-type fetched {}",
+                    prompts.lock().unwrap()[1].options.len()
+                ),
+                format!("destination-type={}", Service::Postgresql),
+                "host started".to_string(),
+                "host fetched 1".to_string(),
+                "host=db.internal".to_string(),
+                "host-port=5432".to_string(),
+                "local-port=20000".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn hosts_are_parsed_with_their_ports() {
+        let hosts = parse_hosts(
+            "db.internal:5433\n# replicas\n\n10.0.0.5  # primary\nfd00::1\n[fd00::2]:6379\n[fd00::3]\n",
+            5432,
+        )
+        .unwrap();
+        let destination = |host: &str, port| Destination {
+            host: host.to_string(),
+            port,
+        };
+        assert_eq!(
+            hosts,
+            vec![
+                destination("db.internal", 5433),
+                destination("10.0.0.5", 5432),
+                destination("fd00::1", 5432),
+                destination("fd00::2", 6379),
+                destination("fd00::3", 5432),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_host_lines_are_rejected() {
+        for (content, problem) in [
+            ("db.internal:0", "invalid host port '0'"),
+            ("db.internal:pg", "line 1: invalid port 'pg'"),
+            (":5432", "line 1: host is empty"),
+            (
+                "db.internal\n$(id):5432",
+                "line 2: invalid host name '$(id)'",
+            ),
+            ("fd00::1]:5432", "line 1: invalid host name 'fd00::1]:5432'"),
+            ("[fd00::1:5432", "line 1: missing ']'"),
+            ("[fd00::1]5432", "line 1: expected ':<port>' after ']'"),
+        ] {
+            let message = parse_hosts(content, 5432).unwrap_err().to_string();
+            assert!(message.contains(problem), "{}: {}", content, message);
+        }
+    }
+
+    #[test]
+    fn edited_parameters_are_checked_like_selected_ones() {
+        let mut port_forwarder = PortForwarder::new(
+            "dev".to_string(),
+            "i-0123".to_string(),
+            Service::all().remove(0),
+            "db.internal".to_string(),
+            5432,
+            relay::free_local_port().unwrap(),
+        )
+        .unwrap();
+        let problem = port_forwarder
+            .apply_parameters(r#"{"host": ["db.internal; id"]}"#)
+            .unwrap_err();
+        assert!(problem.contains("invalid host name"), "{}", problem);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        let problem = port_forwarder
+            .apply_parameters(&format!(r#"{{"localPortNumber": ["{}"]}}"#, taken))
+            .unwrap_err();
+        assert!(problem.contains("in use"), "{}", problem);
+        let local_port = relay::free_local_port().unwrap();
+        port_forwarder
+            .apply_parameters(&format!(
+                r#"{{"host": ["db2.internal"], "localPortNumber": ["{}"]}}"#,
+                local_port
+            ))
+            .unwrap();
+        assert_eq!(port_forwarder.local_port(), local_port);
+        assert_eq!(port_forwarder.destination().host, "db2.internal");
+    }
+
+    #[cfg(feature = "rds")]
+    #[tokio::test]
+    async fn a_ready_builder_builds_the_aws_cli_command() {
+        let local_port = relay::free_local_port().unwrap();
+        let draft = Draft {
+            service: Some(Service::Postgresql),
+            host_name: Some("db.internal".to_string()),
+            host_port: Some(5432),
+            local_port: Some(local_port),
+            ..draft(Some("dev"), Some("i-0123"))
+        };
+        let (builder, prompts) = scripted::<Ready>(draft, vec![]);
+        let port_forwarder = builder.build().await.ok().unwrap();
+        assert_eq!(
+            port_forwarder.command_args().unwrap(),
+            vec![
+                "--profile".to_string(),
+                "dev".to_string(),
+                "ssm".to_string(),
+                "start-session".to_string(),
+                "--target".to_string(),
+                "i-0123".to_string(),
+                "--document-name".to_string(),
+                PORT_FORWARDING_DOCUMENT.to_string(),
+                "--parameters".to_string(),
+                format!(
+                    r#"{{"host":["db.internal"],"localPortNumber":["{}"],"portNumber":["5432"]}}"#,
+                    local_port
+                ),
+            ]
+        );
+        assert!(prompts.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "elasticache")]
+    #[tokio::test]
+    async fn selected_hosts_get_distinct_unreserved_local_ports() {
+        let (mut builder, _) = builder_with::<Host>(
+            Draft {
+                profile_name: Some("dev".to_string()),
+                service: Some(Service::Redis),
+                local_ports: LocalPorts {
+                    reserved: vec![20000, 20002],
+                    ..LocalPorts::default()
+                },
+                ..Draft::default()
+            },
+            vec![
+                Answer::Indices(vec![0, 1, 2]),
+                Answer::Text(String::new()),
+                Answer::Text("20000".to_string()),
+            ],
+            StaticInstanceProvider(vec![]),
+            StaticDestinationProvider(
+                ["a.cache.internal", "b.cache.internal", "c.cache.internal"]
+                    .into_iter()
+                    .map(|host| destination(host, Some(6379)))
+                    .collect(),
+            ),
+        );
+        builder.select_destination().await.ok().unwrap();
+        let draft = &builder.draft;
+        // the reserved port the first host was given is replaced too
+        assert_eq!(draft.local_port, Some(20001));
+        assert!(draft
+            .warnings
+            .contains(&"local port 20000 is reserved, using 20001".to_string()));
+        let additional: Vec<u16> = draft
+            .additional_destinations
+            .iter()
+            .map(|(destination, local_port)| {
+                assert_eq!(destination.port, 6379);
+                *local_port
+            })
+            .collect();
+        assert_eq!(additional, [20003, 20004]);
+    }
+
+    fn configured(local_port: &str, assume_yes: bool) -> Draft {
+        let mut draft = draft(Some("dev"), Some("i-0123"));
+        draft.assume_yes = assume_yes;
+        draft.config = toml::from_str(&format!(
+            "[profiles.dev]\nservice = '{}'\nhost = 'db.internal'\n{}",
+            Service::all().remove(0).config_name(),
+            local_port
+        ))
+        .unwrap();
+        draft
+    }
+
+    #[test]
+    fn a_configured_local_port_wins_over_the_derived_one() {
+        let host_port = Service::all().remove(0).default_port();
+        let (builder, _) = scripted::<Instance>(configured("local_port = 20000", true), vec![]);
+        let draft = builder.configured_destination().ok().unwrap().draft;
+        assert_eq!(draft.host_port, Some(host_port));
+        assert_eq!(draft.local_port, Some(20000));
+        let (builder, _) = scripted::<Instance>(configured("", true), vec![]);
+        let draft = builder.configured_destination().ok().unwrap().draft;
+        assert_eq!(
+            draft.local_port,
+            Some(draft.local_ports.local_port(host_port))
+        );
+    }
+
+    #[test]
+    fn a_typed_local_port_wins_over_the_configured_one() {
+        let (builder, prompts) = scripted::<Instance>(
+            configured("local_port = 20000", false),
+            vec![
+                Answer::Label("Edit".to_string()),
+                Answer::Text(String::new()),
+                Answer::Text(String::new()),
+                Answer::Text("21000".to_string()),
+            ],
+        );
+        let draft = builder.configured_destination().ok().unwrap().draft;
+        assert_eq!(draft.local_port, Some(21000));
+        assert_eq!(
+            prompts.lock().unwrap()[3].title,
+            "Local port [20000], Enter to keep"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_local_port_something_else_listens_on_is_an_error() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut draft = draft(Some("dev"), Some("i-0123"));
+        draft.local_port = Some(port);
+        let (mut builder, _) = scripted::<Ready>(draft, vec![]);
+        assert_eq!(
+            error_message(builder.resolve_local_port().await),
+            format!(
+                "local port {} is in use, something else is listening on it",
+                port
+            )
+        );
+        drop(listener);
+        builder.resolve_local_port().await.ok().unwrap();
+        assert_eq!(builder.draft.local_port, Some(port));
+    }
+
+    #[test]
+    fn host_names_and_ips_are_valid() {
+        for host in [
+            "localhost",
+            "db.cluster-abc.eu-west-1.rds.amazonaws.com",
+            "10.0.1.20",
+            "::1",
+            "fd00::1:2",
+        ] {
+            assert_eq!(validate_host_name(host).unwrap(), host);
+        }
+    }
+
+    #[test]
+    fn host_names_with_shell_or_hosts_syntax_are_rejected() {
+        for host in [
+            "",
+            "$(reboot)",
+            "`id`",
+            "db;id",
+            "db'",
+            "db host",
+            "db\n1.2.3.4 bank.example.com",
+            "-oProxyCommand",
+            ".db",
+            &"a".repeat(MAX_HOST_NAME_LEN + 1),
+        ] {
+            assert!(validate_host_name(host).is_err(), "{:?}", host);
+        }
+    }
+
+    #[cfg(feature = "rds")]
+    #[tokio::test]
+    async fn destinations_of_every_page_are_offered() {
+        let draft = Draft {
+            service: Some(Service::Postgresql),
+            host_port: Some(5432),
+            ..draft(Some("dev"), Some("i-0123"))
+        };
+        let (mut builder, prompts) = scripted::<Host>(
+            draft,
+            vec![
+                Answer::Label("c.internal".to_string()),
+                Answer::Text(String::new()),
+                Answer::Text(String::new()),
+            ],
+        );
+        builder.destinations = Box::new(PagedDestinationProvider(vec![
+            vec![destination("a.internal", None)],
+            vec![
+                destination("b.internal", None),
+                destination("c.internal", None),
+            ],
+        ]));
+        let builder = builder.destination().await.ok().unwrap();
+        assert_eq!(builder.draft.host_name.as_deref(), Some("c.internal"));
+        assert_eq!(
+            prompts.lock().unwrap()[0].options,
+            ["a.internal", "b.internal", "c.internal"]
+        );
+    }
+
+    #[cfg(feature = "rds")]
+    #[tokio::test]
+    async fn the_last_used_destination_is_highlighted_in_place() {
+        let path = std::env::temp_dir()
+            .join(format!("porward-state-{}", std::process::id()))
+            .join("highlighted.json");
+        let _ = std::fs::remove_file(&path);
+        state::remember_destination(&path, "dev", &Service::Postgresql, "b.internal");
+        let draft = Draft {
+            service: Some(Service::Postgresql),
+            host_port: Some(5432),
+            state: Some(path),
+            ..draft(Some("dev"), Some("i-0123"))
+        };
+        let (mut builder, prompts) = scripted::<Host>(
+            draft,
+            vec![
+                Answer::Label("b.internal".to_string()),
+                Answer::Text(String::new()),
+                Answer::Text(String::new()),
+            ],
+        );
+        builder.destinations = Box::new(StaticDestinationProvider(vec![
+            destination("a.internal", None),
+            destination("b.internal", None),
+            destination("c.internal", None),
+        ]));
+        builder.destination().await.ok().unwrap();
+        let prompt = prompts.lock().unwrap()[0].clone();
+        assert_eq!(prompt.options, ["a.internal", "b.internal", "c.internal"]);
+        assert_eq!(prompt.options[prompt.initial], "b.internal");
+    }
+
+    #[cfg(feature = "rds")]
+    #[tokio::test]
+    async fn a_listed_port_wins_over_the_service_default() {
+        for (listed, host_port) in [(Some(6543), 6543), (None, 5432)] {
+            let draft = Draft {
+                service: Some(Service::Postgresql),
+                host_port: Some(5432),
+                ..draft(Some("dev"), Some("i-0123"))
+            };
+            let (mut builder, _) = scripted::<Host>(
+                draft,
+                vec![
+                    Answer::Index(0),
+                    Answer::Text(String::new()),
+                    Answer::Text(String::new()),
+                ],
+            );
+            builder.destinations = Box::new(StaticDestinationProvider(vec![destination(
+                "db.internal",
+                listed,
+            )]));
+            let builder = builder.destination().await.ok().unwrap();
+            let draft = &builder.draft;
+            assert_eq!(draft.host_port, Some(host_port));
+            assert_eq!(
+                draft.local_port,
+                Some(draft.local_ports.local_port(host_port))
+            );
+        }
+    }
+
+    #[test]
+    fn plain_arguments_are_not_quoted() {
+        for arg in ["ssm", "--profile", "i-0123", "eu-west-1", "a=b,c@d:e/f.g_h"] {
+            assert_eq!(shell_quote(arg), arg);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn arguments_with_spaces_and_quotes_are_quoted_for_a_shell() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("look at INC-42"), "'look at INC-42'");
+        assert_eq!(shell_quote(r#"{"host":["db"]}"#), r#"'{"host":["db"]}'"#);
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$(id) `id` $HOME"), "'$(id) `id` $HOME'");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn arguments_with_spaces_and_quotes_are_quoted_for_powershell() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("look at INC-42"), "'look at INC-42'");
+        assert_eq!(shell_quote(r#"{"host":["db"]}"#), r#"'{"host":["db"]}'"#);
+        assert_eq!(shell_quote("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn forwarders_need_both_ports() {
+        let new = |host_port, local_port| {
+            PortForwarder::new(
+                "dev".to_string(),
+                "i-0123".to_string(),
+                Service::all().remove(0),
+                "db.internal".to_string(),
+                host_port,
+                local_port,
+            )
+            .map(|port_forwarder| port_forwarder.local_port())
+            .map_err(|e| e.to_string())
+        };
+        assert_eq!(new(5432, 15432), Ok(15432));
+        assert_eq!(new(5432, 0), Err("invalid local port '0'".to_string()));
+        assert_eq!(new(0, 15432), Err("invalid host port '0'".to_string()));
+    }
+
+    #[test]
+    fn only_windows_instances_with_old_agents_are_warned_about() {
+        let warned = |platform: &str, agent_version: Option<&str>| {
+            let instance = InstanceSummary {
+                id: "i-0123".to_string(),
+                name: String::new(),
+                vpc_id: None,
+                availability_zone: None,
+                private_ip: None,
+                launch_time: None,
+                tags: Tags::new(),
+                hybrid: false,
+                security_groups: vec![],
+                image_id: None,
+                spot: false,
+                platform: Some(platform.to_string()),
+                agent_version: agent_version.map(str::to_string),
+            };
+            agent_warning(&instance).is_some()
+        };
+        assert!(warned("Windows", Some("3.0.1390.0")));
+        assert!(warned("Windows Server 2019", Some("3.1.1260.0")));
+        // missing parts are 0
+        assert!(warned("windows", Some("3.1")));
+        assert!(!warned("Windows", Some("3.1.1374")));
+        assert!(!warned("Windows", Some("3.1.1374.0")));
+        assert!(!warned("Windows", Some("3.2.582.0")));
+        assert!(!warned("Windows", Some("10.0")));
+        assert!(!warned("Windows", Some("unknown")));
+        assert!(!warned("Windows", None));
+        assert!(!warned("Linux/UNIX", Some("2.3.0.0")));
+    }
+
+    // the json after --parameters, as the aws cli reads it
+    #[cfg(feature = "rds")]
+    fn parameters_arg(port_forwarder: &PortForwarder) -> Parameters {
+        let args = port_forwarder.command_args().unwrap();
+        let position = args.iter().position(|arg| arg == "--parameters").unwrap();
+        serde_json::from_str(&args[position + 1]).unwrap()
+    }
+
+    #[cfg(feature = "rds")]
+    #[test]
+    fn hostile_hosts_stay_inside_the_host_parameter() {
+        let service = Service::Postgresql;
+        let port = service.default_port();
+        for host in [
+            r#"db"x"#,
+            r#"db\x"#,
+            r#"db\"#,
+            "db}x",
+            r#"db","x":"y"#,
+            r#"db"],"portNumber":["22"#,
+            r#"db"]}, {"localPortNumber":["1"#,
+        ] {
+            let port_forwarder = PortForwarder::new(
+                "dev".to_string(),
+                "i-0123".to_string(),
+                service.clone(),
+                host.to_string(),
+                port,
+                15000,
+            )
+            .unwrap();
+            assert_eq!(
+                parameters_arg(&port_forwarder),
+                Parameters::from([
+                    ("host".to_string(), vec![host.to_string()]),
+                    ("portNumber".to_string(), vec![port.to_string()]),
+                    ("localPortNumber".to_string(), vec!["15000".to_string()]),
+                ]),
+                "{}",
+                host
+            );
+        }
+    }
+
+    // the arguments as they would be typed in a shell, one line to compare per case
+    #[cfg(all(unix, any(feature = "alb", feature = "rds", feature = "elasticache")))]
+    fn snapshot(args: &[String]) -> String {
+        args.iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[cfg(all(unix, any(feature = "alb", feature = "rds", feature = "elasticache")))]
+    fn forwarder(profile_name: &str, service: Service, host: &str) -> PortForwarder {
+        let port = service.default_port();
+        PortForwarder::new(
+            profile_name.to_string(),
+            "i-0123".to_string(),
+            service,
+            host.to_string(),
+            port,
+            LocalPorts::default().wrap_port(port),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn the_method_flag_wins_over_the_profile_config() {
+        let method = |method_flag: Option<Method>, profile_name: &str| {
+            let (mut builder, _) = scripted::<Profile>(
+                Draft {
+                    method_flag,
+                    config: toml::from_str("[profiles.dev]\nmethod = 'eice'").unwrap(),
+                    ..Draft::default()
+                },
+                vec![],
+            );
+            builder.apply_profile_settings(profile_name).ok().unwrap();
+            builder.draft.method
+        };
+        assert_eq!(method(None, "dev"), Method::Eice);
+        assert_eq!(method(Some(Method::Ssm), "dev"), Method::Ssm);
+        assert_eq!(method(None, "prod"), Method::Ssm);
+        assert_eq!(method(Some(Method::Eice), "prod"), Method::Eice);
+    }
+
+    #[test]
+    fn eice_sessions_use_the_resolved_route() {
+        let mut port_forwarder = forwarder("dev", Service::all().remove(0), "db.internal");
+        port_forwarder.method = Method::Eice;
+        let port = port_forwarder.destination.port.to_string();
+        let local_port = port_forwarder.local_port.to_string();
+        let to_instance = EiceRoute {
+            endpoint_id: "eice-0123".to_string(),
+            private_ip: None,
+        };
+        assert_eq!(
+            port_forwarder.eice_args(&to_instance),
+            [
+                "ec2-instance-connect",
+                "open-tunnel",
+                "--instance-connect-endpoint-id",
+                "eice-0123",
+                "--instance-id",
+                "i-0123",
+                "--remote-port",
+                port.as_str(),
+                "--local-port",
+                local_port.as_str(),
+                "--profile",
+                "dev",
+            ]
+        );
+        let to_host = EiceRoute {
+            endpoint_id: "eice-0123".to_string(),
+            private_ip: Some("10.0.1.5".to_string()),
+        };
+        let args = port_forwarder.eice_args(&to_host);
+        assert_eq!(args[4..6], ["--private-ip-address", "10.0.1.5"]);
+        assert!(!args.contains(&"i-0123".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_resolved_route_is_not_looked_up_again() {
+        let mut port_forwarder = forwarder("dev", Service::all().remove(0), "db.internal");
+        port_forwarder.method = Method::Eice;
+        let route = EiceRoute {
+            endpoint_id: "eice-0123".to_string(),
+            private_ip: Some("10.0.1.5".to_string()),
+        };
+        port_forwarder.eice = Some(route.clone());
+        // a lookup would need aws credentials and fail
+        port_forwarder.resolve_eice().await.unwrap();
+        assert_eq!(port_forwarder.eice_route().await.unwrap(), route);
+    }
+
+    #[cfg(all(unix, feature = "alb"))]
+    #[test]
+    fn load_balancer_command() {
+        let port_forwarder = forwarder(
+            "dev",
+            Service::ApplicationLoadBalancer,
+            "internal-app-123.eu-west-1.elb.amazonaws.com",
+        );
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["internal-app-123.eu-west-1.elb.amazonaws.com"],"localPortNumber":["10443"],"portNumber":["443"]}'"#
+        );
+    }
+
+    #[cfg(all(unix, feature = "rds"))]
+    #[test]
+    fn postgresql_command() {
+        let port_forwarder = forwarder("dev", Service::Postgresql, "db.internal");
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["db.internal"],"localPortNumber":["15432"],"portNumber":["5432"]}'"#
+        );
+    }
+
+    #[cfg(all(unix, feature = "elasticache"))]
+    #[test]
+    fn redis_and_valkey_commands() {
+        for service in [Service::Redis, Service::Valkey] {
+            let port_forwarder = forwarder("dev", service, "cache.internal");
+            assert_eq!(
+                snapshot(&port_forwarder.command_args().unwrap()),
+                r#"--profile dev ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["cache.internal"],"localPortNumber":["16379"],"portNumber":["6379"]}'"#
+            );
+        }
+    }
+
+    // the host only ever ends up in the parameters json, one argument that is not a shell
+    #[cfg(all(unix, feature = "rds"))]
+    #[test]
+    fn host_with_special_characters_command() {
+        let port_forwarder = forwarder("dev", Service::Postgresql, r#"it's "db" $(id)"#);
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["it'\''s \"db\" $(id)"],"localPortNumber":["15432"],"portNumber":["5432"]}'"#
+        );
+    }
+
+    #[cfg(all(unix, feature = "rds"))]
+    #[test]
+    fn custom_document_and_parameters_command() {
+        let parameters = Parameters::from([("portNumber".to_string(), vec!["6432".to_string()])]);
+        let port_forwarder = forwarder("dev", Service::Postgresql, "db.internal").with_document(
+            Some("Custom-PortForwarding".to_string()),
+            parameters.clone(),
+        );
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --document-name Custom-PortForwarding --parameters '{"host":["db.internal"],"localPortNumber":["15432"],"portNumber":["6432"]}'"#
+        );
+        // without a document only the parameters change
+        let port_forwarder =
+            forwarder("dev", Service::Postgresql, "db.internal").with_document(None, parameters);
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["db.internal"],"localPortNumber":["15432"],"portNumber":["6432"]}'"#
+        );
+    }
+
+    // passed credentials replace the profile, the aws cli falls back to its default chain
+    #[cfg(all(unix, feature = "rds"))]
+    #[test]
+    fn passed_credentials_command_has_no_profile() {
+        credentials::set_passed(
+            "passed",
+            aws_credential_types::Credentials::new("AKIA", "secret", None, None, "test"),
+        );
+        let port_forwarder = forwarder("passed", Service::Postgresql, "db.internal");
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"ssm start-session --target i-0123 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["db.internal"],"localPortNumber":["15432"],"portNumber":["5432"]}'"#
+        );
+    }
+
+    #[cfg(all(unix, feature = "rds"))]
+    #[test]
+    fn shell_command_with_region_and_reason() {
+        let mut port_forwarder = forwarder("dev", Service::Postgresql, "db.internal");
+        assert_eq!(
+            snapshot(&port_forwarder.target.shell_args()),
+            "--profile dev ssm start-session --target i-0123"
+        );
+        port_forwarder.target.region = Some("eu-west-1".to_string());
+        port_forwarder.target.reason = Some("INC-42 look at the slow queries".to_string());
+        assert_eq!(
+            snapshot(&port_forwarder.target.shell_args()),
+            "--profile dev ssm start-session --target i-0123 --region eu-west-1"
+        );
+        assert_eq!(
+            snapshot(&port_forwarder.command_args().unwrap()),
+            r#"--profile dev ssm start-session --target i-0123 --region eu-west-1 --document-name AWS-StartPortForwardingSessionToRemoteHost --parameters '{"host":["db.internal"],"localPortNumber":["15432"],"portNumber":["5432"]}' --reason 'INC-42 look at the slow queries'"#
+        );
+    }
 }
@@ -0,0 +1,308 @@
+use crate::error::{self, PorwardError};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+// oldest session-manager-plugin release that supports AWS-StartPortForwardingSessionToRemoteHost
+const MIN_PLUGIN_VERSION: [u64; 4] = [1, 2, 279, 0];
+const VERSION_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const VERSION_CHECK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub const AWS_CLI: &str = "aws";
+pub const SESSION_MANAGER_PLUGIN: &str = "session-manager-plugin";
+
+pub struct Version(Vec<u64>);
+
+impl Version {
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts = text
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        if parts.is_empty() {
+            None
+        } else {
+            Some(Version(parts))
+        }
+    }
+
+    pub fn major(&self) -> u64 {
+        self.0[0]
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|idx| {
+                let left = self.0.get(idx).copied().unwrap_or_default();
+                let right = other.0.get(idx).copied().unwrap_or_default();
+                left.cmp(&right)
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts = self
+            .0
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+// session-manager-plugin --version prints e.g. `1.2.694.0`
+pub fn parse_plugin_version(output: &str) -> Option<Version> {
+    output.lines().find_map(Version::parse)
+}
+
+// aws --version prints e.g. `aws-cli/2.15.30 Python/3.11.8 Linux/6.5.0 exe/x86_64.ubuntu.22 prompt/off`
+pub fn parse_aws_cli_version(output: &str) -> Option<Version> {
+    output
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("aws-cli/"))
+        .and_then(Version::parse)
+}
+
+// the plugin is not needed when the native tunnel talks to the data channel itself
+pub fn check(force: bool, aws_cli: bool, plugin: bool) -> error::Result<Vec<String>> {
+    let mut warnings = vec![];
+
+    if aws_cli {
+        let aws = version_output(AWS_CLI)?.ok_or_else(|| missing("aws cli"))?;
+        // aws cli v1 prints its version to stderr on some python versions
+        let aws_output = format!(
+            "{}\n{}",
+            String::from_utf8_lossy(&aws.stdout),
+            String::from_utf8_lossy(&aws.stderr)
+        );
+        if let Some(version) = parse_aws_cli_version(&aws_output) {
+            if version.major() < 2 {
+                warnings.push(format!(
+                    "aws cli {} detected, ssm start-session parameters are handled differently by v1. Please upgrade to aws cli v2.",
+                    version
+                ));
+            }
+        }
+    }
+
+    if !plugin {
+        return Ok(warnings);
+    }
+    let plugin =
+        version_output(SESSION_MANAGER_PLUGIN)?.ok_or_else(|| missing(SESSION_MANAGER_PLUGIN))?;
+    let minimum = Version(MIN_PLUGIN_VERSION.to_vec());
+    match parse_plugin_version(&String::from_utf8_lossy(&plugin.stdout)) {
+        Some(version) if version < minimum => {
+            let message = format!(
+                "session-manager-plugin {} is installed but {} or newer is required. {}",
+                version,
+                minimum,
+                upgrade_hint()
+            );
+            if !force {
+                return Err(eyre!(message).into());
+            }
+            warnings.push(message);
+        }
+        Some(_) => {}
+        None => warnings.push("could not determine the session-manager-plugin version".into()),
+    }
+
+    Ok(warnings)
+}
+
+fn missing(tool: &str) -> PorwardError {
+    PorwardError::PrerequisiteMissing {
+        tool: tool.to_string(),
+        hint: "Please install it before running this program.".to_string(),
+    }
+}
+
+// Ok(None) when the program can not be started at all
+fn version_output(program: &str) -> Result<Option<Output>> {
+    let Ok(path) = executable(program) else {
+        return Ok(None);
+    };
+    let Ok(mut child) = Command::new(&path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    else {
+        return Ok(None);
+    };
+    let deadline = Instant::now() + VERSION_CHECK_TIMEOUT;
+    while child.try_wait()?.is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(eyre!(
+                "`{} --version` did not finish within {}s",
+                path.display(),
+                VERSION_CHECK_TIMEOUT.as_secs()
+            ));
+        }
+        std::thread::sleep(VERSION_CHECK_POLL_INTERVAL);
+    }
+    Ok(Some(child.wait_with_output()?))
+}
+
+// resolves through PATH (and PATHEXT on windows), so that `aws` finds aws.exe or aws.cmd
+pub fn executable(name: &str) -> Result<PathBuf> {
+    if let Ok(path) = which::which(name) {
+        return Ok(path);
+    }
+    #[cfg(windows)]
+    if name == SESSION_MANAGER_PLUGIN {
+        // the installer does not always add the plugin to PATH of already open shells
+        let path =
+            PathBuf::from(std::env::var("ProgramFiles").unwrap_or("C:\\Program Files".into()))
+                .join("Amazon\\SessionManagerPlugin\\bin\\session-manager-plugin.exe");
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    Err(eyre!("{} was not found in PATH", name))
+}
+
+fn upgrade_hint() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "Upgrade with `brew upgrade --cask session-manager-plugin` or the installer from https://docs.aws.amazon.com/systems-manager/latest/userguide/install-plugin-macos-overview.html"
+    } else if cfg!(target_os = "windows") {
+        "Download and run the latest installer from https://docs.aws.amazon.com/systems-manager/latest/userguide/install-plugin-windows.html"
+    } else {
+        "Install the latest package from https://docs.aws.amazon.com/systems-manager/latest/userguide/install-plugin-linux-overview.html"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn plugin_versions_are_read_from_the_output() {
+        assert_eq!(
+            parse_plugin_version("1.2.694.0\n").map(|v| v.to_string()),
+            Some("1.2.694.0".to_string())
+        );
+        // a notice before the version
+        assert_eq!(
+            parse_plugin_version(
+                "The Session Manager plugin was installed successfully.\n1.2.279.0\n"
+            )
+            .map(|v| v.to_string()),
+            Some("1.2.279.0".to_string())
+        );
+        assert!(parse_plugin_version("").is_none());
+        assert!(parse_plugin_version("command not found\n").is_none());
+    }
+
+    #[test]
+    fn aws_cli_versions_are_read_from_the_output() {
+        let v2 =
+            "aws-cli/2.15.30 Python/3.11.8 Linux/6.5.0-1018-aws exe/x86_64.ubuntu.22 prompt/off\n";
+        let version = parse_aws_cli_version(v2).unwrap();
+        assert_eq!(version.to_string(), "2.15.30");
+        assert_eq!(version.major(), 2);
+        let v1 = "aws-cli/1.32.10 Python/3.8.10 Linux/5.15.0-91-generic botocore/1.34.10\n";
+        assert_eq!(parse_aws_cli_version(v1).unwrap().major(), 1);
+        // v1 on some python versions prints to stderr, which follows an empty stdout
+        assert_eq!(
+            parse_aws_cli_version(&format!("\n{}", v1)).unwrap().major(),
+            1
+        );
+        for garbage in [
+            "",
+            "aws: command not found",
+            "aws-cli/ Python/3.11",
+            "aws-cli/two",
+        ] {
+            assert!(parse_aws_cli_version(garbage).is_none(), "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn garbage_is_not_a_version() {
+        for text in ["", ".", "1..2", "1.2.x", "one", "1.2-beta", "-1"] {
+            assert!(Version::parse(text).is_none(), "{}", text);
+        }
+        assert_eq!(version("v1.2.3").to_string(), "1.2.3");
+        assert_eq!(version(" 1.2 ").to_string(), "1.2");
+    }
+
+    #[test]
+    fn missing_parts_of_short_versions_are_zero() {
+        let minimum = Version(MIN_PLUGIN_VERSION.to_vec());
+        assert!(version("1.2.279") == minimum);
+        assert!(version("1.2.279.0.0") == minimum);
+        assert!(version("1.2.278.9") < minimum);
+        assert!(version("1.2") < minimum);
+        assert!(version("1.3") > minimum);
+        assert!(version("2") > minimum);
+        assert!(version("1.2.1000.0") > minimum);
+        // numerically, not as text
+        assert!(version("1.10") > version("1.9"));
+    }
+
+    #[test]
+    fn programs_missing_from_path_are_an_error() {
+        let error = executable("porward-test-no-such-program").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "porward-test-no-such-program was not found in PATH"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn programs_are_resolved_to_their_path() {
+        let path = executable("sh").unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("sh"));
+    }
+
+    // `aws` is aws.exe or aws.cmd, found through PATHEXT
+    #[cfg(windows)]
+    #[test]
+    fn programs_are_resolved_with_their_extension() {
+        let path = executable("cmd").unwrap();
+        assert!(path.is_absolute());
+        assert_eq!(
+            path.extension()
+                .map(|extension| extension.to_ascii_lowercase()),
+            Some("exe".into())
+        );
+    }
+}
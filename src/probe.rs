@@ -0,0 +1,240 @@
+use serde::{de::IntoDeserializer, Deserialize};
+use std::{
+    fmt::{Display, Formatter},
+    io::ErrorKind,
+    net::Ipv4Addr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// SSLRequest: length 8 and the code 1234 5679, answered with a single S or N
+const POSTGRES_SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+const REDIS_PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+const HTTP_HEAD: &[u8] = b"HEAD / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+/// The protocol spoken behind a local port, which decides what a probe sends.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Expect {
+    Tcp,
+    Http,
+    Redis,
+    Postgres,
+}
+
+impl FromStr for Expect {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Expect::deserialize(value.into_deserializer())
+            .map_err(|e: serde::de::value::Error| e.to_string())
+    }
+}
+
+/// How a probe of a local port went.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Probe {
+    Ok { latency: Duration, answer: String },
+    // nothing listens, the tunnel is not running
+    Refused,
+    // the plugin accepted the connection and closed it, usually because the instance can not
+    // reach the destination
+    Closed,
+    Timeout,
+    Unexpected(String),
+}
+
+impl Probe {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Probe::Ok { .. })
+    }
+}
+
+impl Display for Probe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Probe::Ok { latency, answer } => {
+                write!(f, "ok in {}ms ({})", latency.as_millis(), answer)
+            }
+            Probe::Refused => write!(f, "connection refused, nothing listens on the port"),
+            Probe::Closed => write!(
+                f,
+                "connected but closed, the instance can probably not reach the destination"
+            ),
+            Probe::Timeout => write!(f, "no answer within {}s", PROBE_TIMEOUT.as_secs()),
+            Probe::Unexpected(answer) => write!(f, "unexpected answer: {}", answer),
+        }
+    }
+}
+
+pub async fn probe(local_port: u16, expect: Expect) -> Probe {
+    let started = Instant::now();
+    match tokio::time::timeout(PROBE_TIMEOUT, exchange(local_port, expect)).await {
+        Ok(Ok(answer)) => Probe::Ok {
+            latency: started.elapsed(),
+            answer,
+        },
+        Ok(Err(probe)) => probe,
+        Err(_) => Probe::Timeout,
+    }
+}
+
+async fn exchange(local_port: u16, expect: Expect) -> Result<String, Probe> {
+    let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, local_port))
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::ConnectionRefused => Probe::Refused,
+            _ => Probe::Unexpected(e.to_string()),
+        })?;
+    let request = match expect {
+        // the plugin closes a connection it can not forward right away
+        Expect::Tcp => return tcp(&mut stream).await,
+        Expect::Http => HTTP_HEAD,
+        Expect::Redis => REDIS_PING,
+        Expect::Postgres => &POSTGRES_SSL_REQUEST,
+    };
+    stream.write_all(request).await.map_err(closed)?;
+    let mut answer = vec![0; 64];
+    let length = stream.read(&mut answer).await.map_err(closed)?;
+    if length == 0 {
+        return Err(Probe::Closed);
+    }
+    let answer = &answer[..length];
+    let text = String::from_utf8_lossy(answer);
+    let first_line = text.lines().next().unwrap_or_default().to_string();
+    match expect {
+        Expect::Http if answer.starts_with(b"HTTP/") => Ok(first_line),
+        Expect::Redis if answer.starts_with(b"+PONG") => Ok("PONG".to_string()),
+        // a server that wants a password still answered
+        Expect::Redis if answer.starts_with(b"-") => Ok(first_line),
+        Expect::Postgres if answer == b"S" => Ok("accepts TLS".to_string()),
+        Expect::Postgres if answer == b"N" => Ok("does not offer TLS".to_string()),
+        _ => Err(Probe::Unexpected(first_line)),
+    }
+}
+
+// a connection that stays open for a moment is forwarded
+async fn tcp(stream: &mut TcpStream) -> Result<String, Probe> {
+    let mut byte = [0; 1];
+    match tokio::time::timeout(Duration::from_millis(500), stream.read(&mut byte)).await {
+        Ok(Ok(0)) | Ok(Err(_)) => Err(Probe::Closed),
+        Ok(Ok(_)) => Ok("connected, the server spoke first".to_string()),
+        Err(_) => Ok("connected".to_string()),
+    }
+}
+
+fn closed(_: std::io::Error) -> Probe {
+    Probe::Closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn bind() -> (TcpListener, u16) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    // answers the expected request once, and keeps the connection until the probe hangs up
+    async fn serve(request: &'static [u8], answer: &'static [u8]) -> u16 {
+        let (listener, port) = bind().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = vec![0; request.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, request);
+            stream.write_all(answer).await.unwrap();
+            let _ = stream.read(&mut [0; 1]).await;
+        });
+        port
+    }
+
+    fn answer(probe: Probe) -> String {
+        match probe {
+            Probe::Ok { answer, .. } => answer,
+            probe => panic!("expected an answer, the probe was {:?}", probe),
+        }
+    }
+
+    #[tokio::test]
+    async fn postgres_answers_whether_it_offers_tls() {
+        let port = serve(&POSTGRES_SSL_REQUEST, b"S").await;
+        assert_eq!(answer(probe(port, Expect::Postgres).await), "accepts TLS");
+        let port = serve(&POSTGRES_SSL_REQUEST, b"N").await;
+        assert_eq!(
+            answer(probe(port, Expect::Postgres).await),
+            "does not offer TLS"
+        );
+        let port = serve(&POSTGRES_SSL_REQUEST, b"E").await;
+        assert_eq!(
+            probe(port, Expect::Postgres).await,
+            Probe::Unexpected("E".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn redis_answers_a_ping_with_or_without_a_password() {
+        let port = serve(REDIS_PING, b"+PONG\r\n").await;
+        assert_eq!(answer(probe(port, Expect::Redis).await), "PONG");
+        let port = serve(REDIS_PING, b"-NOAUTH Authentication required.\r\n").await;
+        assert_eq!(
+            answer(probe(port, Expect::Redis).await),
+            "-NOAUTH Authentication required."
+        );
+    }
+
+    #[tokio::test]
+    async fn http_answers_with_a_status_line() {
+        let port = serve(HTTP_HEAD, b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        assert_eq!(answer(probe(port, Expect::Http).await), "HTTP/1.1 200 OK");
+        let port = serve(HTTP_HEAD, b"SSH-2.0-OpenSSH_9.6\r\n").await;
+        assert_eq!(
+            probe(port, Expect::Http).await,
+            Probe::Unexpected("SSH-2.0-OpenSSH_9.6".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn a_connection_closed_right_away_is_closed() {
+        for expect in [Expect::Tcp, Expect::Http, Expect::Redis, Expect::Postgres] {
+            let (listener, port) = bind().await;
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                drop(stream);
+            });
+            assert_eq!(probe(port, expect).await, Probe::Closed, "{:?}", expect);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_port_without_a_listener_is_refused() {
+        let (listener, port) = bind().await;
+        drop(listener);
+        assert_eq!(probe(port, Expect::Postgres).await, Probe::Refused);
+    }
+
+    #[tokio::test]
+    async fn a_silent_peer_times_out() {
+        let (listener, port) = bind().await;
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(PROBE_TIMEOUT * 2).await;
+        });
+        assert_eq!(probe(port, Expect::Postgres).await, Probe::Timeout);
+        // a plain tcp probe only needs the connection to stay open
+        let (listener, port) = bind().await;
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(PROBE_TIMEOUT).await;
+        });
+        assert_eq!(answer(probe(port, Expect::Tcp).await), "connected");
+    }
+}
@@ -0,0 +1,1650 @@
+use crate::{
+    config::{Config, Endpoints},
+    error::{PorwardError, Result},
+    porwarder::Service,
+    proxy,
+};
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_smithy_async::future::pagination_stream::PaginationStream;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    sync::{Arc, OnceLock},
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+pub type Tags = BTreeMap<String, String>;
+
+// regions scanned at the same time by porward find
+const MAX_CONCURRENT_REGIONS: usize = 4;
+// starting a stopped aurora cluster usually takes a few minutes
+#[cfg(feature = "rds")]
+const CLUSTER_START_POLL: std::time::Duration = std::time::Duration::from_secs(15);
+#[cfg(feature = "rds")]
+const CLUSTER_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20 * 60);
+
+static ENDPOINTS: OnceLock<Endpoints> = OnceLock::new();
+
+// a client of an sdk crate, sent to the endpoint configured for its service if there is one
+macro_rules! client {
+    ($sdk:ident, $config:expr, $service:ident) => {{
+        let mut builder = $sdk::config::Builder::from($config);
+        if let Some(endpoint_url) = $crate::providers::endpoints().$service.as_deref() {
+            builder = builder.endpoint_url(endpoint_url);
+        }
+        if $crate::timing::enabled() {
+            builder = builder.interceptor($crate::timing::ApiTimer(stringify!($service)));
+        }
+        $sdk::Client::from_conf(builder.build())
+    }};
+}
+pub(crate) use client;
+
+/// A running EC2 instance that can be used as the SSM target.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstanceSummary {
+    pub id: String,
+    pub name: String,
+    pub vpc_id: Option<String>,
+    pub availability_zone: Option<String>,
+    pub private_ip: Option<String>,
+    /// Seconds since the epoch.
+    pub launch_time: Option<i64>,
+    pub tags: Tags,
+    /// An on-premises or hybrid node registered with SSM (`mi-...`) instead of an EC2 instance.
+    #[serde(default)]
+    pub hybrid: bool,
+    #[serde(default)]
+    pub security_groups: Vec<String>,
+    #[serde(default)]
+    pub image_id: Option<String>,
+    #[serde(default)]
+    pub spot: bool,
+    // e.g. "Linux/UNIX" or "Windows"
+    #[serde(default)]
+    pub platform: Option<String>,
+    /// Version of the SSM agent, when it reported to SSM.
+    #[serde(default)]
+    pub agent_version: Option<String>,
+}
+
+impl InstanceSummary {
+    /// True when `id_or_ip` is exactly the instance id or its private IP.
+    pub fn matches(&self, id_or_ip: &str) -> bool {
+        self.id == id_or_ip || self.private_ip.as_deref() == Some(id_or_ip)
+    }
+}
+
+/// A host that can be reached from the instance, with the label shown in the selector.
+/// `port` is set when the service API reports it, otherwise the service default is proposed.
+/// `vpc_id`, `zones` and `tags` are empty for services whose listers do not look them up,
+/// `tls` and `cluster_mode` are only set for caches that need a client configured for them.
+/// `security_groups` are used for the reachability hint next to the destination.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DestinationSummary {
+    pub host: String,
+    pub label: String,
+    pub port: Option<u16>,
+    pub vpc_id: Option<String>,
+    pub zones: Vec<String>,
+    pub tags: Tags,
+    pub tls: bool,
+    pub cluster_mode: bool,
+    #[serde(default)]
+    pub security_groups: Vec<String>,
+    // a single node of a cluster, e.g. one database instance next to the cluster's endpoints
+    #[serde(default)]
+    pub member: bool,
+    // as the service reports it, e.g. available or stopped for a database
+    #[serde(default)]
+    pub state: Option<String>,
+    // the database cluster the endpoint belongs to, which can be started when it is stopped
+    #[serde(default)]
+    pub cluster_id: Option<String>,
+}
+
+// states in which a destination takes connections, the others are offered last and confirmed
+const USABLE_STATES: &[&str] = &[
+    "available",
+    "active",
+    "active_impaired",
+    "backing-up",
+    "storage-optimization",
+    "snapshotting",
+];
+
+fn usable(state: Option<&str>) -> bool {
+    state.is_none_or(|state| USABLE_STATES.contains(&state))
+}
+
+impl DestinationSummary {
+    pub fn usable(&self) -> bool {
+        usable(self.state.as_deref())
+    }
+}
+
+/// A group of destinations, e.g. a database cluster, selected before one of its endpoints.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClusterSummary {
+    pub id: String,
+    pub engine: Option<String>,
+    pub version: Option<String>,
+    pub tags: Tags,
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+impl ClusterSummary {
+    pub fn usable(&self) -> bool {
+        usable(self.state.as_deref())
+    }
+}
+
+/// A target group of a load balancer, offered to skip the load balancer for one of its targets.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetGroupSummary {
+    pub arn: String,
+    pub name: String,
+    pub protocol: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// A registered target with its health, `host` is the private ip for instance targets too.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetSummary {
+    pub id: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub state: String,
+    pub reason: Option<String>,
+}
+
+/// The account and region a profile resolves to, shown for the rest of the flow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountSummary {
+    pub id: String,
+    pub alias: Option<String>,
+    pub region: Option<String>,
+}
+
+/// An Auto Scaling Group with the ids of its healthy, InService instances.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoScalingGroupSummary {
+    pub name: String,
+    pub instance_ids: Vec<String>,
+}
+
+impl AutoScalingGroupSummary {
+    /// The running instances of the group, newest first.
+    pub fn instances(&self, running: Vec<InstanceSummary>) -> Vec<InstanceSummary> {
+        let mut instances: Vec<_> = running
+            .into_iter()
+            .filter(|instance| self.instance_ids.contains(&instance.id))
+            .collect();
+        instances.sort_by_key(|instance| std::cmp::Reverse(instance.launch_time));
+        instances
+    }
+}
+
+/// What kind of resource matched the name given to `porward find`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceKind {
+    Instance,
+    LoadBalancer,
+    DbCluster,
+}
+
+impl Display for ResourceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceKind::Instance => write!(f, "instance"),
+            ResourceKind::LoadBalancer => write!(f, "load balancer"),
+            ResourceKind::DbCluster => write!(f, "db cluster"),
+        }
+    }
+}
+
+/// A resource found by name, and the region it is in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoundResource {
+    pub region: String,
+    pub kind: ResourceKind,
+    pub id: String,
+    pub name: String,
+}
+
+/// Lists the instances the builder offers after a profile is selected. `region` is set when the
+/// profile has none of its own or another one was settled on, otherwise the profile's is used.
+#[async_trait]
+pub trait InstanceProvider: Send + Sync {
+    async fn running_instances(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<InstanceSummary>>;
+
+    /// A running instance with this id or private IP, for ids typed in by hand.
+    async fn find_instance(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        id_or_ip: &str,
+    ) -> Result<Option<InstanceSummary>> {
+        Ok(self
+            .running_instances(profile_name, region)
+            .await?
+            .into_iter()
+            .find(|instance| instance.matches(id_or_ip)))
+    }
+
+    async fn auto_scaling_groups(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+    ) -> Result<Vec<AutoScalingGroupSummary>> {
+        Ok(vec![])
+    }
+
+    /// Names of the images, to tell instances with the same name apart.
+    async fn image_names(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _image_ids: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        Ok(BTreeMap::new())
+    }
+
+    // providers that can not tell leave the account header out
+    async fn account(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+    ) -> Result<Option<AccountSummary>> {
+        Ok(None)
+    }
+}
+
+/// Lists the destinations of a service the builder offers after the destination type is selected,
+/// in `region` like [`InstanceProvider`].
+#[async_trait]
+pub trait DestinationProvider: Send + Sync {
+    async fn destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+    ) -> Result<Vec<DestinationSummary>>;
+
+    // services without clusters list their destinations directly
+    async fn clusters(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _service: &Service,
+    ) -> Result<Vec<ClusterSummary>> {
+        Ok(vec![])
+    }
+
+    async fn cluster_destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+        _cluster_id: &str,
+    ) -> Result<Vec<DestinationSummary>> {
+        self.destinations(profile_name, region, service).await
+    }
+
+    // clusters that can not be started are connected to as they are
+    async fn start_cluster(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _service: &Service,
+        cluster_id: &str,
+    ) -> Result<()> {
+        Err(eyre!("cluster {} can not be started", cluster_id).into())
+    }
+
+    // load balancers without target groups are always the destination themselves
+    async fn target_groups(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _load_balancer: &str,
+    ) -> Result<Vec<TargetGroupSummary>> {
+        Ok(vec![])
+    }
+
+    async fn targets(
+        &self,
+        _profile_name: &str,
+        _region: Option<&str>,
+        _target_group_arn: &str,
+    ) -> Result<Vec<TargetSummary>> {
+        Ok(vec![])
+    }
+}
+
+/// Lists running EC2 instances. `AWS_ENDPOINT_URL` is honored unless an endpoint is set here.
+#[derive(Default)]
+pub struct AwsInstanceProvider {
+    endpoint_url: Option<String>,
+    // instances per DescribeInstances page, the service's default when not set
+    page_size: Option<i32>,
+}
+
+/// Lists load balancers, database and cache endpoints through the service APIs.
+#[derive(Default)]
+pub struct AwsDestinationProvider {
+    endpoint_url: Option<String>,
+}
+
+impl AwsInstanceProvider {
+    pub fn with_endpoint_url(endpoint_url: impl Into<String>) -> Self {
+        Self {
+            endpoint_url: Some(endpoint_url.into()),
+            page_size: None,
+        }
+    }
+
+    /// Lists the instances `page_size` at a time, 5 to 1000.
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+}
+
+impl AwsDestinationProvider {
+    pub fn with_endpoint_url(endpoint_url: impl Into<String>) -> Self {
+        Self {
+            endpoint_url: Some(endpoint_url.into()),
+        }
+    }
+}
+
+impl Display for AccountSummary {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(alias) = self.alias.as_ref() {
+            write!(f, " ({})", alias)?;
+        }
+        if let Some(region) = self.region.as_ref() {
+            write!(f, " · {}", region)?;
+        }
+        Ok(())
+    }
+}
+
+// every page of a paginated call, so a long list is never cut off after the first one
+pub(crate) async fn all_pages<T, E>(
+    pages: PaginationStream<std::result::Result<T, E>>,
+) -> Result<Vec<T>>
+where
+    PorwardError: From<E>,
+{
+    Ok(pages.collect::<std::result::Result<Vec<_>, _>>().await?)
+}
+
+/// Sends every AWS call to `endpoint_url`, or each service to its endpoint from `[endpoints]` in
+/// config.toml, including the session itself and the aws cli started for it. These win over an
+/// endpoint set on a provider. Only the first call has an effect, and it has to come before any
+/// AWS call.
+pub fn use_endpoints(endpoint_url: Option<&str>) -> color_eyre::Result<()> {
+    let endpoints = match endpoint_url {
+        Some(endpoint_url) => Endpoints::all(endpoint_url),
+        None => Config::load()?.endpoints,
+    };
+    let _ = ENDPOINTS.set(endpoints);
+    Ok(())
+}
+
+pub(crate) fn endpoints() -> &'static Endpoints {
+    ENDPOINTS.get_or_init(Endpoints::default)
+}
+
+// `region` wins over the profile's region, e.g. the one picked for a profile that has none
+pub(crate) async fn load_region_config(
+    profile_name: &str,
+    region: Option<&str>,
+    endpoint_url: Option<&str>,
+) -> SdkConfig {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest()).profile_name(profile_name);
+    if let Some(region) = region {
+        loader = loader.region(aws_config::Region::new(region.to_string()));
+    }
+    // e.g. LocalStack, which serves every service on one endpoint
+    if let Some(endpoint_url) = endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    if let Some(http_client) = proxy::http_client() {
+        loader = loader.http_client(http_client);
+    }
+    loader.load().await
+}
+
+/// Ends a session, e.g. one left open by a client that crashed, in `region` or the profile's
+/// region.
+pub async fn terminate_session(
+    profile_name: &str,
+    region: Option<&str>,
+    session_id: &str,
+) -> Result<()> {
+    let config = load_region_config(profile_name, region, None).await;
+    client!(aws_sdk_ssm, &config, ssm)
+        .terminate_session()
+        .session_id(session_id)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Instances, database clusters and load balancers whose name or id contains `name`, in every
+/// region. Regions that fail, e.g. opt-in regions that are not enabled, are returned as warnings.
+pub(crate) async fn find_resources(
+    profile_name: &str,
+    regions: &[String],
+    name: &str,
+) -> (Vec<FoundResource>, Vec<String>) {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_REGIONS));
+    let mut scans = JoinSet::new();
+    for region in regions {
+        let profile_name = profile_name.to_string();
+        let region = region.clone();
+        let name = name.to_lowercase();
+        let permits = permits.clone();
+        scans.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let config = load_region_config(&profile_name, Some(&region), None).await;
+            let result = resources_in_region(&config, &region, &name).await;
+            (region, result)
+        });
+    }
+    let mut found = vec![];
+    let mut warnings = vec![];
+    for (region, result) in scans.join_all().await {
+        match result {
+            Ok(resources) => found.extend(resources),
+            Err(e) => warnings.push(format!("could not search {}: {}", region, e)),
+        }
+    }
+    found.sort_by(|a, b| (&a.name, &a.region).cmp(&(&b.name, &b.region)));
+    (found, warnings)
+}
+
+async fn resources_in_region(
+    config: &SdkConfig,
+    region: &str,
+    name: &str,
+) -> Result<Vec<FoundResource>> {
+    let matches = |candidate: &str| candidate.to_lowercase().contains(name);
+    let found = |kind, id: &str, name: &str| FoundResource {
+        region: region.to_string(),
+        kind,
+        id: id.to_string(),
+        name: name.to_string(),
+    };
+    let instances = async {
+        let client = client!(aws_sdk_ec2, config, ec2);
+        let pages = all_pages(
+            client
+                .describe_instances()
+                .filters(
+                    aws_sdk_ec2::types::Filter::builder()
+                        .name("instance-state-name")
+                        .values("running")
+                        .build(),
+                )
+                .into_paginator()
+                .send(),
+        )
+        .await?;
+        Ok::<_, PorwardError>(
+            pages
+                .iter()
+                .flat_map(|page| page.reservations())
+                .flat_map(|reservation| reservation.instances())
+                .filter_map(instance_summary)
+                .filter(|instance| matches(&instance.id) || matches(&instance.name))
+                .map(|instance| found(ResourceKind::Instance, &instance.id, &instance.name))
+                .collect::<Vec<_>>(),
+        )
+    };
+    #[cfg(feature = "rds")]
+    let clusters = async {
+        Ok::<_, PorwardError>(
+            postgresql_clusters(config)
+                .await?
+                .iter()
+                .filter(|cluster| matches(&cluster.id))
+                .map(|cluster| found(ResourceKind::DbCluster, &cluster.id, &cluster.id))
+                .collect::<Vec<_>>(),
+        )
+    };
+    #[cfg(not(feature = "rds"))]
+    let clusters = async { Ok::<Vec<FoundResource>, PorwardError>(vec![]) };
+    #[cfg(feature = "alb")]
+    let load_balancers = async {
+        let client = client!(aws_sdk_elasticloadbalancingv2, config, elbv2);
+        let pages = all_pages(client.describe_load_balancers().into_paginator().send()).await?;
+        Ok::<_, PorwardError>(
+            pages
+                .iter()
+                .flat_map(|page| page.load_balancers())
+                .filter_map(|lb| lb.load_balancer_name())
+                .filter(|lb_name| matches(lb_name))
+                .map(|lb_name| found(ResourceKind::LoadBalancer, lb_name, lb_name))
+                .collect::<Vec<_>>(),
+        )
+    };
+    #[cfg(not(feature = "alb"))]
+    let load_balancers = async { Ok::<Vec<FoundResource>, PorwardError>(vec![]) };
+    let (instances, clusters, load_balancers) =
+        tokio::try_join!(instances, clusters, load_balancers)?;
+    Ok(instances
+        .into_iter()
+        .chain(clusters)
+        .chain(load_balancers)
+        .collect())
+}
+
+// nodes that are online with their agent version. On-premises and hybrid nodes are only listed
+// here, EC2 instances are listed through ec2 and only take the agent version from it.
+async fn ssm_nodes(config: &SdkConfig) -> Result<Vec<InstanceSummary>> {
+    let filter = |key: &str, value: &str| {
+        aws_sdk_ssm::types::InstanceInformationStringFilter::builder()
+            .key(key)
+            .values(value)
+            .build()
+            .map_err(|e| PorwardError::Aws(Box::new(e)))
+    };
+    let client = client!(aws_sdk_ssm, config, ssm);
+    let pages = all_pages(
+        client
+            .describe_instance_information()
+            .filters(filter("PingStatus", "Online")?)
+            .into_paginator()
+            .send(),
+    )
+    .await?;
+    Ok(pages
+        .iter()
+        .flat_map(|page| page.instance_information_list())
+        .filter_map(|node| {
+            Some(InstanceSummary {
+                id: node.instance_id()?.to_string(),
+                name: node
+                    .name()
+                    .or(node.computer_name())
+                    .unwrap_or_default()
+                    .to_string(),
+                vpc_id: None,
+                availability_zone: None,
+                private_ip: node.ip_address().map(|ip_address| ip_address.to_string()),
+                launch_time: None,
+                tags: Tags::new(),
+                hybrid: node.resource_type()
+                    == Some(&aws_sdk_ssm::types::ResourceType::ManagedInstance),
+                security_groups: vec![],
+                image_id: None,
+                spot: false,
+                platform: node
+                    .platform_name()
+                    .or(node.platform_type().map(|platform| platform.as_str()))
+                    .map(|platform| platform.to_string()),
+                agent_version: node.agent_version().map(|version| version.to_string()),
+            })
+        })
+        .collect())
+}
+
+pub fn instance_summary(instance: &aws_sdk_ec2::types::Instance) -> Option<InstanceSummary> {
+    let id = instance.instance_id()?;
+    let name = instance
+        .tags()
+        .iter()
+        .find(|tag| tag.key().unwrap_or_default() == "Name")
+        .map(|tag| tag.value().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    Some(InstanceSummary {
+        id: id.to_string(),
+        name,
+        vpc_id: instance.vpc_id().map(|vpc_id| vpc_id.to_string()),
+        availability_zone: instance
+            .placement()
+            .and_then(|placement| placement.availability_zone())
+            .map(|zone| zone.to_string()),
+        private_ip: instance
+            .private_ip_address()
+            .map(|private_ip| private_ip.to_string()),
+        launch_time: instance.launch_time().map(|launch_time| launch_time.secs()),
+        tags: instance
+            .tags()
+            .iter()
+            .filter_map(|tag| Some((tag.key()?.to_string(), tag.value()?.to_string())))
+            .collect(),
+        hybrid: false,
+        security_groups: instance
+            .security_groups()
+            .iter()
+            .filter_map(|group| group.group_id())
+            .map(|group_id| group_id.to_string())
+            .collect(),
+        image_id: instance.image_id().map(|image_id| image_id.to_string()),
+        spot: instance.instance_lifecycle()
+            == Some(&aws_sdk_ec2::types::InstanceLifecycleType::Spot),
+        platform: instance
+            .platform_details()
+            .map(|platform| platform.to_string()),
+        agent_version: None,
+    })
+}
+
+// the Name tags of the instances `ids`, by instance id
+async fn name_tags(
+    client: &aws_sdk_ec2::Client,
+    ids: Vec<String>,
+) -> Result<BTreeMap<String, String>> {
+    let output = client
+        .describe_tags()
+        .filters(
+            aws_sdk_ec2::types::Filter::builder()
+                .name("resource-id")
+                .set_values(Some(ids))
+                .build(),
+        )
+        .filters(
+            aws_sdk_ec2::types::Filter::builder()
+                .name("key")
+                .values("Name")
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(output
+        .tags()
+        .iter()
+        .filter_map(|tag| Some((tag.resource_id()?.to_string(), tag.value()?.to_string())))
+        .collect())
+}
+
+#[async_trait]
+impl InstanceProvider for AwsInstanceProvider {
+    async fn running_instances(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<InstanceSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_ec2, &config, ec2);
+        let pages = all_pages(
+            client
+                .describe_instances()
+                .filters(
+                    aws_sdk_ec2::types::Filter::builder()
+                        .name("instance-state-name")
+                        .values("running")
+                        .build(),
+                )
+                .set_max_results(self.page_size)
+                .into_paginator()
+                .send(),
+        )
+        .await?;
+        let mut instances = pages
+            .iter()
+            .flat_map(|page| page.reservations())
+            .flat_map(|reservation| reservation.instances())
+            .filter_map(instance_summary)
+            .collect::<Vec<_>>();
+        // tag-limited roles get instances without their tags, ec2:DescribeTags may still show the
+        // names, with one call per page of instances. Without it the instances go by their ids.
+        if instances.iter().all(|instance| instance.name.is_empty()) {
+            for page in pages.iter() {
+                let ids: Vec<String> = page
+                    .reservations()
+                    .iter()
+                    .flat_map(|reservation| reservation.instances())
+                    .filter_map(|instance| instance.instance_id())
+                    .map(|id| id.to_string())
+                    .collect();
+                if ids.is_empty() {
+                    continue;
+                }
+                let Ok(names) = name_tags(&client, ids).await else {
+                    break;
+                };
+                for instance in instances.iter_mut() {
+                    if let Some(name) = names.get(&instance.id) {
+                        instance.name = name.clone();
+                        instance.tags.insert("Name".to_string(), name.clone());
+                    }
+                }
+            }
+        }
+        // listing nodes needs ssm:DescribeInstanceInformation, without it only ec2 is listed
+        for node in ssm_nodes(&config).await.unwrap_or_default() {
+            // the ec2 entry of an instance has more details
+            match instances.iter_mut().find(|instance| instance.id == node.id) {
+                Some(instance) => instance.agent_version = node.agent_version,
+                None if node.hybrid => instances.push(node),
+                None => {}
+            }
+        }
+        Ok(instances)
+    }
+
+    // one lookup instead of listing every instance of the account
+    async fn find_instance(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        id_or_ip: &str,
+    ) -> Result<Option<InstanceSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_ec2, &config, ec2);
+        let filter = if id_or_ip.starts_with("i-") {
+            "instance-id"
+        } else {
+            "private-ip-address"
+        };
+        let output = client
+            .describe_instances()
+            .filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name("instance-state-name")
+                    .values("running")
+                    .build(),
+            )
+            .filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name(filter)
+                    .values(id_or_ip)
+                    .build(),
+            )
+            .send()
+            .await?;
+        let instance = output
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances())
+            .filter_map(instance_summary)
+            .find(|instance| instance.matches(id_or_ip));
+        match instance {
+            Some(instance) => Ok(Some(instance)),
+            None => Ok(ssm_nodes(&config)
+                .await?
+                .into_iter()
+                .find(|node| node.hybrid && node.matches(id_or_ip))),
+        }
+    }
+
+    async fn auto_scaling_groups(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<AutoScalingGroupSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_autoscaling, &config, autoscaling);
+        let pages = all_pages(
+            client
+                .describe_auto_scaling_groups()
+                .into_paginator()
+                .send(),
+        )
+        .await?;
+        Ok(pages
+            .iter()
+            .flat_map(|page| page.auto_scaling_groups())
+            .map(|group| AutoScalingGroupSummary {
+                name: group
+                    .auto_scaling_group_name()
+                    .unwrap_or_default()
+                    .to_string(),
+                instance_ids: group
+                    .instances()
+                    .iter()
+                    .filter(|instance| {
+                        instance.health_status() == Some("Healthy")
+                            && instance.lifecycle_state()
+                                == Some(&aws_sdk_autoscaling::types::LifecycleState::InService)
+                    })
+                    .filter_map(|instance| instance.instance_id().map(str::to_string))
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn image_names(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        image_ids: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_ec2, &config, ec2);
+        let output = client
+            .describe_images()
+            .set_image_ids(Some(image_ids.to_vec()))
+            .send()
+            .await?;
+        Ok(output
+            .images()
+            .iter()
+            .filter_map(|image| Some((image.image_id()?.to_string(), image.name()?.to_string())))
+            .collect())
+    }
+
+    async fn account(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+    ) -> Result<Option<AccountSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let identity = client!(aws_sdk_sts, &config, sts)
+            .get_caller_identity()
+            .send()
+            .await?;
+        // listing aliases needs iam:ListAccountAliases, which not every role has
+        let alias = client!(aws_sdk_iam, &config, iam)
+            .list_account_aliases()
+            .send()
+            .await
+            .ok()
+            .and_then(|output| output.account_aliases().first().cloned());
+        Ok(Some(AccountSummary {
+            id: identity.account().unwrap_or_default().to_string(),
+            alias,
+            region: config.region().map(|region| region.to_string()),
+        }))
+    }
+}
+
+#[async_trait]
+impl DestinationProvider for AwsDestinationProvider {
+    async fn destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+    ) -> Result<Vec<DestinationSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        match service {
+            #[cfg(feature = "alb")]
+            Service::ApplicationLoadBalancer => application_load_balancers(&config).await,
+            #[cfg(feature = "rds")]
+            Service::Postgresql => postgresql_servers(&config, None).await,
+            #[cfg(feature = "elasticache")]
+            Service::Redis => redis_servers(&config).await,
+            #[cfg(feature = "elasticache")]
+            Service::Valkey => Ok(vec![]),
+        }
+    }
+
+    // database clusters are picked before their endpoints
+    #[cfg(feature = "rds")]
+    async fn clusters(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+    ) -> Result<Vec<ClusterSummary>> {
+        match service {
+            Service::Postgresql => {
+                let config =
+                    load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+                postgresql_clusters(&config).await
+            }
+            #[allow(unreachable_patterns)]
+            _ => Ok(vec![]),
+        }
+    }
+
+    #[cfg(feature = "rds")]
+    async fn cluster_destinations(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        service: &Service,
+        cluster_id: &str,
+    ) -> Result<Vec<DestinationSummary>> {
+        match service {
+            Service::Postgresql => {
+                let config =
+                    load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+                postgresql_servers(&config, Some(cluster_id)).await
+            }
+            #[allow(unreachable_patterns)]
+            _ => self.destinations(profile_name, region, service).await,
+        }
+    }
+
+    // starts a stopped aurora cluster and waits until it takes connections
+    #[cfg(feature = "rds")]
+    async fn start_cluster(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        _service: &Service,
+        cluster_id: &str,
+    ) -> Result<()> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_rds, &config, rds);
+        client
+            .start_db_cluster()
+            .db_cluster_identifier(cluster_id)
+            .send()
+            .await?;
+        let started = std::time::Instant::now();
+        loop {
+            tokio::time::sleep(CLUSTER_START_POLL).await;
+            let output = client
+                .describe_db_clusters()
+                .db_cluster_identifier(cluster_id)
+                .send()
+                .await?;
+            let status = output
+                .db_clusters()
+                .first()
+                .and_then(|cluster| cluster.status())
+                .unwrap_or_default();
+            if status == "available" {
+                return Ok(());
+            }
+            if started.elapsed() > CLUSTER_START_TIMEOUT {
+                return Err(eyre!(
+                    "cluster {} is still {} after {} minutes",
+                    cluster_id,
+                    status,
+                    CLUSTER_START_TIMEOUT.as_secs() / 60
+                )
+                .into());
+            }
+        }
+    }
+
+    #[cfg(feature = "alb")]
+    async fn target_groups(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        load_balancer: &str,
+    ) -> Result<Vec<TargetGroupSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let client = client!(aws_sdk_elasticloadbalancingv2, &config, elbv2);
+        let output = client
+            .describe_load_balancers()
+            .names(load_balancer)
+            .send()
+            .await?;
+        let Some(arn) = output
+            .load_balancers()
+            .first()
+            .and_then(|lb| lb.load_balancer_arn())
+        else {
+            return Ok(vec![]);
+        };
+        let output = client
+            .describe_target_groups()
+            .load_balancer_arn(arn)
+            .send()
+            .await?;
+        Ok(output
+            .target_groups()
+            .iter()
+            // lambda targets have no address to forward to
+            .filter(|group| group.target_type().map(|kind| kind.as_str()) != Some("lambda"))
+            .filter_map(|group| {
+                Some(TargetGroupSummary {
+                    arn: group.target_group_arn()?.to_string(),
+                    name: group.target_group_name().unwrap_or_default().to_string(),
+                    protocol: group
+                        .protocol()
+                        .map(|protocol| protocol.as_str().to_string()),
+                    port: group.port().and_then(|port| u16::try_from(port).ok()),
+                })
+            })
+            .collect())
+    }
+
+    // instance targets are listed by id, their private ip comes from describe_instances
+    #[cfg(feature = "alb")]
+    async fn targets(
+        &self,
+        profile_name: &str,
+        region: Option<&str>,
+        target_group_arn: &str,
+    ) -> Result<Vec<TargetSummary>> {
+        let config = load_region_config(profile_name, region, self.endpoint_url.as_deref()).await;
+        let output = client!(aws_sdk_elasticloadbalancingv2, &config, elbv2)
+            .describe_target_health()
+            .target_group_arn(target_group_arn)
+            .send()
+            .await?;
+        let descriptions = output.target_health_descriptions();
+        let instance_ids: Vec<String> = descriptions
+            .iter()
+            .filter_map(|description| description.target()?.id())
+            .filter(|id| id.starts_with("i-"))
+            .map(str::to_string)
+            .collect();
+        let mut private_ips = BTreeMap::new();
+        if !instance_ids.is_empty() {
+            let output = client!(aws_sdk_ec2, &config, ec2)
+                .describe_instances()
+                .set_instance_ids(Some(instance_ids))
+                .send()
+                .await?;
+            for instance in output
+                .reservations()
+                .iter()
+                .flat_map(|reservation| reservation.instances())
+            {
+                if let (Some(id), Some(ip)) =
+                    (instance.instance_id(), instance.private_ip_address())
+                {
+                    private_ips.insert(id.to_string(), ip.to_string());
+                }
+            }
+        }
+        Ok(descriptions
+            .iter()
+            .filter_map(|description| {
+                let target = description.target()?;
+                let id = target.id()?.to_string();
+                let host = if id.starts_with("i-") {
+                    private_ips.get(&id)?.clone()
+                } else {
+                    id.clone()
+                };
+                let health = description.target_health();
+                Some(TargetSummary {
+                    id,
+                    host,
+                    port: target.port().and_then(|port| u16::try_from(port).ok()),
+                    state: health
+                        .and_then(|health| health.state())
+                        .map(|state| state.as_str().to_string())
+                        .unwrap_or("unknown".to_string()),
+                    reason: health
+                        .and_then(|health| health.reason())
+                        .map(|reason| reason.as_str().to_string()),
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(any(feature = "alb", feature = "rds"))]
+fn tags<'a>(tags: impl Iterator<Item = (Option<&'a str>, Option<&'a str>)>) -> Tags {
+    tags.filter_map(|(key, value)| Some((key?.to_string(), value.unwrap_or_default().to_string())))
+        .collect()
+}
+
+#[cfg(feature = "alb")]
+async fn application_load_balancers(config: &SdkConfig) -> Result<Vec<DestinationSummary>> {
+    let client = client!(aws_sdk_elasticloadbalancingv2, config, elbv2);
+    let pages = all_pages(client.describe_load_balancers().into_paginator().send()).await?;
+    let load_balancers: Vec<_> = pages
+        .iter()
+        .flat_map(|page| page.load_balancers())
+        .collect();
+    let arns: Vec<_> = load_balancers
+        .iter()
+        .filter_map(|lb| lb.load_balancer_arn())
+        .map(|arn| arn.to_string())
+        .collect();
+    let (tags_by_arn, ports_by_arn) = tokio::join!(
+        load_balancer_tags(&client, arns.clone()),
+        load_balancer_ports(&client, arns)
+    );
+    let mut tags_by_arn = tags_by_arn?;
+    Ok(load_balancers
+        .iter()
+        .filter_map(|lb| {
+            lb.dns_name().map(|dns_name| DestinationSummary {
+                host: dns_name.to_string(),
+                label: lb.load_balancer_name().unwrap_or(dns_name).to_string(),
+                port: lb
+                    .load_balancer_arn()
+                    .and_then(|arn| ports_by_arn.get(arn))
+                    .copied(),
+                vpc_id: lb.vpc_id().map(|vpc_id| vpc_id.to_string()),
+                zones: lb
+                    .availability_zones()
+                    .iter()
+                    .filter_map(|zone| zone.zone_name())
+                    .map(|zone| zone.to_string())
+                    .collect(),
+                tags: lb
+                    .load_balancer_arn()
+                    .and_then(|arn| tags_by_arn.remove(arn))
+                    .unwrap_or_default(),
+                tls: false,
+                cluster_mode: false,
+                security_groups: lb.security_groups().to_vec(),
+                member: false,
+                state: lb
+                    .state()
+                    .and_then(|state| state.code())
+                    .map(|code| code.as_str().to_string()),
+                cluster_id: None,
+            })
+        })
+        .collect())
+}
+
+// describe_tags takes at most 20 load balancers, the batches are requested concurrently
+#[cfg(feature = "alb")]
+async fn load_balancer_tags(
+    client: &aws_sdk_elasticloadbalancingv2::Client,
+    arns: Vec<String>,
+) -> Result<BTreeMap<String, Tags>> {
+    let mut requests = tokio::task::JoinSet::new();
+    for batch in arns.chunks(20) {
+        requests.spawn(
+            client
+                .describe_tags()
+                .set_resource_arns(Some(batch.to_vec()))
+                .send(),
+        );
+    }
+    let mut tags_by_arn = BTreeMap::new();
+    while let Some(output) = requests.join_next().await {
+        let output = output.map_err(color_eyre::Report::from)??;
+        for description in output.tag_descriptions() {
+            if let Some(arn) = description.resource_arn() {
+                tags_by_arn.insert(
+                    arn.to_string(),
+                    tags(
+                        description
+                            .tags()
+                            .iter()
+                            .map(|tag| (tag.key(), tag.value())),
+                    ),
+                );
+            }
+        }
+    }
+    Ok(tags_by_arn)
+}
+
+// the https listener, otherwise the lowest port; without elasticloadbalancing:DescribeListeners
+// the service default is proposed
+#[cfg(feature = "alb")]
+async fn load_balancer_ports(
+    client: &aws_sdk_elasticloadbalancingv2::Client,
+    arns: Vec<String>,
+) -> BTreeMap<String, u16> {
+    let mut requests = tokio::task::JoinSet::new();
+    for arn in arns {
+        let request = client.describe_listeners().load_balancer_arn(&arn).send();
+        requests.spawn(async move { (arn, request.await) });
+    }
+    let mut ports_by_arn = BTreeMap::new();
+    while let Some(result) = requests.join_next().await {
+        let Ok((arn, Ok(output))) = result else {
+            continue;
+        };
+        let port = output
+            .listeners()
+            .iter()
+            .filter_map(|listener| {
+                let port = u16::try_from(listener.port()?).ok()?;
+                let https = listener.protocol().map(|protocol| protocol.as_str()) == Some("HTTPS");
+                Some((!https, port))
+            })
+            .min()
+            .map(|(_, port)| port);
+        if let Some(port) = port {
+            ports_by_arn.insert(arn, port);
+        }
+    }
+    ports_by_arn
+}
+
+#[cfg(feature = "rds")]
+async fn postgresql_clusters(config: &SdkConfig) -> Result<Vec<ClusterSummary>> {
+    let client = client!(aws_sdk_rds, config, rds);
+    let pages = all_pages(client.describe_db_clusters().into_paginator().send()).await?;
+    let mut clusters: Vec<_> = pages
+        .iter()
+        .flat_map(|page| page.db_clusters())
+        .filter_map(|cluster| {
+            Some(ClusterSummary {
+                id: cluster.db_cluster_identifier()?.to_string(),
+                engine: cluster.engine().map(|engine| engine.to_string()),
+                version: cluster.engine_version().map(|version| version.to_string()),
+                tags: tags(
+                    cluster
+                        .tag_list()
+                        .iter()
+                        .map(|tag| (tag.key(), tag.value())),
+                ),
+                state: cluster.status().map(|status| status.to_string()),
+            })
+        })
+        .collect();
+    // clusters that do not take connections, e.g. stopped ones, come last
+    clusters.sort_by(|a, b| (!a.usable(), &a.id).cmp(&(!b.usable(), &b.id)));
+    Ok(clusters)
+}
+
+// endpoints carry neither tags nor a vpc, those come from the clusters and their instances.
+// The endpoints of the clusters' instances follow the cluster endpoints.
+#[cfg(feature = "rds")]
+async fn postgresql_servers(
+    config: &SdkConfig,
+    cluster_id: Option<&str>,
+) -> Result<Vec<DestinationSummary>> {
+    let client = client!(aws_sdk_rds, config, rds);
+    let instance_filter = cluster_id.map(|cluster_id| {
+        aws_sdk_rds::types::Filter::builder()
+            .name("db-cluster-id")
+            .values(cluster_id)
+            .build()
+    });
+    let (endpoint_pages, cluster_pages, instance_pages) = tokio::try_join!(
+        all_pages(
+            client
+                .describe_db_cluster_endpoints()
+                .set_db_cluster_identifier(cluster_id.map(|id| id.to_string()))
+                .into_paginator()
+                .send()
+        ),
+        all_pages(
+            client
+                .describe_db_clusters()
+                .set_db_cluster_identifier(cluster_id.map(|id| id.to_string()))
+                .into_paginator()
+                .send()
+        ),
+        all_pages(
+            client
+                .describe_db_instances()
+                .set_filters(instance_filter.map(|filter| vec![filter]))
+                .into_paginator()
+                .send()
+        ),
+    )?;
+    let clusters: BTreeMap<_, _> = cluster_pages
+        .iter()
+        .flat_map(|page| page.db_clusters())
+        .filter_map(|cluster| Some((cluster.db_cluster_identifier()?, cluster)))
+        .collect();
+    let cluster_vpcs: BTreeMap<_, _> = instance_pages
+        .iter()
+        .flat_map(|page| page.db_instances())
+        .filter_map(|instance| {
+            Some((
+                instance.db_cluster_identifier()?,
+                instance.db_subnet_group()?.vpc_id()?,
+            ))
+        })
+        .collect();
+    let mut endpoints: Vec<_> = endpoint_pages
+        .iter()
+        .flat_map(|page| page.db_cluster_endpoints())
+        .collect();
+    // a cluster's writer, then its readers, then its custom endpoints
+    endpoints.sort_by_key(|&endpoint| {
+        (
+            endpoint.db_cluster_identifier().unwrap_or_default(),
+            match endpoint.endpoint_type() {
+                Some("WRITER") => 0,
+                Some("READER") => 1,
+                _ => 2,
+            },
+            endpoint
+                .db_cluster_endpoint_identifier()
+                .unwrap_or_default(),
+        )
+    });
+    let mut destinations: Vec<_> = endpoints
+        .into_iter()
+        .filter_map(|db_cluster_endpoint| {
+            let cluster_id = db_cluster_endpoint.db_cluster_identifier();
+            db_cluster_endpoint
+                .endpoint()
+                .map(|endpoint| DestinationSummary {
+                    host: endpoint.to_string(),
+                    label: cluster_endpoint_label(db_cluster_endpoint)
+                        .unwrap_or(endpoint.to_string()),
+                    // endpoints share the port of their cluster
+                    port: cluster_id
+                        .and_then(|id| clusters.get(id))
+                        .and_then(|cluster| cluster.port())
+                        .and_then(|port| u16::try_from(port).ok()),
+                    vpc_id: cluster_id
+                        .and_then(|id| cluster_vpcs.get(id))
+                        .map(|vpc_id| vpc_id.to_string()),
+                    zones: cluster_id
+                        .and_then(|id| clusters.get(id))
+                        .map(|cluster| cluster.availability_zones().to_vec())
+                        .unwrap_or_default(),
+                    tags: cluster_id
+                        .and_then(|id| clusters.get(id))
+                        .map(|cluster| {
+                            tags(
+                                cluster
+                                    .tag_list()
+                                    .iter()
+                                    .map(|tag| (tag.key(), tag.value())),
+                            )
+                        })
+                        .unwrap_or_default(),
+                    tls: false,
+                    cluster_mode: false,
+                    security_groups: cluster_id
+                        .and_then(|id| clusters.get(id))
+                        .map(|cluster| {
+                            cluster
+                                .vpc_security_groups()
+                                .iter()
+                                .filter_map(|group| group.vpc_security_group_id())
+                                .map(|group_id| group_id.to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    member: false,
+                    state: cluster_id
+                        .and_then(|id| clusters.get(id))
+                        .and_then(|cluster| cluster.status())
+                        .map(|status| status.to_string()),
+                    cluster_id: cluster_id.map(|id| id.to_string()),
+                })
+        })
+        .collect();
+    // the instances of the listed clusters, to reach e.g. a reader in the same zone directly
+    destinations.extend(
+        instance_pages
+            .iter()
+            .flat_map(|page| page.db_instances())
+            .filter_map(|instance| {
+                let cluster = clusters.get(instance.db_cluster_identifier()?)?;
+                let endpoint = instance.endpoint()?;
+                let address = endpoint.address()?;
+                Some(DestinationSummary {
+                    host: address.to_string(),
+                    label: cluster_instance_label(cluster, instance),
+                    port: endpoint.port().and_then(|port| u16::try_from(port).ok()),
+                    vpc_id: instance
+                        .db_subnet_group()
+                        .and_then(|group| group.vpc_id())
+                        .map(|vpc_id| vpc_id.to_string()),
+                    zones: instance
+                        .availability_zone()
+                        .map(|zone| vec![zone.to_string()])
+                        .unwrap_or_default(),
+                    tags: tags(
+                        instance
+                            .tag_list()
+                            .iter()
+                            .map(|tag| (tag.key(), tag.value())),
+                    ),
+                    tls: false,
+                    cluster_mode: false,
+                    security_groups: instance
+                        .vpc_security_groups()
+                        .iter()
+                        .filter_map(|group| group.vpc_security_group_id())
+                        .map(|group_id| group_id.to_string())
+                        .collect(),
+                    member: true,
+                    state: instance
+                        .db_instance_status()
+                        .map(|status| status.to_string()),
+                    cluster_id: instance.db_cluster_identifier().map(|id| id.to_string()),
+                })
+            }),
+    );
+    Ok(destinations)
+}
+
+// e.g. "payments-prod/payments-prod-2 (reader, eu-west-1a)"
+#[cfg(feature = "rds")]
+fn cluster_instance_label(
+    cluster: &aws_sdk_rds::types::DbCluster,
+    instance: &aws_sdk_rds::types::DbInstance,
+) -> String {
+    let instance_id = instance.db_instance_identifier().unwrap_or_default();
+    let role = match cluster
+        .db_cluster_members()
+        .iter()
+        .find(|member| member.db_instance_identifier() == Some(instance_id))
+        .and_then(|member| member.is_cluster_writer())
+    {
+        Some(true) => "writer",
+        Some(false) => "reader",
+        None => "instance",
+    };
+    let mut details = vec![role];
+    details.extend(instance.availability_zone());
+    format!(
+        "{}/{} ({})",
+        cluster.db_cluster_identifier().unwrap_or_default(),
+        instance_id,
+        details.join(", ")
+    )
+}
+
+// e.g. "payments-prod (writer)" or "payments-prod (custom analytics, reader)"
+#[cfg(feature = "rds")]
+fn cluster_endpoint_label(endpoint: &aws_sdk_rds::types::DbClusterEndpoint) -> Option<String> {
+    let cluster_id = endpoint.db_cluster_identifier()?;
+    let role = match endpoint.endpoint_type()?.to_lowercase().as_str() {
+        "custom" => format!(
+            "custom {}, {}",
+            endpoint
+                .db_cluster_endpoint_identifier()
+                .unwrap_or_default(),
+            endpoint
+                .custom_endpoint_type()
+                .unwrap_or("any")
+                .to_lowercase()
+        ),
+        role => role.to_string(),
+    };
+    Some(format!("{} ({})", cluster_id, role))
+}
+
+#[cfg(feature = "elasticache")]
+async fn redis_servers(config: &SdkConfig) -> Result<Vec<DestinationSummary>> {
+    let client = client!(aws_sdk_elasticache, config, elasticache);
+    let (cluster_pages, group_pages) = tokio::try_join!(
+        all_pages(
+            client
+                .describe_cache_clusters()
+                .show_cache_node_info(true)
+                .into_paginator()
+                .send()
+        ),
+        all_pages(client.describe_replication_groups().into_paginator().send()),
+    )?;
+    // cluster mode is a setting of the replication group, not of its nodes
+    let cluster_mode: BTreeMap<_, _> = group_pages
+        .iter()
+        .flat_map(|page| page.replication_groups())
+        .filter_map(|group| {
+            Some((
+                group.replication_group_id()?,
+                group.cluster_enabled().unwrap_or_default(),
+            ))
+        })
+        .collect();
+    Ok(cluster_pages
+        .iter()
+        .flat_map(|page| page.cache_clusters())
+        .filter_map(|cluster| {
+            let endpoint = cluster.cache_nodes().first()?.endpoint()?;
+            let address = endpoint.address()?;
+            Some(DestinationSummary {
+                host: address.to_string(),
+                label: address.to_string(),
+                port: endpoint.port().and_then(|port| u16::try_from(port).ok()),
+                vpc_id: None,
+                zones: cluster
+                    .preferred_availability_zone()
+                    .map(|zone| vec![zone.to_string()])
+                    .unwrap_or_default(),
+                tags: Tags::new(),
+                tls: cluster.transit_encryption_enabled().unwrap_or_default(),
+                cluster_mode: cluster
+                    .replication_group_id()
+                    .and_then(|id| cluster_mode.get(id))
+                    .copied()
+                    .unwrap_or_default(),
+                security_groups: cluster
+                    .security_groups()
+                    .iter()
+                    .filter_map(|group| group.security_group_id())
+                    .map(|group_id| group_id.to_string())
+                    .collect(),
+                member: false,
+                state: cluster
+                    .cache_cluster_status()
+                    .map(|status| status.to_string()),
+                cluster_id: None,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ec2::types::{GroupIdentifier, Instance, InstanceLifecycleType, Placement, Tag};
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag::builder().key(key).value(value).build()
+    }
+
+    #[test]
+    fn instances_are_named_by_their_name_tag() {
+        let instance = Instance::builder()
+            .instance_id("i-0123")
+            .tags(tag("team", "payments"))
+            .tags(tag("Name", "bastion"))
+            .placement(Placement::builder().availability_zone("eu-west-1a").build())
+            .security_groups(GroupIdentifier::builder().group_id("sg-1").build())
+            .instance_lifecycle(InstanceLifecycleType::Spot)
+            .build();
+        let summary = instance_summary(&instance).unwrap();
+        assert_eq!(summary.id, "i-0123");
+        assert_eq!(summary.name, "bastion");
+        assert_eq!(summary.availability_zone.as_deref(), Some("eu-west-1a"));
+        assert_eq!(summary.security_groups, ["sg-1"]);
+        assert!(summary.spot);
+        assert_eq!(
+            summary.tags,
+            Tags::from([
+                ("Name".to_string(), "bastion".to_string()),
+                ("team".to_string(), "payments".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn instances_without_a_name_tag_have_an_empty_name() {
+        let instance = Instance::builder()
+            .instance_id("i-0123")
+            .tags(tag("name", "lowercase"))
+            .build();
+        let summary = instance_summary(&instance).unwrap();
+        assert_eq!(summary.name, "");
+        assert!(!summary.spot);
+        // without an id there is nothing to start a session on
+        assert_eq!(instance_summary(&Instance::builder().build()), None);
+    }
+
+    #[cfg(any(feature = "alb", feature = "rds"))]
+    #[test]
+    fn tags_without_a_key_are_left_out() {
+        let tags = tags(
+            [
+                (Some("env"), Some("prod")),
+                (Some("empty"), None),
+                (None, Some("orphan")),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            tags,
+            Tags::from([
+                ("empty".to_string(), String::new()),
+                ("env".to_string(), "prod".to_string()),
+            ])
+        );
+    }
+
+    #[cfg(feature = "rds")]
+    #[test]
+    fn cluster_instances_are_labeled_with_their_role_and_zone() {
+        use aws_sdk_rds::types::{DbCluster, DbClusterMember, DbInstance};
+        let cluster = DbCluster::builder()
+            .db_cluster_identifier("payments-prod")
+            .db_cluster_members(
+                DbClusterMember::builder()
+                    .db_instance_identifier("payments-prod-1")
+                    .is_cluster_writer(true)
+                    .build(),
+            )
+            .db_cluster_members(
+                DbClusterMember::builder()
+                    .db_instance_identifier("payments-prod-2")
+                    .is_cluster_writer(false)
+                    .build(),
+            )
+            .build();
+        let instance = |id: &str, zone: Option<&str>| {
+            DbInstance::builder()
+                .db_instance_identifier(id)
+                .set_availability_zone(zone.map(str::to_string))
+                .build()
+        };
+        assert_eq!(
+            cluster_instance_label(&cluster, &instance("payments-prod-1", Some("eu-west-1a"))),
+            "payments-prod/payments-prod-1 (writer, eu-west-1a)"
+        );
+        assert_eq!(
+            cluster_instance_label(&cluster, &instance("payments-prod-2", Some("eu-west-1b"))),
+            "payments-prod/payments-prod-2 (reader, eu-west-1b)"
+        );
+        assert_eq!(
+            cluster_instance_label(&cluster, &instance("payments-prod-3", None)),
+            "payments-prod/payments-prod-3 (instance)"
+        );
+    }
+
+    #[cfg(feature = "rds")]
+    #[test]
+    fn cluster_endpoints_are_labeled_with_their_type() {
+        use aws_sdk_rds::types::DbClusterEndpoint;
+        let endpoint = |endpoint_type: Option<&str>, custom: Option<&str>| {
+            DbClusterEndpoint::builder()
+                .db_cluster_identifier("payments-prod")
+                .db_cluster_endpoint_identifier("analytics")
+                .set_endpoint_type(endpoint_type.map(str::to_string))
+                .set_custom_endpoint_type(custom.map(str::to_string))
+                .build()
+        };
+        assert_eq!(
+            cluster_endpoint_label(&endpoint(Some("WRITER"), None)).as_deref(),
+            Some("payments-prod (writer)")
+        );
+        assert_eq!(
+            cluster_endpoint_label(&endpoint(Some("CUSTOM"), Some("READER"))).as_deref(),
+            Some("payments-prod (custom analytics, reader)")
+        );
+        assert_eq!(
+            cluster_endpoint_label(&endpoint(Some("CUSTOM"), None)).as_deref(),
+            Some("payments-prod (custom analytics, any)")
+        );
+        // the host name is shown instead
+        assert_eq!(cluster_endpoint_label(&endpoint(None, None)), None);
+    }
+}
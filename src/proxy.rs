@@ -0,0 +1,128 @@
+use crate::config::Config;
+use aws_sdk_ssm::config::SharedHttpClient;
+use aws_smithy_http_client::{proxy::ProxyConfig, tls, Builder, Connector};
+use color_eyre::{eyre::eyre, Result};
+use std::sync::OnceLock;
+use tokio::process::Command;
+
+static PROXY: OnceLock<Proxy> = OnceLock::new();
+static HTTP_CLIENT: OnceLock<SharedHttpClient> = OnceLock::new();
+
+/// The proxy settings every AWS call and every started aws cli or session-manager-plugin use.
+#[derive(Clone, Debug, Default)]
+pub struct Proxy {
+    pub https: Option<String>,
+    pub no_proxy: Option<String>,
+    // where the values come from, for --verbose
+    pub source: &'static str,
+}
+
+impl Proxy {
+    fn from_env() -> Self {
+        let var = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        };
+        Proxy {
+            https: var(&["HTTPS_PROXY", "https_proxy"]),
+            no_proxy: var(&["NO_PROXY", "no_proxy"]),
+            source: "environment",
+        }
+    }
+
+    /// What `--verbose` prints, with a NO_PROXY that lets the session's websocket bypass the
+    /// proxy, which is what usually breaks behind one.
+    pub fn describe(&self, region: Option<&str>) -> Vec<String> {
+        let Some(https) = self.https.as_ref() else {
+            return vec![format!("proxy: none ({})", self.source)];
+        };
+        let mut lines = vec![
+            format!("proxy: {} ({})", https, self.source),
+            format!(
+                "no proxy: {}",
+                self.no_proxy.as_deref().unwrap_or("not set")
+            ),
+        ];
+        let websocket = format!("ssmmessages.{}.amazonaws.com", region.unwrap_or("<region>"));
+        let mut suggested: Vec<&str> = self
+            .no_proxy
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .collect();
+        if !suggested.contains(&websocket.as_str()) {
+            suggested.push(&websocket);
+            lines.push(format!(
+                "if sessions fail to connect, try NO_PROXY={}",
+                suggested.join(",")
+            ));
+        }
+        lines
+    }
+
+    // both spellings, the aws cli and the plugin do not agree on one
+    pub(crate) fn apply(&self, command: &mut Command) {
+        if let Some(https) = self.https.as_ref() {
+            command.env("HTTPS_PROXY", https).env("https_proxy", https);
+        }
+        if let Some(no_proxy) = self.no_proxy.as_ref() {
+            command.env("NO_PROXY", no_proxy).env("no_proxy", no_proxy);
+        }
+    }
+}
+
+/// Takes the proxy from `[proxy]` in config.toml, each value falling back to the environment.
+/// Only the first call has an effect, and it has to come before any AWS call.
+pub fn use_proxy() -> Result<&'static Proxy> {
+    let configured = Config::load()?.proxy;
+    let env = Proxy::from_env();
+    let source = if configured.https.is_some() || configured.no_proxy.is_some() {
+        "config.toml"
+    } else {
+        env.source
+    };
+    let proxy = Proxy {
+        https: configured.https.or(env.https),
+        no_proxy: configured.no_proxy.or(env.no_proxy),
+        source,
+    };
+    if let Some(https) = proxy.https.as_ref() {
+        // checked once here instead of failing every call later
+        ProxyConfig::all(https.as_str()).map_err(|e| eyre!("invalid proxy '{}': {}", https, e))?;
+    }
+    Ok(PROXY.get_or_init(|| proxy))
+}
+
+pub(crate) fn proxy() -> &'static Proxy {
+    PROXY.get_or_init(Proxy::from_env)
+}
+
+// the same proxy for every SdkConfig, None leaves the default client in place
+pub(crate) fn http_client() -> Option<SharedHttpClient> {
+    let proxy = proxy();
+    let https = proxy.https.as_ref()?;
+    let client = HTTP_CLIENT.get_or_init(|| {
+        let mut proxy_config =
+            ProxyConfig::all(https.as_str()).unwrap_or_else(|_| ProxyConfig::disabled());
+        if let Some(no_proxy) = proxy.no_proxy.as_ref() {
+            proxy_config = proxy_config.no_proxy(no_proxy);
+        }
+        // the proxy is a connector setting, so the connector is built the way build_https does
+        Builder::new().build_with_connector_fn(move |settings, components| {
+            let mut connector = Connector::builder().proxy_config(proxy_config.clone());
+            connector.set_connector_settings(settings.cloned());
+            if let Some(components) = components {
+                connector.set_sleep_impl(components.sleep_impl());
+            }
+            connector
+                .tls_provider(tls::Provider::Rustls(
+                    tls::rustls_provider::CryptoMode::AwsLc,
+                ))
+                .build()
+        })
+    });
+    Some(client.clone())
+}
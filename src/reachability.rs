@@ -0,0 +1,167 @@
+use crate::{
+    error::Result,
+    providers::{all_pages, client, load_region_config},
+};
+use std::{
+    fmt::{Display, Formatter},
+    net::Ipv4Addr,
+};
+
+/// A best-effort guess whether the instance can open a connection to a destination, from the
+/// ingress rules of the destination's security groups.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reachability {
+    Reachable,
+    Blocked,
+    Unknown,
+}
+
+impl Display for Reachability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reachability::Reachable => write!(f, "✓ reachable"),
+            Reachability::Blocked => write!(f, "✗ likely blocked"),
+            Reachability::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+pub(crate) struct SecurityGroupSummary {
+    id: String,
+    ingress: Vec<IngressRule>,
+}
+
+// tcp ingress, sources are security group ids of the same account and ipv4 ranges
+struct IngressRule {
+    from_port: u16,
+    to_port: u16,
+    groups: Vec<String>,
+    cidrs: Vec<String>,
+    // prefix lists and groups of other accounts can not be checked
+    unresolved: bool,
+}
+
+/// The security groups of a vpc, for [`reachability`].
+pub(crate) async fn security_groups(
+    profile_name: String,
+    region: Option<String>,
+    vpc_id: String,
+) -> Result<Vec<SecurityGroupSummary>> {
+    let config = load_region_config(&profile_name, region.as_deref(), None).await;
+    let client = client!(aws_sdk_ec2, &config, ec2);
+    let pages = all_pages(
+        client
+            .describe_security_groups()
+            .filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name("vpc-id")
+                    .values(vpc_id)
+                    .build(),
+            )
+            .into_paginator()
+            .send(),
+    )
+    .await?;
+    Ok(pages
+        .iter()
+        .flat_map(|page| page.security_groups())
+        .filter_map(|group| {
+            let owner = group.owner_id();
+            Some(SecurityGroupSummary {
+                id: group.group_id()?.to_string(),
+                ingress: group
+                    .ip_permissions()
+                    .iter()
+                    .filter_map(|permission| {
+                        let (from_port, to_port) = match permission.ip_protocol()? {
+                            "-1" => (0, u16::MAX),
+                            "tcp" | "6" => (
+                                u16::try_from(permission.from_port()?).ok()?,
+                                u16::try_from(permission.to_port()?).ok()?,
+                            ),
+                            _ => return None,
+                        };
+                        let pairs = permission.user_id_group_pairs();
+                        Some(IngressRule {
+                            from_port,
+                            to_port,
+                            groups: pairs
+                                .iter()
+                                .filter(|pair| pair.user_id().is_none() || pair.user_id() == owner)
+                                .filter_map(|pair| pair.group_id())
+                                .map(|group_id| group_id.to_string())
+                                .collect(),
+                            cidrs: permission
+                                .ip_ranges()
+                                .iter()
+                                .filter_map(|range| range.cidr_ip())
+                                .map(|cidr| cidr.to_string())
+                                .collect(),
+                            unresolved: !permission.prefix_list_ids().is_empty()
+                                || pairs.iter().any(|pair| {
+                                    pair.user_id().is_some() && pair.user_id() != owner
+                                }),
+                        })
+                    })
+                    .collect(),
+            })
+        })
+        .collect())
+}
+
+/// Whether a rule of `destination_groups` lets the instance, with its security groups and
+/// private ip, connect to `port`. Groups that are not in `groups`, e.g. because they could not be
+/// described, make the answer unknown rather than blocked.
+pub(crate) fn reachability(
+    groups: &[SecurityGroupSummary],
+    destination_groups: &[String],
+    instance_groups: &[String],
+    instance_ip: Option<&str>,
+    port: u16,
+) -> Reachability {
+    let instance_ip = instance_ip.and_then(|ip| ip.parse::<Ipv4Addr>().ok());
+    let mut unresolved = destination_groups.is_empty();
+    for group_id in destination_groups {
+        let Some(group) = groups.iter().find(|group| &group.id == group_id) else {
+            unresolved = true;
+            continue;
+        };
+        for rule in group
+            .ingress
+            .iter()
+            .filter(|rule| (rule.from_port..=rule.to_port).contains(&port))
+        {
+            if rule
+                .groups
+                .iter()
+                .any(|group| instance_groups.contains(group))
+            {
+                return Reachability::Reachable;
+            }
+            match instance_ip {
+                Some(ip) if rule.cidrs.iter().any(|cidr| cidr_contains(cidr, ip)) => {
+                    return Reachability::Reachable
+                }
+                Some(_) => {}
+                None => unresolved |= !rule.cidrs.is_empty(),
+            }
+            unresolved |= rule.unresolved;
+        }
+    }
+    if unresolved {
+        Reachability::Unknown
+    } else {
+        Reachability::Blocked
+    }
+}
+
+fn cidr_contains(cidr: &str, ip: Ipv4Addr) -> bool {
+    let Some((network, length)) = cidr.split_once('/') else {
+        return false;
+    };
+    let (Ok(network), Ok(length)) = (network.parse::<Ipv4Addr>(), length.parse::<u32>()) else {
+        return false;
+    };
+    let mask = u32::MAX.checked_shl(32 - length.min(32)).unwrap_or(0);
+    u32::from(ip) & mask == u32::from(network) & mask
+}
@@ -0,0 +1,324 @@
+use crate::{config::Config, session::format_duration};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+// accept fails again right away while e.g. the process is out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+// older connections only remain in the totals of Traffic
+pub(crate) const RECENT_CONNECTIONS: usize = 3;
+
+// Listens on the requested address and forwards every connection to the tunnel's localhost port,
+// since session-manager-plugin only ever binds to localhost. Also the place where traffic is counted.
+pub struct Relay {
+    address: SocketAddr,
+    task: JoinHandle<()>,
+    events: UnboundedReceiver<String>,
+    traffic: Arc<Traffic>,
+}
+
+#[derive(Default)]
+struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+    active: AtomicU64,
+    connections: AtomicU64,
+    recent: Mutex<VecDeque<ConnectionRecord>>,
+}
+
+/// A closed connection through the relay.
+#[derive(Clone)]
+pub struct ConnectionRecord {
+    pub peer: SocketAddr,
+    pub started: SystemTime,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl ConnectionRecord {
+    pub fn summary(&self) -> String {
+        format!(
+            "{}  {}  {}  {}",
+            humantime::format_rfc3339_seconds(self.started),
+            self.peer,
+            format_bytes(self.bytes),
+            format_duration(self.duration)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct TrafficSnapshot {
+    pub sent: u64,
+    pub received: u64,
+    pub active: u64,
+    pub connections: u64,
+}
+
+impl Traffic {
+    fn snapshot(&self) -> TrafficSnapshot {
+        TrafficSnapshot {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, connection: ConnectionRecord) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CONNECTIONS {
+            recent.pop_front();
+        }
+        recent.push_back(connection);
+    }
+}
+
+impl Relay {
+    pub async fn start(address: SocketAddr, target_port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| eyre!("could not listen on {}: {}", address, e))?;
+        // the bound port, when port 0 was asked for
+        let address = listener.local_addr()?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let traffic = Arc::new(Traffic::default());
+        let task = tokio::spawn(accept(listener, target_port, sender, traffic.clone()));
+        Ok(Relay {
+            address,
+            task,
+            events,
+            traffic,
+        })
+    }
+
+    pub fn traffic(&self) -> TrafficSnapshot {
+        self.traffic.snapshot()
+    }
+
+    /// The last closed connections, oldest first.
+    pub fn recent_connections(&self) -> Vec<ConnectionRecord> {
+        self.traffic
+            .recent
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn events(&mut self) -> Vec<String> {
+        let mut events = vec![];
+        while let Ok(event) = self.events.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// an unused port for the plugin to listen on behind the relay
+pub fn free_local_port() -> Result<u16> {
+    Config::load()?.local_ports.free_port()
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    target_port: u16,
+    events: UnboundedSender<String>,
+    traffic: Arc<Traffic>,
+) {
+    loop {
+        let (mut inbound, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                let _ = events.send(format!("relay: accept failed: {}", e));
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let _ = events.send(format!("relay: connection from {}", peer));
+        let events = events.clone();
+        let traffic = traffic.clone();
+        tokio::spawn(async move {
+            match TcpStream::connect((Ipv4Addr::LOCALHOST, target_port)).await {
+                Ok(mut upstream) => {
+                    traffic.connections.fetch_add(1, Ordering::Relaxed);
+                    traffic.active.fetch_add(1, Ordering::Relaxed);
+                    let started_at = SystemTime::now();
+                    let started = Instant::now();
+                    let bytes = AtomicU64::new(0);
+                    let (inbound_read, inbound_write) = inbound.split();
+                    let (upstream_read, upstream_write) = upstream.split();
+                    let _ = tokio::join!(
+                        pipe(inbound_read, upstream_write, [&traffic.sent, &bytes]),
+                        pipe(upstream_read, inbound_write, [&traffic.received, &bytes]),
+                    );
+                    traffic.active.fetch_sub(1, Ordering::Relaxed);
+                    let connection = ConnectionRecord {
+                        peer,
+                        started: started_at,
+                        bytes: bytes.load(Ordering::Relaxed),
+                        duration: started.elapsed(),
+                    };
+                    let _ = events.send(format!("relay: closed {}", connection.summary()));
+                    traffic.record(connection);
+                }
+                Err(e) => {
+                    let _ = events.send(format!(
+                        "relay: tunnel is not reachable for {}: {}",
+                        peer, e
+                    ));
+                }
+            }
+        });
+    }
+}
+
+// like tokio::io::copy, but counts bytes as they flow instead of when the connection closes,
+// in the totals and in the connection's own counter
+async fn pipe(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    counters: [&AtomicU64; 2],
+) -> std::io::Result<()> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buffer[..read]).await?;
+        for counter in counters {
+            counter.fetch_add(read as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // reads a request to its end and answers with "pong"
+    async fn upstream() -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                stream.read_to_end(&mut request).await.unwrap();
+                stream.write_all(b"pong").await.unwrap();
+            }
+        });
+        port
+    }
+
+    async fn request(relay: &Relay, body: &[u8]) -> (SocketAddr, Vec<u8>) {
+        let mut stream = TcpStream::connect(relay.address()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        (stream.local_addr().unwrap(), response)
+    }
+
+    // connections are recorded once the relay has closed both directions
+    async fn closed(relay: &Relay, peer: SocketAddr) {
+        for _ in 0..100 {
+            let recent = relay.recent_connections();
+            if recent.iter().any(|connection| connection.peer == peer)
+                && relay.traffic().active == 0
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("connections through the relay were not closed");
+    }
+
+    #[tokio::test]
+    async fn traffic_through_the_relay_is_counted() {
+        let mut relay = Relay::start((Ipv4Addr::LOCALHOST, 0).into(), upstream().await)
+            .await
+            .unwrap();
+        assert_ne!(relay.address().port(), 0);
+        let (peer, response) = request(&relay, b"ping ping").await;
+        assert_eq!(response, b"pong");
+        closed(&relay, peer).await;
+
+        let traffic = relay.traffic();
+        assert_eq!((traffic.sent, traffic.received), (9, 4));
+        assert_eq!((traffic.active, traffic.connections), (0, 1));
+        let recent = relay.recent_connections();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].peer, peer);
+        assert_eq!(recent[0].bytes, 13);
+        assert!(relay
+            .events()
+            .contains(&format!("relay: connection from {}", peer)));
+    }
+
+    #[tokio::test]
+    async fn only_the_recent_connections_are_kept() {
+        let relay = Relay::start((Ipv4Addr::LOCALHOST, 0).into(), upstream().await)
+            .await
+            .unwrap();
+        let mut peers = vec![];
+        for _ in 0..=RECENT_CONNECTIONS {
+            let (peer, _) = request(&relay, b"ping").await;
+            closed(&relay, peer).await;
+            peers.push(peer);
+        }
+
+        let traffic = relay.traffic();
+        assert_eq!(traffic.connections, RECENT_CONNECTIONS as u64 + 1);
+        assert_eq!(traffic.sent, 4 * traffic.connections);
+        assert_eq!(
+            relay
+                .recent_connections()
+                .iter()
+                .map(|connection| connection.peer)
+                .collect::<Vec<_>>(),
+            peers[1..]
+        );
+    }
+}
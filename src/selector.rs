@@ -1,62 +1,147 @@
-use crate::porwarder::StringListSelector;
-use color_eyre::{eyre::eyre, Result};
+use crate::{
+    config::Config,
+    error::{PorwardError, Result},
+    events,
+    porwarder::{ErrorAction, SelectItem, StringListSelector},
+    prerequisites, terminal,
+    theme::Theme,
+};
+use color_eyre::eyre::eyre;
 use crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::{
-    style::{Modifier, Style},
-    text::Line,
-    widgets::{Block, Borders, HighlightSpacing, List, ListItem, ListState},
-    DefaultTerminal, TerminalOptions,
+    text::{Line, Span},
+    widgets::{Block, Borders, HighlightSpacing, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal,
+};
+use serde::{de::IntoDeserializer, Deserialize};
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    process::Stdio,
+    str::FromStr,
 };
 
+/// Inline ratatui list selector. The terminal is initialized on the first selection.
 pub struct TUIStringListSelector {
-    terminal: DefaultTerminal,
+    lines: u16,
+    terminal: Option<DefaultTerminal>,
     state: ListState,
+    theme: Theme,
+    account: Option<Line<'static>>,
 }
 impl TUIStringListSelector {
     pub fn inline_view(lines: u16) -> Self {
-        let terminal = ratatui::init_with_options(TerminalOptions {
-            viewport: ratatui::Viewport::Inline(lines),
-        });
         Self {
-            terminal,
+            lines,
+            terminal: None,
             state: ListState::default(),
+            theme: Theme::default(),
+            account: None,
         }
     }
+
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    // for NO_COLOR: no colors and no bold or dim text, the highlight symbol still marks the row
+    pub fn monochrome(mut self) -> Self {
+        self.theme = self.theme.monochrome();
+        self
+    }
 }
 
-impl StringListSelector for TUIStringListSelector {
-    fn select(&mut self, title: String, options: Vec<String>) -> Result<(usize, String)> {
+// the border of every prompt, with the account on the right once a profile is selected
+fn prompt_block(title: String, theme: &Theme, account: Option<&Line<'static>>) -> Block<'static> {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(theme.border)
+        .title(Line::from(title).style(theme.title).left_aligned());
+    match account {
+        Some(account) => block.title(account.clone().right_aligned()),
+        None => block,
+    }
+}
+
+impl TUIStringListSelector {
+    // with `multiple`, Space checks items and Enter confirms them, or the highlighted one alone
+    fn pick(
+        &mut self,
+        title: String,
+        options: Vec<SelectItem>,
+        initial: usize,
+        multiple: bool,
+    ) -> Result<Vec<SelectItem>> {
         if options.is_empty() {
-            return Err(eyre!("No options to select from for '{}'", title));
+            return Err(PorwardError::NoOptions { step: title });
         }
-        let mut index = 0;
-        let mut selected: Option<String> = None;
+        let lines = self.lines;
+        let theme = self.theme.clone();
+        let account = self.account.clone();
+        let terminal = self
+            .terminal
+            .get_or_insert_with(|| terminal::init_inline(lines));
+        let mut index = initial.min(options.len() - 1);
+        let mut checked = vec![false; options.len()];
+        let mut selected: Option<Vec<SelectItem>> = None;
         while selected.is_none() {
             self.state = self.state.clone().with_selected(Some(index));
-            self.terminal.draw(|frame| {
+            terminal.draw(|frame| {
                 let area = frame.area();
 
                 let items: Vec<_> = options
                     .iter()
                     .enumerate()
-                    .map(|(idx, item)| ListItem::from(format!("{}. {}", idx + 1, item)))
+                    .map(|(idx, item)| {
+                        let mark = match (multiple, checked[idx]) {
+                            (false, _) => "",
+                            (true, true) => "[x] ",
+                            (true, false) => "[ ] ",
+                        };
+                        let label = format!("{}{}. {}", mark, idx + 1, item.label);
+                        match item.detail.as_ref() {
+                            Some(detail) => ListItem::from(Line::from(vec![
+                                Span::raw(format!("{}  ", label)),
+                                Span::styled(detail.clone(), theme.detail),
+                            ])),
+                            None => ListItem::from(label),
+                        }
+                    })
                     .collect();
+                let mut block = prompt_block(
+                    format!("{} [{}/{}]", title, index + 1, options.len()),
+                    &theme,
+                    account.as_ref(),
+                );
+                if multiple {
+                    block = block
+                        .title_bottom(Line::from("space select · enter confirm").right_aligned());
+                }
                 let list = List::new(items)
-                    .block(
-                        Block::default().borders(Borders::ALL).title(
-                            Line::from(format!("{} [{}/{}]", title, index + 1, options.len()))
-                                .left_aligned(),
-                        ),
-                    )
-                    .highlight_symbol("〉")
+                    .block(block)
+                    .highlight_symbol(theme.symbol.as_str())
                     .highlight_spacing(HighlightSpacing::Always)
-                    .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+                    .highlight_style(theme.highlight);
                 frame.render_stateful_widget(list, area, &mut self.state);
             })?;
             match event::read()? {
                 Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Enter if checked.contains(&true) => {
+                        selected = Some(
+                            options
+                                .iter()
+                                .zip(checked.iter())
+                                .filter(|(_, checked)| **checked)
+                                .map(|(item, _)| item.clone())
+                                .collect(),
+                        );
+                    }
                     KeyCode::Enter => {
-                        selected = options.get(index).cloned();
+                        selected = options.get(index).cloned().map(|item| vec![item]);
+                    }
+                    KeyCode::Char(' ') if multiple => {
+                        checked[index] = !checked[index];
                     }
                     KeyCode::Up => {
                         index += options.len() - 1;
@@ -67,16 +152,351 @@ impl StringListSelector for TUIStringListSelector {
                         index %= options.len();
                     }
                     KeyCode::Esc => {
-                        return Err(eyre!("User canceled selection"));
+                        return Err(PorwardError::Cancelled);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        terminal.draw(|frame| {
+            frame.render_widget(Block::new(), frame.area());
+        })?;
+        Ok(selected.unwrap())
+    }
+}
+
+impl StringListSelector for TUIStringListSelector {
+    fn select_items(&mut self, title: String, options: Vec<SelectItem>) -> Result<SelectItem> {
+        self.select_items_at(title, options, 0)
+    }
+
+    fn select_many(&mut self, title: String, options: Vec<SelectItem>) -> Result<Vec<SelectItem>> {
+        self.pick(title, options, 0, true)
+    }
+
+    fn select_items_at(
+        &mut self,
+        title: String,
+        options: Vec<SelectItem>,
+        initial: usize,
+    ) -> Result<SelectItem> {
+        let mut selected = self.pick(title, options, initial, false)?;
+        Ok(selected.remove(0))
+    }
+
+    fn select_many_at(
+        &mut self,
+        title: String,
+        options: Vec<SelectItem>,
+        initial: usize,
+    ) -> Result<Vec<SelectItem>> {
+        self.pick(title, options, initial, true)
+    }
+
+    fn input(&mut self, title: String) -> Result<String> {
+        let lines = self.lines;
+        let terminal = self
+            .terminal
+            .get_or_insert_with(|| terminal::init_inline(lines));
+        let theme = self.theme.clone();
+        let account = self.account.clone();
+        let mut value = String::new();
+        loop {
+            terminal.draw(|frame| {
+                let input = Paragraph::new(format!("{}{}▏", theme.symbol, value))
+                    .block(prompt_block(title.clone(), &theme, account.as_ref()));
+                frame.render_widget(input, frame.area());
+            })?;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Enter => break,
+                    KeyCode::Backspace => {
+                        value.pop();
+                    }
+                    KeyCode::Char(c) => value.push(c),
+                    KeyCode::Esc => {
+                        return Err(PorwardError::Cancelled);
                     }
                     _ => {}
                 },
                 _ => {}
             }
         }
-        self.terminal.draw(|frame| {
+        terminal.draw(|frame| {
+            frame.render_widget(Block::new(), frame.area());
+        })?;
+        Ok(value)
+    }
+
+    fn error(&mut self, title: String, error: &PorwardError) -> Result<ErrorAction> {
+        let lines = self.lines;
+        let border_style = self.theme.error;
+        let terminal = self
+            .terminal
+            .get_or_insert_with(|| terminal::init_inline(lines));
+        let message = error.to_string();
+        let action = loop {
+            terminal.draw(|frame| {
+                let panel = Paragraph::new(message.clone())
+                    .wrap(Wrap { trim: false })
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .border_style(border_style)
+                            .title(Line::from(title.clone()).left_aligned())
+                            .title_bottom(Line::from("r retry · b back · q quit").right_aligned()),
+                    );
+                frame.render_widget(panel, frame.area());
+            })?;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Char('r') | KeyCode::Enter => break ErrorAction::Retry,
+                    KeyCode::Char('b') | KeyCode::Backspace => break ErrorAction::Back,
+                    KeyCode::Char('q') | KeyCode::Esc => break ErrorAction::Quit,
+                    _ => {}
+                },
+                _ => {}
+            }
+        };
+        terminal.draw(|frame| {
             frame.render_widget(Block::new(), frame.area());
         })?;
-        Ok((index, selected.unwrap()))
+        Ok(action)
+    }
+
+    fn show_account(&mut self, account: &str, production: bool) {
+        let style = if production {
+            self.theme.warning
+        } else {
+            self.theme.title
+        };
+        self.account = Some(Line::from(format!(" {} ", account)).style(style));
+    }
+
+    fn release(&mut self) {
+        self.terminal = None;
+    }
+}
+
+/// Line based selector for dumb terminals and pipes: numbered options and a typed answer.
+#[derive(Default)]
+pub struct PlainStringListSelector;
+
+impl PlainStringListSelector {
+    // EOF cancels, like Esc in the TUI
+    fn prompt(&self, prompt: &str) -> Result<String> {
+        let mut out = events::human();
+        write!(out, "{}> ", prompt)?;
+        out.flush()?;
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)? == 0 {
+            return Err(PorwardError::Cancelled);
+        }
+        Ok(line.trim().to_string())
+    }
+
+    // with `multiple`, several numbers separated by commas or spaces
+    fn pick(
+        &self,
+        title: String,
+        options: Vec<SelectItem>,
+        multiple: bool,
+    ) -> Result<Vec<SelectItem>> {
+        if options.is_empty() {
+            return Err(PorwardError::NoOptions { step: title });
+        }
+        events::say(&title);
+        for (idx, item) in options.iter().enumerate() {
+            match item.detail.as_ref() {
+                Some(detail) => events::say(&format!("{:>3}. {}  {}", idx + 1, item.label, detail)),
+                None => events::say(&format!("{:>3}. {}", idx + 1, item.label)),
+            }
+        }
+        let prompt = if multiple {
+            format!("1-{}, e.g. 1,3", options.len())
+        } else {
+            format!("1-{}", options.len())
+        };
+        'answer: loop {
+            let answer = self.prompt(&prompt)?;
+            if answer.is_empty() {
+                return Err(PorwardError::Cancelled);
+            }
+            let numbers: Vec<&str> = if multiple {
+                answer
+                    .split([',', ' '])
+                    .filter(|number| !number.is_empty())
+                    .collect()
+            } else {
+                vec![answer.as_str()]
+            };
+            let mut selected = vec![];
+            for number in numbers {
+                match number.parse::<usize>() {
+                    Ok(number) if (1..=options.len()).contains(&number) => {
+                        selected.push(options[number - 1].clone());
+                    }
+                    _ => {
+                        events::say(&format!("'{}' is not one of the options", number));
+                        continue 'answer;
+                    }
+                }
+            }
+            return Ok(selected);
+        }
+    }
+}
+
+impl StringListSelector for PlainStringListSelector {
+    fn select_items(&mut self, title: String, options: Vec<SelectItem>) -> Result<SelectItem> {
+        let mut selected = self.pick(title, options, false)?;
+        Ok(selected.remove(0))
+    }
+
+    fn select_many(&mut self, title: String, options: Vec<SelectItem>) -> Result<Vec<SelectItem>> {
+        self.pick(title, options, true)
+    }
+
+    fn input(&mut self, title: String) -> Result<String> {
+        events::say(&title);
+        self.prompt("")
+    }
+
+    fn error(&mut self, title: String, error: &PorwardError) -> Result<ErrorAction> {
+        events::say(&format!("{}: {}", title, error));
+        loop {
+            match self.prompt("[r]etry, [b]ack or [q]uit ")?.as_str() {
+                "r" => return Ok(ErrorAction::Retry),
+                "b" => return Ok(ErrorAction::Back),
+                "q" => return Ok(ErrorAction::Quit),
+                _ => {}
+            }
+        }
+    }
+
+    fn show_account(&mut self, account: &str, _production: bool) {
+        events::say(&format!("account: {}", account));
+    }
+}
+
+/// Which selector `--ui` or `ui` in config.toml asks for, the terminal decides without one.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ui {
+    Tui,
+    Plain,
+    Fzf,
+}
+
+impl FromStr for Ui {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Ui::deserialize(value.into_deserializer())
+            .map_err(|e: serde::de::value::Error| e.to_string())
+    }
+}
+
+impl Ui {
+    pub fn configured() -> Result<Option<Ui>> {
+        Ok(Config::load()?.ui)
+    }
+}
+
+/// Pipes the options through fzf, or the program set as `fzf` in config.toml. Inputs and errors
+/// are asked line by line like [`PlainStringListSelector`].
+pub struct FzfStringListSelector {
+    program: PathBuf,
+    account: Option<String>,
+    plain: PlainStringListSelector,
+}
+
+impl FzfStringListSelector {
+    pub fn from_config() -> Result<Self> {
+        let name = Config::load()?.fzf.unwrap_or("fzf".to_string());
+        Ok(FzfStringListSelector {
+            program: prerequisites::executable(&name)?,
+            account: None,
+            plain: PlainStringListSelector,
+        })
+    }
+
+    // each line starts with the index of its option, which fzf does not show
+    fn pick(
+        &self,
+        title: String,
+        options: Vec<SelectItem>,
+        multiple: bool,
+    ) -> Result<Vec<SelectItem>> {
+        if options.is_empty() {
+            return Err(PorwardError::NoOptions { step: title });
+        }
+        let mut command = std::process::Command::new(&self.program);
+        command
+            .args(["--delimiter", "\t", "--with-nth", "2..", "--reverse"])
+            .args(["--height", "40%", "--prompt", &format!("{}> ", title)])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        if multiple {
+            command.arg("--multi");
+        }
+        if let Some(account) = self.account.as_ref() {
+            command.args(["--header", account]);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| eyre!("could not start {}: {}", self.program.display(), e))?;
+        let lines: String = options
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| match item.detail.as_ref() {
+                Some(detail) => format!("{}\t{}  {}\n", idx, item.label, detail),
+                None => format!("{}\t{}\n", idx, item.label),
+            })
+            .collect();
+        // closed right after, fzf only finishes its list on EOF
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(lines.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        match output.status.code() {
+            Some(0) => {}
+            // 1 when nothing matches the query, 130 for Esc and Ctrl-C
+            Some(1) | Some(130) => return Err(PorwardError::Cancelled),
+            _ => return Err(eyre!("{} failed ({})", self.program.display(), output.status).into()),
+        }
+        let selected: Vec<_> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split('\t').next()?.parse::<usize>().ok())
+            .filter_map(|idx| options.get(idx).cloned())
+            .collect();
+        if selected.is_empty() {
+            return Err(PorwardError::Cancelled);
+        }
+        Ok(selected)
+    }
+}
+
+impl StringListSelector for FzfStringListSelector {
+    fn select_items(&mut self, title: String, options: Vec<SelectItem>) -> Result<SelectItem> {
+        let mut selected = self.pick(title, options, false)?;
+        Ok(selected.remove(0))
+    }
+
+    fn select_many(&mut self, title: String, options: Vec<SelectItem>) -> Result<Vec<SelectItem>> {
+        self.pick(title, options, true)
+    }
+
+    fn input(&mut self, title: String) -> Result<String> {
+        self.plain.input(title)
+    }
+
+    fn error(&mut self, title: String, error: &PorwardError) -> Result<ErrorAction> {
+        self.plain.error(title, error)
+    }
+
+    fn show_account(&mut self, account: &str, _production: bool) {
+        self.account = Some(account.to_string());
     }
 }
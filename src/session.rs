@@ -0,0 +1,1303 @@
+use crate::{
+    credentials,
+    error::PorwardError,
+    events::{self, Event, ExitEvent},
+    history::{self, HistoryEntry},
+    metrics::MetricsServer,
+    notification,
+    porwarder::{Method, PortForwarder, Service, TunnelAdder, TunnelSummary},
+    probe::{self, Expect},
+    providers::Tags,
+    relay::{format_bytes, ConnectionRecord, Relay, TrafficSnapshot, RECENT_CONNECTIONS},
+    session_log::SessionLog,
+    shutdown::{Shutdown, IDLE_TIMEOUT_EXIT_CODE},
+    state,
+    status::{StatusCommand, StatusView},
+    supervisor::SupervisedTunnel,
+    terminal,
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use crossterm::style::Stylize;
+use rand::Rng;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter},
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    net::TcpStream,
+    process::{Child, Command},
+    task::JoinHandle,
+};
+
+const OUTPUT_TAIL_LINES: usize = 20;
+const READY_MARKER: &str = "Waiting for connections";
+// the plugin starts with "Starting session with SessionId: <id>"
+const SESSION_ID_MARKER: &str = "SessionId: ";
+pub(crate) const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+const STABLE_SESSION: Duration = Duration::from_secs(60);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub struct SessionOptions {
+    pub force: bool,
+    pub aws_cli: bool,
+    pub reconnect: bool,
+    pub status_view: bool,
+    pub keep_alive: Option<Duration>,
+    // probes the tunnel like porward check and marks it degraded while the probe fails
+    pub health_interval: Option<Duration>,
+    pub reason: Option<String>,
+    pub session_profile: Option<String>,
+    pub idle_timeout: Option<Duration>,
+    pub ready_timeout: Duration,
+    pub session_log: Option<PathBuf>,
+    pub notify: bool,
+    pub bind: IpAddr,
+    pub allow_exposed: bool,
+    pub stats: bool,
+    pub destination_tags: Tags,
+    pub same_vpc: bool,
+    pub auto_scaling_group: bool,
+    pub check: bool,
+    pub check_permissions: bool,
+    pub etc_hosts: bool,
+    pub open: bool,
+    pub metrics_port: Option<u16>,
+    pub yes: bool,
+    pub allow_production: bool,
+    pub edit_parameters: bool,
+    // the session profile's credentials go to the aws cli and the plugin as environment variables
+    pub pass_credentials: bool,
+    // --method, otherwise the method of the profile's config
+    pub method: Option<Method>,
+    #[cfg(feature = "native-tunnel")]
+    pub native_tunnel: bool,
+    pub shutdown: Shutdown,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            force: false,
+            aws_cli: false,
+            reconnect: true,
+            status_view: false,
+            keep_alive: None,
+            health_interval: None,
+            reason: None,
+            session_profile: None,
+            idle_timeout: None,
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+            session_log: None,
+            notify: false,
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            allow_exposed: false,
+            stats: false,
+            destination_tags: Tags::new(),
+            same_vpc: false,
+            auto_scaling_group: false,
+            check: false,
+            check_permissions: false,
+            etc_hosts: false,
+            open: false,
+            metrics_port: None,
+            yes: false,
+            allow_production: false,
+            edit_parameters: false,
+            pass_credentials: false,
+            method: None,
+            #[cfg(feature = "native-tunnel")]
+            native_tunnel: false,
+            shutdown: Shutdown::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SessionState {
+    Connecting,
+    Ready,
+    Reconnecting,
+}
+
+pub(crate) struct SessionStatus {
+    pub mapping: String,
+    pub started: Instant,
+    pub state: SessionState,
+    pub reconnects: u32,
+    pub last_output: Option<String>,
+    pub traffic: Option<TrafficSnapshot>,
+    pub recent_connections: Vec<ConnectionRecord>,
+    pub url: Option<String>,
+    // of the current attempt, for CloudTrail and the Session Manager history
+    pub session_id: Option<String>,
+    // why the last health check of a ready tunnel failed
+    pub degraded: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitReason {
+    User,
+    RemoteClosed,
+    IdleTimeout,
+    Error,
+}
+
+impl Display for ExitReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitReason::User => write!(f, "closed by user"),
+            ExitReason::RemoteClosed => write!(f, "closed by remote"),
+            ExitReason::IdleTimeout => write!(f, "idle timeout"),
+            ExitReason::Error => write!(f, "error"),
+        }
+    }
+}
+
+enum Outcome {
+    Exited(ExitStatus, Vec<String>),
+    // the session could not be started or did not get ready, with the output so far
+    Failed(String, Vec<String>),
+    Restart,
+    Stopped,
+}
+
+/// Starts what one attempt of a session runs, with the id of the session if it is known.
+#[async_trait]
+pub(crate) trait SessionCommand: Send + Sync {
+    async fn start(
+        &self,
+        port_forwarder: &PortForwarder,
+        aws_cli: bool,
+    ) -> Result<(Command, Option<String>)>;
+}
+
+// session-manager-plugin after StartSession, or the aws cli
+struct PluginCommand;
+
+#[async_trait]
+impl SessionCommand for PluginCommand {
+    async fn start(
+        &self,
+        port_forwarder: &PortForwarder,
+        aws_cli: bool,
+    ) -> Result<(Command, Option<String>)> {
+        port_forwarder.started_command(aws_cli).await
+    }
+}
+
+pub(crate) struct Session<'a> {
+    options: &'a SessionOptions,
+    command: Box<dyn SessionCommand>,
+    status: SessionStatus,
+    view: Option<StatusView>,
+    keep_alive: Option<KeepAlive>,
+    health: Option<Health>,
+    idle: Option<Idle>,
+    log: Option<SessionLog>,
+    relay: Option<Relay>,
+    // opened in the browser the first time the tunnel is ready
+    browser_url: Option<String>,
+    metrics: Option<MetricsServer>,
+    connection_lines: u16,
+    // tunnels added from the status view, through the same profile and instance
+    adder: Option<TunnelAdder>,
+    // selected together with the main destination, started with it
+    pending: Vec<PortForwarder>,
+    tunnels: Vec<SupervisedTunnel>,
+    local_ports: Vec<u16>,
+    // every attempt starts a new session
+    session_ids: Vec<String>,
+    // the destination of a session that got ready is remembered, unless it ended in an error
+    was_ready: bool,
+    // the data files, none to keep a session out of them
+    history: Option<PathBuf>,
+    state: Option<PathBuf>,
+}
+
+struct KeepAlive {
+    interval: Duration,
+    next: Instant,
+    failures: u32,
+}
+
+struct Health {
+    interval: Duration,
+    next: Instant,
+}
+
+struct Idle {
+    timeout: Duration,
+    bytes: u64,
+    since: Instant,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl OutputTail {
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == OUTPUT_TAIL_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<String> {
+        self.lines.lock().unwrap().back().cloned()
+    }
+}
+
+pub(crate) fn forward_output<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    echo: bool,
+    tails: Vec<OutputTail>,
+    log: Option<(SessionLog, &'static str)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if echo {
+                events::say(&format!("{} {}", "│".dark_grey(), line));
+            }
+            if let Some((log, stream)) = log.as_ref() {
+                log.write(&format!("{}: {}", stream, line));
+            }
+            for tail in tails.iter() {
+                tail.push(line.clone());
+            }
+        }
+    })
+}
+
+impl<'a> Session<'a> {
+    pub fn new(
+        options: &'a SessionOptions,
+        mapping: String,
+        log: Option<SessionLog>,
+        relay: Option<Relay>,
+        url: Option<String>,
+    ) -> Self {
+        // room for the last connections when they go through the relay
+        let connection_lines = if relay.is_some() {
+            RECENT_CONNECTIONS as u16
+        } else {
+            0
+        };
+        let view = options
+            .status_view
+            .then(|| StatusView::inline(connection_lines));
+        Session {
+            options,
+            command: Box::new(PluginCommand),
+            status: SessionStatus {
+                mapping,
+                started: Instant::now(),
+                state: SessionState::Connecting,
+                reconnects: 0,
+                last_output: None,
+                traffic: None,
+                recent_connections: vec![],
+                url: url.clone(),
+                session_id: None,
+                degraded: None,
+            },
+            view,
+            keep_alive: options.keep_alive.map(|interval| KeepAlive {
+                interval,
+                next: Instant::now() + interval,
+                failures: 0,
+            }),
+            health: options.health_interval.map(|interval| Health {
+                interval,
+                next: Instant::now() + interval,
+            }),
+            idle: options.idle_timeout.map(|timeout| Idle {
+                timeout,
+                bytes: 0,
+                since: Instant::now(),
+            }),
+            log,
+            relay,
+            browser_url: url,
+            metrics: None,
+            connection_lines,
+            adder: None,
+            pending: vec![],
+            tunnels: vec![],
+            local_ports: vec![],
+            session_ids: vec![],
+            was_ready: false,
+            history: history::path(),
+            state: state::path(),
+        }
+    }
+
+    pub fn with_adder(mut self, adder: Option<TunnelAdder>) -> Self {
+        self.adder = adder;
+        self
+    }
+
+    pub fn with_tunnels(mut self, tunnels: Vec<PortForwarder>) -> Self {
+        self.pending = tunnels;
+        self
+    }
+
+    pub async fn run(
+        mut self,
+        port_forwarder: &PortForwarder,
+        summary: &TunnelSummary,
+        local_port: u16,
+    ) -> Result<i32> {
+        if let Some(port) = self.options.metrics_port {
+            self.metrics = Some(MetricsServer::start(port, self.status.started).await?);
+        }
+        self.local_ports.push(local_port);
+        self.local_ports
+            .extend(self.relay.as_ref().map(|relay| relay.address().port()));
+        for tunnel in std::mem::take(&mut self.pending) {
+            self.start_tunnel(tunnel).await?;
+        }
+        let result = self
+            .supervise(port_forwarder, &summary.instance_id, local_port)
+            .await;
+        drop(self.view.take());
+        for tunnel in self.tunnels.drain(..) {
+            let _ = tunnel.stop().await;
+        }
+        drop(self.metrics.take());
+        let (reason, exit_code) = match &result {
+            Ok((reason, exit_code)) => (*reason, *exit_code),
+            Err(_) => (ExitReason::Error, 1),
+        };
+        let duration = self.status.started.elapsed();
+        let traffic = self.relay.as_ref().map(Relay::traffic);
+        let mut closed = format!(
+            "Session closed after {} ({} reconnects, {})",
+            format_duration(duration),
+            self.status.reconnects,
+            reason
+        );
+        if let Some(traffic) = traffic {
+            closed.push_str(&format!(
+                ", transferred {} over {} connections",
+                format_bytes(traffic.sent + traffic.received),
+                traffic.connections
+            ));
+        }
+        if !self.session_ids.is_empty() {
+            closed.push_str(&format!(", session ids: {}", self.session_ids.join(", ")));
+        }
+        self.log(&closed);
+        events::say(&closed);
+        let exit = ExitEvent {
+            code: exit_code,
+            duration_s: duration.as_secs(),
+        };
+        let entry = HistoryEntry {
+            ended: humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string(),
+            mapping: self.status.mapping.clone(),
+            tunnel: summary.clone(),
+            exit,
+            reconnects: self.status.reconnects,
+            reason: port_forwarder.reason().map(str::to_string),
+            exit_reason: reason,
+            bytes: traffic.map(|traffic| traffic.sent + traffic.received),
+            session_ids: self.session_ids.clone(),
+        };
+        if let Some(path) = self.history.as_deref() {
+            history::append(path, &entry);
+        }
+        // failed sessions are not worth proposing again, closing a working one with Ctrl+C is fine
+        if self.was_ready && !matches!(reason, ExitReason::Error) {
+            if let (Some(service), Some(path)) = (summary.service.as_ref(), self.state.as_deref()) {
+                state::remember_destination(path, &summary.profile, service, &summary.host);
+            }
+        }
+        port_forwarder.events().on_session_event(&Event::Exit(exit));
+        result.map(|(_, exit_code)| exit_code)
+    }
+
+    async fn supervise(
+        &mut self,
+        port_forwarder: &PortForwarder,
+        instance_id: &str,
+        local_port: u16,
+    ) -> Result<(ExitReason, i32)> {
+        let mut backoff = Backoff::new();
+        loop {
+            let started = Instant::now();
+            self.status.state = SessionState::Connecting;
+            self.status.degraded = None;
+            let (ended, output) = match self.attempt(port_forwarder, local_port).await? {
+                Outcome::Stopped => return Ok(self.stopped()),
+                Outcome::Restart => {
+                    self.status.reconnects += 1;
+                    port_forwarder.events().on_session_event(&Event::Reconnect {
+                        attempt: self.status.reconnects,
+                    });
+                    self.notice(format!(
+                        "Reconnecting on request [reconnect #{}]",
+                        self.status.reconnects
+                    ))?;
+                    continue;
+                }
+                Outcome::Exited(status, stderr) => {
+                    self.log(&format!("session exited ({})", status));
+                    (status.to_string(), (!status.success()).then_some(stderr))
+                }
+                Outcome::Failed(reason, output) => {
+                    self.log(&format!("session failed ({})", reason));
+                    (reason, Some(output))
+                }
+            };
+            // only failures a new session can not fix end the loop, e.g. missing permissions
+            if let Some(output) = output {
+                let failure = SessionFailure::classify(&output);
+                if failure.is_permanent() || !self.options.reconnect {
+                    let error = PorwardError::SessionFailed {
+                        status: ended,
+                        message: failure.message(instance_id, &output),
+                    };
+                    self.log(&error.to_string());
+                    self.notify(&format!("{} failed", self.status.mapping));
+                    return Err(error.into());
+                }
+            }
+            if !self.options.reconnect {
+                return Ok((ExitReason::RemoteClosed, 0));
+            }
+            if started.elapsed() > STABLE_SESSION {
+                backoff.reset();
+            }
+            if let Some(warning) =
+                credentials::expired_warning(port_forwarder.target().session_profile())
+            {
+                self.notice(format!("warning: {}", warning))?;
+            }
+            self.status.reconnects += 1;
+            port_forwarder.events().on_session_event(&Event::Reconnect {
+                attempt: self.status.reconnects,
+            });
+            self.status.state = SessionState::Reconnecting;
+            self.notify(&format!("{} dropped, reconnecting", self.status.mapping));
+            let delay = backoff.next_delay();
+            self.notice(format!(
+                "Session ended ({}), reconnecting in {:.1}s [reconnect #{}]",
+                ended,
+                delay.as_secs_f32(),
+                self.status.reconnects
+            ))?;
+            if self.wait(delay).await? {
+                return Ok(self.stopped());
+            }
+        }
+    }
+
+    fn stopped(&self) -> (ExitReason, i32) {
+        match self.options.shutdown.exit_code() {
+            Some(IDLE_TIMEOUT_EXIT_CODE) => (ExitReason::IdleTimeout, IDLE_TIMEOUT_EXIT_CODE),
+            exit_code => (ExitReason::User, exit_code.unwrap_or_default()),
+        }
+    }
+
+    async fn attempt(
+        &mut self,
+        port_forwarder: &PortForwarder,
+        local_port: u16,
+    ) -> Result<Outcome> {
+        let ready_timeout = self.options.ready_timeout;
+        self.status.session_id = None;
+        let starting = self.command.start(port_forwarder, self.options.aws_cli);
+        let started = tokio::time::timeout(ready_timeout, starting).await;
+        let (mut command, session_id) = match started {
+            Ok(Ok(started)) => started,
+            // a denied or otherwise permanent failure ends the session straight away
+            Ok(Err(e))
+                if matches!(
+                    e.downcast_ref::<PorwardError>(),
+                    Some(PorwardError::SessionFailed { .. })
+                ) =>
+            {
+                return Err(e)
+            }
+            // classified by supervise, which retries network errors and gives up on denied ones
+            Ok(Err(e)) => {
+                return Ok(Outcome::Failed(
+                    "StartSession failed".to_string(),
+                    vec![format!("{:#}", e)],
+                ))
+            }
+            Err(_) => {
+                return Ok(Outcome::Failed(
+                    format!(
+                        "StartSession did not respond within {}s",
+                        ready_timeout.as_secs()
+                    ),
+                    vec![],
+                ))
+            }
+        };
+        let program = command.as_std().get_program().to_string_lossy().to_string();
+        // keep the child out of our process group so that Ctrl+C reaches porward only
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        if let Some(session_id) = session_id {
+            self.session_started(session_id);
+        }
+
+        let echo = self.view.is_none();
+        let output_tail = OutputTail::default();
+        let stdout_tail = OutputTail::default();
+        let stderr_tail = OutputTail::default();
+        let readers = [
+            child.stdout.take().map(|stdout| {
+                forward_output(
+                    stdout,
+                    echo,
+                    vec![stdout_tail.clone(), output_tail.clone()],
+                    self.log.clone().map(|log| (log, "stdout")),
+                )
+            }),
+            child.stderr.take().map(|stderr| {
+                forward_output(
+                    stderr,
+                    echo,
+                    vec![stderr_tail.clone(), output_tail.clone()],
+                    self.log.clone().map(|log| (log, "stderr")),
+                )
+            }),
+        ];
+
+        let ready_deadline = tokio::time::sleep(ready_timeout);
+        tokio::pin!(ready_deadline);
+        let mut ticks = tokio::time::interval(POLL_INTERVAL);
+        let outcome = loop {
+            tokio::select! {
+                status = child.wait() => break Outcome::Exited(status?, vec![]),
+                _ = self.options.shutdown.wait() => {
+                    terminate(&mut child).await?;
+                    break Outcome::Stopped;
+                }
+                _ = &mut ready_deadline, if self.status.state != SessionState::Ready => {
+                    let _ = child.kill().await;
+                    break Outcome::Failed(
+                        format!(
+                            "{} hung: local port {} was not ready after {}s",
+                            program,
+                            local_port,
+                            ready_timeout.as_secs()
+                        ),
+                        vec![],
+                    );
+                }
+                _ = ticks.tick() => {
+                    if self.status.session_id.is_none() {
+                        if let Some(session_id) = parse_session_id(&stdout_tail.lines()) {
+                            self.session_started(session_id);
+                        }
+                    }
+                    if self.status.state != SessionState::Ready
+                        && (stdout_tail
+                            .lines()
+                            .iter()
+                            .any(|line| line.contains(READY_MARKER))
+                            || is_listening(local_port).await)
+                    {
+                        self.status.state = SessionState::Ready;
+                        self.was_ready = true;
+                        if let Some(keep_alive) = self.keep_alive.as_mut() {
+                            keep_alive.reset();
+                        }
+                        self.notice(format!("tunnel ready: {}", self.status.mapping))?;
+                        self.notify(&format!("{} ready", self.status.mapping));
+                        port_forwarder.events().on_session_event(&Event::Ready {
+                            // the port people connect to, not the plugin's behind a relay
+                            local_port: self
+                                .relay
+                                .as_ref()
+                                .map(|relay| relay.address().port())
+                                .unwrap_or(local_port),
+                            host: &port_forwarder.destination().host,
+                        });
+                        self.open_browser()?;
+                    }
+                    if self.status.state == SessionState::Ready {
+                        self.keep_alive(local_port).await?;
+                        self.health_check(port_forwarder, local_port).await?;
+                    }
+                    self.status.last_output = output_tail.last();
+                    match self.tick().await? {
+                        Some(StatusCommand::Quit) => {
+                            terminate(&mut child).await?;
+                            break Outcome::Stopped;
+                        }
+                        Some(StatusCommand::Reconnect) => {
+                            terminate(&mut child).await?;
+                            break Outcome::Restart;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        };
+
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.await;
+        }
+        Ok(match outcome {
+            Outcome::Exited(status, _) => Outcome::Exited(status, stderr_tail.lines()),
+            Outcome::Failed(reason, _) => {
+                let mut output = stdout_tail.lines();
+                output.extend(stderr_tail.lines());
+                Outcome::Failed(reason, output)
+            }
+            outcome => outcome,
+        })
+    }
+
+    fn session_started(&mut self, session_id: String) {
+        self.log(&format!("session id: {}", session_id));
+        self.status.session_id = Some(session_id.clone());
+        self.session_ids.push(session_id);
+    }
+
+    async fn keep_alive(&mut self, local_port: u16) -> Result<()> {
+        let Some(keep_alive) = self.keep_alive.as_mut() else {
+            return Ok(());
+        };
+        if Instant::now() < keep_alive.next {
+            return Ok(());
+        }
+        if is_listening(local_port).await {
+            keep_alive.reset();
+            return Ok(());
+        }
+        keep_alive.failures += 1;
+        let delay = keep_alive.interval * 2u32.pow(keep_alive.failures.min(3));
+        keep_alive.next = Instant::now() + delay;
+        self.notice(format!(
+            "keep-alive: localhost:{} is not accepting connections, retrying in {}s",
+            local_port,
+            delay.as_secs()
+        ))
+    }
+
+    async fn health_check(
+        &mut self,
+        port_forwarder: &PortForwarder,
+        local_port: u16,
+    ) -> Result<()> {
+        let Some(health) = self.health.as_mut() else {
+            return Ok(());
+        };
+        if Instant::now() < health.next {
+            return Ok(());
+        }
+        health.next = Instant::now() + health.interval;
+        let expect = port_forwarder
+            .service()
+            .map(Service::expect)
+            .unwrap_or(Expect::Tcp);
+        let probe = probe::probe(local_port, expect).await;
+        match (probe.is_ok(), self.status.degraded.is_some()) {
+            (false, _) => {
+                if self.status.degraded.is_none() {
+                    self.notify(&format!("{} degraded", self.status.mapping));
+                }
+                self.status.degraded = Some(probe.to_string());
+                self.notice(format!("health check failed: {}", probe))
+            }
+            (true, true) => {
+                self.status.degraded = None;
+                self.notice(format!("health check recovered: {}", probe))
+            }
+            (true, false) => Ok(()),
+        }
+    }
+
+    async fn wait(&mut self, duration: Duration) -> Result<bool> {
+        let deadline = tokio::time::sleep(duration);
+        tokio::pin!(deadline);
+        let mut ticks = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => return Ok(self.options.shutdown.is_requested()),
+                _ = self.options.shutdown.wait() => return Ok(true),
+                _ = ticks.tick() => match self.tick().await? {
+                    Some(StatusCommand::Quit) => return Ok(true),
+                    Some(StatusCommand::Reconnect) => return Ok(false),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    async fn tick(&mut self) -> Result<Option<StatusCommand>> {
+        let events = self.relay.as_mut().map(Relay::events).unwrap_or_default();
+        for event in events {
+            self.notice(event)?;
+        }
+        self.status.traffic = self.relay.as_ref().map(Relay::traffic);
+        self.status.recent_connections = self
+            .relay
+            .as_ref()
+            .map(Relay::recent_connections)
+            .unwrap_or_default();
+        self.check_idle()?;
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.update(&self.status);
+        }
+        for tunnel in self.tunnels.iter_mut() {
+            tunnel.refresh()?;
+        }
+        let command = match self.view.as_mut() {
+            Some(view) => {
+                view.draw(&self.status, &self.tunnels, self.adder.is_some())?;
+                view.poll(Duration::ZERO)?
+            }
+            None => None,
+        };
+        match command {
+            Some(StatusCommand::Add) => {
+                self.add_tunnel().await?;
+                Ok(None)
+            }
+            Some(StatusCommand::Close(index)) => {
+                self.close_tunnel(index).await?;
+                Ok(None)
+            }
+            command => Ok(command),
+        }
+    }
+
+    // the prompts take over the lines of the status view until a destination is picked
+    async fn add_tunnel(&mut self) -> Result<()> {
+        let Some(adder) = self.adder.as_mut() else {
+            return Ok(());
+        };
+        let mut ports_in_use = self.local_ports.clone();
+        ports_in_use.extend(self.tunnels.iter().filter_map(SupervisedTunnel::local_port));
+        drop(self.view.take());
+        let result = adder.next(&ports_in_use).await;
+        terminal::restore();
+        let mut port_forwarder = match result {
+            Ok(port_forwarder) => port_forwarder,
+            Err(e) => {
+                self.view = Some(self.status_view());
+                return match e {
+                    PorwardError::Cancelled => Ok(()),
+                    e => self.notice(format!("could not add a tunnel: {}", e)),
+                };
+            }
+        };
+        let additional = port_forwarder.take_additional();
+        self.start_tunnel(*port_forwarder).await?;
+        for tunnel in additional {
+            self.start_tunnel(tunnel).await?;
+        }
+        Ok(())
+    }
+
+    async fn start_tunnel(&mut self, port_forwarder: PortForwarder) -> Result<()> {
+        let port_forwarder = port_forwarder.with_resolved_region().await;
+        let local_port = port_forwarder.local_port();
+        let destination = port_forwarder.destination();
+        let name = format!(
+            "localhost:{} → {}:{}",
+            local_port, destination.host, destination.port
+        );
+        let command = port_forwarder.session_command(self.options.aws_cli).await;
+        let tunnel = SupervisedTunnel::spawn(name.clone(), Some(local_port), command);
+        let failure = tunnel.failure().map(str::to_string);
+        self.tunnels.push(tunnel);
+        // the view grows by a line for every tunnel
+        if self.options.status_view {
+            drop(self.view.take());
+            self.view = Some(self.status_view());
+        }
+        for warning in port_forwarder.warnings() {
+            self.notice(format!("warning: {}", warning))?;
+        }
+        match failure {
+            Some(reason) => self.notice(format!("could not add {}: {}", name, reason)),
+            None => self.notice(format!("tunnel added: {}", name)),
+        }
+    }
+
+    async fn close_tunnel(&mut self, index: usize) -> Result<()> {
+        if index >= self.tunnels.len() {
+            return Ok(());
+        }
+        let tunnel = self.tunnels.remove(index);
+        let name = tunnel.name().to_string();
+        tunnel.stop().await?;
+        drop(self.view.take());
+        self.view = Some(self.status_view());
+        self.notice(format!("tunnel closed: {}", name))
+    }
+
+    // one line for every added tunnel
+    fn status_view(&self) -> StatusView {
+        StatusView::inline(self.connection_lines + self.tunnels.len() as u16)
+    }
+
+    fn notice(&mut self, message: String) -> Result<()> {
+        self.log(&message);
+        match self.view.as_mut() {
+            Some(view) => view.print(&message),
+            None => {
+                events::say(&message);
+                Ok(())
+            }
+        }
+    }
+
+    fn check_idle(&mut self) -> Result<()> {
+        let (Some(idle), Some(traffic)) = (self.idle.as_mut(), self.status.traffic) else {
+            return Ok(());
+        };
+        let bytes = traffic.sent + traffic.received;
+        if bytes != idle.bytes {
+            idle.bytes = bytes;
+            idle.since = Instant::now();
+            return Ok(());
+        }
+        if idle.since.elapsed() < idle.timeout || self.options.shutdown.is_requested() {
+            return Ok(());
+        }
+        let timeout = idle.timeout;
+        self.options.shutdown.request(IDLE_TIMEOUT_EXIT_CODE);
+        self.notice(format!(
+            "Closing the tunnel after {} without traffic",
+            humantime::format_duration(timeout)
+        ))
+    }
+
+    fn open_browser(&mut self) -> Result<()> {
+        let Some(url) = self.browser_url.take() else {
+            return Ok(());
+        };
+        match open::that_detached(&url) {
+            Ok(()) => self.notice(format!("opened {}", url)),
+            Err(e) => self.notice(format!("could not open {} in the browser: {}", url, e)),
+        }
+    }
+
+    fn notify(&self, message: &str) {
+        if self.options.notify {
+            notification::notify(message);
+        }
+    }
+
+    fn log(&self, message: &str) {
+        if let Some(log) = self.log.as_ref() {
+            log.write(message);
+        }
+    }
+}
+
+impl KeepAlive {
+    fn reset(&mut self) {
+        self.next = Instant::now() + self.interval;
+        self.failures = 0;
+    }
+}
+
+fn parse_session_id(lines: &[String]) -> Option<String> {
+    lines
+        .iter()
+        .find_map(|line| line.split_once(SESSION_ID_MARKER))
+        .map(|(_, session_id)| session_id.trim().to_string())
+        .filter(|session_id| !session_id.is_empty())
+}
+
+async fn is_listening(local_port: u16) -> bool {
+    matches!(
+        tokio::time::timeout(
+            POLL_INTERVAL,
+            TcpStream::connect((Ipv4Addr::LOCALHOST, local_port))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+pub(crate) async fn terminate(child: &mut Child) -> Result<ExitStatus> {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = child.start_kill();
+
+    match tokio::time::timeout(TERMINATE_TIMEOUT, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            child.kill().await?;
+            Ok(child.wait().await?)
+        }
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+pub struct Backoff {
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff {
+            current: INITIAL_BACKOFF,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.current = INITIAL_BACKOFF;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let jitter = rand::thread_rng().gen_range(0..=self.current.as_millis() as u64 / 2);
+        let delay = self.current + Duration::from_millis(jitter);
+        self.current = (self.current * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq)]
+pub enum SessionFailure {
+    TargetNotConnected,
+    StartSessionDenied,
+    // expired, invalid or missing credentials, or another call that was denied
+    NotAuthorized,
+    PluginTooOld,
+    InvalidParameters,
+    Unknown,
+}
+
+impl SessionFailure {
+    pub fn classify(stderr: &[String]) -> Self {
+        let output = stderr.join("\n");
+        if output.contains("TargetNotConnected") {
+            SessionFailure::TargetNotConnected
+        } else if output.contains("AccessDenied") && output.contains("ssm:StartSession") {
+            SessionFailure::StartSessionDenied
+        } else if [
+            "AccessDenied",
+            "UnauthorizedOperation",
+            "ExpiredToken",
+            "InvalidClientTokenId",
+            "UnrecognizedClient",
+            "SignatureDoesNotMatch",
+            "no credentials",
+            "failed to load credentials",
+        ]
+        .iter()
+        .any(|marker| output.contains(marker))
+        {
+            SessionFailure::NotAuthorized
+        } else if output.contains("Unknown session type")
+            || output.contains("not supported by the session-manager-plugin")
+            || output.contains("upgrade the session-manager-plugin")
+        {
+            SessionFailure::PluginTooOld
+        } else if output.contains("InvalidParameters")
+            || output.contains("InvalidDocument")
+            || output.contains("ValidationException")
+        {
+            SessionFailure::InvalidParameters
+        } else {
+            SessionFailure::Unknown
+        }
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        *self != SessionFailure::Unknown
+    }
+
+    pub fn message(&self, instance_id: &str, stderr: &[String]) -> String {
+        match self {
+            SessionFailure::TargetNotConnected => format!(
+                "instance {} is not connected to SSM — check the agent and instance profile",
+                instance_id
+            ),
+            SessionFailure::StartSessionDenied => {
+                "your role lacks ssm:StartSession on this instance".to_string()
+            }
+            SessionFailure::NotAuthorized => format!(
+                "the profile's credentials were rejected:\r\n{}",
+                stderr.join("\r\n")
+            ),
+            SessionFailure::PluginTooOld => {
+                "session-manager-plugin too old for remote-host forwarding".to_string()
+            }
+            SessionFailure::InvalidParameters => format!(
+                "the session parameters were rejected:\r\n{}",
+                stderr.join("\r\n")
+            ),
+            SessionFailure::Unknown => stderr.join("\r\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the destination host as a shell script in place of the plugin
+    #[cfg(unix)]
+    struct ScriptCommand;
+
+    #[cfg(unix)]
+    #[async_trait]
+    impl SessionCommand for ScriptCommand {
+        async fn start(
+            &self,
+            port_forwarder: &PortForwarder,
+            _aws_cli: bool,
+        ) -> Result<(Command, Option<String>)> {
+            let mut command = Command::new("sh");
+            command.args(["-c", &port_forwarder.destination().host]);
+            Ok((command, None))
+        }
+    }
+
+    // a session whose plugin is the shell script `script`
+    #[cfg(unix)]
+    fn script(script: &str) -> PortForwarder {
+        PortForwarder::new(
+            "dev".to_string(),
+            "i-0123".to_string(),
+            Service::all().remove(0),
+            script.to_string(),
+            5432,
+            crate::relay::free_local_port().unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[cfg(unix)]
+    async fn supervise(
+        options: &SessionOptions,
+        relay: Option<Relay>,
+        port_forwarder: &PortForwarder,
+    ) -> (Result<(ExitReason, i32)>, u32) {
+        let mut session = Session::new(options, "test".to_string(), None, relay, None);
+        session.command = Box::new(ScriptCommand);
+        let result = session
+            .supervise(port_forwarder, "i-0123", port_forwarder.local_port())
+            .await;
+        (result, session.status.reconnects)
+    }
+
+    fn classify(output: &str) -> SessionFailure {
+        SessionFailure::classify(&[output.to_string()])
+    }
+
+    #[test]
+    fn denied_and_unauthorized_failures_are_permanent() {
+        for output in [
+            "An error occurred (TargetNotConnected) when calling the StartSession operation",
+            "AccessDeniedException: not authorized to perform: ssm:StartSession",
+            "service error: ExpiredTokenException: The security token included in the request is expired",
+            "UnrecognizedClientException: The security token included in the request is invalid",
+            "dispatch failure: other: no credentials in the property bag",
+        ] {
+            assert!(classify(output).is_permanent(), "{}", output);
+        }
+    }
+
+    #[test]
+    fn network_failures_and_timeouts_are_retried() {
+        for output in [
+            "dispatch failure: io error: connection reset by peer",
+            "dispatch failure: timeout: error trying to connect: dns error",
+            "ThrottlingException: Rate exceeded",
+            "",
+        ] {
+            assert!(!classify(output).is_permanent(), "{}", output);
+        }
+        assert!(!SessionFailure::classify(&[]).is_permanent());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_fast_exiting_plugin_is_restarted_with_backoff() {
+        let options = SessionOptions::default();
+        let shutdown = options.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(2500)).await;
+            shutdown.request(130);
+        });
+        let started = Instant::now();
+        let (result, reconnects) = supervise(&options, None, &script("exit 0")).await;
+        assert!(matches!(result.unwrap(), (ExitReason::User, 130)));
+        // restarts after 1-1.5s and 2-3s, a busy loop would have restarted hundreds of times
+        assert!((1..=2).contains(&reconnects), "{} reconnects", reconnects);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn only_destinations_of_sessions_that_got_ready_are_remembered() {
+        let dir = std::env::temp_dir().join(format!("porward-session-{}", std::process::id()));
+        for (name, script, remembered) in [
+            ("ready", "echo Waiting for connections; exec sleep 30", true),
+            ("never-ready", "exec sleep 30", false),
+        ] {
+            let path = dir.join(format!("{}.json", name));
+            let _ = std::fs::remove_file(&path);
+            let options = SessionOptions::default();
+            let shutdown = options.shutdown.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                shutdown.request(130);
+            });
+            let port_forwarder = script(script);
+            let summary = port_forwarder.summary();
+            let mut session = Session::new(&options, "test".to_string(), None, None, None);
+            session.command = Box::new(ScriptCommand);
+            session.history = None;
+            session.state = Some(path.clone());
+            let exit_code = session
+                .run(&port_forwarder, &summary, port_forwarder.local_port())
+                .await
+                .unwrap();
+            assert_eq!(exit_code, 130);
+            let last = state::last_destination(&path, "dev", summary.service.as_ref().unwrap());
+            assert_eq!(
+                last.map(|last| last.host),
+                remembered.then_some(summary.host)
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_and_resets() {
+        let mut backoff = Backoff::new();
+        for base in [1, 2, 4, 8, 16, 32, 60, 60] {
+            let delay = backoff.next_delay();
+            let base = Duration::from_secs(base);
+            assert!(delay >= base && delay <= base + base / 2, "{:?}", delay);
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= INITIAL_BACKOFF + INITIAL_BACKOFF / 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn without_reconnect_the_plugin_exit_status_decides() {
+        let options = SessionOptions {
+            reconnect: false,
+            ..SessionOptions::default()
+        };
+        let (result, _) = supervise(&options, None, &script("exit 0")).await;
+        assert!(matches!(result.unwrap(), (ExitReason::RemoteClosed, 0)));
+        let (result, reconnects) =
+            supervise(&options, None, &script("echo broken pipe >&2; exit 7")).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "session failed (exit status: 7): broken pipe"
+        );
+        assert_eq!(reconnects, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn permanent_failures_are_not_reconnected() {
+        let options = SessionOptions::default();
+        let (result, reconnects) = supervise(
+            &options,
+            None,
+            &script("echo 'An error occurred (TargetNotConnected)' >&2; exit 255"),
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "session failed (exit status: 255): instance i-0123 is not connected to SSM — check the agent and instance profile"
+        );
+        assert_eq!(reconnects, 0);
+    }
+
+    // what the signal handler stores, Ctrl+C and SIGTERM
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_shutdown_ends_the_session_with_its_exit_code() {
+        for exit_code in [130, 143] {
+            let options = SessionOptions::default();
+            let shutdown = options.shutdown.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                shutdown.request(exit_code);
+            });
+            let (result, reconnects) = supervise(&options, None, &script("exec sleep 30")).await;
+            let (reason, code) = result.unwrap();
+            assert!(matches!(reason, ExitReason::User));
+            assert_eq!(code, exit_code);
+            assert_eq!(reconnects, 0);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn an_idle_tunnel_ends_with_the_idle_timeout_exit_code() {
+        let options = SessionOptions {
+            idle_timeout: Some(Duration::from_millis(300)),
+            ..SessionOptions::default()
+        };
+        let port_forwarder = script("exec sleep 30");
+        let relay = Relay::start((Ipv4Addr::LOCALHOST, 0).into(), port_forwarder.local_port())
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let (result, _) = supervise(&options, Some(relay), &port_forwarder).await;
+        let (reason, code) = result.unwrap();
+        assert!(matches!(reason, ExitReason::IdleTimeout));
+        assert_eq!(code, IDLE_TIMEOUT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,42 @@
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+#[derive(Clone)]
+pub struct SessionLog {
+    file: Arc<Mutex<File>>,
+}
+
+impl SessionLog {
+    pub fn create(dir: &Path, local_port: u16) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| eyre!("could not create {}: {}", dir.display(), e))?;
+        // colons are not allowed in windows file names
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now())
+            .to_string()
+            .replace(':', "-");
+        let path = dir.join(format!("porward-{}-{}.log", timestamp, local_port));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| eyre!("could not open {}: {}", path.display(), e))?;
+        Ok(SessionLog {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn write(&self, message: &str) {
+        let timestamp = humantime::format_rfc3339_millis(SystemTime::now());
+        let mut file = self.file.lock().unwrap();
+        for line in message.lines() {
+            // the log is best effort and must never take the tunnel down
+            let _ = writeln!(file, "{} {}", timestamp, line);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::SystemTime};
+
+/// A session of a running porward, one file each until the session ends.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct LiveSession {
+    pub pid: u32,
+    pub profile: String,
+    pub mapping: String,
+    pub started: String,
+    // tells this porward apart from a process that got its pid after it exited
+    #[serde(default)]
+    pub process_start: Option<String>,
+    // part of the file name, <pid>-<local port>.json
+    #[serde(skip)]
+    pub local_port: u16,
+}
+
+impl LiveSession {
+    // the part of the mapping after the local address, e.g. "db:5432 via bastion (i-0123)"
+    pub fn destination(&self) -> &str {
+        self.mapping
+            .split_once("→ ")
+            .map(|(_, destination)| destination)
+            .unwrap_or(&self.mapping)
+    }
+
+    pub fn ago(&self) -> String {
+        let seconds = humantime::parse_rfc3339(&self.started)
+            .ok()
+            .and_then(|started| SystemTime::now().duration_since(started).ok())
+            .map(|age| age.as_secs())
+            .unwrap_or_default();
+        state::ago(seconds)
+    }
+}
+
+/// Removes the session's file when dropped.
+pub(crate) struct Registration {
+    path: PathBuf,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether other porwards' sessions can be told apart from files left by dead ones. There is no
+/// liveness check for a pid on windows, its sessions are neither registered nor counted there.
+pub(crate) const TRACKED: bool = cfg!(unix);
+
+/// Held while sessions are counted and registered, so that two porwards started at the same time
+/// can not both take the last session of a profile. Released when dropped.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct Lock {
+    _file: std::fs::File,
+}
+
+fn dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("porward").join("sessions"))
+}
+
+// best effort, a session that could not be written is not counted by the others
+pub(crate) fn register(profile: &str, mapping: &str, local_port: u16) -> Option<Registration> {
+    if !TRACKED {
+        return None;
+    }
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).ok()?;
+    let session = LiveSession {
+        pid: std::process::id(),
+        profile: profile.to_string(),
+        mapping: mapping.to_string(),
+        started: humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string(),
+        process_start: process_start(std::process::id()),
+        local_port,
+    };
+    let path = dir.join(format!("{}-{}.json", session.pid, local_port));
+    std::fs::write(&path, serde_json::to_string(&session).ok()?).ok()?;
+    Some(Registration { path })
+}
+
+// best effort like the registration, without a lock file the sessions are counted unguarded
+#[cfg(unix)]
+pub(crate) fn lock() -> Option<Lock> {
+    use std::os::fd::AsRawFd;
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).ok()?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.with_extension("lock"))
+        .ok()?;
+    // waits for the porward holding it, the lock goes with the file when that one is closed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return None;
+    }
+    Some(Lock { _file: file })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock() -> Option<Lock> {
+    None
+}
+
+// the sessions of every running porward, the files of porwards that did not exit cleanly are
+// removed on the way
+fn running() -> Vec<LiveSession> {
+    if !TRACKED {
+        return vec![];
+    }
+    let Some(entries) = dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return vec![];
+    };
+    let mut sessions = vec![];
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let Some(mut session) = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<LiveSession>(&content).ok())
+        else {
+            continue;
+        };
+        if !is_live(&session) {
+            let _ = std::fs::remove_file(&path);
+            continue;
+        }
+        session.local_port = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.rsplit_once('-'))
+            .and_then(|(_, port)| port.parse().ok())
+            .unwrap_or_default();
+        sessions.push(session);
+    }
+    sessions.sort_by(|a, b| a.started.cmp(&b.started));
+    sessions
+}
+
+pub(crate) fn live(profile: &str) -> Vec<LiveSession> {
+    running()
+        .into_iter()
+        .filter(|session| session.profile == profile)
+        .collect()
+}
+
+// the live sessions of the profile when `starting` more would go past `max_sessions`
+pub(crate) fn over_limit(
+    profile: &str,
+    starting: usize,
+    max_sessions: usize,
+) -> Option<Vec<LiveSession>> {
+    let live = live(profile);
+    (live.len() + starting > max_sessions).then_some(live)
+}
+
+// one line per session, for the error that the limit is reached
+pub(crate) fn listing(sessions: &[LiveSession]) -> String {
+    sessions
+        .iter()
+        .map(|session| {
+            format!(
+                "  pid {}  {} (since {})",
+                session.pid, session.mapping, session.started
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// another porward's session on the local port, this one's own tunnels are not in the way
+pub(crate) fn forwarding(local_port: u16) -> Option<LiveSession> {
+    running()
+        .into_iter()
+        .find(|session| session.local_port == local_port && session.pid != std::process::id())
+}
+
+// the same as ctrl-c in that porward, which closes its session before exiting; checked again
+// right before, the porward may have exited since the sessions were listed
+#[cfg(unix)]
+pub(crate) fn stop(session: &LiveSession) -> bool {
+    is_live(session) && unsafe { libc::kill(session.pid as libc::pid_t, libc::SIGTERM) == 0 }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn stop(_session: &LiveSession) -> bool {
+    false
+}
+
+// the file of a porward that crashed is stale, also when its pid belongs to another process now,
+// e.g. one of another user
+fn is_live(session: &LiveSession) -> bool {
+    session.process_start.is_some() && process_start(session.pid) == session.process_start
+}
+
+// the start time in clock ticks after boot, the 22nd field of /proc/<pid>/stat
+#[cfg(target_os = "linux")]
+fn process_start(pid: u32) -> Option<String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name before it may contain spaces and parentheses
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19).map(str::to_string)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_start(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let start = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !start.is_empty()).then_some(start)
+}
+
+#[cfg(not(unix))]
+fn process_start(_pid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(pid: u32, process_start: Option<String>) -> LiveSession {
+        LiveSession {
+            pid,
+            profile: "dev".to_string(),
+            mapping: "localhost:5432 → db:5432 via i-0123".to_string(),
+            started: "2025-01-01T00:00:00Z".to_string(),
+            process_start,
+            local_port: 5432,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_session_is_live_only_while_its_process_runs() {
+        let pid = std::process::id();
+        assert!(is_live(&session(pid, process_start(pid))));
+        // the pid was reused by a process that started later
+        assert!(!is_live(&session(pid, Some("0".to_string()))));
+        // written by a porward that did not record it
+        assert!(!is_live(&session(pid, None)));
+    }
+}
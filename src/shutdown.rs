@@ -0,0 +1,113 @@
+use crate::terminal;
+use std::{
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const IDLE_TIMEOUT_EXIT_CODE: i32 = 3;
+const SIGINT_EXIT_CODE: i32 = 130;
+#[cfg(unix)]
+const SIGTERM_EXIT_CODE: i32 = 143;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    exit_code: Arc<AtomicI32>,
+}
+
+impl Shutdown {
+    pub fn install() -> Self {
+        let shutdown = Shutdown::default();
+        let handler = shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let exit_code = next_signal().await;
+                if handler.exit_code().is_some() {
+                    terminal::restore();
+                    std::process::exit(exit_code);
+                }
+                handler.exit_code.store(exit_code, Ordering::SeqCst);
+            }
+        });
+        shutdown
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.exit_code.load(Ordering::SeqCst) {
+            0 => None,
+            code => Some(code),
+        }
+    }
+
+    pub fn request(&self, exit_code: i32) {
+        let _ = self
+            .exit_code
+            .compare_exchange(0, exit_code, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.exit_code().is_some()
+    }
+
+    pub async fn wait(&self) {
+        while !self.is_requested() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn next_signal() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).ok();
+    tokio::select! {
+        Ok(()) = tokio::signal::ctrl_c() => SIGINT_EXIT_CODE,
+        Some(()) = async { terminate.as_mut()?.recv().await } => SIGTERM_EXIT_CODE,
+        else => std::future::pending::<i32>().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn next_signal() -> i32 {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => SIGINT_EXIT_CODE,
+        Err(_) => std::future::pending::<i32>().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn signals_map_to_their_exit_codes() {
+        for (signal, exit_code) in [
+            (libc::SIGINT, SIGINT_EXIT_CODE),
+            (libc::SIGTERM, SIGTERM_EXIT_CODE),
+        ] {
+            let next = tokio::spawn(next_signal());
+            // the handlers are in place once the task has run
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            unsafe {
+                libc::kill(libc::getpid(), signal);
+            }
+            let received = tokio::time::timeout(Duration::from_secs(5), next).await;
+            assert_eq!(received.unwrap().unwrap(), exit_code);
+        }
+    }
+
+    #[test]
+    fn the_first_requested_exit_code_wins() {
+        let shutdown = Shutdown::default();
+        assert_eq!(shutdown.exit_code(), None);
+        shutdown.request(IDLE_TIMEOUT_EXIT_CODE);
+        shutdown.request(SIGINT_EXIT_CODE);
+        assert!(shutdown.is_requested());
+        assert_eq!(shutdown.exit_code(), Some(IDLE_TIMEOUT_EXIT_CODE));
+    }
+}
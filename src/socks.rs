@@ -0,0 +1,177 @@
+use crate::{
+    events,
+    porwarder::{Destination, InstanceTarget},
+    relay,
+    session::{self, DEFAULT_READY_TIMEOUT},
+    shutdown::Shutdown,
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_GENERAL_FAILURE: u8 = 1;
+const REPLY_HOST_UNREACHABLE: u8 = 4;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Local SOCKS5 proxy that starts a port forwarding session through one instance for every
+/// CONNECT. It only listens on localhost and does not ask for credentials.
+pub struct SocksProxy {
+    target: InstanceTarget,
+    aws_cli: bool,
+}
+
+impl SocksProxy {
+    pub fn new(target: InstanceTarget, aws_cli: bool) -> Self {
+        SocksProxy { target, aws_cli }
+    }
+
+    pub async fn serve(mut self, port: u16, shutdown: &Shutdown) -> Result<()> {
+        self.target.resolve_region().await;
+        let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| eyre!("could not listen on {}: {}", address, e))?;
+        events::say(&format!("SOCKS5 proxy listening on {}", address));
+        let proxy = Arc::new(self);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            eprintln!("socks: accept failed: {}", e);
+                            continue;
+                        }
+                    };
+                    let proxy = proxy.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = proxy.handle(stream).await {
+                            eprintln!("socks: {}: {}", peer, e);
+                        }
+                    });
+                }
+                _ = shutdown.wait() => return Ok(()),
+            }
+        }
+    }
+
+    async fn handle(&self, mut client: TcpStream) -> Result<()> {
+        // greeting: version, then the authentication methods the client offers
+        let mut header = [0u8; 2];
+        client.read_exact(&mut header).await?;
+        if header[0] != VERSION {
+            return Err(eyre!("unsupported SOCKS version {}", header[0]));
+        }
+        let mut methods = vec![0u8; header[1] as usize];
+        client.read_exact(&mut methods).await?;
+        if !methods.contains(&NO_AUTHENTICATION) {
+            client.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+            return Err(eyre!("the client requires authentication"));
+        }
+        client.write_all(&[VERSION, NO_AUTHENTICATION]).await?;
+
+        // request: version, command, reserved, address type, address and port
+        let mut request = [0u8; 4];
+        client.read_exact(&mut request).await?;
+        if request[1] != CONNECT {
+            reply(&mut client, REPLY_COMMAND_NOT_SUPPORTED).await?;
+            return Err(eyre!("only CONNECT is supported"));
+        }
+        let host = match request[3] {
+            ADDRESS_IPV4 => {
+                let mut octets = [0u8; 4];
+                client.read_exact(&mut octets).await?;
+                Ipv4Addr::from(octets).to_string()
+            }
+            ADDRESS_IPV6 => {
+                let mut octets = [0u8; 16];
+                client.read_exact(&mut octets).await?;
+                Ipv6Addr::from(octets).to_string()
+            }
+            ADDRESS_DOMAIN => {
+                let length = client.read_u8().await?;
+                let mut domain = vec![0u8; length as usize];
+                client.read_exact(&mut domain).await?;
+                String::from_utf8(domain).map_err(|_| eyre!("invalid domain name"))?
+            }
+            address_type => {
+                reply(&mut client, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+                return Err(eyre!("unsupported address type {}", address_type));
+            }
+        };
+        let port = client.read_u16().await?;
+
+        events::say(&format!(
+            "socks: {}:{} through {}",
+            host,
+            port,
+            self.target.instance_id()
+        ));
+        let local_port = relay::free_local_port()?;
+        let port_forwarder = self
+            .target
+            .forward_to(Destination { host, port }, local_port);
+        let mut command = match port_forwarder.session_command(self.aws_cli).await {
+            Ok(command) => command,
+            Err(e) => {
+                reply(&mut client, REPLY_GENERAL_FAILURE).await?;
+                return Err(e);
+            }
+        };
+        // keep the child out of our process group so that Ctrl+C reaches porward only
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let started = tokio::time::Instant::now();
+        let mut upstream = loop {
+            if let Ok(upstream) = TcpStream::connect((Ipv4Addr::LOCALHOST, local_port)).await {
+                break upstream;
+            }
+            if started.elapsed() > DEFAULT_READY_TIMEOUT || child.try_wait()?.is_some() {
+                reply(&mut client, REPLY_HOST_UNREACHABLE).await?;
+                let _ = session::terminate(&mut child).await;
+                return Err(eyre!(
+                    "the session to {} did not start",
+                    port_forwarder.destination().host
+                ));
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        };
+        reply(&mut client, REPLY_SUCCEEDED).await?;
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+        session::terminate(&mut child).await?;
+        Ok(())
+    }
+}
+
+// the bound address is not meaningful for a tunnel, so it is always 0.0.0.0:0
+async fn reply(client: &mut TcpStream, code: u8) -> Result<()> {
+    client
+        .write_all(&[VERSION, code, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use crate::porwarder::Service;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What porward remembers between runs, next to the history.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    // profile, then service
+    #[serde(default)]
+    last_destinations: BTreeMap<String, BTreeMap<String, LastDestination>>,
+}
+
+/// The host of the last successful session with a profile and service.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct LastDestination {
+    pub host: String,
+    pub used: u64,
+}
+
+impl LastDestination {
+    pub fn ago(&self) -> String {
+        ago(now().saturating_sub(self.used))
+    }
+}
+
+// coarse on purpose, e.g. "2h ago"
+pub(crate) fn ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+pub(crate) fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("porward").join("state.json"))
+}
+
+// a missing or unreadable state file is the same as an empty one
+fn load(path: &Path) -> State {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub(crate) fn last_destination(
+    path: &Path,
+    profile_name: &str,
+    service: &Service,
+) -> Option<LastDestination> {
+    load(path)
+        .last_destinations
+        .get(profile_name)?
+        .get(&service.to_string())
+        .cloned()
+}
+
+// best effort like the history, and written through a temporary file so a crash can not leave
+// half a state file behind
+pub(crate) fn remember_destination(path: &Path, profile_name: &str, service: &Service, host: &str) {
+    let mut state = load(path);
+    state
+        .last_destinations
+        .entry(profile_name.to_string())
+        .or_default()
+        .insert(
+            service.to_string(),
+            LastDestination {
+                host: host.to_string(),
+                used: now(),
+            },
+        );
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let Ok(content) = serde_json::to_string_pretty(&state) else {
+        return;
+    };
+    let temporary = path.with_extension("json.tmp");
+    if std::fs::write(&temporary, content).is_ok() {
+        let _ = std::fs::rename(&temporary, path);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a state file of its own per test, next to the others of this run
+    fn state_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("porward-state-{}", std::process::id()))
+            .join(format!("{}.json", name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn a_remembered_destination_is_kept_per_profile_and_service() {
+        let path = state_file("round-trip");
+        let service = Service::all().remove(0);
+        remember_destination(&path, "dev", &service, "db.internal");
+        remember_destination(&path, "prod", &service, "db.prod.internal");
+        let last = last_destination(&path, "dev", &service).unwrap();
+        assert_eq!(last.host, "db.internal");
+        assert_eq!(last.ago(), "just now");
+        assert_eq!(
+            last_destination(&path, "prod", &service).unwrap().host,
+            "db.prod.internal"
+        );
+        assert!(last_destination(&path, "staging", &service).is_none());
+        for other in Service::all().into_iter().skip(1) {
+            assert!(
+                last_destination(&path, "dev", &other).is_none(),
+                "{}",
+                other
+            );
+        }
+        // the last session wins
+        remember_destination(&path, "dev", &service, "replica.internal");
+        assert_eq!(
+            last_destination(&path, "dev", &service).unwrap().host,
+            "replica.internal"
+        );
+    }
+
+    #[test]
+    fn a_missing_state_file_remembers_nothing() {
+        let path = state_file("missing");
+        assert!(last_destination(&path, "dev", &Service::all().remove(0)).is_none());
+    }
+
+    #[test]
+    fn a_corrupt_state_file_is_ignored_and_replaced() {
+        let path = state_file("corrupt");
+        let service = Service::all().remove(0);
+        for content in ["{\"last_destinations\": {\"dev\": 3", "not json", ""] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            assert!(
+                last_destination(&path, "dev", &service).is_none(),
+                "{}",
+                content
+            );
+            remember_destination(&path, "dev", &service, "db.internal");
+            assert_eq!(
+                last_destination(&path, "dev", &service).unwrap().host,
+                "db.internal"
+            );
+        }
+    }
+}
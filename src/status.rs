@@ -0,0 +1,179 @@
+use crate::{
+    relay::format_bytes,
+    session::{format_duration, SessionState, SessionStatus},
+    supervisor::SupervisedTunnel,
+    terminal,
+};
+use color_eyre::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Widget},
+    DefaultTerminal,
+};
+use std::time::Duration;
+
+const STATUS_LINES: u16 = 4;
+
+pub enum StatusCommand {
+    Quit,
+    Reconnect,
+    Add,
+    // the index of the highlighted added tunnel
+    Close(usize),
+}
+
+pub struct StatusView {
+    terminal: DefaultTerminal,
+    selected: usize,
+    tunnels: usize,
+}
+
+impl StatusView {
+    pub fn inline(extra_lines: u16) -> Self {
+        let terminal = crate::terminal::init_inline(STATUS_LINES + extra_lines);
+        Self {
+            terminal,
+            selected: 0,
+            tunnels: 0,
+        }
+    }
+
+    pub fn print(&mut self, message: &str) -> Result<()> {
+        self.terminal.insert_before(1, |buf| {
+            Paragraph::new(message.to_string()).render(buf.area, buf);
+        })?;
+        Ok(())
+    }
+
+    // added tunnels are listed below the session, the highlighted one is closed with x
+    pub fn draw(
+        &mut self,
+        status: &SessionStatus,
+        tunnels: &[SupervisedTunnel],
+        can_add: bool,
+    ) -> Result<()> {
+        self.tunnels = tunnels.len();
+        self.selected = self.selected.min(tunnels.len().saturating_sub(1));
+        let selected = self.selected;
+        let keys = match (can_add, tunnels.is_empty()) {
+            (false, _) => " q quit · r reconnect ",
+            (true, true) => " q quit · r reconnect · a add ",
+            (true, false) => " q quit · r reconnect · a add · x close ",
+        };
+        let state = match status.state {
+            SessionState::Connecting => Span::styled("connecting", Style::default().yellow()),
+            SessionState::Ready if status.degraded.is_some() => {
+                Span::styled("degraded", Style::default().red())
+            }
+            SessionState::Ready => Span::styled("ready", Style::default().green()),
+            SessionState::Reconnecting => Span::styled("reconnecting", Style::default().yellow()),
+        };
+        let session_id = status
+            .session_id
+            .as_ref()
+            .map(|session_id| format!("  session: {}", session_id))
+            .unwrap_or_default();
+        let traffic = status
+            .traffic
+            .map(|traffic| {
+                format!(
+                    "  ↑ {} ↓ {}  connections: {} active / {} total",
+                    format_bytes(traffic.sent),
+                    format_bytes(traffic.received),
+                    traffic.active,
+                    traffic.connections
+                )
+            })
+            .unwrap_or_default();
+        self.terminal.draw(|frame| {
+            let mut block = Block::default()
+                .borders(Borders::ALL)
+                .title(Line::from(format!(" {} ", status.mapping)).left_aligned())
+                .title_bottom(Line::from(keys).right_aligned());
+            if let Some(url) = status.url.as_ref() {
+                block = block.title_bottom(Line::from(format!(" {} ", url)).left_aligned());
+            }
+            let mut lines = vec![
+                Line::from(vec![
+                    state,
+                    Span::raw(format!(
+                        "  {}  reconnects: {}{}{}",
+                        format_duration(status.started.elapsed()),
+                        status.reconnects,
+                        traffic,
+                        session_id
+                    )),
+                ]),
+                // the failed health check says more than the plugin output
+                match status.degraded.as_ref() {
+                    Some(degraded) => Line::from(degraded.clone()).red(),
+                    None => Line::from(status.last_output.clone().unwrap_or_default()).dark_gray(),
+                },
+            ];
+            lines.extend(
+                status
+                    .recent_connections
+                    .iter()
+                    .rev()
+                    .map(|connection| Line::from(connection.summary()).dark_gray()),
+            );
+            lines.extend(tunnels.iter().enumerate().map(|(index, tunnel)| {
+                let (local_port, state, uptime) = tunnel.row();
+                let line = Line::from(format!(
+                    "{}  {}  {}  {}",
+                    tunnel.name(),
+                    local_port,
+                    state,
+                    uptime
+                ));
+                if index == selected {
+                    line.reversed()
+                } else {
+                    line
+                }
+            }));
+            frame.render_widget(Paragraph::new(lines).block(block), frame.area());
+        })?;
+        Ok(())
+    }
+
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<StatusCommand>> {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Char('q') => Ok(Some(StatusCommand::Quit)),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(Some(StatusCommand::Quit))
+                }
+                KeyCode::Char('r') => Ok(Some(StatusCommand::Reconnect)),
+                KeyCode::Char('a') => Ok(Some(StatusCommand::Add)),
+                KeyCode::Char('x') if self.tunnels > 0 => {
+                    Ok(Some(StatusCommand::Close(self.selected)))
+                }
+                KeyCode::Up | KeyCode::Char('k') if self.tunnels > 0 => {
+                    self.selected = (self.selected + self.tunnels - 1) % self.tunnels;
+                    Ok(None)
+                }
+                KeyCode::Down | KeyCode::Char('j') if self.tunnels > 0 => {
+                    self.selected = (self.selected + 1) % self.tunnels;
+                    Ok(None)
+                }
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for StatusView {
+    fn drop(&mut self) {
+        let _ = self.terminal.draw(|frame| {
+            frame.render_widget(Block::new(), frame.area());
+        });
+        terminal::restore();
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    config::Config,
+    events,
+    session::{format_duration, terminate},
+    sessions::{self, Registration},
+    shutdown::Shutdown,
+    tunnels::TunnelDefinition,
+};
+use color_eyre::{eyre::eyre, Result};
+use crossterm::{
+    cursor::MoveUp,
+    queue,
+    terminal::{Clear, ClearType},
+};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+use tokio::{
+    process::{Child, Command},
+    task::JoinSet,
+};
+
+enum TunnelState {
+    Running(Child),
+    Exited(ExitStatus),
+    Failed(String),
+}
+
+// a session without reconnects or output, for tunnels that run next to others
+pub(crate) struct SupervisedTunnel {
+    name: String,
+    local_port: Option<u16>,
+    started: Instant,
+    state: TunnelState,
+    // counted by max_sessions while the tunnel runs
+    registration: Option<Registration>,
+}
+
+impl SupervisedTunnel {
+    pub fn spawn(name: String, local_port: Option<u16>, command: Result<Command>) -> Self {
+        let state = match command.and_then(|mut command| {
+            Ok(command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()?)
+        }) {
+            Ok(child) => TunnelState::Running(child),
+            Err(e) => TunnelState::Failed(e.to_string()),
+        };
+        SupervisedTunnel {
+            name,
+            local_port,
+            started: Instant::now(),
+            state,
+            registration: None,
+        }
+    }
+
+    pub fn register(&mut self, profile: &str, mapping: &str) {
+        if let (TunnelState::Running(_), Some(local_port)) = (&self.state, self.local_port) {
+            self.registration = sessions::register(profile, mapping, local_port);
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn local_port(&self) -> Option<u16> {
+        self.local_port
+    }
+
+    pub fn failure(&self) -> Option<&str> {
+        match &self.state {
+            TunnelState::Failed(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, TunnelState::Running(_))
+    }
+
+    // dropping a tunnel kills it, this gives the plugin a chance to close the session
+    pub async fn stop(mut self) -> Result<()> {
+        if let TunnelState::Running(child) = &mut self.state {
+            terminate(child).await?;
+        }
+        Ok(())
+    }
+
+    pub fn refresh(&mut self) -> Result<()> {
+        if let TunnelState::Running(child) = &mut self.state {
+            if let Some(status) = child.try_wait()? {
+                self.state = TunnelState::Exited(status);
+                self.registration = None;
+            }
+        }
+        Ok(())
+    }
+
+    // local port, state and uptime as shown in the tables
+    pub fn row(&self) -> (String, String, String) {
+        let (state, uptime) = match &self.state {
+            TunnelState::Running(_) => (
+                "running".to_string(),
+                format_duration(self.started.elapsed()),
+            ),
+            TunnelState::Exited(status) => (
+                status
+                    .code()
+                    .map(|code| format!("exited ({})", code))
+                    .unwrap_or("exited".to_string()),
+                "-".to_string(),
+            ),
+            TunnelState::Failed(_) => ("failed".to_string(), "-".to_string()),
+        };
+        let local_port = self
+            .local_port
+            .map(|port| port.to_string())
+            .unwrap_or("-".to_string());
+        (local_port, state, uptime)
+    }
+}
+
+// the tunnels of a profile all count against its max_sessions, none is started unless they fit
+fn check_session_limits<'a>(profiles: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut starting = BTreeMap::<&str, usize>::new();
+    for profile in profiles {
+        *starting.entry(profile).or_default() += 1;
+    }
+    let config = Config::load()?;
+    for (profile, starting) in starting {
+        let Some(max_sessions) = config.max_sessions(profile) else {
+            continue;
+        };
+        if !sessions::TRACKED {
+            events::say(&format!(
+                "warning: max_sessions = {} of profile '{}' is not enforced on this platform",
+                max_sessions, profile
+            ));
+            continue;
+        }
+        if let Some(live) = sessions::over_limit(profile, starting, max_sessions) {
+            return Err(eyre!(
+                "profile '{}' has {} sessions, {} more go past max_sessions = {}:\n{}\nclose some (q in their status panel or kill <pid>) or start fewer tunnels",
+                profile,
+                live.len(),
+                starting,
+                max_sessions,
+                sessions::listing(&live)
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub struct Supervisor {
+    tunnels: Vec<SupervisedTunnel>,
+    rendered_lines: u16,
+}
+
+impl Supervisor {
+    pub async fn start(
+        definitions: Vec<(String, TunnelDefinition)>,
+        aws_cli: bool,
+    ) -> Result<Self> {
+        let mut resolving = JoinSet::new();
+        for (idx, (name, definition)) in definitions.into_iter().enumerate() {
+            resolving.spawn(async move {
+                let port_forwarder = definition.resolve().await;
+                (idx, name, definition, port_forwarder)
+            });
+        }
+        let mut resolved = resolving.join_all().await;
+        resolved.sort_by_key(|(idx, ..)| *idx);
+
+        // checked once for the whole batch and before StartSession, which would leave sessions
+        // open on AWS when the batch is refused. Under the lock so that porwards started meanwhile
+        // are counted too
+        let _lock = sessions::lock();
+        check_session_limits(
+            resolved
+                .iter()
+                .filter(|(.., port_forwarder)| port_forwarder.is_ok())
+                .map(|(_, _, definition, _)| definition.profile.as_str()),
+        )?;
+        let mut starting = JoinSet::new();
+        for (idx, name, definition, port_forwarder) in resolved {
+            starting.spawn(async move {
+                let command = match port_forwarder {
+                    Ok(port_forwarder) => {
+                        port_forwarder
+                            .session_command(aws_cli)
+                            .await
+                            .map(|command| {
+                                (
+                                    port_forwarder.local_port(),
+                                    port_forwarder.mapping(),
+                                    command,
+                                )
+                            })
+                    }
+                    Err(e) => Err(e),
+                };
+                (idx, name, definition, command)
+            });
+        }
+        let mut started = starting.join_all().await;
+        started.sort_by_key(|(idx, ..)| *idx);
+        let mut tunnels = Vec::new();
+        for (_, name, definition, command) in started {
+            let (local_port, mapping, command) = match command {
+                Ok((port, mapping, command)) => (Some(port), Some(mapping), Ok(command)),
+                Err(e) => (definition.destination.local_port, None, Err(e)),
+            };
+            let mut tunnel = SupervisedTunnel::spawn(name, local_port, command);
+            if let Some(reason) = tunnel.failure() {
+                events::say(&format!("Skipping '{}': {}", tunnel.name, reason));
+            }
+            if let Some(mapping) = mapping {
+                tunnel.register(&definition.profile, &mapping);
+            }
+            tunnels.push(tunnel);
+        }
+        Ok(Supervisor {
+            tunnels,
+            rendered_lines: 0,
+        })
+    }
+
+    // runs until every tunnel exited or a shutdown is requested, which stops the others
+    pub async fn wait(mut self, shutdown: &Shutdown) -> Result<()> {
+        loop {
+            for tunnel in self.tunnels.iter_mut() {
+                tunnel.refresh()?;
+            }
+            self.render()?;
+            if !self.tunnels.iter().any(SupervisedTunnel::is_running) {
+                return Ok(());
+            }
+            if shutdown.is_requested() {
+                break;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+        for tunnel in self.tunnels.drain(..) {
+            let _ = tunnel.stop().await;
+        }
+        Ok(())
+    }
+
+    fn render(&mut self) -> Result<()> {
+        let name_width = self
+            .tunnels
+            .iter()
+            .map(|tunnel| tunnel.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("NAME".len());
+        let mut lines = vec![format!(
+            "{:<name_width$}  {:<10}  {:<12}  UPTIME",
+            "NAME", "LOCAL PORT", "STATE"
+        )];
+        for tunnel in &self.tunnels {
+            let (local_port, state, uptime) = tunnel.row();
+            lines.push(format!(
+                "{:<name_width$}  {:<10}  {:<12}  {}",
+                tunnel.name, local_port, state, uptime
+            ));
+        }
+
+        let mut out = events::human();
+        if self.rendered_lines > 0 {
+            queue!(out, MoveUp(self.rendered_lines))?;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown))?;
+        for line in &lines {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()?;
+        self.rendered_lines = lines.len() as u16;
+        Ok(())
+    }
+}
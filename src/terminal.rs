@@ -0,0 +1,205 @@
+use ratatui::{DefaultTerminal, TerminalOptions};
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// restoring a terminal that was never initialized still writes escape sequences
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// What the terminal porward runs in can display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminalSupport {
+    Full,
+    NoColor,
+    Plain,
+}
+
+impl TerminalSupport {
+    pub fn detect() -> Self {
+        Self::from_env(
+            std::env::var("TERM").ok().as_deref(),
+            std::env::var("NO_COLOR").ok().as_deref(),
+            std::io::stdout().is_terminal(),
+        )
+    }
+
+    // NO_COLOR only counts when it is set to something, see https://no-color.org
+    pub fn from_env(term: Option<&str>, no_color: Option<&str>, is_terminal: bool) -> Self {
+        if !is_terminal || term == Some("dumb") {
+            TerminalSupport::Plain
+        } else if no_color.is_some_and(|value| !value.is_empty()) {
+            TerminalSupport::NoColor
+        } else {
+            TerminalSupport::Full
+        }
+    }
+}
+
+pub fn init_inline(lines: u16) -> DefaultTerminal {
+    ACTIVE.store(true, Ordering::SeqCst);
+    ratatui::init_with_options(TerminalOptions {
+        viewport: ratatui::Viewport::Inline(lines),
+    })
+}
+
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        #[cfg(not(test))]
+        ratatui::restore();
+        // tests count the restores instead of writing to the terminal running them
+        #[cfg(test)]
+        tests::RESTORES.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Restores the terminal before the panic hook set so far prints the panic, which would
+/// otherwise end up in a terminal without echo. Install it after any other panic hook.
+pub fn install_panic_hook() {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+}
+
+/// Restores the terminal when dropped, however the code holding it is left.
+#[must_use]
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+pub fn guard() -> TerminalGuard {
+    TerminalGuard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{atomic::AtomicUsize, Mutex, Once},
+    };
+
+    pub(super) static RESTORES: AtomicUsize = AtomicUsize::new(0);
+    // the restores when the hook installed before ours ran last
+    static RESTORES_BEFORE_PRINTING: AtomicUsize = AtomicUsize::new(usize::MAX);
+    // the tests below share the active flag and the panic hook
+    static TERMINAL: Mutex<()> = Mutex::new(());
+
+    fn install_hooks() {
+        static INSTALLED: Once = Once::new();
+        INSTALLED.call_once(|| {
+            let print = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                RESTORES_BEFORE_PRINTING.store(RESTORES.load(Ordering::SeqCst), Ordering::SeqCst);
+                print(info);
+            }));
+            install_panic_hook();
+        });
+    }
+
+    // the restores `run` causes after the terminal was initialized
+    fn restores(run: impl FnOnce()) -> usize {
+        let before = RESTORES.load(Ordering::SeqCst);
+        ACTIVE.store(true, Ordering::SeqCst);
+        run();
+        RESTORES.load(Ordering::SeqCst) - before
+    }
+
+    #[test]
+    fn a_panic_restores_once_before_it_is_printed() {
+        let _terminal = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+        install_hooks();
+        let restored = restores(|| {
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let _guard = guard();
+                panic!("selector failed");
+            }));
+            assert!(result.is_err());
+        });
+        assert_eq!(restored, 1);
+        assert_eq!(
+            RESTORES_BEFORE_PRINTING.load(Ordering::SeqCst),
+            RESTORES.load(Ordering::SeqCst)
+        );
+    }
+
+    #[test]
+    fn returning_early_or_with_an_error_restores_once() {
+        let _terminal = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+        fn profiles(fail: bool) -> Result<(), String> {
+            match fail {
+                true => Err("no profiles".to_string()),
+                false => Ok(()),
+            }
+        }
+        fn run(fail: bool, early: bool) -> Result<(), String> {
+            let _guard = guard();
+            if early {
+                return Ok(());
+            }
+            profiles(fail)?;
+            // the selector hands the terminal back before the guard is dropped
+            restore();
+            Ok(())
+        }
+        assert_eq!(restores(|| assert!(run(false, true).is_ok())), 1);
+        assert_eq!(restores(|| assert!(run(true, false).is_err())), 1);
+        assert_eq!(restores(|| assert!(run(false, false).is_ok())), 1);
+    }
+
+    #[test]
+    fn an_inactive_terminal_is_left_alone() {
+        let _terminal = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+        let before = RESTORES.load(Ordering::SeqCst);
+        ACTIVE.store(false, Ordering::SeqCst);
+        drop(guard());
+        restore();
+        assert_eq!(RESTORES.load(Ordering::SeqCst), before);
+    }
+
+    #[test]
+    fn a_capable_terminal_gets_everything() {
+        for term in [Some("xterm-256color"), Some("screen"), None] {
+            assert_eq!(
+                TerminalSupport::from_env(term, None, true),
+                TerminalSupport::Full
+            );
+        }
+    }
+
+    #[test]
+    fn an_empty_no_color_is_not_set() {
+        assert_eq!(
+            TerminalSupport::from_env(Some("xterm"), Some(""), true),
+            TerminalSupport::Full
+        );
+        for no_color in ["1", "0", "true"] {
+            assert_eq!(
+                TerminalSupport::from_env(Some("xterm"), Some(no_color), true),
+                TerminalSupport::NoColor
+            );
+        }
+    }
+
+    #[test]
+    fn dumb_terminals_and_pipes_are_plain() {
+        for no_color in [None, Some(""), Some("1")] {
+            assert_eq!(
+                TerminalSupport::from_env(Some("dumb"), no_color, true),
+                TerminalSupport::Plain
+            );
+            for term in [Some("xterm"), Some("dumb"), None] {
+                assert_eq!(
+                    TerminalSupport::from_env(term, no_color, false),
+                    TerminalSupport::Plain
+                );
+            }
+        }
+    }
+}
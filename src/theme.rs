@@ -0,0 +1,150 @@
+use crate::config::{Config, ThemeConfig};
+use color_eyre::{eyre::eyre, Result};
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+
+const UNICODE_SYMBOL: &str = "〉";
+const ASCII_SYMBOL: &str = "> ";
+
+/// Styles of the TUI selector, from a preset and the `[theme]` section of the config file.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub highlight: Style,
+    pub border: Style,
+    pub title: Style,
+    pub detail: Style,
+    pub error: Style,
+    pub warning: Style,
+    pub symbol: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            highlight: Style::default().add_modifier(Modifier::BOLD),
+            border: Style::default(),
+            title: Style::default(),
+            detail: Style::default().add_modifier(Modifier::DIM),
+            error: Style::default().fg(Color::Red),
+            warning: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            symbol: UNICODE_SYMBOL.to_string(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Theme::default()),
+            "high-contrast" => Some(Theme {
+                highlight: Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+                border: Style::default().fg(Color::White),
+                title: Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+                detail: Style::default().fg(Color::Cyan),
+                error: Style::default()
+                    .fg(Color::LightRed)
+                    .add_modifier(Modifier::BOLD),
+                warning: Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::LightRed)
+                    .add_modifier(Modifier::BOLD),
+                symbol: UNICODE_SYMBOL.to_string(),
+            }),
+            "monochrome" => Some(Theme::default().monochrome()),
+            _ => None,
+        }
+    }
+
+    // the highlight symbol still marks the selected row
+    pub fn monochrome(self) -> Self {
+        Theme {
+            highlight: Style::default(),
+            border: Style::default(),
+            title: Style::default(),
+            detail: Style::default(),
+            error: Style::default(),
+            warning: Style::default(),
+            symbol: self.symbol,
+        }
+    }
+
+    pub fn load() -> Result<Self> {
+        Self::from_config(&Config::load()?.theme)
+    }
+
+    pub(crate) fn from_config(config: &ThemeConfig) -> Result<Self> {
+        let mut theme = match config.preset.as_deref() {
+            Some(name) => {
+                Theme::preset(name).ok_or(eyre!("theme.preset: unknown preset '{}'", name))?
+            }
+            None => Theme::default(),
+        };
+        for (key, value, style) in [
+            ("highlight", &config.highlight, &mut theme.highlight),
+            ("border", &config.border, &mut theme.border),
+            ("title", &config.title, &mut theme.title),
+            ("detail", &config.detail, &mut theme.detail),
+            ("error", &config.error, &mut theme.error),
+            ("warning", &config.warning, &mut theme.warning),
+        ] {
+            if let Some(value) = value {
+                *style = parse_style(value).map_err(|e| eyre!("theme.{}: {}", key, e))?;
+            }
+        }
+        if let Some(symbol) = config.symbol.as_deref() {
+            theme.symbol = match symbol {
+                "ascii" => ASCII_SYMBOL,
+                "unicode" => UNICODE_SYMBOL,
+                symbol => symbol,
+            }
+            .to_string();
+        }
+        Ok(theme)
+    }
+}
+
+// e.g. "bold yellow" or "black on #ffaf00": the first color is the foreground, `on` sets the background
+fn parse_style(value: &str) -> Result<Style> {
+    let mut style = Style::default();
+    let mut background = false;
+    for word in value.split_whitespace() {
+        if word == "on" {
+            background = true;
+            continue;
+        }
+        if let Some(modifier) = parse_modifier(word) {
+            style = style.add_modifier(modifier);
+            continue;
+        }
+        let color = Color::from_str(word).map_err(|_| eyre!("invalid color '{}'", word))?;
+        style = if background {
+            style.bg(color)
+        } else {
+            style.fg(color)
+        };
+        background = false;
+    }
+    if background {
+        return Err(eyre!("'on' has to be followed by a color"));
+    }
+    Ok(style)
+}
+
+fn parse_modifier(word: &str) -> Option<Modifier> {
+    match word {
+        "bold" => Some(Modifier::BOLD),
+        "dim" => Some(Modifier::DIM),
+        "italic" => Some(Modifier::ITALIC),
+        "underlined" => Some(Modifier::UNDERLINED),
+        "reversed" => Some(Modifier::REVERSED),
+        "crossed-out" => Some(Modifier::CROSSED_OUT),
+        _ => None,
+    }
+}
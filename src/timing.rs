@@ -0,0 +1,124 @@
+use aws_sdk_ssm::{
+    config::{
+        interceptors::{BeforeSerializationInterceptorContextRef, FinalizerInterceptorContextRef},
+        ConfigBag, Intercept, RuntimeComponents,
+    },
+    error::BoxError,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+// only set with --timing, recording is a no-op otherwise
+static TIMING: OnceLock<Timing> = OnceLock::new();
+
+struct Timing {
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    pending_steps: BTreeMap<String, Instant>,
+    steps: Vec<(String, Duration)>,
+    services: BTreeMap<&'static str, Calls>,
+}
+
+// the total latency is the sum of the finish offsets minus the sum of the start offsets, so
+// concurrent calls of one client do not have to be paired up
+#[derive(Default)]
+struct Calls {
+    count: u32,
+    started: Duration,
+    finished: Duration,
+}
+
+/// Records how long each builder step takes to list its options and the count and latency of
+/// the AWS calls per service, for [`report`]. Has to come before any AWS client is created.
+pub fn enable() {
+    let _ = TIMING.set(Timing {
+        started: Instant::now(),
+        state: Mutex::new(State::default()),
+    });
+}
+
+pub(crate) fn enabled() -> bool {
+    TIMING.get().is_some()
+}
+
+fn record(update: impl FnOnce(&mut State, Duration)) {
+    if let Some(timing) = TIMING.get() {
+        update(&mut timing.state.lock().unwrap(), timing.started.elapsed());
+    }
+}
+
+pub(crate) fn step_started(step: &str) {
+    record(|state, _| {
+        state.pending_steps.insert(step.to_string(), Instant::now());
+    });
+}
+
+pub(crate) fn step_finished(step: &str) {
+    record(|state, _| {
+        if let Some(started) = state.pending_steps.remove(step) {
+            state.steps.push((step.to_string(), started.elapsed()));
+        }
+    });
+}
+
+/// The breakdown of everything recorded so far, `None` without [`enable`].
+pub fn report() -> Option<String> {
+    let timing = TIMING.get()?;
+    let state = timing.state.lock().unwrap();
+    let mut report = format!("timing: {:.1?} in total\n", timing.started.elapsed());
+    for (step, duration) in state.steps.iter() {
+        let _ = writeln!(report, "  step {:<18} {:>8.1?}", step, duration);
+    }
+    for (service, calls) in state.services.iter() {
+        let _ = writeln!(
+            report,
+            "  {:<12} {:>3} calls {:>8.1?}",
+            service,
+            calls.count,
+            calls.finished.saturating_sub(calls.started)
+        );
+    }
+    Some(report)
+}
+
+// added to every client by the client! macro while timing is enabled
+#[derive(Debug)]
+pub(crate) struct ApiTimer(pub &'static str);
+
+impl Intercept for ApiTimer {
+    fn name(&self) -> &'static str {
+        "porward timing"
+    }
+
+    fn read_before_execution(
+        &self,
+        _context: &BeforeSerializationInterceptorContextRef<'_>,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        record(|state, offset| state.services.entry(self.0).or_default().started += offset);
+        Ok(())
+    }
+
+    // the first call of a profile includes resolving its credentials, e.g. an sso login
+    fn read_after_execution(
+        &self,
+        _context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        record(|state, offset| {
+            let calls = state.services.entry(self.0).or_default();
+            calls.count += 1;
+            calls.finished += offset;
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{
+    config::{Config, DestinationDefinition},
+    events,
+    porwarder::{Parameters, PortForwarder},
+    providers::{client, AwsInstanceProvider, InstanceProvider},
+};
+use aws_config::BehaviorVersion;
+use aws_runtime::env_config;
+use color_eyre::{eyre::eyre, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+// instance = "asg:<name>" picks an instance of that Auto Scaling Group when the tunnel starts
+const ASG_PREFIX: &str = "asg:";
+const DEFAULT_LOGIN_USER: &str = "ec2-user";
+const SSH_PORT: u16 = 22;
+
+#[derive(Clone, Deserialize)]
+pub struct TunnelDefinition {
+    pub profile: String,
+    pub instance: String,
+    #[serde(flatten)]
+    pub destination: DestinationDefinition,
+    pub document_name: Option<String>,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterValue>,
+}
+
+// ssm parameters are always lists of strings, a single string is accepted for convenience
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Default, Deserialize)]
+pub struct TunnelsFile {
+    #[serde(default)]
+    pub tunnels: BTreeMap<String, TunnelDefinition>,
+}
+
+impl TunnelsFile {
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("porward").join("tunnels.toml"))
+            .ok_or(eyre!("could not determine the config directory"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read tunnels file '{}': {}", path.display(), e))?;
+        let tunnels: TunnelsFile = toml::from_str(&content)
+            .map_err(|e| eyre!("invalid tunnels file '{}': {}", path.display(), e))?;
+        for (name, tunnel) in tunnels.tunnels.iter() {
+            tunnel
+                .destination
+                .validate()
+                .map_err(|e| eyre!("tunnel '{}': {}", name, e))?;
+        }
+        Ok(tunnels)
+    }
+
+    pub fn select(&self, names: &[String]) -> Result<Vec<(String, TunnelDefinition)>> {
+        if names.is_empty() {
+            if self.tunnels.is_empty() {
+                return Err(eyre!("no tunnels are defined"));
+            }
+            return Ok(self
+                .tunnels
+                .iter()
+                .map(|(name, tunnel)| (name.clone(), tunnel.clone()))
+                .collect());
+        }
+        names
+            .iter()
+            .map(|name| {
+                self.tunnels
+                    .get(name)
+                    .map(|tunnel| (name.clone(), tunnel.clone()))
+                    .ok_or(eyre!("tunnel '{}' is not defined", name))
+            })
+            .collect()
+    }
+}
+
+impl TunnelDefinition {
+    pub async fn resolve(&self) -> Result<PortForwarder> {
+        let (port_forwarder, warnings) = self.resolve_with_warnings().await?;
+        for warning in warnings {
+            events::say(&format!("warning: {}", warning));
+        }
+        Ok(port_forwarder)
+    }
+
+    /// A bash script that starts the tunnel with the aws cli, for machines without porward. The
+    /// instance, also one picked from an auto scaling group, is resolved now and written into it.
+    pub async fn export_script(&self, name: &str) -> Result<String> {
+        let (port_forwarder, warnings) = self.resolve_with_warnings().await?;
+        self.script(name, &port_forwarder, &warnings)
+    }
+
+    fn script(
+        &self,
+        name: &str,
+        port_forwarder: &PortForwarder,
+        warnings: &[String],
+    ) -> Result<String> {
+        let destination = port_forwarder.destination();
+        let mut script = String::from("#!/usr/bin/env bash\n");
+        script.push_str(&self.export_header(name, port_forwarder, warnings));
+        script.push_str(&format!(
+            "# tunnel: localhost:{} -> {}:{}\n",
+            port_forwarder.local_port(),
+            destination.host,
+            destination.port
+        ));
+        script.push_str("set -euo pipefail\n\n");
+        // the aws cli by name, its path on this machine may not exist where the script runs
+        script.push_str(&format!(
+            "exec {}\n",
+            port_forwarder.portable_command_string()?
+        ));
+        Ok(script)
+    }
+
+    /// A `~/.ssh/config` stanza named after the tunnel that reaches its instance over
+    /// AWS-StartSSHSession.
+    pub async fn export_ssh_config(&self, name: &str, login_user: Option<&str>) -> Result<String> {
+        let (port_forwarder, warnings) = self.resolve_with_warnings().await?;
+        self.ssh_config(name, login_user, &port_forwarder, &warnings)
+    }
+
+    fn ssh_config(
+        &self,
+        name: &str,
+        login_user: Option<&str>,
+        port_forwarder: &PortForwarder,
+        warnings: &[String],
+    ) -> Result<String> {
+        let mut config = self.export_header(name, port_forwarder, warnings);
+        config.push_str(&port_forwarder.target().ssh_config(
+            name,
+            login_user.unwrap_or(DEFAULT_LOGIN_USER),
+            SSH_PORT,
+        )?);
+        Ok(config)
+    }
+
+    // comments on where the exported command came from, and when it stops working
+    fn export_header(
+        &self,
+        name: &str,
+        port_forwarder: &PortForwarder,
+        warnings: &[String],
+    ) -> String {
+        let target = port_forwarder.target();
+        let mut header = format!(
+            "# porward tunnel '{}', exported {}\n",
+            name,
+            humantime::format_rfc3339_seconds(std::time::SystemTime::now())
+        );
+        header.push_str(&format!("# profile: {}", self.profile));
+        if target.session_profile() != self.profile {
+            header.push_str(&format!(
+                " (sessions start with '{}')",
+                target.session_profile()
+            ));
+        }
+        header.push('\n');
+        header.push_str(&format!("# instance: {}", target.instance_id()));
+        if self.instance != target.instance_id() {
+            header.push_str(&format!(" (resolved from {})", self.instance));
+        }
+        header.push('\n');
+        header.push_str(
+            "# instance ids change when instances are replaced, export again when the session fails\n",
+        );
+        header.push_str("# with TargetNotConnected\n");
+        for warning in warnings {
+            header.push_str(&format!("# warning: {}\n", warning));
+        }
+        header
+    }
+
+    async fn resolve_with_warnings(&self) -> Result<(PortForwarder, Vec<String>)> {
+        let fs = aws_types::os_shim_internal::Fs::real();
+        let env = aws_types::os_shim_internal::Env::real();
+        let profile_files = env_config::file::EnvConfigFiles::default();
+        let profiles_set = aws_config::profile::load(&fs, &env, &profile_files, None).await?;
+        if profiles_set.get_profile(&self.profile).is_none() {
+            return Err(eyre!("profile '{}' does not exist", self.profile));
+        }
+
+        let instance_id = match self.instance.strip_prefix(ASG_PREFIX) {
+            Some(group_name) => {
+                self.group_instance(&AwsInstanceProvider::default(), group_name)
+                    .await?
+            }
+            // managed nodes are not ec2 instances, session manager reports them when they are offline
+            None if self.instance.starts_with("mi-") => self.instance.clone(),
+            None => {
+                self.check_running().await?;
+                self.instance.clone()
+            }
+        };
+
+        let host_port = self.destination.host_port();
+        let config = Config::load()?;
+        let mut warnings = vec![];
+        let local_port = match self.destination.local_port {
+            Some(local_port) => {
+                warnings.extend(config.local_ports.outside_range(local_port));
+                local_port
+            }
+            None => config.local_ports.local_port(host_port),
+        };
+        let port_forwarder = PortForwarder::new(
+            self.profile.clone(),
+            instance_id,
+            self.destination.service.clone(),
+            self.destination.host.clone(),
+            host_port,
+            local_port,
+        )?
+        .with_document(self.document_name.clone(), self.parameters())
+        .with_session_profile(config.profile(&self.profile).session_profile)
+        .with_resolved_region()
+        .await;
+        Ok((port_forwarder, warnings))
+    }
+
+    async fn check_running(&self) -> Result<()> {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name(&self.profile)
+            .load()
+            .await;
+        let client = client!(aws_sdk_ec2, &config, ec2);
+        let running = client
+            .describe_instances()
+            .instance_ids(&self.instance)
+            .filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name("instance-state-name")
+                    .values("running")
+                    .build(),
+            )
+            .send()
+            .await?
+            .reservations()
+            .iter()
+            .any(|reservation| !reservation.instances().is_empty());
+        if !running {
+            return Err(eyre!("instance '{}' is not running", self.instance));
+        }
+        Ok(())
+    }
+
+    // instances of a group are replaced over time, so the newest healthy one is used
+    async fn group_instance(
+        &self,
+        provider: &dyn InstanceProvider,
+        group_name: &str,
+    ) -> Result<String> {
+        let group = provider
+            .auto_scaling_groups(&self.profile, None)
+            .await?
+            .into_iter()
+            .find(|group| group.name == group_name)
+            .ok_or(eyre!("auto scaling group '{}' does not exist", group_name))?;
+        group
+            .instances(provider.running_instances(&self.profile, None).await?)
+            .into_iter()
+            .next()
+            .map(|instance| instance.id)
+            .ok_or(eyre!(
+                "auto scaling group '{}' has no healthy running instance",
+                group_name
+            ))
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.parameters
+            .iter()
+            .map(|(key, value)| {
+                let values = match value {
+                    ParameterValue::One(value) => vec![value.clone()],
+                    ParameterValue::Many(values) => values.clone(),
+                };
+                (key.clone(), values)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::StaticGroupProvider,
+        porwarder::Service,
+        providers::{AutoScalingGroupSummary, InstanceSummary, Tags},
+    };
+
+    fn tunnel(instance: &str) -> TunnelDefinition {
+        toml::from_str(&format!(
+            "profile = 'dev'\ninstance = '{}'\nservice = '{}'\nhost = 'db.internal'",
+            instance,
+            Service::all().remove(0).config_name()
+        ))
+        .unwrap()
+    }
+
+    fn instance(id: &str, launch_time: i64) -> InstanceSummary {
+        InstanceSummary {
+            id: id.to_string(),
+            name: "web".to_string(),
+            vpc_id: None,
+            availability_zone: None,
+            private_ip: None,
+            launch_time: Some(launch_time),
+            tags: Tags::new(),
+            hybrid: false,
+            security_groups: vec![],
+            image_id: None,
+            spot: false,
+            platform: None,
+            agent_version: None,
+        }
+    }
+
+    // i-launching is running but not yet healthy in the group
+    fn groups() -> StaticGroupProvider {
+        StaticGroupProvider {
+            groups: vec![
+                AutoScalingGroupSummary {
+                    name: "web".to_string(),
+                    instance_ids: vec!["i-old".to_string(), "i-new".to_string()],
+                },
+                AutoScalingGroupSummary {
+                    name: "drained".to_string(),
+                    instance_ids: vec!["i-gone".to_string()],
+                },
+            ],
+            instances: vec![
+                instance("i-old", 100),
+                instance("i-new", 200),
+                instance("i-launching", 300),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn the_newest_healthy_group_instance_is_used() {
+        let tunnel = tunnel("asg:web");
+        assert_eq!(
+            tunnel.group_instance(&groups(), "web").await.unwrap(),
+            "i-new"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_groups_and_groups_without_healthy_instances_fail() {
+        let tunnel = tunnel("asg:api");
+        assert_eq!(
+            tunnel
+                .group_instance(&groups(), "api")
+                .await
+                .unwrap_err()
+                .to_string(),
+            "auto scaling group 'api' does not exist"
+        );
+        assert_eq!(
+            tunnel
+                .group_instance(&groups(), "drained")
+                .await
+                .unwrap_err()
+                .to_string(),
+            "auto scaling group 'drained' has no healthy running instance"
+        );
+    }
+
+    // the instance the tunnel's group resolved to
+    fn resolved() -> PortForwarder {
+        PortForwarder::new(
+            "dev".to_string(),
+            "i-new".to_string(),
+            Service::all().remove(0),
+            "db.internal".to_string(),
+            5432,
+            15432,
+        )
+        .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exported_scripts_start_the_resolved_session() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let port_forwarder = resolved();
+        let script = tunnel("asg:web")
+            .script("db", &port_forwarder, &[])
+            .unwrap();
+        assert!(script.contains("# instance: i-new (resolved from asg:web)\n"));
+        assert!(script.contains("# tunnel: localhost:15432 -> db.internal:5432\n"));
+        assert!(script
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("exec aws --profile dev ssm start-session --target i-new "));
+
+        // an aws cli that prints the arguments it was started with, one per line
+        let dir = std::env::temp_dir().join(format!("porward-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let aws = dir.join("aws");
+        std::fs::write(&aws, "#!/bin/sh\nprintf '%s\\n' \"$@\"\n").unwrap();
+        std::fs::set_permissions(&aws, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("db.sh"), &script).unwrap();
+        let output = std::process::Command::new("bash")
+            .arg(dir.join("db.sh"))
+            .env("PATH", format!("{}:/usr/bin:/bin", dir.display()))
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            port_forwarder.command_args().unwrap()
+        );
+    }
+
+    #[test]
+    fn exported_ssh_config_proxies_through_the_resolved_instance() {
+        let config = tunnel("asg:web")
+            .ssh_config("db", None, &resolved(), &["a warning".to_string()])
+            .unwrap();
+        assert!(config.starts_with("# porward tunnel 'db', exported "));
+        assert!(config.contains("# warning: a warning\n"));
+        assert!(config.ends_with(
+            "Host db\n    HostName i-new\n    User ec2-user\n    Port 22\n    ProxyCommand aws ssm start-session --target %h --document-name AWS-StartSSHSession --parameters portNumber=%p --profile dev\n"
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    porwarder::{validate_host_name, validate_host_port, Service},
+    providers::InstanceSummary,
+};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+const PREFIX: &str = "porward://profile/";
+const TAG_PREFIX: &str = "tag:";
+const KEYS: &[&str] = &["instance", "service", "host", "port"];
+// longer values are not an instance, profile or host anyone shares
+const MAX_VALUE_LEN: usize = 253;
+
+/// The instance of a shared tunnel: a fixed id, or whichever running instance has a tag.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InstanceRef {
+    Id(String),
+    Tag { key: String, value: String },
+}
+
+impl InstanceRef {
+    pub fn matches(&self, instance: &InstanceSummary) -> bool {
+        match self {
+            InstanceRef::Id(id) => instance.id == *id,
+            InstanceRef::Tag { key, value } => instance.tags.get(key) == Some(value),
+        }
+    }
+}
+
+impl Display for InstanceRef {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            InstanceRef::Id(id) => write!(f, "{}", id),
+            InstanceRef::Tag { key, value } => write!(f, "{}{}={}", TAG_PREFIX, key, value),
+        }
+    }
+}
+
+/// A tunnel to share, `porward://profile/<profile>?instance=<id or tag:key=value>&service=<service>
+/// &host=<host>&port=<port>`, started again with `porward open`. Parsing is strict: unknown or
+/// repeated keys, and values that could pass for options of the aws cli or the plugin, are
+/// rejected rather than skipped.
+#[derive(Clone, Debug)]
+pub struct TunnelUri {
+    pub profile: String,
+    // asked for when not given
+    pub instance: Option<InstanceRef>,
+    pub service: Service,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Display for TunnelUri {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}{}?", PREFIX, encode(&self.profile))?;
+        if let Some(instance) = self.instance.as_ref() {
+            write!(f, "instance={}&", encode(&instance.to_string()))?;
+        }
+        write!(
+            f,
+            "service={}&host={}&port={}",
+            self.service.config_name(),
+            encode(&self.host),
+            self.port
+        )
+    }
+}
+
+impl FromStr for TunnelUri {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let rest = value
+            .strip_prefix(PREFIX)
+            .ok_or(format!("a tunnel uri starts with {}", PREFIX))?;
+        if rest.contains('#') {
+            return Err("a tunnel uri has no fragment".to_string());
+        }
+        let (profile, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if profile.contains('/') {
+            return Err("a tunnel uri has no path after the profile".to_string());
+        }
+        let profile = decode(profile)?;
+        validate_name("profile", &profile)?;
+
+        let mut instance = None;
+        let mut service = None;
+        let mut host = None;
+        let mut port = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("'{}' has no value", pair))?;
+            if !KEYS.contains(&key) {
+                return Err(format!("unknown key '{}'", key));
+            }
+            let value = decode(value)?;
+            let duplicate = match key {
+                "instance" => instance.replace(parse_instance(&value)?).is_some(),
+                "service" => service
+                    .replace(Service::from_str(&value).map_err(|e| format!("service: {}", e))?)
+                    .is_some(),
+                "host" => {
+                    validate_host_name(&value).map_err(|e| e.to_string())?;
+                    host.replace(value).is_some()
+                }
+                _ => {
+                    let value = value
+                        .parse::<u16>()
+                        .map_err(|_| format!("invalid port '{}'", value))
+                        .and_then(|port| validate_host_port(port).map_err(|e| e.to_string()))?;
+                    port.replace(value).is_some()
+                }
+            };
+            if duplicate {
+                return Err(format!("'{}' is given more than once", key));
+            }
+        }
+        let service: Service = service.ok_or("service is missing")?;
+        Ok(TunnelUri {
+            profile,
+            instance,
+            port: port.unwrap_or(service.default_port()),
+            service,
+            host: host.ok_or("host is missing")?,
+        })
+    }
+}
+
+fn parse_instance(value: &str) -> Result<InstanceRef, String> {
+    if let Some(tag) = value.strip_prefix(TAG_PREFIX) {
+        let (key, value) = tag
+            .split_once('=')
+            .ok_or(format!("tag '{}' is not key=value", tag))?;
+        validate_name("tag key", key)?;
+        if value.chars().any(char::is_control) || value.len() > MAX_VALUE_LEN {
+            return Err(format!("invalid tag value '{}'", value));
+        }
+        return Ok(InstanceRef::Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    // i-... for EC2, mi-... for hybrid nodes
+    let id = value
+        .strip_prefix("i-")
+        .or(value.strip_prefix("mi-"))
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()));
+    match id {
+        Some(_) => Ok(InstanceRef::Id(value.to_string())),
+        None => Err(format!("invalid instance '{}'", value)),
+    }
+}
+
+// profiles and tag keys end up next to options, so they may not look like one
+fn validate_name(name: &str, value: &str) -> Result<(), String> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_VALUE_LEN
+        && !value.starts_with('-')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+@:/".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid {} '{}'", name, value))
+    }
+}
+
+// everything but unreserved characters and the separators readable in a tag is escaped
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~:=@".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.bytes();
+    while let Some(byte) = rest.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [rest.next(), rest.next()];
+        let decoded = match hex {
+            [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        bytes.push(decoded.ok_or(format!("invalid escape in '{}'", value))?);
+    }
+    String::from_utf8(bytes).map_err(|_| format!("'{}' is not valid utf-8", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(instance: Option<InstanceRef>, host: &str) -> TunnelUri {
+        let service = Service::all().remove(0);
+        TunnelUri {
+            profile: "dev".to_string(),
+            instance,
+            port: service.default_port(),
+            service,
+            host: host.to_string(),
+        }
+    }
+
+    fn parse(value: &str) -> Result<TunnelUri, String> {
+        value.parse::<TunnelUri>()
+    }
+
+    #[test]
+    fn formatted_uris_parse_back() {
+        let instances = [
+            None,
+            Some(InstanceRef::Id("i-0123abcd".to_string())),
+            Some(InstanceRef::Id("mi-0123abcd".to_string())),
+            Some(InstanceRef::Tag {
+                key: "Name".to_string(),
+                value: "bastion".to_string(),
+            }),
+            // separators of the query inside a tag value
+            Some(InstanceRef::Tag {
+                key: "team".to_string(),
+                value: "a&b=c%d e?f#g".to_string(),
+            }),
+        ];
+        for instance in instances {
+            for host in ["db.internal", "10.0.0.5", "fd00::5"] {
+                let uri = uri(instance.clone(), host);
+                let parsed = parse(&uri.to_string()).unwrap();
+                assert_eq!(parsed.profile, uri.profile);
+                assert_eq!(parsed.instance, uri.instance);
+                assert_eq!(parsed.service.config_name(), uri.service.config_name());
+                assert_eq!(parsed.host, uri.host);
+                assert_eq!(parsed.port, uri.port);
+                assert_eq!(parsed.to_string(), uri.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn hosts_with_query_syntax_stay_one_value() {
+        // encoded, so the whole host is rejected instead of adding keys
+        for host in ["db&port=22", "db=x", "db%41", "db&instance=i-1"] {
+            let error = parse(&uri(None, host).to_string()).err().unwrap();
+            assert!(error.contains(host), "{}", error);
+        }
+    }
+
+    #[test]
+    fn unknown_and_repeated_keys_are_rejected() {
+        let service = Service::all().remove(0);
+        let base = format!("{}dev?service={}&host=db", PREFIX, service.config_name());
+        assert_eq!(
+            parse(&format!("{}&region=eu-west-1", base)).err().unwrap(),
+            "unknown key 'region'"
+        );
+        assert_eq!(
+            parse(&format!("{}&host=other", base)).err().unwrap(),
+            "'host' is given more than once"
+        );
+        assert_eq!(
+            parse(&format!("{}&port=1&port=2", base)).err().unwrap(),
+            "'port' is given more than once"
+        );
+        assert!(parse(&format!("{}&port", base)).is_err());
+        assert!(parse(&format!("{}#fragment", base)).is_err());
+    }
+
+    #[test]
+    fn values_that_smuggle_arguments_are_rejected() {
+        let service = Service::all().remove(0).config_name();
+        for value in [
+            // a second path segment, plain and encoded
+            format!("{}dev/extra?service={}&host=db", PREFIX, service),
+            format!("{}dev?service={}&host=db%2F..%2Fetc", PREFIX, service),
+            // options of the aws cli or the plugin
+            format!("{}--debug?service={}&host=db", PREFIX, service),
+            format!("{}dev?service={}&host=--profile", PREFIX, service),
+            format!("{}dev?service={}&host=db%20--region%20x", PREFIX, service),
+            // control characters, e.g. a new line in /etc/hosts or a shell script
+            format!("{}dev?service={}&host=db%0A127.0.0.1", PREFIX, service),
+            format!("{}dev%00?service={}&host=db", PREFIX, service),
+            format!(
+                "{}dev?instance=tag:Name=a%0Ab&service={}&host=db",
+                PREFIX, service
+            ),
+            format!(
+                "{}dev?instance=i-1%20--x&service={}&host=db",
+                PREFIX, service
+            ),
+            // broken escapes
+            format!("{}dev?service={}&host=db%G1", PREFIX, service),
+            format!("{}dev?service={}&host=db%2", PREFIX, service),
+            format!("{}dev?service={}&host=db&port=0", PREFIX, service),
+        ] {
+            assert!(parse(&value).is_err(), "{} was accepted", value);
+        }
+    }
+}
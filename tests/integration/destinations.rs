@@ -0,0 +1,149 @@
+#[cfg(feature = "alb")]
+mod load_balancers {
+    use crate::{config, endpoint_url, run_prefix, PROFILE};
+    use porward::{
+        porwarder::Service,
+        providers::{AwsDestinationProvider, DestinationProvider},
+    };
+
+    // an application load balancer needs subnets in two zones
+    async fn subnets(config: &aws_config::SdkConfig) -> (String, Vec<String>) {
+        let client = aws_sdk_ec2::Client::new(config);
+        let vpc = client
+            .create_vpc()
+            .cidr_block("10.20.0.0/16")
+            .send()
+            .await
+            .expect("create_vpc");
+        let vpc_id = vpc.vpc().unwrap().vpc_id().unwrap().to_string();
+        let mut subnet_ids = vec![];
+        for (cidr, zone) in [
+            ("10.20.1.0/24", "us-east-1a"),
+            ("10.20.2.0/24", "us-east-1b"),
+        ] {
+            let subnet = client
+                .create_subnet()
+                .vpc_id(&vpc_id)
+                .cidr_block(cidr)
+                .availability_zone(zone)
+                .send()
+                .await
+                .expect("create_subnet");
+            subnet_ids.push(subnet.subnet().unwrap().subnet_id().unwrap().to_string());
+        }
+        (vpc_id, subnet_ids)
+    }
+
+    #[tokio::test]
+    async fn load_balancers_are_listed_by_name() {
+        let config = config().await;
+        let (vpc_id, subnet_ids) = subnets(&config).await;
+        let client = aws_sdk_elasticloadbalancingv2::Client::new(&config);
+        let mut created = vec![];
+        for i in 0..2 {
+            let name = format!("{}-{i}", run_prefix("alb"));
+            let output = client
+                .create_load_balancer()
+                .name(&name)
+                .set_subnets(Some(subnet_ids.clone()))
+                .send()
+                .await
+                .expect("create_load_balancer");
+            let lb = &output.load_balancers()[0];
+            created.push((
+                lb.load_balancer_arn().unwrap().to_string(),
+                lb.dns_name().unwrap().to_string(),
+                name,
+            ));
+        }
+
+        let provider = AwsDestinationProvider::with_endpoint_url(endpoint_url());
+        let destinations = provider
+            .destinations(PROFILE, None, &Service::ApplicationLoadBalancer)
+            .await;
+        for (arn, _, _) in created.iter() {
+            let _ = client
+                .delete_load_balancer()
+                .load_balancer_arn(arn)
+                .send()
+                .await;
+        }
+
+        let destinations = destinations.expect("destinations");
+        for (_, dns_name, name) in created.iter() {
+            let destination = destinations
+                .iter()
+                .find(|destination| &destination.label == name)
+                .unwrap_or_else(|| panic!("{name} is not listed"));
+            assert_eq!(&destination.host, dns_name);
+            assert_eq!(destination.vpc_id.as_deref(), Some(vpc_id.as_str()));
+        }
+    }
+}
+
+#[cfg(feature = "rds")]
+mod databases {
+    use crate::{config, endpoint_url, run_prefix, PROFILE};
+    use porward::{
+        porwarder::Service,
+        providers::{AwsDestinationProvider, DestinationProvider},
+    };
+
+    #[tokio::test]
+    #[ignore = "RDS needs LocalStack Pro"]
+    async fn clusters_and_their_endpoints_are_listed() {
+        let client = aws_sdk_rds::Client::new(&config().await);
+        let cluster_id = run_prefix("cluster");
+        let cluster = client
+            .create_db_cluster()
+            .db_cluster_identifier(&cluster_id)
+            .engine("aurora-postgresql")
+            .master_username("porward")
+            .master_user_password("porward-test")
+            .send()
+            .await
+            .expect("create_db_cluster");
+        let writer = cluster
+            .db_cluster()
+            .and_then(|cluster| cluster.endpoint())
+            .expect("cluster endpoint")
+            .to_string();
+        client
+            .create_db_instance()
+            .db_instance_identifier(format!("{cluster_id}-1"))
+            .db_cluster_identifier(&cluster_id)
+            .engine("aurora-postgresql")
+            .db_instance_class("db.t3.medium")
+            .send()
+            .await
+            .expect("create_db_instance");
+
+        let provider = AwsDestinationProvider::with_endpoint_url(endpoint_url());
+        let clusters = provider.clusters(PROFILE, None, &Service::Postgresql).await;
+        let destinations = provider
+            .cluster_destinations(PROFILE, None, &Service::Postgresql, &cluster_id)
+            .await;
+        let _ = client
+            .delete_db_instance()
+            .db_instance_identifier(format!("{cluster_id}-1"))
+            .skip_final_snapshot(true)
+            .send()
+            .await;
+        let _ = client
+            .delete_db_cluster()
+            .db_cluster_identifier(&cluster_id)
+            .skip_final_snapshot(true)
+            .send()
+            .await;
+
+        let clusters = clusters.expect("clusters");
+        assert!(clusters.iter().any(|cluster| cluster.id == cluster_id));
+        let destinations = destinations.expect("cluster_destinations");
+        assert!(destinations
+            .iter()
+            .any(|destination| destination.host == writer));
+        assert!(destinations
+            .iter()
+            .all(|destination| destination.cluster_id.as_deref() == Some(cluster_id.as_str())));
+    }
+}
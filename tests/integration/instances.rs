@@ -0,0 +1,94 @@
+use crate::{config, endpoint_url, run_prefix, PROFILE};
+use aws_sdk_ec2::types::{InstanceType, ResourceType, Tag, TagSpecification};
+use porward::providers::{AwsInstanceProvider, InstanceProvider};
+
+// one of LocalStack's mocked images
+const IMAGE_ID: &str = "ami-df5de72bdb3b";
+// the smallest page DescribeInstances takes
+const PAGE_SIZE: i32 = 5;
+
+async fn run_instances(prefix: &str, count: usize) -> Vec<(String, String)> {
+    let client = aws_sdk_ec2::Client::new(&config().await);
+    let mut instances = vec![];
+    for i in 0..count {
+        let name = format!("{prefix}-{i}");
+        let output = client
+            .run_instances()
+            .image_id(IMAGE_ID)
+            .instance_type(InstanceType::T3Micro)
+            .min_count(1)
+            .max_count(1)
+            .tag_specifications(
+                TagSpecification::builder()
+                    .resource_type(ResourceType::Instance)
+                    .tags(Tag::builder().key("Name").value(&name).build())
+                    .build(),
+            )
+            .send()
+            .await
+            .expect("run_instances");
+        let id = output.instances()[0].instance_id().unwrap().to_string();
+        instances.push((id, name));
+    }
+    instances
+}
+
+async fn terminate(ids: Vec<String>) {
+    let client = aws_sdk_ec2::Client::new(&config().await);
+    let _ = client
+        .terminate_instances()
+        .set_instance_ids(Some(ids))
+        .send()
+        .await;
+}
+
+fn listed(
+    instances: &[porward::providers::InstanceSummary],
+    prefix: &str,
+) -> Vec<(String, String)> {
+    let mut listed: Vec<_> = instances
+        .iter()
+        .filter(|instance| instance.name.starts_with(prefix))
+        .map(|instance| (instance.id.clone(), instance.name.clone()))
+        .collect();
+    listed.sort();
+    listed
+}
+
+#[tokio::test]
+async fn running_instances_are_listed_with_their_names() {
+    let prefix = run_prefix("instances");
+    let mut created = run_instances(&prefix, 2).await;
+    created.sort();
+
+    let provider = AwsInstanceProvider::with_endpoint_url(endpoint_url());
+    let instances = provider.running_instances(PROFILE, None).await;
+    terminate(created.iter().map(|(id, _)| id.clone()).collect()).await;
+
+    let instances = instances.expect("running_instances");
+    assert_eq!(listed(&instances, &prefix), created);
+    for instance in instances.iter().filter(|i| i.name.starts_with(&prefix)) {
+        assert_eq!(instance.tags.get("Name"), Some(&instance.name));
+        assert!(instance.private_ip.is_some());
+    }
+}
+
+#[tokio::test]
+async fn instances_past_the_first_page_are_listed() {
+    let prefix = run_prefix("pages");
+    // more than one page of our own, whatever other instances are running
+    let mut created = run_instances(&prefix, PAGE_SIZE as usize + 2).await;
+    created.sort();
+
+    let provider = AwsInstanceProvider::with_endpoint_url(endpoint_url()).with_page_size(PAGE_SIZE);
+    let instances = provider.running_instances(PROFILE, None).await;
+    terminate(created.iter().map(|(id, _)| id.clone()).collect()).await;
+
+    let instances = instances.expect("running_instances");
+    assert_eq!(listed(&instances, &prefix), created);
+    // an instance on two pages would be offered twice
+    let mut ids: Vec<_> = instances.iter().map(|instance| &instance.id).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), instances.len());
+}
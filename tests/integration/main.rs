@@ -0,0 +1,45 @@
+//! The instance and destination listers against LocalStack, run with
+//! `cargo test --features localstack-tests --test integration`.
+#![cfg(feature = "localstack-tests")]
+
+mod destinations;
+mod instances;
+
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PROFILE: &str = "default";
+const REGION: &str = "us-east-1";
+
+/// LocalStack's endpoint, `AWS_ENDPOINT_URL` when it is set.
+pub fn endpoint_url() -> String {
+    // the listers load the profile, LocalStack takes any credentials
+    for (name, value) in [
+        ("AWS_ACCESS_KEY_ID", "test"),
+        ("AWS_SECRET_ACCESS_KEY", "test"),
+        ("AWS_REGION", REGION),
+    ] {
+        if std::env::var_os(name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    std::env::var("AWS_ENDPOINT_URL").unwrap_or_else(|_| "http://localhost:4566".to_string())
+}
+
+/// Config for the clients that create the resources the listers should find.
+pub async fn config() -> SdkConfig {
+    aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(REGION))
+        .endpoint_url(endpoint_url())
+        .load()
+        .await
+}
+
+/// A prefix for the names of the resources of one run, so runs do not see each other's.
+pub fn run_prefix(kind: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    format!("porward-{kind}-{nanos:x}")
+}